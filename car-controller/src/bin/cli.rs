@@ -1,6 +1,15 @@
 //! Sends car control commands to the car's bt module
 
-use car_controller::{Bluetooth, Controller};
+use std::{io::Write, num::ParseIntError, path::PathBuf};
+
+use car_controller::{Bluetooth, CAR_LOG_TARGET, Car, Controller, Error, read_image};
+use car_transport::{
+	Capabilities, ErrorCode, LogLevel, Message, ParamId, ParamValue, Topics, auth::Key, clock,
+};
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::eyre;
+use futures::{StreamExt, pin_mut};
+use tokio::time::Duration;
 
 #[cfg(feature = "classic-bt")]
 /// Bluetooth name of the HC-06 Classic BT module
@@ -9,28 +18,313 @@ const BLUETOOTH_MODULE_HC_06: &str = "RenaultClio";
 /// Bluetooth name of the HM-10 BLE module
 const BLUETOOTH_MODULE_HM_10: &str = "RenaultClioBLE";
 
+/// Time of silence after which the car takes the control lease back
+const LEASE_DURATION: Duration = Duration::from_secs(1);
+/// Lease used during updates, erasing the flash keeps the car silent for a while
+const UPDATE_LEASE_DURATION: Duration = Duration::from_secs(10);
+/// Number of times an interrupted update is resumed before giving up
+const UPDATE_ATTEMPTS: u8 = 5;
+/// Number of clock samples taken before watching the telemetry
+const CLOCK_SAMPLES: u8 = 8;
+/// Number of snapshots printed between two clock samples, which follow the drift
+const SNAPSHOTS_PER_CLOCK_SAMPLE: usize = 50;
+
+/// Sends commands to the car
+#[derive(Parser)]
+struct Args {
+	/// Key shared with the car, as 64 hex digits, e.g. from `openssl rand -hex 32`
+	#[clap(long, env = "CAR_PSK", value_parser = parse_key, hide_env_values = true)]
+	psk: Option<Key>,
+	/// Prints the log records of the car of this level and above while the command runs
+	#[clap(long, value_name = "LEVEL")]
+	car_log: Option<LogLevel>,
+	/// What to do once connected, pings the car by default
+	#[clap(subcommand)]
+	command: Option<Command>,
+}
+
+/// Commands that can be sent to the car
+#[derive(Subcommand)]
+enum Command {
+	/// Checks that the car answers
+	Ping,
+	/// Lists every tunable with its current value
+	Params,
+	/// Prints the current value of a tunable
+	Get {
+		/// Name or number of the tunable
+		#[clap(value_parser = parse_param_id)]
+		param: ParamId,
+	},
+	/// Changes a tunable
+	Set {
+		/// Name or number of the tunable
+		#[clap(value_parser = parse_param_id)]
+		param: ParamId,
+		/// New value, parsed with the type of the current value
+		value: String,
+	},
+	/// Prints the telemetry pushed by the car
+	Watch {
+		/// Time between two snapshots in milliseconds
+		#[clap(long, default_value_t = 200)]
+		period_ms: u64,
+	},
+	/// Prints the log records of the car
+	Logs {
+		/// Least important level to print
+		#[clap(long, default_value_t = LogLevel::Info)]
+		level: LogLevel,
+	},
+	/// Brakes the car and keeps it stopped until the fault is cleared
+	Stop,
+	/// Clears the fault latched by an emergency stop
+	ClearFault,
+	/// Uploads a new firmware and reboots the car on it
+	Update {
+		/// The firmware, an ELF file as built by cargo or a raw binary
+		image: PathBuf,
+	},
+	/// Drives the car with the gamepad
+	Drive {
+		/// Time between two commands in milliseconds
+		#[clap(long, default_value_t = 50)]
+		period_ms: u64,
+	},
+}
+
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
 	color_eyre::install()?;
-	pretty_env_logger::init();
+	// The records of the car are shown unless filtered out by `RUST_LOG`
+	pretty_env_logger::formatted_timed_builder()
+		.filter_module(CAR_LOG_TARGET, log::LevelFilter::Trace)
+		.parse_default_env()
+		.init();
+
+	let args = Args::parse();
 
 	println!("Connecting...");
 
 	let gamepad = Controller::new()?;
-	let mut bluetooth = Bluetooth::connect_by_name(BLUETOOTH_MODULE_HC_06, None).await?;
-	// Blue
+	let bluetooth = Bluetooth::connect_by_name(BLUETOOTH_MODULE_HC_06, None).await?;
+	let mut car = Car::new(bluetooth);
+	let session = car.handshake().await?;
+	match args.psk {
+		Some(key) => car.authenticate(key).await?,
+		None if session.capabilities.contains(Capabilities::AUTHENTICATION) => {
+			return Err(eyre!("the car needs a key, pass `--psk` or set `CAR_PSK`"));
+		}
+		None => {}
+	}
 
 	println!("Connected");
 
-	loop {
-		// bluetooth.write(&[20, 40, 0, 10]).await?;
-		let res = bluetooth.read().await?;
+	if args.car_log.is_some() {
+		car.set_log_level(args.car_log).await?;
+	}
+
+	match args.command.unwrap_or(Command::Ping) {
+		Command::Ping => {
+			let answer = car.request(Message::Ping).await?;
+			log::debug!("{answer:?}");
+		}
+		Command::Params => {
+			for param in car.list_params().await? {
+				println!("{} = {}", param.id, param.value);
+			}
+		}
+		Command::Get { param } => {
+			println!("{param} = {}", car.get_param(param).await?);
+		}
+		Command::Set { param, value } => {
+			let current = car.get_param(param).await?;
+			let value = parse_param_value(current, &value)
+				.ok_or_else(|| eyre!("`{value}` is not a valid value for {param}"))?;
 
-		if res != [0, 0] {
-			log::debug!("{res:?}");
-			break;
+			car.acquire_control(LEASE_DURATION).await?;
+			println!("{param} = {}", car.set_param(param, value).await?);
+			car.release_control().await?;
+		}
+		Command::Watch { period_ms } => {
+			watch(&mut car, session.version >= clock::SINCE, period_ms).await?;
+		}
+		Command::Logs { level } => {
+			car.set_log_level(Some(level)).await?;
+			car.follow_logs().await?;
+		}
+		Command::Stop => {
+			car.emergency_stop().await?;
+			println!("Stopped, run `clear-fault` to drive again");
+		}
+		Command::ClearFault => {
+			car.acquire_control(LEASE_DURATION).await?;
+			match car.clear_fault().await? {
+				Some(fault) => println!("Cleared {fault}"),
+				None => println!("No fault to clear"),
+			}
+			car.release_control().await?;
+		}
+		Command::Update { image } => {
+			let image = read_image(&image)?;
+			update(&mut car, &image).await?;
+			println!("Updated, the car reboots on the new firmware");
+		}
+		Command::Drive { period_ms } => {
+			let mut gamepad = gamepad.ok_or_else(|| eyre!("no gamepad is connected"))?;
+			drive(&mut car, &mut gamepad, Duration::from_millis(period_ms)).await?;
 		}
 	}
 
 	Ok(())
 }
+
+/// Prints the telemetry pushed by the car until the connection fails
+///
+/// When the car stamps its snapshots, its clock is synchronized first and each snapshot is
+/// printed with the time elapsed since it was sampled.
+async fn watch(car: &mut Car, stamped: bool, period_ms: u64) -> color_eyre::Result<()> {
+	if stamped {
+		for _ in 0..CLOCK_SAMPLES {
+			car.sync_clock().await?;
+		}
+	}
+
+	let period = car
+		.subscribe(Topics::ALL, Duration::from_millis(period_ms))
+		.await?;
+	println!("Receiving telemetry every {period:?}");
+
+	loop {
+		let clock = car.clock();
+		if let Some(clock) = clock {
+			log::debug!("Car clock estimate: {:?}", clock.estimate());
+		}
+
+		{
+			let telemetry = car.telemetry().take(SNAPSHOTS_PER_CLOCK_SAMPLE);
+			pin_mut!(telemetry);
+			while let Some(snapshot) = telemetry.next().await {
+				let snapshot = snapshot?;
+				match (clock, snapshot.sampled_at.0) {
+					(Some(clock), Some(at)) => {
+						println!(
+							"{snapshot:?}, sampled {:?} ago",
+							clock.instant(at).elapsed()
+						);
+					}
+					_ => println!("{snapshot:?}"),
+				}
+			}
+		}
+
+		if stamped {
+			car.sync_clock().await?;
+		}
+	}
+}
+
+/// Forwards the gamepad state to the car until the connection fails
+///
+/// The commands sent on every tick keep the control lease alive.
+async fn drive(
+	car: &mut Car,
+	gamepad: &mut Controller,
+	period: Duration,
+) -> color_eyre::Result<()> {
+	let mut interval = tokio::time::interval(period);
+	car.acquire_control(LEASE_DURATION).await?;
+
+	loop {
+		interval.tick().await;
+		gamepad.update();
+		let state = gamepad.state();
+
+		// The emergency stop goes first and is delivered reliably
+		if state.emergency_stop {
+			car.emergency_stop().await?;
+			gamepad.buzz()?;
+			continue;
+		}
+		if state.clear_fault
+			&& let Some(fault) = car.clear_fault().await?
+		{
+			println!("Cleared {fault}");
+		}
+
+		for message in car.drive_commands(state.throttle, state.steering)? {
+			match car.request(message).await {
+				// Lost commands are superseded by the next tick, and speed changes are refused
+				// while a fault is latched
+				Ok(_) | Err(Error::NoAnswer | Error::Refused(ErrorCode::Faulted)) => {}
+				// The lease expired after too many lost commands, it is taken again if it is free
+				Err(Error::Refused(ErrorCode::NotLeaseHolder)) => {
+					log::warn!("Lost control of the car, acquiring it again");
+					car.acquire_control(LEASE_DURATION).await?;
+					break;
+				}
+				Err(err) => return Err(err.into()),
+			}
+		}
+	}
+}
+
+/// Uploads the image, resuming the upload when the link drops
+async fn update(car: &mut Car, image: &[u8]) -> color_eyre::Result<()> {
+	let mut attempt = 1;
+
+	loop {
+		car.acquire_control(UPDATE_LEASE_DURATION).await?;
+
+		let uploaded = car
+			.update(image, |offset, size| {
+				print!("\rUploaded {offset}/{size} bytes");
+				// The progress is only informative
+				let _ = std::io::stdout().flush();
+			})
+			.await;
+		println!();
+
+		match uploaded {
+			Ok(()) => return Ok(()),
+			Err(Error::NoAnswer) if attempt < UPDATE_ATTEMPTS => {
+				log::warn!("The car stopped answering, resuming the update");
+				attempt += 1;
+			}
+			Err(err) => return Err(err.into()),
+		}
+	}
+}
+
+/// Parses a key written as 64 hex digits
+fn parse_key(input: &str) -> Result<Key, &'static str> {
+	Key::from_hex(input).ok_or("expected 64 hex digits")
+}
+
+/// Parses a tunable name, or its number for tunables unknown to this controller
+fn parse_param_id(input: &str) -> Result<ParamId, ParseIntError> {
+	ParamId::from_name(input).map_or_else(|| input.parse().map(ParamId), Ok)
+}
+
+/// Parses a value with the same type as `current`
+fn parse_param_value(current: ParamValue, input: &str) -> Option<ParamValue> {
+	match current {
+		ParamValue::Bool(_) => input.parse().ok().map(ParamValue::Bool),
+		ParamValue::Integer(_) => input.parse().ok().map(ParamValue::Integer),
+		ParamValue::Milli(_) => {
+			let (negative, input) = input
+				.strip_prefix('-')
+				.map_or((false, input), |input| (true, input));
+			let (whole, fraction) = input.split_once('.').unwrap_or((input, ""));
+			if fraction.len() > 3 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+				return None;
+			}
+
+			let whole = whole.parse::<i32>().ok()?;
+			let fraction = format!("{fraction:0<3}").parse::<i32>().ok()?;
+			let value = whole.checked_mul(1000)?.checked_add(fraction)?;
+
+			Some(ParamValue::Milli(if negative { -value } else { value }))
+		}
+	}
+}
@@ -0,0 +1,115 @@
+//! Converts hex frames into JSON and back, to read captures and write fixtures
+
+use std::io::{self, BufRead};
+
+use car_transport::{Answer, Envelope, FrameDecoder, FrameEncoder, Message, Transport};
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{Context, eyre};
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Converts frames between their hex and JSON representations
+#[derive(Parser)]
+struct Args {
+	/// The conversion to do
+	#[clap(subcommand)]
+	command: Command,
+
+	/// Whether the frames hold answers sent by the car instead of messages sent by the controller
+	#[clap(long, global = true)]
+	answer: bool,
+}
+
+/// Conversions between representations
+#[derive(Subcommand)]
+enum Command {
+	/// Prints each hex frame as a JSON envelope
+	Decode {
+		/// Hex frames with their delimiter, read line by line from stdin when missing
+		frames: Vec<String>,
+	},
+	/// Prints each JSON envelope as a hex frame
+	Encode {
+		/// JSON envelopes, read line by line from stdin when missing
+		envelopes: Vec<String>,
+	},
+}
+
+fn main() -> color_eyre::Result<()> {
+	color_eyre::install()?;
+
+	let args = Args::parse();
+
+	let (Command::Decode { frames: inputs } | Command::Encode { envelopes: inputs }) =
+		&args.command;
+	let inputs = if inputs.is_empty() {
+		io::stdin().lock().lines().collect::<Result<_, _>>()?
+	} else {
+		inputs.clone()
+	};
+
+	for input in inputs.iter().filter(|input| !input.trim().is_empty()) {
+		let output = match (&args.command, args.answer) {
+			(Command::Decode { .. }, false) => decode::<Message>(input),
+			(Command::Decode { .. }, true) => decode::<Answer>(input),
+			(Command::Encode { .. }, false) => encode::<Message>(input),
+			(Command::Encode { .. }, true) => encode::<Answer>(input),
+		}
+		.wrap_err_with(|| format!("could not convert `{input}`"))?;
+
+		println!("{output}");
+	}
+
+	Ok(())
+}
+
+/// Decodes the frames of a hex line into JSON envelopes, one per line
+fn decode<T: Transport + Serialize>(input: &str) -> color_eyre::Result<String> {
+	let bytes = parse_hex(input)?;
+	let mut decoder = FrameDecoder::<Envelope<T>>::new();
+
+	let mut envelopes = Vec::new();
+	for envelope in decoder.feed(&bytes) {
+		envelopes.push(serde_json::to_string(&envelope?)?);
+	}
+
+	if envelopes.is_empty() {
+		return Err(eyre!(
+			"no complete frame, is the trailing `00` delimiter missing?"
+		));
+	}
+
+	Ok(envelopes.join("\n"))
+}
+
+/// Encodes a JSON envelope into a hex frame
+fn encode<T: Transport + DeserializeOwned>(input: &str) -> color_eyre::Result<String> {
+	let envelope = serde_json::from_str::<Envelope<T>>(input)?;
+
+	let mut frame = [0_u8; 128];
+	let length = FrameEncoder::<128>::new().encode(&envelope, &mut frame)?;
+
+	Ok(frame[..length]
+		.iter()
+		.map(|byte| format!("{byte:02x}"))
+		.collect::<Vec<_>>()
+		.join(" "))
+}
+
+/// Parses hex bytes, separated by whitespace or not
+fn parse_hex(input: &str) -> color_eyre::Result<Vec<u8>> {
+	let digits = input
+		.chars()
+		.filter(|char| !char.is_whitespace())
+		.collect::<Vec<_>>();
+	if digits.len() % 2 != 0 {
+		return Err(eyre!("odd number of hex digits"));
+	}
+
+	digits
+		.chunks(2)
+		.map(|pair| {
+			let pair = pair.iter().collect::<String>();
+			u8::from_str_radix(&pair, 16).wrap_err_with(|| format!("`{pair}` is not a hex byte"))
+		})
+		.collect()
+}
@@ -0,0 +1,39 @@
+//! Exports the description of the protocol and the tools generated from it
+
+use car_controller::{dissector, reference};
+use car_transport::schema::PROTOCOL;
+use clap::{Parser, Subcommand};
+
+/// Prints a description of the protocol generated from its Rust definitions
+#[derive(Parser)]
+struct Args {
+	/// The description to print
+	#[clap(subcommand)]
+	command: Command,
+}
+
+/// Descriptions of the protocol
+#[derive(Subcommand)]
+enum Command {
+	/// Prints the schema of every message and answer as JSON
+	Schema,
+	/// Prints a Wireshark Lua dissector, to decode captures of the link such as `btmon -w`
+	Dissector,
+	/// Prints a Markdown reference of the protocol
+	Reference,
+}
+
+fn main() -> color_eyre::Result<()> {
+	color_eyre::install()?;
+
+	let args = Args::parse();
+
+	let output = match args.command {
+		Command::Schema => serde_json::to_string_pretty(&PROTOCOL)?,
+		Command::Dissector => dissector(&PROTOCOL)?,
+		Command::Reference => reference(&PROTOCOL),
+	};
+	println!("{output}");
+
+	Ok(())
+}
@@ -4,35 +4,52 @@ use std::{fmt, io, pin::Pin};
 
 use btleplug::{
 	api::{
-		Central, CentralEvent, CharPropFlags, Characteristic, Manager as _, Peripheral as _,
-		ScanFilter, ValueNotification, WriteType,
+		Central, CentralEvent, CharPropFlags, Manager as _, Peripheral as _, ScanFilter,
+		ValueNotification, WriteType,
 	},
 	platform::{Manager, Peripheral},
 };
-use futures::{Stream, StreamExt};
+use car_transport::{
+	Answer, ControllerCodec, Envelope, FrameEncoder, Message, TransportError,
+	auth::Session,
+	compat,
+	frame::{DEFAULT_FRAME_BUFFER_SIZE, max_frame_size},
+	telemetry::LinkStats,
+};
+use futures::{Sink, SinkExt, Stream, StreamExt, sink};
 use tokio::time::{Duration, sleep};
+use tokio_util::{
+	bytes::{Bytes, BytesMut},
+	codec::{Encoder, FramedRead},
+	io::StreamReader,
+};
+
+/// Payloads of the notifications received through the `Bluetooth` characteristic
+type Notifications = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// Writes to the `Bluetooth` characteristic
+type Writes = Pin<Box<dyn Sink<Bytes, Error = Error> + Send>>;
 
 /// Implements the `Bluetooth` communication logic
 pub struct Bluetooth {
-	/// The distant `Bluetooth` device
-	pub peripheral: Peripheral,
-	/// The `Bluetooth` characteristic to send and receive data
-	pub characteristic: Characteristic,
-	/// Events received through the `Bluetooth` characteristic
-	events: Pin<Box<dyn Stream<Item = ValueNotification> + Send>>,
+	/// Writes the frames to the distant `Bluetooth` device
+	writes: Writes,
+	/// Answers decoded from the notifications, its codec also frames the sent messages
+	answers: FramedRead<StreamReader<Notifications, Bytes>, ControllerCodec>,
+	/// Counts the received and dropped frames
+	stats: LinkStats,
 }
 
 impl fmt::Debug for Bluetooth {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
-		f.debug_struct("CarBluetooth")
-			.field("peripheral", &self.peripheral)
-			.field("characteristic", &self.characteristic)
+		f.debug_struct("Bluetooth")
+			.field("stats", &self.stats)
 			.finish_non_exhaustive()
 	}
 }
 
 impl Bluetooth {
-	/// Creates a new [`CarBluetooth`] from the given [`Peripheral`]
+	/// Creates a new [`Bluetooth`] from the given [`Peripheral`]
 	///
 	/// # Errors
 	/// In case we don't find a device or if the peripheral does not have a characteristic to write and notify
@@ -55,13 +72,50 @@ impl Bluetooth {
 			)))?;
 
 		peripheral.subscribe(&characteristic).await?;
-		let events = peripheral.notifications().await?;
+		let notifications: Notifications = Box::pin(
+			peripheral
+				.notifications()
+				.await?
+				.map(|notification: ValueNotification| Ok(Bytes::from(notification.value))),
+		);
 
-		Ok(Self {
-			peripheral,
-			characteristic,
-			events,
-		})
+		let writes: Writes = Box::pin(sink::unfold(
+			(peripheral, characteristic),
+			|(peripheral, characteristic), bytes: Bytes| async move {
+				peripheral
+					.write(&characteristic, &bytes, WriteType::WithoutResponse)
+					.await?;
+				Ok((peripheral, characteristic))
+			},
+		));
+
+		Ok(Self::from_parts(notifications, writes))
+	}
+
+	/// Creates a new [`Bluetooth`] receiving the notifications and sending the writes
+	fn from_parts(notifications: Notifications, writes: Writes) -> Self {
+		Self {
+			writes,
+			answers: FramedRead::new(StreamReader::new(notifications), ControllerCodec::new()),
+			stats: LinkStats::default(),
+		}
+	}
+
+	/// Creates a [`Bluetooth`] over an in-memory pipe, the other end plays the car
+	#[cfg(test)]
+	pub(crate) fn pipe() -> (Self, tokio::io::DuplexStream) {
+		use tokio::io::AsyncWriteExt;
+
+		let (link, car) = tokio::io::duplex(1024);
+		let (received, sent) = tokio::io::split(link);
+
+		let notifications: Notifications = Box::pin(tokio_util::io::ReaderStream::new(received));
+		let writes: Writes = Box::pin(sink::unfold(sent, |mut sent, bytes: Bytes| async move {
+			sent.write_all(&bytes).await?;
+			Ok(sent)
+		}));
+
+		(Self::from_parts(notifications, writes), car)
 	}
 
 	/// Find a bluetooth peripheral by it's name and connect to it. Default timeout is 2s.
@@ -115,46 +169,76 @@ impl Bluetooth {
 	/// # Errors
 	/// In case the write operation fails
 	pub async fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
-		self.peripheral
-			.write(&self.characteristic, bytes, WriteType::WithoutResponse)
-			.await?;
+		self.writes.send(Bytes::copy_from_slice(bytes)).await
+	}
+
+	/// Send a framed message to the car
+	///
+	/// # Errors
+	/// In case the message cannot be framed or the write operation fails
+	pub async fn send(&mut self, message: &Envelope<Message>) -> Result<(), Error> {
+		let mut frame = BytesMut::new();
+		self.answers.decoder_mut().encode(message, &mut frame)?;
 
-		Ok(())
+		self.write(&frame).await
 	}
 
-	/// Read bytes from the `Bluetooth` device
+	/// Send a framed message to a car speaking the previous revision of the protocol
+	///
+	/// Such cars cannot authenticate, the frame is always plain.
 	///
 	/// # Errors
-	/// In case the read operation fails
-	pub async fn read(&mut self) -> Result<Vec<u8>, Error> {
-		let bytes = self.peripheral.read(&self.characteristic).await?;
+	/// In case the message cannot be framed or the write operation fails
+	pub async fn send_compat(&mut self, message: &Envelope<compat::Message>) -> Result<(), Error> {
+		let mut frame = [0_u8; max_frame_size(DEFAULT_FRAME_BUFFER_SIZE)];
+		let length =
+			FrameEncoder::<DEFAULT_FRAME_BUFFER_SIZE>::new().encode(message, &mut frame)?;
+
+		self.write(&frame[..length]).await
+	}
 
-		Ok(bytes)
+	/// Seals the following messages and opens the answers with the session key, [`None`] goes
+	/// back to plain frames
+	pub fn set_session(&mut self, session: Option<Session>) {
+		self.answers.decoder_mut().set_session(session);
 	}
 
-	/// Receive a message from the bluetooth device
+	/// Wait for the next complete answer from the car
+	///
+	/// Corrupted frames are logged, counted in [`Bluetooth::stats`] and skipped.
 	///
 	/// # Errors
 	/// In case the read operation fails
-	pub async fn receive(&mut self, bytes: &mut [u8]) -> Result<usize, Error> {
-		let stream = self.events.next().await.ok_or_else(|| {
-			std::io::Error::new(
-				std::io::ErrorKind::TimedOut,
-				"Bluetooth notification timeout occurred",
-			)
-		})?;
-		let data = &stream.value;
-
-		// Only write up until the length of the buffer
-		let length = bytes.len().min(data.len());
-		bytes[..length].copy_from_slice(&data[..length]);
-
-		// Return the number of bytes received from the stream
-		Ok(data.len())
+	pub async fn recv(&mut self) -> Result<Envelope<Answer>, Error> {
+		loop {
+			let answer = self.answers.next().await.ok_or_else(|| {
+				io::Error::new(
+					io::ErrorKind::UnexpectedEof,
+					"Bluetooth notification stream ended",
+				)
+			})??;
+
+			match answer {
+				Ok(answer) => {
+					self.stats.received = self.stats.received.wrapping_add(1);
+					return Ok(answer);
+				}
+				Err(error) => {
+					self.stats.dropped = self.stats.dropped.wrapping_add(1);
+					log::warn!("Dropped a corrupted frame: {error}");
+				}
+			}
+		}
+	}
+
+	/// Returns the counters of received and dropped frames
+	#[must_use]
+	pub const fn stats(&self) -> LinkStats {
+		self.stats
 	}
 }
 
-/// Errors that can occur when using [`CarBluetooth`]
+/// Errors that can occur when using [`Bluetooth`]
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
 	/// Bluetooth is not supported on this device
@@ -168,4 +252,8 @@ pub enum Error {
 	/// An error occurred while using the underlying library or communicating with the bluetooth device
 	#[error(transparent)]
 	BtlePlug(#[from] btleplug::Error),
+
+	/// A frame could not be encoded or decoded
+	#[error(transparent)]
+	Transport(#[from] TransportError),
 }
@@ -0,0 +1,904 @@
+//! High level client that matches every answer from the car to the message that caused it
+
+use std::{
+	collections::VecDeque,
+	convert::Infallible,
+	hash::{BuildHasher, Hasher, RandomState},
+	time::SystemTime,
+};
+
+use car_transport::{
+	Answer, Capabilities, Envelope, ErrorCode, Fault, LeaseToken, LogLevel, Message,
+	PROTOCOL_VERSION, Param, ParamId, ParamValue, ProtocolVersion, Steering, Telemetry, Throttle,
+	Timestamp, Topics,
+	arq::{Event, Retransmitter},
+	auth::{ControllerHandshake, Key, Nonce},
+	clock::{ClockEstimator, Estimate, SyncSample},
+	compat,
+	dfu::Uploader,
+	envelope::{Reply, Requests},
+	handshake::{self, Session, VersionMismatch},
+};
+use futures::{Stream, stream};
+use tokio::time::{Duration, Instant, timeout};
+
+use crate::bluetooth::{self, Bluetooth};
+
+/// Maximum number of messages waiting for an answer at the same time
+const MAX_PENDING_REQUESTS: usize = 8;
+/// Maximum number of snapshots kept until [`Car::telemetry`] reads them, older ones are dropped
+const MAX_BUFFERED_TELEMETRY: usize = 32;
+/// Target of the log records forwarded by the car, to tell them apart from the controller's
+pub const CAR_LOG_TARGET: &str = "car-core";
+
+/// Exchanges messages with the car over a [`Bluetooth`] link
+#[derive(Debug)]
+pub struct Car {
+	/// The link to the car
+	bluetooth: Bluetooth,
+	/// Messages waiting for their answer
+	requests: Requests<MAX_PENDING_REQUESTS>,
+	/// What was agreed on during the handshake
+	session: Option<Session>,
+	/// Snapshots received while waiting for an answer
+	telemetry: VecDeque<Telemetry>,
+	/// The token of the control lease, if it was acquired
+	lease: Option<LeaseToken>,
+
+	/// Sends reliable messages again until they are answered
+	retransmitter: Retransmitter<Message, MAX_PENDING_REQUESTS>,
+	/// Origin of the clock given to the retransmitter and to the car clock synchronization
+	started: Instant,
+	/// Relates the car clock to [`Car::started`]
+	clock: ClockEstimator,
+}
+
+impl Car {
+	/// Creates a client over an established [`Bluetooth`] link
+	#[must_use]
+	pub fn new(bluetooth: Bluetooth) -> Self {
+		Self {
+			bluetooth,
+			requests: Requests::new(),
+			session: None,
+			telemetry: VecDeque::new(),
+			lease: None,
+			retransmitter: Retransmitter::new(300, 2),
+			started: Instant::now(),
+			clock: ClockEstimator::new(),
+		}
+	}
+
+	/// Changes the time to wait for an answer and the number of times a reliable message is
+	/// sent again before giving up
+	#[must_use]
+	pub fn with_retransmission(mut self, timeout: Duration, retries: u8) -> Self {
+		self.retransmitter.timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+		self.retransmitter.retries = retries;
+		self
+	}
+
+	/// Returns what was agreed on during the handshake, if it happened
+	#[must_use]
+	pub const fn session(&self) -> Option<&Session> {
+		self.session.as_ref()
+	}
+
+	/// Exchanges the protocol version and learns which components the car has
+	///
+	/// When the car speaks an older compatible revision, the session is degraded. Messages it does
+	/// not know and messages for missing components are then refused locally. Cars speaking the
+	/// previous major revision are driven through [`compat`].
+	///
+	/// [`compat`]: car_transport::compat
+	///
+	/// # Errors
+	/// In case the car does not answer, which firmwares older than the handshake never do, or
+	/// speaks an incompatible revision
+	pub async fn handshake(&mut self) -> Result<Session, Error> {
+		let hello = Message::Hello {
+			version: PROTOCOL_VERSION,
+		};
+		let answer = match self.request(hello).await {
+			Err(Error::NoAnswer) => return Err(Error::NoHandshake),
+			answer => answer?,
+		};
+
+		let Answer::Hello {
+			version,
+			firmware,
+			capabilities,
+		} = answer
+		else {
+			return Err(Error::UnexpectedAnswer(answer));
+		};
+
+		let session = handshake::negotiate(version, firmware, capabilities)?;
+		log::info!("Car runs firmware {firmware} with protocol {version}");
+		if session.is_compat() {
+			log::warn!("Car speaks the previous protocol {version}, messages are converted to it");
+		} else if session.is_degraded() {
+			log::warn!("Car speaks the older protocol {version}, newer messages are disabled");
+		}
+
+		self.session = Some(session);
+
+		Ok(session)
+	}
+
+	/// Proves to the car that both sides know the pre-shared key, every following frame is
+	/// then sealed with the session key
+	///
+	/// The handshake travels in plain frames, a new one replaces the previous session.
+	///
+	/// # Errors
+	/// In case the request fails or the car does not know the key
+	pub async fn authenticate(&mut self, key: Key) -> Result<(), Error> {
+		self.bluetooth.set_session(None);
+
+		let handshake = ControllerHandshake::new(key, random_nonce());
+		let (nonce, proof) = match self.request(handshake.challenge()).await? {
+			Answer::AuthChallenge { nonce, proof } => (nonce, proof),
+			answer => return Err(Error::UnexpectedAnswer(answer)),
+		};
+
+		let (message, session) = handshake
+			.respond(nonce, proof)
+			.map_err(|_| Error::WrongKey)?;
+		match self.request(message).await? {
+			Answer::Authenticated => {
+				self.bluetooth.set_session(Some(session));
+				Ok(())
+			}
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Acquires the control lease, or renews it when it is already held, and returns the
+	/// duration granted by the car
+	///
+	/// The lease expires when no command is sent for that long. Cars speaking the previous
+	/// major revision have no lease, control is granted right away.
+	///
+	/// # Errors
+	/// In case the request fails or another controller holds the lease
+	pub async fn acquire_control(&mut self, duration: Duration) -> Result<Duration, Error> {
+		if self.session.is_some_and(|session| session.is_compat()) {
+			self.lease = Some(self.lease.unwrap_or_default());
+			return Ok(duration);
+		}
+
+		let duration_ms = u16::try_from(duration.as_millis()).unwrap_or(u16::MAX);
+
+		let message = Message::AcquireControl {
+			renew: self.lease,
+			duration_ms,
+		};
+		match self.request(message).await? {
+			Answer::ControlGranted { lease, duration_ms } => {
+				self.lease = Some(lease);
+				Ok(Duration::from_millis(duration_ms.into()))
+			}
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Gives the control lease up, the car stops
+	///
+	/// # Errors
+	/// In case the lease was not acquired or the request fails
+	pub async fn release_control(&mut self) -> Result<(), Error> {
+		let lease = self.lease()?;
+		if self.session.is_some_and(|session| session.is_compat()) {
+			self.set_speed(Throttle::ZERO).await?;
+			self.lease = None;
+			return Ok(());
+		}
+
+		match self.request(Message::ReleaseControl { lease }).await? {
+			Answer::AckReleaseControl => {
+				self.lease = None;
+				Ok(())
+			}
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Returns the token of the control lease, needed by the commands
+	///
+	/// # Errors
+	/// In case the lease was not acquired with [`Car::acquire_control`]
+	pub fn lease(&self) -> Result<LeaseToken, Error> {
+		self.lease.ok_or(Error::NoLease)
+	}
+
+	/// Changes the speed of the car
+	///
+	/// # Errors
+	/// In case the lease was not acquired or the request fails
+	pub async fn set_speed(&mut self, throttle: Throttle) -> Result<(), Error> {
+		let lease = self.lease()?;
+		match self.request(Message::SetSpeed { lease, throttle }).await? {
+			Answer::AckSpeed => Ok(()),
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Changes the direction of the car
+	///
+	/// # Errors
+	/// In case the lease was not acquired or the request fails
+	pub async fn set_direction(&mut self, steering: Steering) -> Result<(), Error> {
+		let lease = self.lease()?;
+		match self
+			.request(Message::SetDirection { lease, steering })
+			.await?
+		{
+			Answer::AckDirection => Ok(()),
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Returns the commands setting the throttle and the steering, leaving out those the car
+	/// cannot handle, such as the steering of a car without [`Capabilities::SERVO`]
+	///
+	/// # Errors
+	/// In case the lease was not acquired
+	pub fn drive_commands(
+		&self,
+		throttle: Throttle,
+		steering: Steering,
+	) -> Result<Vec<Message>, Error> {
+		let lease = self.lease()?;
+
+		Ok([
+			Message::SetSpeed { lease, throttle },
+			Message::SetDirection { lease, steering },
+		]
+		.into_iter()
+		.filter(|message| self.session.is_none_or(|session| session.supports(message)))
+		.collect())
+	}
+
+	/// Returns the current value of a tunable
+	///
+	/// # Errors
+	/// In case the request fails or the car does not know the tunable
+	pub async fn get_param(&mut self, id: ParamId) -> Result<ParamValue, Error> {
+		match self.request(Message::GetParam(id)).await? {
+			Answer::Param(param) => Ok(param.value),
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Changes a tunable and returns the value stored by the car, which may have been clamped
+	///
+	/// # Errors
+	/// In case the lease was not acquired, the request fails or the car refused the change
+	pub async fn set_param(&mut self, id: ParamId, value: ParamValue) -> Result<ParamValue, Error> {
+		let message = Message::SetParam {
+			lease: self.lease()?,
+			param: Param { id, value },
+		};
+		match self.request(message).await? {
+			Answer::AckParam(param) => Ok(param.value),
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Returns every tunable of the car with its current value
+	///
+	/// # Errors
+	/// In case one of the requests fails
+	pub async fn list_params(&mut self) -> Result<Vec<Param>, Error> {
+		let mut params = Vec::new();
+
+		for index in 0..=u8::MAX {
+			match self.request(Message::ListParams { index }).await? {
+				Answer::ParamEntry {
+					param: Some(param), ..
+				} => params.push(param),
+				Answer::ParamEntry { param: None, .. } => break,
+				answer => return Err(Error::UnexpectedAnswer(answer)),
+			}
+		}
+
+		Ok(params)
+	}
+
+	/// Asks the car to push telemetry snapshots with the given topics periodically
+	///
+	/// Returns the period used by the car, which may be longer than the requested one.
+	/// Snapshots are read with [`Car::telemetry`].
+	///
+	/// # Errors
+	/// In case the request fails
+	pub async fn subscribe(&mut self, topics: Topics, period: Duration) -> Result<Duration, Error> {
+		let period_ms = u16::try_from(period.as_millis()).unwrap_or(u16::MAX);
+
+		match self
+			.request(Message::Subscribe { topics, period_ms })
+			.await?
+		{
+			Answer::Subscribed { period_ms } => Ok(Duration::from_millis(period_ms.into())),
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Stops the telemetry snapshots
+	///
+	/// # Errors
+	/// In case the request fails
+	pub async fn unsubscribe(&mut self) -> Result<(), Error> {
+		match self.request(Message::Unsubscribe).await? {
+			Answer::Unsubscribed => Ok(()),
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Asks the car to forward its log records of `level` and above, [`None`] stops forwarding
+	///
+	/// Records are logged with the [`CAR_LOG_TARGET`] target as they arrive, whatever the client
+	/// is waiting for, see [`Car::follow_logs`] to wait for them alone.
+	///
+	/// # Errors
+	/// In case the request fails
+	pub async fn set_log_level(&mut self, level: Option<LogLevel>) -> Result<(), Error> {
+		match self.request(Message::SetLogLevel { level }).await? {
+			Answer::AckLogLevel => Ok(()),
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Logs the records forwarded by the car after [`Car::set_log_level`] until the link fails
+	///
+	/// Other answers received meanwhile are dropped.
+	///
+	/// # Errors
+	/// In case the link fails
+	pub async fn follow_logs(&mut self) -> Result<Infallible, Error> {
+		loop {
+			let answer = self.bluetooth.recv().await?;
+			if !print_log(&answer.content) {
+				let reply = self.requests.resolve(&answer);
+				log::debug!("Dropped {reply:?} answer {answer:?} while following logs");
+			}
+		}
+	}
+
+	/// Samples the car clock once and returns the updated estimate of its offset and drift
+	///
+	/// Each call refines the estimate, a few calls in a row give a usable one and a call from
+	/// time to time follows the drift. Needs a car speaking at least [`clock::SINCE`].
+	///
+	/// [`clock::SINCE`]: car_transport::clock::SINCE
+	///
+	/// # Errors
+	/// In case the request fails
+	pub async fn sync_clock(&mut self) -> Result<CarClock, Error> {
+		let origin = self.timestamp();
+		let answer = self.request(Message::TimeSync { origin }).await?;
+		let answered = self.timestamp();
+
+		match answer {
+			Answer::TimeSync {
+				origin: echoed,
+				received,
+				transmitted,
+			} if echoed == origin => self.clock.add(SyncSample {
+				origin,
+				received,
+				transmitted,
+				answered,
+			}),
+			answer => return Err(Error::UnexpectedAnswer(answer)),
+		}
+
+		self.clock().ok_or(Error::NoAnswer)
+	}
+
+	/// Returns the relation between the car clock and this one, [`None`] until
+	/// [`Car::sync_clock`] is called
+	#[must_use]
+	pub fn clock(&self) -> Option<CarClock> {
+		self.clock.estimate().map(|estimate| CarClock {
+			estimate,
+			started: self.started,
+		})
+	}
+
+	/// Brakes the car and latches a fault, speed changes are refused until [`Car::clear_fault`]
+	///
+	/// # Errors
+	/// In case the request fails, the car may still be driving
+	pub async fn emergency_stop(&mut self) -> Result<(), Error> {
+		match self.request(Message::EmergencyStop).await? {
+			Answer::AckEmergencyStop => Ok(()),
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Clears the latched fault and returns it, [`None`] when there was none
+	///
+	/// # Errors
+	/// In case the lease was not acquired or the request fails
+	pub async fn clear_fault(&mut self) -> Result<Option<Fault>, Error> {
+		let lease = self.lease()?;
+		match self.request(Message::ClearFault { lease }).await? {
+			Answer::AckClearFault { cleared } => Ok(cleared),
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Returns the latched fault, [`None`] when the car runs normally
+	///
+	/// # Errors
+	/// In case the request fails
+	pub async fn fault(&mut self) -> Result<Option<Fault>, Error> {
+		match self.request(Message::GetFault).await? {
+			Answer::Fault(fault) => Ok(fault),
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Uploads a new firmware image and reboots the car on it, `progress` is called with the
+	/// number of bytes the car received
+	///
+	/// Calling it again with the same image after a failure resumes the upload where the car
+	/// stopped. The lease must be held, see [`Car::acquire_control`].
+	///
+	/// # Errors
+	/// In case the lease was not acquired, a request fails or the car refused the image
+	pub async fn update(
+		&mut self,
+		image: &[u8],
+		mut progress: impl FnMut(u32, u32),
+	) -> Result<(), Error> {
+		let lease = self.lease()?;
+		let mut uploader = Uploader::new(image).ok_or(Error::ImageTooLarge)?;
+
+		match self.request(uploader.begin(lease)).await? {
+			Answer::UpdateReady { offset } => uploader.seek(offset),
+			answer => return Err(Error::UnexpectedAnswer(answer)),
+		}
+		progress(uploader.offset(), uploader.size());
+
+		while let Some(message) = uploader.next_write(lease) {
+			match self.request(message).await? {
+				Answer::AckWriteUpdate { next } => uploader.seek(next),
+				answer => return Err(Error::UnexpectedAnswer(answer)),
+			}
+			progress(uploader.offset(), uploader.size());
+		}
+
+		match self.request(Message::VerifyUpdate { lease }).await? {
+			Answer::UpdateVerified => {}
+			answer => return Err(Error::UnexpectedAnswer(answer)),
+		}
+		match self.request(Message::CommitUpdate { lease }).await? {
+			Answer::AckCommitUpdate => {
+				// The car reboots on the new firmware, and its clock with it
+				self.lease = None;
+				self.session = None;
+				self.clock.clear();
+				Ok(())
+			}
+			answer => Err(Error::UnexpectedAnswer(answer)),
+		}
+	}
+
+	/// Returns the stream of telemetry snapshots pushed by the car after [`Car::subscribe`]
+	///
+	/// Late answers received meanwhile are dropped, the stream ends when the link fails.
+	pub fn telemetry(&mut self) -> impl Stream<Item = Result<Telemetry, Error>> + '_ {
+		stream::unfold(Some(self), |car| async move {
+			let car = car?;
+
+			loop {
+				if let Some(snapshot) = car.telemetry.pop_front() {
+					return Some((Ok(snapshot), Some(car)));
+				}
+
+				match car.bluetooth.recv().await {
+					Ok(Envelope {
+						content: Answer::Telemetry(snapshot),
+						..
+					}) => return Some((Ok(snapshot), Some(car))),
+					Ok(answer) if print_log(&answer.content) => {}
+					Ok(answer) => {
+						let reply = car.requests.resolve(&answer);
+						log::debug!(
+							"Dropped {reply:?} answer {answer:?} while streaming telemetry"
+						);
+					}
+					Err(error) => return Some((Err(error.into()), None)),
+				}
+			}
+		})
+	}
+
+	/// Sends the message and waits for its answer
+	///
+	/// Reliable messages, see [`Message::delivery`], are sent again in the same envelope on
+	/// timeout, so that a late answer to a previous attempt is still matched and the car can
+	/// tell it is a duplicate. Best effort messages are sent once.
+	///
+	/// # Errors
+	/// In case the car cannot handle the message, the link fails, the car does not answer after
+	/// every retry or refuses the message
+	pub async fn request(&mut self, message: Message) -> Result<Answer, Error> {
+		if let Some(session) = &self.session
+			&& !session.supports(&message)
+		{
+			return Err(Error::Unsupported {
+				required: message.required_capabilities(),
+				since: message.since(),
+			});
+		}
+
+		let envelope = self.requests.track(message).ok_or(Error::TooManyRequests)?;
+
+		let answer = self.exchange(&envelope).await;
+		if answer.is_err() {
+			self.requests.forget(envelope.sequence);
+		}
+
+		match answer? {
+			Answer::Nack { reason, .. } => Err(Error::Refused(reason)),
+			answer => Ok(answer),
+		}
+	}
+
+	/// Sends the envelope until it is answered or there are no retries left
+	async fn exchange(&mut self, envelope: &Envelope<Message>) -> Result<Answer, Error> {
+		let delivery = envelope.content.delivery();
+		if !self.retransmitter.send(envelope, delivery, self.now()) {
+			return Err(Error::TooManyRequests);
+		}
+
+		let answer = self.deliver(envelope).await;
+		self.retransmitter.acknowledge(envelope.sequence);
+
+		answer
+	}
+
+	/// Sends the envelope and its retransmissions while waiting for the answer
+	async fn deliver(&mut self, envelope: &Envelope<Message>) -> Result<Answer, Error> {
+		self.send(envelope).await?;
+
+		loop {
+			let remaining = self.retransmitter.remaining_ms(self.now()).unwrap_or(0);
+			if let Ok(answer) = timeout(
+				Duration::from_millis(remaining.into()),
+				self.wait_answer(envelope),
+			)
+			.await
+			{
+				return answer;
+			}
+
+			while let Some(event) = self.retransmitter.poll(self.now()) {
+				match event {
+					Event::Retransmit(envelope) => {
+						log::debug!("No answer to {envelope:?}, sending it again");
+						self.send(&envelope).await?;
+					}
+					Event::Expired(envelope) => {
+						log::debug!("No answer to {envelope:?}, giving up");
+						return Err(Error::NoAnswer);
+					}
+				}
+			}
+		}
+	}
+
+	/// Sends the envelope in the revision spoken by the car
+	///
+	/// Messages for a car speaking the previous major revision are converted with [`compat`],
+	/// its answers decode as current ones.
+	///
+	/// [`compat`]: car_transport::compat
+	async fn send(&mut self, envelope: &Envelope<Message>) -> Result<(), Error> {
+		if !self.session.is_some_and(|session| session.is_compat()) {
+			return Ok(self.bluetooth.send(envelope).await?);
+		}
+
+		let downgraded =
+			Envelope::<compat::Message>::downgrade(envelope).ok_or(Error::Unsupported {
+				required: envelope.content.required_capabilities(),
+				since: envelope.content.since(),
+			})?;
+
+		Ok(self.bluetooth.send_compat(&downgraded).await?)
+	}
+
+	/// Returns the time given to the retransmitter, in wrapping milliseconds
+	fn now(&self) -> u32 {
+		// The retransmitter only compares times close to each other, truncating keeps them right
+		#[allow(clippy::cast_possible_truncation)]
+		let now = self.started.elapsed().as_millis() as u32;
+
+		now
+	}
+
+	/// Returns the time given to the car clock synchronization, in microseconds
+	fn timestamp(&self) -> Timestamp {
+		Timestamp(u64::try_from(self.started.elapsed().as_micros()).unwrap_or(u64::MAX))
+	}
+
+	/// Receives answers until the one to the given message arrives
+	async fn wait_answer(&mut self, envelope: &Envelope<Message>) -> Result<Answer, Error> {
+		loop {
+			let answer = self.bluetooth.recv().await?;
+			if let Answer::Telemetry(snapshot) = answer.content {
+				if self.telemetry.len() == MAX_BUFFERED_TELEMETRY {
+					self.telemetry.pop_front();
+				}
+				self.telemetry.push_back(snapshot);
+				continue;
+			}
+			if print_log(&answer.content) {
+				continue;
+			}
+
+			match self.requests.resolve(&answer) {
+				Reply::Matched if answer.sequence == envelope.sequence => {
+					return Ok(answer.content);
+				}
+				Reply::Matched => log::debug!("Received late answer {answer:?}"),
+				reply => log::warn!("Dropped {reply:?} answer {answer:?}"),
+			}
+		}
+	}
+}
+
+/// Converts the timestamps of the car to instants of this computer, see [`Car::sync_clock`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CarClock {
+	/// Offset and drift of the car clock against [`CarClock::started`]
+	estimate: Estimate,
+	/// Instant of the timestamps of this computer
+	started: Instant,
+}
+
+impl CarClock {
+	/// Returns the offset and drift of the car clock
+	#[must_use]
+	pub const fn estimate(&self) -> &Estimate {
+		&self.estimate
+	}
+
+	/// Returns the instant at which the car clock showed the timestamp
+	///
+	/// Times before the client was created are clamped to its creation.
+	#[must_use]
+	pub fn instant(&self, at: Timestamp) -> Instant {
+		self.started + Duration::from_micros(self.estimate.to_host(at).0)
+	}
+}
+
+/// Errors that can occur when using [`Car`]
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	/// The car did not answer in time, even after retrying
+	#[error("The car did not answer in time")]
+	NoAnswer,
+
+	/// Too many messages are waiting for their answer
+	#[error("Too many messages are waiting for their answer")]
+	TooManyRequests,
+
+	/// The car answered with an answer of the wrong kind
+	#[error("The car answered with an unexpected {0:?}")]
+	UnexpectedAnswer(Answer),
+
+	/// The car lacks the components needed by the message or speaks an older revision
+	#[error("The car cannot handle the message, it needs protocol {since} and {required:?}")]
+	Unsupported {
+		/// The components needed by the message
+		required: Capabilities,
+		/// The first revision with the message
+		since: ProtocolVersion,
+	},
+
+	/// The car did not answer the handshake, its firmware may predate it
+	#[error(
+		"The car did not answer the handshake, its firmware may predate it and needs an update"
+	)]
+	NoHandshake,
+
+	/// The car speaks an incompatible revision of the protocol
+	#[error(transparent)]
+	IncompatibleProtocol(#[from] VersionMismatch),
+
+	/// The car refused the message
+	#[error("The car refused the message: {0}")]
+	Refused(ErrorCode),
+
+	/// The car does not know the pre-shared key
+	#[error("The car does not know the pre-shared key")]
+	WrongKey,
+
+	/// The firmware image is larger than the protocol allows
+	#[error("The firmware image is too large")]
+	ImageTooLarge,
+
+	/// The message needs the control lease, which was not acquired
+	#[error("Control of the car was not acquired")]
+	NoLease,
+
+	/// An error occurred on the underlying link
+	#[error(transparent)]
+	Bluetooth(#[from] bluetooth::Error),
+}
+
+/// Logs the answer if it is a record forwarded by the car, returns whether it was
+fn print_log(answer: &Answer) -> bool {
+	let Answer::Log {
+		level,
+		dropped,
+		text,
+	} = answer
+	else {
+		return false;
+	};
+
+	if *dropped != 0 {
+		log::warn!(target: CAR_LOG_TARGET, "{dropped} records were dropped");
+	}
+
+	let level = match level {
+		LogLevel::Trace => log::Level::Trace,
+		LogLevel::Debug => log::Level::Debug,
+		LogLevel::Info => log::Level::Info,
+		LogLevel::Warn => log::Level::Warn,
+		LogLevel::Error => log::Level::Error,
+	};
+	log::log!(target: CAR_LOG_TARGET, level, "{text}");
+
+	true
+}
+
+/// Returns a nonce that is never reused
+///
+/// The standard library seeds its hashers from the system randomness, the time makes sure two
+/// hashers never give the same nonce.
+fn random_nonce() -> Nonce {
+	let mut hasher = RandomState::new().build_hasher();
+	if let Ok(time) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+		hasher.write_u128(time.as_nanos());
+	}
+
+	Nonce(hasher.finish().to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+	use car_transport::{CarCodec, FirmwareVersion, Transport};
+	use futures::{SinkExt, StreamExt};
+	use tokio::{io::AsyncWriteExt, task::JoinHandle};
+	use tokio_util::codec::Framed;
+
+	use super::*;
+
+	/// A frame with a wrong checksum
+	const CORRUPTED_FRAME: [u8; 4] = [0x03, 0xFF, 0xFF, 0x00];
+
+	/// Plays a car answering the messages with `answer`, and returns the messages it received
+	///
+	/// A corrupted frame goes before every answer when `noisy`.
+	fn fake_car(
+		link: tokio::io::DuplexStream,
+		noisy: bool,
+		mut answer: impl FnMut(&Message) -> Option<Answer> + Send + 'static,
+	) -> JoinHandle<Vec<Message>> {
+		tokio::spawn(async move {
+			let mut framed = Framed::new(link, CarCodec::new());
+			let mut received = Vec::new();
+
+			while let Some(Ok(Ok(envelope))) = framed.next().await {
+				if let Some(content) = answer(&envelope.content) {
+					if noisy {
+						framed
+							.get_mut()
+							.write_all(&CORRUPTED_FRAME)
+							.await
+							.expect("the pipe is open");
+					}
+					framed
+						.send(Envelope {
+							sequence: envelope.sequence,
+							content,
+						})
+						.await
+						.expect("the pipe is open");
+				}
+				received.push(envelope.content);
+			}
+
+			received
+		})
+	}
+
+	#[tokio::test]
+	async fn corrupted_frames_are_skipped() -> Result<(), Error> {
+		let (bluetooth, link) = Bluetooth::pipe();
+		let received = fake_car(link, true, |message| match message {
+			Message::Ping => Some(Answer::Pong),
+			Message::GetFault => Some(Answer::Fault(None)),
+			_ => None,
+		});
+		let mut car = Car::new(bluetooth);
+
+		assert_eq!(car.request(Message::Ping).await?, Answer::Pong);
+		assert_eq!(car.fault().await?, None);
+		assert_eq!(car.bluetooth.stats().dropped, 2);
+		assert_eq!(car.bluetooth.stats().received, 2);
+
+		drop(car);
+		assert_eq!(
+			received.await.expect("the car ran"),
+			[Message::Ping, Message::GetFault]
+		);
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn motors_only_cars_are_driven_without_steering() -> Result<(), Error> {
+		let (bluetooth, link) = Bluetooth::pipe();
+		let received = fake_car(link, false, |message| match message {
+			Message::Hello { .. } => Some(Answer::Hello {
+				version: PROTOCOL_VERSION,
+				firmware: FirmwareVersion::parse("0.1.0"),
+				capabilities: Capabilities::MOTORS,
+			}),
+			Message::AcquireControl { duration_ms, .. } => Some(Answer::ControlGranted {
+				lease: LeaseToken(1),
+				duration_ms: *duration_ms,
+			}),
+			Message::SetSpeed { .. } => Some(Answer::AckSpeed),
+			_ => Some(Answer::Nack {
+				for_id: message.id(),
+				reason: ErrorCode::MissingComponent,
+			}),
+		});
+		let mut car = Car::new(bluetooth);
+
+		car.handshake().await?;
+		car.acquire_control(Duration::from_secs(1)).await?;
+		for _ in 0..2 {
+			for message in car.drive_commands(Throttle::MAX, Steering::MAX)? {
+				car.request(message).await?;
+			}
+		}
+
+		drop(car);
+		let received = received.await.expect("the car ran");
+		assert_eq!(received.len(), 4);
+		assert!(
+			!received
+				.iter()
+				.any(|message| matches!(message, Message::SetDirection { .. }))
+		);
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn cars_predating_the_handshake_are_reported() {
+		let (bluetooth, link) = Bluetooth::pipe();
+		let received = fake_car(link, false, |_| None);
+		let mut car = Car::new(bluetooth).with_retransmission(Duration::from_millis(10), 1);
+
+		assert!(matches!(car.handshake().await, Err(Error::NoHandshake)));
+		assert_eq!(car.session(), None);
+
+		drop(car);
+		assert!(
+			received
+				.await
+				.expect("the car ran")
+				.iter()
+				.all(|message| matches!(message, Message::Hello { .. }))
+		);
+	}
+}
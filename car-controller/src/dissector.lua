@@ -0,0 +1,350 @@
+-- Decodes the frames of the car protocol described by the `PROTOCOL` table above.
+--
+-- Frames are cut in arbitrary packets by the bluetooth modules, the bytes of each direction are
+-- gathered until a delimiter completes a frame. Frames sent by the capturing host are decoded as
+-- messages, received ones as answers.
+
+local car = Proto("car", "Embedded car protocol")
+
+-- Values of `pinfo.p2p_dir`
+local P2P_DIR_SENT = 0
+
+-- Longest incomplete frame kept, bytes of a lost delimiter are dropped after that
+local MAX_PENDING_SIZE = 256
+
+--- Returns the names of the variants indexed by id, for value strings
+local function names(variants)
+	local by_id = {}
+	for _, variant in ipairs(variants) do
+		by_id[variant.id] = variant.name
+	end
+	return by_id
+end
+
+local fields = {
+	sequence = ProtoField.uint8("car.sequence", "Sequence"),
+	message = ProtoField.uint8("car.message", "Message", base.DEC, names(PROTOCOL.messages)),
+	answer = ProtoField.uint8("car.answer", "Answer", base.DEC, names(PROTOCOL.answers)),
+	counter = ProtoField.uint32("car.seal.counter", "Seal counter"),
+	tag = ProtoField.bytes("car.seal.tag", "Seal tag"),
+	checksum = ProtoField.uint16("car.checksum", "Checksum", base.HEX),
+}
+car.fields = fields
+
+local experts = {
+	checksum = ProtoExpert.new("car.checksum.bad", "Bad checksum", expert.group.CHECKSUM, expert.severity.ERROR),
+	framing = ProtoExpert.new("car.framing", "Invalid byte stuffing", expert.group.MALFORMED, expert.severity.ERROR),
+	unknown = ProtoExpert.new("car.unknown", "Unknown variant", expert.group.MALFORMED, expert.severity.ERROR),
+	truncated = ProtoExpert.new("car.truncated", "Truncated payload", expert.group.MALFORMED, expert.severity.ERROR),
+	trailing = ProtoExpert.new("car.trailing", "Trailing bytes", expert.group.MALFORMED, expert.severity.WARN),
+	fragment = ProtoExpert.new("car.fragment", "Frame continues in a later packet", expert.group.REASSEMBLE, expert.severity.NOTE),
+}
+car.experts = experts
+
+--- Returns the variant with the given id, nil when there is none
+local function find_variant(variants, id)
+	for _, variant in ipairs(variants) do
+		if variant.id == id then
+			return variant
+		end
+	end
+end
+
+--- Returns the exclusive or of two 16 bits numbers, without relying on a bit library
+local function xor16(a, b)
+	local result, bit = 0, 1
+	for _ = 1, 16 do
+		if a % 2 ~= b % 2 then
+			result = result + bit
+		end
+		a, b, bit = math.floor(a / 2), math.floor(b / 2), bit * 2
+	end
+	return result
+end
+
+--- Computes the CRC-16/CCITT-FALSE checksum of the first bytes of the array
+local function crc16(bytes, length)
+	local crc = 0xFFFF
+	for index = 0, length - 1 do
+		crc = xor16(crc, bytes:get_index(index) * 256)
+		for _ = 1, 8 do
+			if crc >= 0x8000 then
+				crc = xor16((crc * 2) % 0x10000, 0x1021)
+			else
+				crc = (crc * 2) % 0x10000
+			end
+		end
+	end
+	return crc
+end
+
+--- Reverts the COBS byte stuffing of a frame without its delimiter, nil when it is invalid
+local function unstuff(stuffed)
+	local decoded = ByteArray.new()
+	local length = stuffed:len()
+	local index = 0
+
+	while index < length do
+		local code = stuffed:get_index(index)
+		if code == 0 or index + code > length then
+			return nil
+		end
+		if code > 1 then
+			decoded:append(stuffed:subset(index + 1, code - 1))
+		end
+		index = index + code
+
+		-- A full block does not stand for a zero, neither does the last block
+		if code ~= 0xFF and index ~= length then
+			decoded:append(ByteArray.new("00"))
+		end
+	end
+
+	return decoded
+end
+
+local decode
+
+--- Adds the members to the tree, returns the offset after them or nil when truncated
+local function decode_members(members, tvb, offset, limit, tree)
+	for _, member in ipairs(members) do
+		offset = decode(member.shape, tvb, offset, limit, tree, member.name, member.unit)
+		if not offset then
+			return nil
+		end
+	end
+	return offset
+end
+
+--- Returns the text shown after a value
+local function suffix(unit)
+	return unit and (" " .. unit) or ""
+end
+
+--- Adds the value at the offset to the tree, returns the offset after it or nil when truncated
+decode = function(shape, tvb, offset, limit, tree, label, unit)
+	local kind = shape.kind
+
+	if kind == "unsigned" or kind == "signed" or kind == "bool" or kind == "bytes" then
+		local size = shape.size or 1
+		if offset + size > limit then
+			return nil
+		end
+
+		local range = tvb(offset, size)
+		local value
+		if kind == "bool" then
+			value = tostring(range:uint() ~= 0)
+		elseif kind == "bytes" then
+			value = tostring(range:bytes())
+		elseif kind == "signed" then
+			value = tostring(size > 4 and range:int64() or range:int())
+		else
+			value = tostring(size > 4 and range:uint64() or range:uint())
+		end
+		tree:add(range, label .. ": " .. value .. suffix(unit))
+		return offset + size
+	end
+
+	if kind == "option" then
+		if offset + 1 > limit then
+			return nil
+		end
+		if tvb(offset, 1):uint() == 0 then
+			tree:add(tvb(offset, 1), label .. ": None")
+			return offset + 1
+		end
+		return decode(shape.value, tvb, offset + 1, limit, tree, label, unit)
+	end
+
+	-- Absent at the end of the payload, or when only the seal of the frame is left
+	if kind == "trailing" then
+		if offset >= limit or limit - offset == PROTOCOL.framing.seal_size then
+			return offset
+		end
+		return decode(shape.value, tvb, offset, limit, tree, label, unit)
+	end
+
+	if kind == "text" or kind == "list" then
+		if offset + shape.prefix > limit then
+			return nil
+		end
+		local start = offset
+		local count = tvb(offset, shape.prefix):uint()
+		offset = offset + shape.prefix
+
+		if kind == "text" then
+			if offset + count > limit then
+				return nil
+			end
+			local text = count > 0 and tvb(offset, count):string(ENC_UTF_8) or ""
+			tree:add(tvb(start, shape.prefix + count), label .. ": \"" .. text .. "\"")
+			return offset + count
+		end
+
+		local subtree = tree:add(tvb(start, shape.prefix), label .. ": " .. count .. " items")
+		for index = 1, count do
+			offset = decode(shape.item, tvb, offset, limit, subtree, "[" .. index .. "]", unit)
+			if not offset then
+				return nil
+			end
+		end
+		subtree:set_len(offset - start)
+		return offset
+	end
+
+	if kind == "struct" then
+		local members = shape.members
+		-- Wrappers of a single value show it directly, with the unit of the type
+		if #members == 1 and members[1].name == "0" then
+			return decode(members[1].shape, tvb, offset, limit, tree, label, shape.unit or unit)
+		end
+
+		local start = offset
+		local subtree = tree:add(tvb(offset, 0), label .. ": " .. shape.name)
+		offset = decode_members(members, tvb, offset, limit, subtree)
+		if offset then
+			subtree:set_len(offset - start)
+		end
+		return offset
+	end
+
+	if kind == "enum" then
+		if offset + 1 > limit then
+			return nil
+		end
+		local variant = find_variant(shape.variants, tvb(offset, 1):uint())
+		if not variant then
+			tree:add(tvb(offset, 1), label .. ": unknown " .. shape.name):add_proto_expert_info(experts.unknown)
+			return nil
+		end
+
+		local start = offset
+		local subtree = tree:add(tvb(offset, 1), label .. ": " .. variant.name)
+		offset = decode_members(variant.members, tvb, offset + 1, limit, subtree)
+		if offset then
+			subtree:set_len(offset - start)
+		end
+		return offset
+	end
+
+	return nil
+end
+
+--- Adds a decoded frame to the tree and returns the name of its content
+local function dissect_frame(bytes, sent, tree)
+	local tvb = bytes:tvb("Car frame")
+	local checksum_size = PROTOCOL.framing.checksum_size
+	local limit = tvb:len() - checksum_size
+	local direction = sent and "Message" or "Answer"
+
+	local subtree = tree:add(car, tvb(), "Car " .. direction)
+	if limit < 2 then
+		subtree:add_proto_expert_info(experts.truncated)
+		return "Malformed " .. direction
+	end
+
+	local checksum = subtree:add(fields.checksum, tvb(limit, checksum_size))
+	if crc16(bytes, limit) ~= tvb(limit, checksum_size):uint() then
+		checksum:add_proto_expert_info(experts.checksum)
+	end
+
+	local sequence = tvb(0, 1):uint()
+	subtree:add(fields.sequence, tvb(0, 1))
+	subtree:add(sent and fields.message or fields.answer, tvb(1, 1))
+
+	local variant = find_variant(sent and PROTOCOL.messages or PROTOCOL.answers, tvb(1, 1):uint())
+	if not variant then
+		subtree:add_proto_expert_info(experts.unknown)
+		return "Unknown " .. direction
+	end
+	subtree:append_text(": " .. variant.name)
+
+	local offset = decode_members(variant.members, tvb, 2, limit, subtree)
+	if not offset then
+		subtree:add_proto_expert_info(experts.truncated)
+	elseif limit - offset == PROTOCOL.framing.seal_size then
+		local seal = subtree:add(tvb(offset, limit - offset), "Seal")
+		seal:add(fields.counter, tvb(offset, 4))
+		seal:add(fields.tag, tvb(offset + 4, limit - offset - 4))
+		subtree:append_text(" (sealed)")
+	elseif offset ~= limit then
+		subtree:add(tvb(offset, limit - offset), "Trailing bytes"):add_proto_expert_info(experts.trailing)
+	end
+
+	return variant.name .. " #" .. sequence
+end
+
+-- Stuffed bytes of the incomplete frame of each direction
+local pending = {}
+-- Stuffed frames completed by each packet, by packet number
+local completed = {}
+
+function car.init()
+	pending = {}
+	completed = {}
+end
+
+function car.dissector(tvb, pinfo, tree)
+	local sent = pinfo.p2p_dir == P2P_DIR_SENT
+
+	-- Packets are seen in order on the first pass only, the frames they complete are kept
+	if not pinfo.visited then
+		local buffer = (pending[sent] or ByteArray.new()) .. tvb:bytes()
+		local frames = {}
+		local start = 0
+
+		for index = 0, buffer:len() - 1 do
+			if buffer:get_index(index) == PROTOCOL.framing.delimiter then
+				if index > start then
+					frames[#frames + 1] = buffer:subset(start, index - start)
+				end
+				start = index + 1
+			end
+		end
+
+		local rest = buffer:len() - start
+		if rest > 0 and rest <= MAX_PENDING_SIZE then
+			pending[sent] = buffer:subset(start, rest)
+		else
+			pending[sent] = nil
+		end
+		completed[pinfo.number] = frames
+	end
+
+	pinfo.cols.protocol = "CAR"
+
+	local frames = completed[pinfo.number] or {}
+	if #frames == 0 then
+		tree:add(car, tvb()):add_proto_expert_info(experts.fragment)
+		pinfo.cols.info = "Car frame fragment"
+		return tvb:len()
+	end
+
+	local contents = {}
+	for _, stuffed in ipairs(frames) do
+		local bytes = unstuff(stuffed)
+		if bytes then
+			contents[#contents + 1] = dissect_frame(bytes, sent, tree)
+		else
+			tree:add(car, tvb()):add_proto_expert_info(experts.framing)
+		end
+	end
+	pinfo.cols.info = table.concat(contents, ", ")
+
+	return tvb:len()
+end
+
+--- Registers the dissector in a table, when the table exists in this version of Wireshark
+local function register(name, key)
+	local found, table = pcall(DissectorTable.get, name)
+	if found and table then
+		table:add(key, car)
+	end
+end
+
+-- HM-10 modules carry the serial link in the value of this characteristic
+register("bluetooth.uuid", "ffe1")
+-- HC-06 modules use the first RFCOMM channel, its DLCI depends on which side connected
+register("btrfcomm.dlci", 2)
+register("btrfcomm.dlci", 3)
@@ -0,0 +1,84 @@
+//! Firmware images to upload with [`Car::update`](crate::Car::update)
+
+use std::{fs, io, path::Path};
+
+use object::{
+	Endianness, elf,
+	read::elf::{ElfFile32, FileHeader, ProgramHeader},
+};
+
+/// Value of erased flash, used to fill the gaps between segments
+const ERASED: u8 = 0xFF;
+
+/// Reads a firmware image, either an ELF file as built by cargo or a raw binary
+///
+/// # Errors
+/// In case the file cannot be read or is a malformed ELF file
+pub fn read_image(path: &Path) -> Result<Vec<u8>, Error> {
+	let file = fs::read(path)?;
+
+	if file.starts_with(&elf::ELFMAG) {
+		flatten(&file)
+	} else {
+		Ok(file)
+	}
+}
+
+/// Lays the loaded segments of an ELF file out as they are in flash
+///
+/// Segments are placed at their load address, the initial values of `.data` included,
+/// so that the image starts at the lowest one.
+fn flatten(file: &[u8]) -> Result<Vec<u8>, Error> {
+	let elf = ElfFile32::<Endianness>::parse(file)?;
+	let endian = elf.endian();
+
+	let mut segments = elf
+		.raw_header()
+		.program_headers(endian, file)?
+		.iter()
+		.filter(|header| header.p_type(endian) == elf::PT_LOAD && header.p_filesz(endian) != 0)
+		.map(|header| {
+			let data = header.data(endian, file).map_err(|()| Error::Truncated)?;
+			Ok((header.p_paddr(endian), data))
+		})
+		.collect::<Result<Vec<_>, Error>>()?;
+	segments.sort_by_key(|(address, _)| *address);
+
+	let start = segments.first().ok_or(Error::Empty)?.0;
+	let mut image = Vec::new();
+	for (address, data) in segments {
+		let offset = usize::try_from(address - start).map_err(|_| Error::Truncated)?;
+		if offset < image.len() {
+			return Err(Error::Overlapping(address));
+		}
+
+		image.resize(offset, ERASED);
+		image.extend_from_slice(data);
+	}
+
+	Ok(image)
+}
+
+/// Errors that can occur when reading a firmware image
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	/// The file could not be read
+	#[error(transparent)]
+	Io(#[from] io::Error),
+
+	/// The file is not a valid 32 bits ELF file
+	#[error(transparent)]
+	Elf(#[from] object::Error),
+
+	/// A segment goes past the end of the file
+	#[error("A segment goes past the end of the ELF file")]
+	Truncated,
+
+	/// Two segments are loaded at the same address
+	#[error("Two segments are loaded at {0:#010x}")]
+	Overlapping(u32),
+
+	/// No segment is loaded in flash
+	#[error("The ELF file has nothing to load in flash")]
+	Empty,
+}
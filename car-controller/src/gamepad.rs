@@ -1,18 +1,28 @@
 //! The implementation of a controller abstraction to control the car
 
+use car_transport::{Steering, Throttle};
 use color_eyre::eyre::{Context, eyre};
 use gilrs::{
-	Axis, Gamepad, GamepadId, Gilrs,
+	Axis, Button, Gamepad, GamepadId, Gilrs,
 	ff::{self, BaseEffect, BaseEffectType, Effect, EffectBuilder, Ticks},
 };
 
+/// Button that stops the car and latches a fault, `B` on most gamepads
+pub const EMERGENCY_STOP_BUTTON: Button = Button::East;
+/// Button that clears the latched fault
+pub const CLEAR_FAULT_BUTTON: Button = Button::Start;
+
 /// The current state that the car should follow
 #[derive(Debug)]
 pub struct ControlState {
-	/// The speed of the car, between -1.0 and 1.0
-	pub speed: f32,
-	/// The direction of the car, between -1.0 and 1.0
-	pub direction: f32,
+	/// The power sent to the motors
+	pub throttle: Throttle,
+	/// The position of the front wheels
+	pub steering: Steering,
+	/// Whether the emergency stop button is pressed
+	pub emergency_stop: bool,
+	/// Whether the button clearing the fault is pressed
+	pub clear_fault: bool,
 }
 
 /// A controller that can be used to control the car
@@ -73,8 +83,10 @@ impl Controller {
 		let gamepad = self.active_controller();
 
 		ControlState {
-			speed: gamepad.value(Axis::LeftStickY),
-			direction: gamepad.value(Axis::LeftStickX),
+			throttle: Throttle::from_ratio(gamepad.value(Axis::LeftStickY)),
+			steering: Steering::from_ratio(gamepad.value(Axis::LeftStickX)),
+			emergency_stop: gamepad.is_pressed(EMERGENCY_STOP_BUTTON),
+			clear_fault: gamepad.is_pressed(CLEAR_FAULT_BUTTON),
 		}
 	}
 
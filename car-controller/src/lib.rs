@@ -1,7 +1,15 @@
 //! Embedded Car controller, includes communication logic and control logic
 
 pub(crate) mod bluetooth;
+pub(crate) mod client;
+pub(crate) mod firmware;
 pub(crate) mod gamepad;
+pub(crate) mod reference;
+pub(crate) mod wireshark;
 
 pub use bluetooth::Bluetooth;
+pub use client::{CAR_LOG_TARGET, Car, CarClock, Error};
+pub use firmware::{Error as ImageError, read_image};
 pub use gamepad::Controller;
+pub use reference::reference;
+pub use wireshark::dissector;
@@ -0,0 +1,290 @@
+//! Markdown reference of the protocol generated from its schema
+//!
+//! Messages and answers are listed with their ids and members, followed by every type they carry.
+//! Descriptions are the documentation comments of the Rust definitions.
+
+use std::fmt::{self, Write};
+
+use car_transport::schema::{Member, Protocol, Shape, Variant};
+
+/// Returns the Markdown reference of the protocol
+#[must_use]
+pub fn reference(protocol: &Protocol) -> String {
+	let mut output = String::new();
+	// Cannot fail, writing to a string
+	let _ = write_reference(protocol, &mut output);
+
+	output
+}
+
+/// Writes the whole reference
+fn write_reference(protocol: &Protocol, output: &mut String) -> fmt::Result {
+	let framing = &protocol.framing;
+
+	writeln!(output, "# Car protocol {}\n", protocol.version)?;
+	writeln!(
+		output,
+		"Generated from the protocol schema by `cargo run --bin protocol -- reference`, do not edit.\n"
+	)?;
+
+	writeln!(output, "## Framing\n")?;
+	writeln!(
+		output,
+		"Every frame carries an envelope: a sequence number byte, the id of the variant and its \
+		 members one after the other. Integers are big endian. Answers echo the sequence number of \
+		 the message they answer, unsolicited answers the one of the message that asked for them.\n"
+	)?;
+	writeln!(
+		output,
+		"The `{}` checksum of the envelope is appended on {} bytes, then the frame is stuffed with \
+		 {} and ends with a `0x{:02X}` delimiter. Once a session is authenticated, envelopes are \
+		 sealed with {} bytes of counter and tag before the checksum.\n",
+		framing.checksum,
+		framing.checksum_size,
+		framing.stuffing,
+		framing.delimiter,
+		framing.seal_size,
+	)?;
+
+	write_variants(
+		output,
+		"Message",
+		"Sent by the controller.",
+		protocol.messages,
+	)?;
+	write_variants(output, "Answer", "Sent by the car.", protocol.answers)?;
+
+	let mut types = Vec::new();
+	for variant in protocol.messages.iter().chain(protocol.answers) {
+		collect_types(variant.members, &mut types);
+	}
+
+	writeln!(output, "## Types\n")?;
+	for shape in types {
+		write_type(output, shape)?;
+	}
+
+	Ok(())
+}
+
+/// Writes the summary and the section of every variant of `Message` or `Answer`
+fn write_variants(
+	output: &mut String,
+	kind: &str,
+	description: &str,
+	variants: &[Variant],
+) -> fmt::Result {
+	writeln!(output, "## {kind}s\n\n{description}\n")?;
+	writeln!(output, "| Id | {kind} | Size | Description |")?;
+	writeln!(output, "|---:|---|---:|---|")?;
+	for variant in variants {
+		writeln!(
+			output,
+			"| {} | [`{}`](#{}) | {} | {} |",
+			variant.id,
+			variant.name,
+			anchor(&format!("{kind}::{}", variant.name)),
+			variant.max_size(),
+			summary(variant.doc),
+		)?;
+	}
+	writeln!(output)?;
+
+	for variant in variants {
+		writeln!(output, "### `{kind}::{}`\n", variant.name)?;
+		writeln!(output, "Id `{}`. {}\n", variant.id, links(variant.doc))?;
+		write_members(output, variant.members)?;
+	}
+
+	Ok(())
+}
+
+/// Writes the section of a named type
+fn write_type(output: &mut String, shape: &Shape) -> fmt::Result {
+	match *shape {
+		Shape::Struct {
+			name,
+			doc,
+			unit,
+			members,
+		} => {
+			writeln!(output, "### `{name}`\n\n{}\n", links(doc))?;
+			match (members, unit) {
+				([member], Some(unit)) if member.name == "0" => writeln!(
+					output,
+					"Encoded as {}, in `{unit}`.\n",
+					type_name(member.shape)
+				),
+				([member], None) if member.name == "0" => {
+					writeln!(output, "Encoded as {}.\n", type_name(member.shape))
+				}
+				_ => write_members(output, members),
+			}
+		}
+		Shape::Enum {
+			name,
+			doc,
+			variants,
+		} => {
+			writeln!(output, "### `{name}`\n\n{}\n", links(doc))?;
+			writeln!(output, "| Id | Variant | Members | Description |")?;
+			writeln!(output, "|---:|---|---|---|")?;
+			for variant in variants {
+				let members = variant
+					.members
+					.iter()
+					.map(|member| format!("`{}`: {}", member.name, member_type(member)))
+					.collect::<Vec<_>>()
+					.join(", ");
+
+				writeln!(
+					output,
+					"| {} | `{}` | {members} | {} |",
+					variant.id,
+					variant.name,
+					summary(variant.doc),
+				)?;
+			}
+			writeln!(output)
+		}
+		_ => Ok(()),
+	}
+}
+
+/// Writes the table of the members, or says there are none
+fn write_members(output: &mut String, members: &[Member]) -> fmt::Result {
+	if members.is_empty() {
+		return writeln!(output, "No members.\n");
+	}
+
+	writeln!(output, "| Member | Type | Size | Description |")?;
+	writeln!(output, "|---|---|---:|---|")?;
+	for member in members {
+		writeln!(
+			output,
+			"| `{}` | {} | {} | {} |",
+			member.name,
+			member_type(member),
+			member.shape.max_size(),
+			summary(member.doc),
+		)?;
+	}
+
+	writeln!(output)
+}
+
+/// Returns the type of the member, with its unit
+fn member_type(member: &Member) -> String {
+	let name = type_name(member.shape);
+
+	match member.unit {
+		Some(unit) => format!("{name} (`{unit}`)"),
+		None => name,
+	}
+}
+
+/// Returns the name of the type, linked to its section for named types
+fn type_name(shape: &Shape) -> String {
+	match *shape {
+		Shape::Unsigned { size } => format!("u{}", size * 8),
+		Shape::Signed { size } => format!("i{}", size * 8),
+		Shape::Bool => "bool".to_owned(),
+		Shape::Bytes { size } => format!("[u8; {size}]"),
+		Shape::Option { value } => format!("Option<{}>", type_name(value)),
+		Shape::Trailing { value } => format!("Trailing<{}>", type_name(value)),
+		Shape::List { capacity, item, .. } => format!("Vec<{}, {capacity}>", type_name(item)),
+		Shape::Text { capacity, .. } => format!("String<{capacity}>"),
+		Shape::Struct { name, .. } | Shape::Enum { name, .. } => {
+			format!("[{name}](#{})", anchor(name))
+		}
+	}
+}
+
+/// Adds the named types carried by the members to `types`, once each
+fn collect_types(members: &'static [Member], types: &mut Vec<&'static Shape>) {
+	for member in members {
+		collect_shape(member.shape, types);
+	}
+}
+
+/// Adds the shape and the named types it carries to `types`, once each
+fn collect_shape(shape: &'static Shape, types: &mut Vec<&'static Shape>) {
+	match *shape {
+		Shape::Option { value: inner }
+		| Shape::Trailing { value: inner }
+		| Shape::List { item: inner, .. } => {
+			collect_shape(inner, types);
+		}
+		Shape::Struct { .. } | Shape::Enum { .. } => {
+			if types
+				.iter()
+				.any(|known| type_name(known) == type_name(shape))
+			{
+				return;
+			}
+			types.push(shape);
+
+			if let Shape::Struct { members, .. } = shape {
+				collect_types(members, types);
+			}
+			if let Shape::Enum { variants, .. } = shape {
+				for variant in *variants {
+					collect_types(variant.members, types);
+				}
+			}
+		}
+		_ => {}
+	}
+}
+
+/// Returns the anchor GitHub gives to a heading with the text in code
+fn anchor(text: &str) -> String {
+	text.chars()
+		.filter(|char| char.is_alphanumeric() || *char == '_' || *char == '-')
+		.flat_map(char::to_lowercase)
+		.collect()
+}
+
+/// Returns the first paragraph of the documentation on one line, for table cells
+fn summary(doc: &str) -> String {
+	let paragraph = doc.split("\n\n").next().unwrap_or_default();
+
+	links(&paragraph.replace('\n', " ")).replace('|', "\\|")
+}
+
+/// Rewrites the intra-doc links of the documentation
+///
+/// Links to messages and answers point to their section, other links are kept as plain text.
+fn links(doc: &str) -> String {
+	let mut output = String::with_capacity(doc.len());
+	let mut rest = doc;
+
+	while let Some(start) = rest.find('[') {
+		output.push_str(&rest[..start]);
+		let Some(end) = rest[start..].find(']').map(|end| start + end) else {
+			output.push_str(&rest[start..]);
+			return output;
+		};
+
+		let text = &rest[start + 1..end];
+		rest = &rest[end + 1..];
+
+		// Explicit destinations are Rust paths, meaningless outside of the documentation
+		if rest.starts_with('(')
+			&& let Some(close) = rest.find(')')
+		{
+			rest = &rest[close + 1..];
+		}
+
+		let path = text.trim_matches('`');
+		if path.starts_with("Message::") || path.starts_with("Answer::") {
+			// Cannot fail, writing to a string
+			let _ = write!(output, "[{text}](#{})", anchor(path));
+		} else {
+			output.push_str(text);
+		}
+	}
+	output.push_str(rest);
+
+	output
+}
@@ -0,0 +1,103 @@
+//! Wireshark dissector generated from the protocol schema
+//!
+//! The dissector is a Lua plugin, the schema is written as a Lua table in front of a fixed
+//! runtime that decodes the frames by walking it. Captures of the link, such as the ones written
+//! by `btmon -w`, are then decoded down to the members of every message and answer.
+
+use std::fmt::Write;
+
+use car_transport::schema::Protocol;
+use serde_json::Value;
+
+/// Decodes frames by walking the `PROTOCOL` table defined before it
+const RUNTIME: &str = include_str!("dissector.lua");
+
+/// Returns the source of the Wireshark Lua dissector for the protocol
+///
+/// # Errors
+/// In case the protocol cannot be serialized, which does not happen with the derived schema
+pub fn dissector(protocol: &Protocol) -> Result<String, serde_json::Error> {
+	let mut schema = serde_json::to_value(protocol)?;
+	strip_docs(&mut schema);
+
+	let mut source = format!(
+		"-- Wireshark dissector for the embedded car protocol {}\n\
+		 -- Generated from the protocol schema by `cargo run --bin protocol -- dissector`, do not edit\n\
+		 --\n\
+		 -- Copy it to the personal Lua plugins folder shown in Help > About Wireshark > Folders.\n\n\
+		 local PROTOCOL = ",
+		protocol.version
+	);
+	write_lua(&schema, 0, &mut source);
+	source.push_str("\n\n");
+	source.push_str(RUNTIME);
+
+	Ok(source)
+}
+
+/// Removes the documentation from the schema, the dissector does not show it
+fn strip_docs(value: &mut Value) {
+	match value {
+		Value::Object(object) => {
+			object.remove("doc");
+			object.values_mut().for_each(strip_docs);
+		}
+		Value::Array(array) => array.iter_mut().for_each(strip_docs),
+		_ => {}
+	}
+}
+
+/// Writes the JSON value as a Lua literal, arrays become sequences and objects tables
+fn write_lua(value: &Value, depth: usize, output: &mut String) {
+	let indent = "\t".repeat(depth + 1);
+	let closing = "\t".repeat(depth);
+
+	match value {
+		Value::Null => output.push_str("nil"),
+		Value::Bool(bool) => output.push_str(if *bool { "true" } else { "false" }),
+		Value::Number(number) => output.push_str(&number.to_string()),
+		Value::String(string) => write_lua_string(string, output),
+		Value::Array(array) if array.is_empty() => output.push_str("{}"),
+		Value::Array(array) => {
+			output.push_str("{\n");
+			for item in array {
+				output.push_str(&indent);
+				write_lua(item, depth + 1, output);
+				output.push_str(",\n");
+			}
+			output.push_str(&closing);
+			output.push('}');
+		}
+		Value::Object(object) => {
+			output.push_str("{\n");
+			for (key, item) in object {
+				output.push_str(&indent);
+				output.push('[');
+				write_lua_string(key, output);
+				output.push_str("] = ");
+				write_lua(item, depth + 1, output);
+				output.push_str(",\n");
+			}
+			output.push_str(&closing);
+			output.push('}');
+		}
+	}
+}
+
+/// Writes a quoted Lua string, escaping quotes, backslashes and control characters
+fn write_lua_string(string: &str, output: &mut String) {
+	output.push('"');
+	for char in string.chars() {
+		match char {
+			'"' => output.push_str("\\\""),
+			'\\' => output.push_str("\\\\"),
+			'\n' => output.push_str("\\n"),
+			char if char.is_ascii_control() => {
+				// Cannot fail, writing to a string
+				let _ = write!(output, "\\{:03}", u32::from(char));
+			}
+			char => output.push(char),
+		}
+	}
+	output.push('"');
+}
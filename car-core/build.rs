@@ -1,4 +1,20 @@
+use std::{env, fs, path::PathBuf};
+
 fn main() {
+	if env::var_os("CARGO_FEATURE_BOOTLOADER").is_some() {
+		assert!(
+			env::var_os("CARGO_FEATURE_MEMORY_X").is_none(),
+			"`bootloader` has its own memory layout, build it with `--no-default-features`"
+		);
+
+		// The partitions of the bootloader replace the memory layout of the chip
+		let out = PathBuf::from(env::var_os("OUT_DIR").expect("set by cargo"));
+		fs::copy("memory-bootloader.x", out.join("memory.x"))
+			.expect("memory-bootloader.x is readable");
+		println!("cargo:rustc-link-search={}", out.display());
+	}
+	println!("cargo:rerun-if-changed=memory-bootloader.x");
+
 	println!("cargo:rustc-link-arg-bins=--nmagic");
 	println!("cargo:rustc-link-arg-bins=-Tlink.x");
 	println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
@@ -0,0 +1,28 @@
+/* Layout of the STM32F103C8 flash for the `embassy-boot` bootloader, used with the `bootloader`
+ * feature.
+ *
+ * The bootloader is built from the `examples/boot/bootloader/stm32` example of embassy with
+ * the same layout and flashed once with probe-rs, see the README. The firmware then runs from
+ * `FLASH` and receives updates in `DFU`, which must be one page larger than `FLASH`.
+ */
+MEMORY
+{
+  BOOTLOADER       : ORIGIN = 0x08000000, LENGTH = 12K
+  BOOTLOADER_STATE : ORIGIN = 0x08003000, LENGTH = 1K
+  FLASH            : ORIGIN = 0x08003400, LENGTH = 25K
+  DFU              : ORIGIN = 0x08009800, LENGTH = 26K
+  RAM        (rwx) : ORIGIN = 0x20000000, LENGTH = 20K
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOTLOADER);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOTLOADER);
+
+/* The initial values of `.data` are the last bytes of the image, which the bootloader copies from
+ * `DFU` to `FLASH` page by page */
+ASSERT(__sidata + (__edata - __sdata) <= ORIGIN(FLASH) + LENGTH(FLASH),
+  "The firmware does not fit in the FLASH partition of the bootloader");
+ASSERT(LENGTH(DFU) >= LENGTH(FLASH) + 1K, "DFU must be one page larger than FLASH");
+ASSERT(ORIGIN(DFU) + LENGTH(DFU) <= 0x08000000 + 64K, "The partitions do not fit in the flash");
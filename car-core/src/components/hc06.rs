@@ -1,17 +1,24 @@
 //! `HC-06` or `HM-10` bluetooth module driver (don't know yet)
 
-use car_transport::{Answer, Message, Transport};
+use car_transport::{
+	Answer, CarLink, Encoding, Envelope, LinkError, Message, Sequence, TransportError,
+	auth::{Opened, Session},
+	telemetry::LinkStats,
+};
 use embassy_stm32::{
 	Peri,
 	interrupt::typelevel::Binding,
 	mode,
-	usart::{self, Config, InterruptHandler, Uart},
+	usart::{self, Config, InterruptHandler, Uart, UartRx, UartTx},
 };
 
 /// Represents a `HC-06` bluetooth module.
+///
+/// The controller sends binary frames while serial terminals type text lines, the encoding is
+/// detected on every message and answers are sent in the encoding of the last one.
 pub struct Hc06<'a> {
-	/// The underlying UART instance.
-	uart: Uart<'a, mode::Async>,
+	/// Frames or writes as lines the answers and messages exchanged over the UART.
+	link: CarLink<IdleReader<'a>, UartTx<'a, mode::Async>>,
 }
 
 impl<'a> Hc06<'a> {
@@ -37,41 +44,110 @@ impl<'a> Hc06<'a> {
 		config.baudrate = 9600;
 
 		let uart = Uart::new(peri, rx, tx, irq, tx_dma, rx_dma, config).unwrap();
+		let (tx, rx) = uart.split();
 
-		Self { uart }
+		Self {
+			link: CarLink::new(IdleReader(rx), tx),
+		}
 	}
 
-	/// Returns whether a server has successfully answered our ping
-	pub async fn ping(&mut self) -> Result<bool, Error> {
-		let mut buffer = [0_u8; Answer::BUFFER_SIZE];
-		let length = Answer::Pong.serialize(&mut buffer);
+	/// Sends a framed answer to the controller
+	pub async fn send(&mut self, answer: &Envelope<Answer>) -> Result<(), Error> {
+		self.link.send(answer).await?;
+		defmt::debug!("Sent {:?}", answer);
+
+		Ok(())
+	}
+
+	/// Seals the answer with the session key and sends it to the controller
+	pub async fn send_sealed(&mut self, answer: &Envelope<Answer>) -> Result<(), Error> {
+		self.link.send_sealed(answer).await?;
+		defmt::debug!("Sent sealed {:?}", answer);
+
+		Ok(())
+	}
 
-		self.uart.write(&buffer[..length]).await?;
+	/// Seals the following frames with the session key, [`None`] goes back to plain frames
+	pub const fn set_session(&mut self, session: Option<Session>) {
+		self.link.set_session(session);
+	}
 
-		defmt::debug!("Sent Pong");
-		let mut buffer = [0_u8; Message::BUFFER_SIZE];
-		self.uart.read(&mut buffer).await?;
-		defmt::trace!("Received {}", &buffer);
+	/// Returns whether a session was authenticated
+	pub const fn has_session(&self) -> bool {
+		self.link.has_session()
+	}
 
-		let message = Message::deserialize(&buffer).map_err(|_| Error::UnableToDeserialize)?;
-		defmt::debug!("Received {:?}", &message);
+	/// Waits for the next complete message from the controller
+	///
+	/// Corrupted frames are reported as errors, the next call resumes with the following frame.
+	/// Frames with a valid checksum but an invalid or replayed message are reported as
+	/// [`Error::Rejected`], as are invalid lines. Messages tell whether they were sealed with the
+	/// session key, lines never are.
+	/// Cancelling the call while waiting for bytes may lose the frame being received.
+	pub async fn receive(&mut self) -> Result<Opened<Envelope<Message>>, Error> {
+		match self.link.recv_opened().await {
+			Ok(message) => {
+				defmt::debug!("Received {:?}", &message);
+				Ok(message)
+			}
+			Err(LinkError::Transport(reason)) => {
+				match Envelope::<Message>::peek(self.link.last_frame()) {
+					Some((sequence, for_id)) => Err(Error::Rejected {
+						sequence,
+						for_id,
+						reason,
+					}),
+					None => Err(Error::Transport(reason)),
+				}
+			}
+			Err(error) => Err(error.into()),
+		}
+	}
 
-		Ok(message == Message::Ping)
+	/// Returns the encoding of the last message, frames or typed lines.
+	pub const fn encoding(&self) -> Encoding {
+		self.link.encoding()
 	}
 
-	pub async fn ping_text(&mut self) -> Result<bool, Error> {
-		self.uart.write(b"PING").await?;
+	/// Returns the counters of received and dropped frames
+	pub const fn stats(&self) -> LinkStats {
+		self.link.stats()
+	}
+
+	/// Waits for the next message and answers it if it is a ping
+	///
+	/// Returns whether the message was a ping from the controller
+	pub async fn ping(&mut self) -> Result<bool, Error> {
+		let message = self.receive().await?.into_inner();
+
+		if message.content != Message::Ping {
+			return Ok(false);
+		}
 
-		defmt::debug!("Sent Pong");
+		self.send(&message.reply(Answer::Pong)).await?;
 
-		let mut buffer = [0_u8; 7];
-		self.uart.read(&mut buffer).await?;
-		defmt::debug!("Received {}", &buffer);
+		Ok(true)
+	}
+}
 
-		// let message = Message::deserialize(&buffer).map_err(|_| Error::UnableToDeserialize)?;
-		// defmt::debug!("Received {:?}", &message);
+/// Reads the bytes received until the line goes idle, instead of waiting for a full buffer.
+struct IdleReader<'a>(UartRx<'a, mode::Async>);
 
-		Ok(&buffer == b"PONGFDP")
+impl embedded_io_async::ErrorType for IdleReader<'_> {
+	type Error = usart::Error;
+}
+
+impl embedded_io_async::Read for IdleReader<'_> {
+	async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+		loop {
+			let length = self.0.read_until_idle(buffer).await?;
+
+			// An idle line without any byte is not the end of the stream
+			if length != 0 {
+				defmt::trace!("Received {}", &buffer[..length]);
+				return Ok(length);
+			}
+		}
 	}
 }
 
@@ -81,11 +157,30 @@ pub enum Error {
 	/// The module did not respond with `OK` or the right answer for AT commands.
 	NotOkResponse,
 
-	/// Could not deserialize the answer
-	UnableToDeserialize,
+	/// Could not decode a frame from the controller
+	Transport(TransportError),
+
+	/// Decoded a frame from the controller that does not hold a valid message
+	Rejected {
+		/// The sequence number of the message
+		sequence: Sequence,
+		/// The id of the message
+		for_id: u8,
+		/// Why the message is invalid
+		reason: TransportError,
+	},
 
 	/// There was a problem with the UART communication itself.
 	USArt(usart::Error),
+
+	/// The UART reader reported the end of the stream.
+	Closed,
+}
+
+impl From<TransportError> for Error {
+	fn from(error: TransportError) -> Self {
+		Self::Transport(error)
+	}
 }
 
 impl From<usart::Error> for Error {
@@ -93,3 +188,13 @@ impl From<usart::Error> for Error {
 		Self::USArt(error)
 	}
 }
+
+impl From<LinkError<usart::Error>> for Error {
+	fn from(error: LinkError<usart::Error>) -> Self {
+		match error {
+			LinkError::Transport(error) => Self::Transport(error),
+			LinkError::Io(error) => Self::USArt(error),
+			LinkError::Closed => Self::Closed,
+		}
+	}
+}
@@ -1,5 +1,11 @@
 //! `HC-SR04` ultrasonic sensor driver
 
+use core::ops::RangeInclusive;
+
+use car_transport::{
+	Millimeters,
+	params::{ParamId, ParamSpec},
+};
 use embassy_stm32::{
 	Peri,
 	exti::ExtiInput,
@@ -7,6 +13,14 @@ use embassy_stm32::{
 };
 use embassy_time::{Duration, Instant, Timer};
 
+/// Tunables of the ultrasonic sensor, the distance is in millimeters.
+pub const PARAMS: &[ParamSpec] = &[ParamSpec::integer(
+	ParamId::COLLISION_DISTANCE,
+	200,
+	20,
+	4000,
+)];
+
 // TODO: think about a timer to limit ping calls to 1 per 60ms, to ensure echo from previous pings is not returned
 /// Represents a `HC-SR04` ultrasonic sensor.
 ///
@@ -32,15 +46,19 @@ impl<'a> HcSr04<'a> {
 		Self { trigger, echo }
 	}
 
-	/// Returns the distance in centimeters (`cm`).
-	pub async fn ping_distance(&mut self) -> u64 {
-		/// Constant to convert the duration of the echo to a distance in centimeters (`cm`).
-		/// (`343.21m/s` / `1000` (speed of light in cm/us)) / `2` (round trip)
-		const SOUND_US_TO_MM: f64 = (343.21 / 10_000.0) / 2.0;
+	/// Returns the distance to the obstacle, [`None`] when it is out of the sensor range.
+	pub async fn ping_distance(&mut self) -> Option<Millimeters> {
+		/// Range the sensor can measure reliably, in millimeters (`mm`).
+		const RANGE_MM: RangeInclusive<u64> = 20..=4000;
 
 		let ping_duration = self.ping().await;
 
-		(ping_duration as f64 * SOUND_US_TO_MM) as u64
+		// `343m/s` is `343mm/ms`, halved for the round trip
+		let distance = ping_duration * 343 / 2000;
+
+		RANGE_MM
+			.contains(&distance)
+			.then(|| Millimeters(u16::try_from(distance).unwrap_or(u16::MAX)))
 	}
 
 	/// Returns the duration of the echo in microseconds (`us`).
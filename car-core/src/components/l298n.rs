@@ -3,6 +3,10 @@
 //! You may need the `L298` and `L298N` datasheet to understand this module.
 //! Both are available in the [`hardware-specs`](https://github.com/mrnossiom/embedded-car/tree/main/hardware-specs) folder in the repository.
 
+use car_transport::{
+	Throttle,
+	params::{ParamId, ParamSpec},
+};
 use embassy_stm32::{
 	Peri,
 	gpio::{Level, Output, OutputType, Pin, Speed},
@@ -14,6 +18,15 @@ use embassy_stm32::{
 	},
 };
 
+/// Tunables of the motors, the deadband is in percent and the frequency in hertz.
+pub const PARAMS: &[ParamSpec] = &[
+	ParamSpec::integer(ParamId::MOTOR_DEADBAND, 10, 0, 50),
+	ParamSpec::integer(ParamId::PWM_FREQUENCY, 50, 20, 20_000),
+	ParamSpec::milli(ParamId::PID_KP, 1000, 0, 100_000),
+	ParamSpec::milli(ParamId::PID_KI, 0, 0, 100_000),
+	ParamSpec::milli(ParamId::PID_KD, 0, 0, 100_000),
+];
+
 /// Manages a new L298N a Dual H-Bridge Motor Controller module
 pub struct L298N<'a, TimerPin: GeneralInstance4Channel> {
 	/// The left motor controller
@@ -79,8 +92,11 @@ impl<'a, TimerPin: GeneralInstance4Channel> L298N<'a, TimerPin> {
 		self
 	}
 
-	/// Brakes the motor (Fast Motor Stop)
+	/// Brakes the motor (Fast Motor Stop), enables both channels fully so that the motors short
 	pub fn brake(&mut self) -> &mut Self {
+		self.pwm.ch1().set_duty_cycle_fully_on();
+		self.pwm.ch2().set_duty_cycle_fully_on();
+
 		self.left.brake();
 		self.right.brake();
 		self
@@ -130,6 +146,21 @@ impl<'a, TimerPin: GeneralInstance4Channel> L298N<'a, TimerPin> {
 
 		self
 	}
+
+	/// Drives both motors forward or backward with the given throttle
+	pub fn set_throttle(&mut self, throttle: Throttle) -> &mut Self {
+		let percent = throttle.percent();
+		if percent < 0 {
+			self.reverse();
+		} else {
+			self.forward();
+		}
+
+		let duty = u32::from(self.get_max_duty()) * u32::from(percent.unsigned_abs()) / 100;
+		let duty = u16::try_from(duty).unwrap_or(u16::MAX);
+
+		self.set_duty(Some(duty), Some(duty))
+	}
 }
 
 /// Manages a single motor
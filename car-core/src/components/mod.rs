@@ -1,6 +1,6 @@
 //! Contains multiple abstractions for the components used with the car.
 
-mod hc06;
+pub mod hc06;
 mod hcsr04;
 mod l298n;
 mod sg90;
@@ -9,3 +9,22 @@ pub use hc06::Hc06;
 pub use hcsr04::HcSr04;
 pub use l298n::L298N;
 pub use sg90::Sg90;
+
+use car_transport::params::{ParamError, ParamTable};
+
+/// Maximum number of tunables on the car.
+pub const MAX_PARAMS: usize = 16;
+
+/// Table of the tunables of every component.
+pub type Params = ParamTable<MAX_PARAMS>;
+
+/// Creates the table with the tunables of every component, set to their default value.
+pub fn params() -> Result<Params, ParamError> {
+	let mut params = Params::new();
+
+	params.register_all(hcsr04::PARAMS)?;
+	params.register_all(l298n::PARAMS)?;
+	params.register_all(sg90::PARAMS)?;
+
+	Ok(params)
+}
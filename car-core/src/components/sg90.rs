@@ -2,6 +2,10 @@
 
 use core::ops::{Add, Div, Mul, Sub};
 
+use car_transport::{
+	Steering,
+	params::{ParamId, ParamSpec},
+};
 use embassy_stm32::{
 	Peri,
 	gpio::OutputType,
@@ -13,6 +17,9 @@ use embassy_stm32::{
 	},
 };
 
+/// Tunables of the servo motor, the trim is in degrees.
+pub const PARAMS: &[ParamSpec] = &[ParamSpec::integer(ParamId::SERVO_TRIM, 0, -20, 20)];
+
 /// Represents a small `SG-90` servo motor.
 pub struct Sg90<'a, TimerPeripheral: GeneralInstance4Channel> {
 	/// The underlying `PWM` to control the servo motor
@@ -66,6 +73,14 @@ impl<'a, TimerPeripheral: GeneralInstance4Channel> Sg90<'a, TimerPeripheral> {
 		self
 	}
 
+	/// Turns the front wheels, `-100%` being fully left and `100%` fully right
+	pub fn set_steering(&mut self, steering: Steering) -> &mut Self {
+		let angle = map_range((-100, 100), (0, 180), i16::from(steering.percent()));
+
+		// The angle is within `0..=180`
+		self.set_angle(u8::try_from(angle).unwrap_or(90))
+	}
+
 	// // 50Hz => 20ms => 20_000μs
 	// // Servo motor Pulse Width is from 500 to 2400 μs
 
@@ -0,0 +1,84 @@
+//! Firmware updates written to the `DFU` partition of the `embassy-boot` bootloader.
+//!
+//! The partitions are laid out in `memory-bootloader.x`. On reset after
+//! [`UpdateFlash::mark_updated`], the bootloader swaps the `DFU` and `FLASH` partitions and boots
+//! the new firmware, which must mark itself as booted or the previous firmware is swapped back on
+//! the next reset.
+
+use core::cell::RefCell;
+
+use car_transport::dfu::{CHUNK_SIZE, HASH_SIZE, ImageHash, UpdateFlash};
+use embassy_boot_stm32::{
+	AlignedBuffer, BlockingFirmwareUpdater, FirmwareUpdaterConfig, FirmwareUpdaterError,
+};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_stm32::flash::{Blocking, Flash, WRITE_SIZE};
+use embassy_sync::blocking_mutex::{Mutex, raw::NoopRawMutex};
+use sha2::Sha256;
+
+/// Size of the `FLASH` partition in `memory-bootloader.x`, images must fit in it.
+const ACTIVE_SIZE: u32 = 25 * 1024;
+/// Value of erased flash, pads the last chunk to a whole number of words.
+const ERASED: u8 = 0xFF;
+
+/// The internal flash, shared by the partitions.
+pub type SharedFlash = Mutex<NoopRawMutex, RefCell<Flash<'static, Blocking>>>;
+/// A partition of the internal flash.
+type Partition<'a> = BlockingPartition<'a, NoopRawMutex, Flash<'static, Blocking>>;
+
+/// The `DFU` partition and the state of the bootloader.
+///
+/// The flash is blocking, erasing the partition stalls the car for about a second.
+pub struct BootFlash<'a> {
+	/// Writes the image and the bootloader state.
+	updater: BlockingFirmwareUpdater<'a, Partition<'a>, Partition<'a>>,
+}
+
+impl<'a> BootFlash<'a> {
+	/// Opens the partitions laid out in `memory-bootloader.x` and confirms that this firmware boots.
+	pub fn new(flash: &'a SharedFlash, magic: &'a mut AlignedBuffer<WRITE_SIZE>) -> Self {
+		let config = FirmwareUpdaterConfig::from_linkerfile_blocking(flash, flash);
+		let mut updater = BlockingFirmwareUpdater::new(config, &mut magic.0);
+
+		// Otherwise the bootloader swaps the previous firmware back on the next reset
+		if let Err(error) = updater.mark_booted() {
+			defmt::warn!("Could not mark the firmware as booted: {}", error);
+		}
+
+		Self { updater }
+	}
+}
+
+impl UpdateFlash for BootFlash<'_> {
+	type Error = FirmwareUpdaterError;
+
+	fn capacity(&self) -> u32 {
+		ACTIVE_SIZE
+	}
+
+	fn erase(&mut self) -> Result<(), Self::Error> {
+		self.updater.prepare_update().map(|_| ())
+	}
+
+	fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error> {
+		// Chunks are written at multiples of their size, only the last one may be shorter
+		let mut buffer = AlignedBuffer([ERASED; CHUNK_SIZE]);
+		buffer.0[..data.len()].copy_from_slice(data);
+		let length = data.len().next_multiple_of(WRITE_SIZE);
+
+		self.updater
+			.write_firmware(offset as usize, &buffer.0[..length])
+	}
+
+	fn hash(&mut self, size: u32) -> Result<ImageHash, Self::Error> {
+		let mut chunk = [0; CHUNK_SIZE];
+		let mut hash = ImageHash([0; HASH_SIZE]);
+		self.updater.hash::<Sha256>(size, &mut chunk, &mut hash.0)?;
+
+		Ok(hash)
+	}
+
+	fn mark_updated(&mut self) -> Result<(), Self::Error> {
+		self.updater.mark_updated()
+	}
+}
@@ -0,0 +1,21 @@
+//! Expiry of the control lease held by a controller.
+
+use car_transport::lease::ControlLease;
+use embassy_time::{Duration, Instant, Timer};
+
+/// Returns the time given to the lease, in wrapping milliseconds.
+pub fn now_ms() -> u32 {
+	// The clock wraps around, like sequence numbers
+	#[allow(clippy::cast_possible_truncation)]
+	let now = Instant::now().as_millis() as u32;
+
+	now
+}
+
+/// Waits until the lease expires, forever when nobody holds it.
+pub async fn expiry(lease: &ControlLease) {
+	match lease.remaining_ms(now_ms()) {
+		Some(remaining) => Timer::after(Duration::from_millis(remaining.into())).await,
+		None => core::future::pending().await,
+	}
+}
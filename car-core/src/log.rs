@@ -0,0 +1,64 @@
+//! Log records mirrored to the controller.
+//!
+//! [`report!`] logs with `defmt` like its macros, and queues the record for the
+//! [`LogForwarder`](car_transport::log::LogForwarder) of the main loop, which sends it to the
+//! controller when it asked for that level.
+
+use core::{
+	fmt::{self, Write},
+	sync::atomic::{AtomicU16, Ordering},
+};
+
+use car_transport::{LogLevel, LogText};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+
+/// Number of records sent in a row before the rate limit applies.
+pub const BURST: u8 = 4;
+/// Time to earn the right to send one more record, in milliseconds.
+pub const PERIOD_MS: u32 = 250;
+/// Number of records waiting for the main loop.
+const QUEUE_SIZE: usize = 4;
+
+/// Records waiting for the main loop.
+static QUEUE: Channel<CriticalSectionRawMutex, (LogLevel, LogText), QUEUE_SIZE> = Channel::new();
+/// Number of records lost because the queue was full.
+static DROPPED: AtomicU16 = AtomicU16::new(0);
+
+/// Logs a formatted record with `defmt` and mirrors it to the controller.
+///
+/// The first argument is the name of a [`LogLevel`] variant.
+macro_rules! report {
+	($level:ident, $($arguments:tt)+) => {
+		$crate::log::report(car_transport::LogLevel::$level, format_args!($($arguments)+))
+	};
+}
+pub(crate) use report;
+
+/// Logs the record with `defmt` and queues it, prefer the [`report!`] macro.
+pub fn report(level: LogLevel, arguments: fmt::Arguments<'_>) {
+	let mut text = LogText::default();
+	// Cannot fail, the text is truncated instead
+	let _ = text.write_fmt(arguments);
+
+	match level {
+		LogLevel::Trace => defmt::trace!("{=str}", text.as_str()),
+		LogLevel::Debug => defmt::debug!("{=str}", text.as_str()),
+		LogLevel::Info => defmt::info!("{=str}", text.as_str()),
+		LogLevel::Warn => defmt::warn!("{=str}", text.as_str()),
+		LogLevel::Error => defmt::error!("{=str}", text.as_str()),
+	}
+
+	if QUEUE.try_send((level, text)).is_err() {
+		// Cannot fail, the closure always returns a value
+		let _ = DROPPED.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |dropped| {
+			Some(dropped.saturating_add(1))
+		});
+	}
+}
+
+/// Waits for the next queued record, with the number of records lost before it.
+pub async fn next() -> (u16, LogLevel, LogText) {
+	let (level, text) = QUEUE.receive().await;
+
+	(DROPPED.swap(0, Ordering::Relaxed), level, text)
+}
@@ -5,25 +5,83 @@
 // The executor is single threaded
 #![allow(clippy::future_not_send)]
 
+#[cfg(feature = "bootloader")]
+use core::cell::RefCell;
 use core::sync::atomic::{AtomicBool, Ordering};
 
+#[cfg(feature = "bootloader")]
+use car_transport::dfu::Updater;
+use car_transport::{
+	Answer, Capabilities, Encoding, Envelope, ErrorCode, Fault, FirmwareVersion, Message,
+	PROTOCOL_VERSION, Param, Throttle, Transport,
+	arq::{Deduplicator, Incoming},
+	auth::{CarHandshake, Key},
+	clock,
+	lease::ControlLease,
+	log::LogForwarder,
+};
 use defmt::unwrap;
+#[cfg(feature = "bootloader")]
+use embassy_boot_stm32::AlignedBuffer;
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either4, select4};
+#[cfg(feature = "bootloader")]
+use embassy_stm32::flash::{Flash, WRITE_SIZE};
 use embassy_stm32::{
 	Config, bind_interrupts,
 	gpio::{Level, Output, Speed},
 	peripherals, usart,
 };
-use embassy_time::{Duration, Timer};
+#[cfg(feature = "bootloader")]
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
 use {defmt_rtt as _, panic_probe as _};
 
 mod components;
+#[cfg(feature = "bootloader")]
+mod dfu;
+mod lease;
+mod log;
+mod telemetry;
 
-use components::{Hc06, HcSr04, L298N, Sg90};
+use components::{Hc06, HcSr04, L298N, Sg90, hc06};
+use log::report;
+use telemetry::{State, Subscription};
 
 /// Indicate if the program is connected to a computer.
 pub static IS_CONNECTED_TO_CONTROLLER: AtomicBool = AtomicBool::new(false);
 
+/// Version of this firmware, reported during the handshake.
+const FIRMWARE_VERSION: FirmwareVersion = FirmwareVersion::parse(env!("CARGO_PKG_VERSION"));
+/// Key shared with the controllers, as 64 hex digits in `CAR_PSK` at build time.
+///
+/// Without a key, the car accepts plain messages from anyone in range.
+const PSK: Option<Key> = match option_env!("CAR_PSK") {
+	Some(hex) => match Key::from_hex(hex) {
+		Some(key) => Some(key),
+		None => panic!("`CAR_PSK` must be 64 hex digits"),
+	},
+	None => None,
+};
+/// Firmware updates, they need the bootloader to swap the firmware.
+#[cfg(feature = "bootloader")]
+const UPDATE: Capabilities = Capabilities::UPDATE;
+/// Firmware updates, they need the bootloader to swap the firmware.
+#[cfg(not(feature = "bootloader"))]
+const UPDATE: Capabilities = Capabilities::NONE;
+/// Components wired on this car, reported during the handshake.
+const CAPABILITIES: Capabilities = Capabilities(
+	Capabilities::MOTORS.0
+		| UPDATE.0
+		| if PSK.is_some() {
+			Capabilities::AUTHENTICATION.0
+		} else {
+			0
+		},
+);
+/// Number of answers kept to answer retransmitted messages.
+const MAX_REPLAYED_ANSWERS: usize = 4;
+
 #[embassy_executor::task]
 /// Tells if the program is running on the microcontroller.
 async fn alive_blinker(mut led: Output<'static>) {
@@ -59,18 +117,302 @@ async fn main(spawner: Spawner) {
 	// TODO: check connections for PA3 et PA2
 	let mut bluetooth = Hc06::from_pins(p.USART2, p.PA3, p.PA2, Interrupts, p.DMA1_CH7, p.DMA1_CH6);
 
+	let mut motors = L298N::from_pins(p.PA7, p.PA6, p.PA8, p.PA5, p.PA4, p.PA9, p.TIM1);
+
+	#[cfg(feature = "bootloader")]
+	let flash: dfu::SharedFlash = Mutex::new(RefCell::new(Flash::new_blocking(p.FLASH)));
+	#[cfg(feature = "bootloader")]
+	let mut magic = AlignedBuffer([0; WRITE_SIZE]);
+	#[cfg(feature = "bootloader")]
+	let mut updater = Updater::new(dfu::BootFlash::new(&flash, &mut magic));
+
+	let mut params = unwrap!(components::params());
+	let mut state = State::default();
+	let mut fault = None::<Fault>;
+	let mut lease = ControlLease::new(0);
+	let mut subscription = None::<Subscription>;
+	let mut forwarder = LogForwarder::new(log::BURST, log::PERIOD_MS);
+	let mut replies = Deduplicator::<Answer, MAX_REPLAYED_ANSWERS>::new();
+	let mut handshake = None::<CarHandshake>;
+	let mut handshakes = 0_u32;
+	// Encoding of the last message, lines and frames number their messages on their own
+	let mut encoding = Encoding::Binary;
+	// Whether the controller understands the time at which snapshots are sampled
+	let mut stamped = false;
+
 	loop {
-		unwrap!(bluetooth.ping().await);
+		let received = select4(
+			bluetooth.receive(),
+			telemetry::next(subscription.as_mut()),
+			lease::expiry(&lease),
+			log::next(),
+		)
+		.await;
+		// Taken before anything else to keep the clock synchronization accurate
+		let received_at = telemetry::now();
+
+		let (message, sealed) = match received {
+			Either4::First(Ok(opened)) => {
+				let sealed = opened.is_sealed();
+				(opened.into_inner(), sealed)
+			}
+			Either4::First(Err(hc06::Error::Rejected {
+				sequence,
+				for_id,
+				reason,
+			})) => {
+				report!(
+					Warn,
+					"Rejected message {} with id {}: {}",
+					sequence.0,
+					for_id,
+					reason
+				);
+
+				let nack = Answer::Nack {
+					for_id,
+					reason: reason.into(),
+				};
+				send(&mut bluetooth, &Envelope::new(sequence, nack), false).await;
+				continue;
+			}
+			Either4::First(Err(error)) => {
+				defmt::warn!("Could not receive message: {}", error);
+				continue;
+			}
+			Either4::Second(()) => {
+				if let Some(subscription) = &subscription {
+					let snapshot = state.snapshot(subscription.topics, bluetooth.stats(), stamped);
+					let envelope =
+						Envelope::new(subscription.sequence, Answer::Telemetry(snapshot));
+					let sealed = bluetooth.has_session();
+					send(&mut bluetooth, &envelope, sealed).await;
+				}
+				continue;
+			}
+			Either4::Third(()) => {
+				// The controller went silent, it may have crashed while the car was driving
+				if let Some(token) = lease.expire(lease::now_ms()) {
+					report!(Warn, "Control lease {} expired, stopping", token.0);
+					motors.set_throttle(Throttle::ZERO);
+					state.throttle = Throttle::ZERO;
+				}
+				continue;
+			}
+			Either4::Fourth((dropped, level, text)) => {
+				forwarder.drop_records(dropped);
+				if let Some(record) = forwarder.forward(level, text, lease::now_ms()) {
+					let sealed = bluetooth.has_session();
+					send(&mut bluetooth, &record, sealed).await;
+				}
+				continue;
+			}
+		};
+
+		// Typed lines start their sequence numbers over, the answers of the previous peer would
+		// take them for retransmissions
+		if bluetooth.encoding() != encoding {
+			encoding = bluetooth.encoding();
+			replies.reset();
+		}
+
+		// Emergency stops skip every other check to brake as soon as possible, plain ones
+		// included, and are answered in the encoding of the message
+		if message.content == Message::EmergencyStop {
+			motors.brake();
+			state.throttle = Throttle::ZERO;
+			fault = Some(Fault::EmergencyStop);
+			report!(Warn, "Emergency stop, speed is locked");
+
+			// Braking again on retransmissions is harmless
+			let _ = replies.observe(message.sequence);
+			let reply = message.reply(Answer::AckEmergencyStop);
+			replies.record(&reply);
+			send(&mut bluetooth, &reply, sealed).await;
+			continue;
+		}
+
+		// Once a key is set, only the handshake may travel in plain frames
+		if PSK.is_some() && !sealed && !message.content.is_accepted_plain() {
+			defmt::warn!("Refused plain message {}", message);
+
+			let nack = Answer::Nack {
+				for_id: message.content.id(),
+				reason: ErrorCode::Unauthenticated,
+			};
+			send(&mut bluetooth, &message.reply(nack), false).await;
+			continue;
+		}
+
+		// A new controller starts its sequence numbers over
+		if matches!(message.content, Message::Hello { .. }) {
+			replies.reset();
+		}
+
+		match replies.observe(message.sequence) {
+			Incoming::Fresh => {}
+			Incoming::Duplicate(Some(reply)) => {
+				defmt::debug!("Answering retransmitted message {} again", message);
+				send(&mut bluetooth, reply, sealed).await;
+				continue;
+			}
+			Incoming::Duplicate(None) => {
+				defmt::warn!("Dropped retransmitted message {}", message);
+				continue;
+			}
+		}
+
+		let now = lease::now_ms();
+		let answer = match message.content {
+			ref content if !CAPABILITIES.contains(content.required_capabilities()) => {
+				Err(ErrorCode::MissingComponent)
+			}
+			ref content
+				if content
+					.lease()
+					.is_some_and(|token| lease.authorize(token, now).is_err()) =>
+			{
+				Err(ErrorCode::NotLeaseHolder)
+			}
+			Message::Ping => Ok(Answer::Pong),
+			Message::GetSpeed => Ok(Answer::Speed(state.throttle)),
+			Message::SetSpeed { .. } if fault.is_some() => Err(ErrorCode::Faulted),
+			Message::SetSpeed { throttle, .. } => {
+				motors.set_throttle(throttle);
+				state.throttle = throttle;
+				Ok(Answer::AckSpeed)
+			}
+			Message::GetFault => Ok(Answer::Fault(fault)),
+			Message::ClearFault { .. } => {
+				if let Some(fault) = fault {
+					report!(Info, "Cleared fault: {}", fault);
+				}
+
+				Ok(Answer::AckClearFault {
+					cleared: fault.take(),
+				})
+			}
+			Message::Hello { version } => {
+				defmt::info!("Controller speaks protocol {}", version);
+				IS_CONNECTED_TO_CONTROLLER.store(true, Ordering::Relaxed);
+				stamped = version >= clock::SINCE;
+
+				Ok(Answer::Hello {
+					version: PROTOCOL_VERSION,
+					firmware: FIRMWARE_VERSION,
+					capabilities: CAPABILITIES,
+				})
+			}
+			Message::GetParam(id) => params
+				.get(id)
+				.map(|value| Answer::Param(Param { id, value }))
+				.map_err(ErrorCode::from),
+			Message::ListParams { index } => Ok(Answer::ParamEntry {
+				count: u8::try_from(params.len()).unwrap_or(u8::MAX),
+				param: params.at(usize::from(index)),
+			}),
+			Message::SetParam {
+				param: Param { id, value },
+				..
+			} => params
+				.set(id, value)
+				.map(|value| Answer::AckParam(Param { id, value }))
+				.map_err(ErrorCode::from),
+			Message::Subscribe { topics, period_ms } => {
+				let started = Subscription::new(topics, period_ms, message.sequence);
+				let period_ms = started.period_ms;
+				subscription = Some(started);
+
+				Ok(Answer::Subscribed { period_ms })
+			}
+			Message::Unsubscribe => {
+				subscription = None;
+				Ok(Answer::Unsubscribed)
+			}
+			Message::SetLogLevel { level } => {
+				forwarder.set_level(level, message.sequence);
+				Ok(Answer::AckLogLevel)
+			}
+			Message::TimeSync { origin } => Ok(Answer::TimeSync {
+				origin,
+				received: received_at,
+				transmitted: telemetry::now(),
+			}),
+			Message::AuthChallenge { nonce } => PSK.map_or(Err(ErrorCode::Unsupported), |key| {
+				// There is no random number generator, the nonce of the car is derived from the
+				// time and a counter so that it never repeats
+				handshakes = handshakes.wrapping_add(1);
+				let mut entropy = [0_u8; 12];
+				entropy[..8].copy_from_slice(&Instant::now().as_ticks().to_le_bytes());
+				entropy[8..].copy_from_slice(&handshakes.to_le_bytes());
+
+				let started = CarHandshake::new(key, nonce, &entropy);
+				let challenge = started.challenge();
+				handshake = Some(started);
+				Ok(challenge)
+			}),
+			Message::Authenticate { proof } => handshake
+				.take()
+				.ok_or(ErrorCode::Unauthenticated)
+				.and_then(|handshake| {
+					handshake
+						.verify(proof)
+						.map_err(|_| ErrorCode::Unauthenticated)
+				})
+				.map(|session| {
+					report!(Info, "Authenticated a controller");
+					bluetooth.set_session(Some(session));
+					Answer::Authenticated
+				}),
+			Message::AcquireControl { renew, duration_ms } => lease
+				.acquire(renew, duration_ms, now)
+				.map(|(token, duration_ms)| {
+					report!(Info, "Granted lease {} for {}ms", token.0, duration_ms);
+					Answer::ControlGranted {
+						lease: token,
+						duration_ms,
+					}
+				}),
+			Message::ReleaseControl { lease: token } => lease.release(token, now).map(|()| {
+				motors.set_throttle(Throttle::ZERO);
+				state.throttle = Throttle::ZERO;
+				Answer::AckReleaseControl
+			}),
+			#[cfg(feature = "bootloader")]
+			ref content @ (Message::BeginUpdate { .. }
+			| Message::WriteUpdate { .. }
+			| Message::VerifyUpdate { .. }
+			| Message::CommitUpdate { .. }) => updater
+				.handle(content)
+				.unwrap_or(Err(ErrorCode::Unsupported)),
+			_ => Err(ErrorCode::Unsupported),
+		};
+
+		let answer = answer.unwrap_or_else(|reason| {
+			report!(Warn, "Refused message {}: {}", message.content.id(), reason);
+
+			Answer::Nack {
+				for_id: message.content.id(),
+				reason,
+			}
+		});
+
+		let reply = message.reply(answer);
+		replies.record(&reply);
+		send(&mut bluetooth, &reply, sealed).await;
+
+		if reply.content == Answer::AckCommitUpdate {
+			defmt::info!("Rebooting on the new firmware");
+			// Leaves the time for the answer to leave the UART
+			Timer::after(Duration::from_millis(100)).await;
+			cortex_m::peripheral::SCB::sys_reset();
+		}
 	}
 
 	// let _ultrasonic = HcSr04::from_pins(p.PB4, p.PB5, p.EXTI5);
 	// TODO: pins already in use
 	// let mut servo = Sg90::from_pin(p.PB3, p.TIM2);
 
-	// let _motor_driver = L298N::from_pins(p.PA7, p.PA6, p.PA8, p.PA5, p.PA4, p.PA9, p.TIM1);
-
-	// unwrap!(bluetooth.ping_text().await);
-
 	// loop {
 	// 	servo.set_angle(0);
 	// 	Timer::after(Duration::from_secs(1)).await;
@@ -80,3 +422,18 @@ async fn main(spawner: Spawner) {
 	// 	Timer::after(Duration::from_secs(1)).await;
 	// }
 }
+
+/// Sends the answer, sealed with the session key when the message it answers was
+///
+/// Failures are only logged, panicking would leave the motors driving at the last throttle.
+async fn send(bluetooth: &mut Hc06<'_>, answer: &Envelope<Answer>, sealed: bool) {
+	let sent = if sealed {
+		bluetooth.send_sealed(answer).await
+	} else {
+		bluetooth.send(answer).await
+	};
+
+	if let Err(error) = sent {
+		defmt::warn!("Could not send {}: {}", answer, error);
+	}
+}
@@ -0,0 +1,78 @@
+//! Periodic telemetry snapshots sent to the controller.
+
+use car_transport::{
+	Millimeters, Millivolts, Sequence, Steering, Telemetry, Throttle, Timestamp, Topics, Trailing,
+	telemetry::LinkStats,
+};
+use embassy_time::{Duration, Instant, Ticker};
+
+/// Shortest period between two snapshots, so that they do not saturate the link.
+pub const MIN_PERIOD_MS: u16 = 50;
+
+/// Last known state of the car, reported in the snapshots.
+#[derive(Default)]
+pub struct State {
+	/// The last requested speed.
+	pub throttle: Throttle,
+	/// The last requested direction.
+	pub steering: Steering,
+	/// The last measured distance.
+	pub distance: Option<Millimeters>,
+	/// The last measured battery level.
+	pub battery: Option<Millivolts>,
+}
+
+impl State {
+	/// Returns a snapshot of the state with the given topics, stamped with the time it was taken
+	/// when the controller understands it.
+	pub fn snapshot(&self, topics: Topics, link: LinkStats, stamped: bool) -> Telemetry {
+		Telemetry {
+			speed: Some(self.throttle),
+			direction: Some(self.steering),
+			distance: Some(self.distance),
+			battery: self.battery,
+			link: Some(link),
+			sampled_at: Trailing(stamped.then(now)),
+		}
+		.filter(topics)
+	}
+}
+
+/// An active subscription of the controller.
+pub struct Subscription {
+	/// The values to send.
+	pub topics: Topics,
+	/// Sequence number of the subscribe message, reused for every snapshot.
+	pub sequence: Sequence,
+	/// Time between two snapshots, in milliseconds.
+	pub period_ms: u16,
+	/// Fires once per period.
+	ticker: Ticker,
+}
+
+impl Subscription {
+	/// Starts a subscription, the period is raised to [`MIN_PERIOD_MS`] if needed.
+	pub fn new(topics: Topics, period_ms: u16, sequence: Sequence) -> Self {
+		let period_ms = period_ms.max(MIN_PERIOD_MS);
+
+		Self {
+			topics,
+			sequence,
+			period_ms,
+			ticker: Ticker::every(Duration::from_millis(period_ms.into())),
+		}
+	}
+}
+
+/// Returns the time of the car clock, as given to the controller.
+pub fn now() -> Timestamp {
+	Timestamp(Instant::now().as_micros())
+}
+
+/// Waits for the next snapshot to send, forever when there is no subscription.
+pub async fn next(subscription: Option<&mut Subscription>) {
+	match subscription {
+		Some(subscription) => subscription.ticker.next().await,
+		None => core::future::pending().await,
+	}
+}
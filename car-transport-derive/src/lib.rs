@@ -0,0 +1,445 @@
+//! Derive macros for the `Transport` and `Field` traits of `car-transport`
+
+use std::collections::HashMap;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+	Attribute, Data, DeriveInput, Expr, ExprLit, Fields, Ident, Lit, LitInt, LitStr, Meta,
+	parse_macro_input,
+};
+
+/// Derives `Transport` and `Schema` for an enum
+///
+/// Every variant needs a unique `#[transport(id = N)]` attribute, its fields must implement `Field`.
+/// Fields can give the unit of their value with `#[transport(unit = "ms")]`.
+#[proc_macro_derive(Transport, attributes(transport))]
+pub fn derive_transport(input: TokenStream) -> TokenStream {
+	let input = parse_macro_input!(input as DeriveInput);
+
+	expand_transport(&input)
+		.unwrap_or_else(syn::Error::into_compile_error)
+		.into()
+}
+
+/// Derives `Field` for a struct or an enum
+///
+/// Enum variants need a unique `#[transport(id = N)]` attribute used as tag, every field must implement `Field`.
+/// Structs and fields can give the unit of their value with `#[transport(unit = "mm")]`.
+#[proc_macro_derive(Field, attributes(transport))]
+pub fn derive_field(input: TokenStream) -> TokenStream {
+	let input = parse_macro_input!(input as DeriveInput);
+
+	expand_field(&input)
+		.unwrap_or_else(syn::Error::into_compile_error)
+		.into()
+}
+
+/// A variant with its parsed id
+struct Variant<'a> {
+	/// The name of the variant
+	ident: &'a Ident,
+	/// The unique id of the variant
+	id: u8,
+	/// The documentation of the variant
+	doc: String,
+	/// The fields of the variant
+	fields: &'a Fields,
+}
+
+impl Variant<'_> {
+	/// Returns the pattern that binds every field of the variant
+	fn pattern(&self) -> TokenStream2 {
+		let ident = self.ident;
+		let bindings = bindings(self.fields);
+
+		match self.fields {
+			Fields::Unit => quote!(Self::#ident),
+			Fields::Unnamed(_) => quote!(Self::#ident(#(#bindings),*)),
+			Fields::Named(_) => quote!(Self::#ident { #(#bindings),* }),
+		}
+	}
+
+	/// Returns the pattern that matches the variant without binding its fields
+	fn pattern_ignoring_fields(&self) -> TokenStream2 {
+		let ident = self.ident;
+
+		match self.fields {
+			Fields::Unit => quote!(Self::#ident),
+			Fields::Unnamed(_) => quote!(Self::#ident(..)),
+			Fields::Named(_) => quote!(Self::#ident { .. }),
+		}
+	}
+
+	/// Returns the expression that decodes the variant fields from `reader`
+	fn construct(&self) -> TokenStream2 {
+		let ident = self.ident;
+		construct(&quote!(Self::#ident), self.fields)
+	}
+
+	/// Returns the statements that encode the bound fields into `writer`
+	fn encode(&self) -> TokenStream2 {
+		let bindings = bindings(self.fields);
+
+		quote!(#(::car_transport::Field::encode(#bindings, writer);)*)
+	}
+
+	/// Returns the expression that describes the variant in the schema
+	fn schema(&self) -> syn::Result<TokenStream2> {
+		let name = self.ident.to_string();
+		let id = self.id;
+		let doc = &self.doc;
+		let members = members(self.fields)?;
+
+		Ok(quote! {
+			::car_transport::schema::Variant {
+				name: #name,
+				id: #id,
+				doc: #doc,
+				members: #members,
+			}
+		})
+	}
+}
+
+/// Parses the variants of an enum and checks their ids
+fn variants(input: &DeriveInput) -> syn::Result<Vec<Variant<'_>>> {
+	let Data::Enum(data) = &input.data else {
+		return Err(syn::Error::new_spanned(
+			&input.ident,
+			"`Transport` can only be derived for enums",
+		));
+	};
+
+	let mut seen = HashMap::<u8, &Ident>::new();
+	let mut variants = Vec::with_capacity(data.variants.len());
+
+	for variant in &data.variants {
+		let id = parse_attributes(&variant.attrs)?.id.ok_or_else(|| {
+			syn::Error::new_spanned(
+				&variant.ident,
+				"missing `#[transport(id = N)]` attribute on variant",
+			)
+		})?;
+
+		if let Some(previous) = seen.insert(id.value, &variant.ident) {
+			return Err(syn::Error::new(
+				id.span,
+				format!(
+					"duplicate transport id `{}`, already used by `{previous}`",
+					id.value
+				),
+			));
+		}
+
+		variants.push(Variant {
+			ident: &variant.ident,
+			id: id.value,
+			doc: doc(&variant.attrs),
+			fields: &variant.fields,
+		});
+	}
+
+	Ok(variants)
+}
+
+/// A parsed `#[transport(id = N)]` attribute
+struct Id {
+	/// The id value
+	value: u8,
+	/// Where the id is written
+	span: Span,
+}
+
+/// The parsed `#[transport(...)]` attributes of an item
+#[derive(Default)]
+struct Attributes {
+	/// The `id = N` attribute
+	id: Option<Id>,
+	/// The `unit = "..."` attribute
+	unit: Option<LitStr>,
+}
+
+/// Parses the `#[transport(id = N, unit = "...")]` attributes
+fn parse_attributes(attrs: &[Attribute]) -> syn::Result<Attributes> {
+	let mut attributes = Attributes::default();
+
+	for attr in attrs
+		.iter()
+		.filter(|attr| attr.path().is_ident("transport"))
+	{
+		attr.parse_nested_meta(|meta| {
+			if meta.path.is_ident("id") {
+				let literal: LitInt = meta.value()?.parse()?;
+				attributes.id = Some(Id {
+					value: literal.base10_parse()?,
+					span: literal.span(),
+				});
+				Ok(())
+			} else if meta.path.is_ident("unit") {
+				attributes.unit = Some(meta.value()?.parse()?);
+				Ok(())
+			} else {
+				Err(meta.error("unsupported transport attribute"))
+			}
+		})?;
+	}
+
+	Ok(attributes)
+}
+
+/// Returns the documentation comments of an item, one line per comment line
+fn doc(attrs: &[Attribute]) -> String {
+	let lines = attrs.iter().filter_map(|attr| match &attr.meta {
+		Meta::NameValue(meta) if meta.path.is_ident("doc") => match &meta.value {
+			Expr::Lit(ExprLit {
+				lit: Lit::Str(line),
+				..
+			}) => Some(line.value()),
+			_ => None,
+		},
+		_ => None,
+	});
+
+	lines
+		.map(|line| line.strip_prefix(' ').map(str::to_owned).unwrap_or(line))
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+/// Returns the expression of an optional unit in the schema
+fn unit(unit: Option<&LitStr>) -> TokenStream2 {
+	unit.map_or_else(|| quote!(None), |unit| quote!(Some(#unit)))
+}
+
+/// Returns the expression that describes the fields in the schema
+fn members(fields: &Fields) -> syn::Result<TokenStream2> {
+	let members = fields
+		.iter()
+		.enumerate()
+		.map(|(index, field)| {
+			let name = field
+				.ident
+				.as_ref()
+				.map_or_else(|| index.to_string(), ToString::to_string);
+			let doc = doc(&field.attrs);
+			let unit = unit(parse_attributes(&field.attrs)?.unit.as_ref());
+			let ty = &field.ty;
+
+			Ok(quote! {
+				::car_transport::schema::Member {
+					name: #name,
+					doc: #doc,
+					unit: #unit,
+					shape: &<#ty as ::car_transport::Field>::SHAPE,
+				}
+			})
+		})
+		.collect::<syn::Result<Vec<_>>>()?;
+
+	Ok(quote!(&[#(#members),*]))
+}
+
+/// Returns the names the fields are bound to
+fn bindings(fields: &Fields) -> Vec<Ident> {
+	fields
+		.iter()
+		.enumerate()
+		.map(|(index, field)| {
+			field
+				.ident
+				.clone()
+				.unwrap_or_else(|| format_ident!("field_{index}"))
+		})
+		.collect()
+}
+
+/// Returns the expression that decodes every field from `reader` into `path`
+fn construct(path: &TokenStream2, fields: &Fields) -> TokenStream2 {
+	let decode = quote!(::car_transport::Field::decode(reader)?);
+
+	match fields {
+		Fields::Unit => quote!(#path),
+		Fields::Unnamed(fields) => {
+			let decodes = fields.unnamed.iter().map(|_| &decode);
+			quote!(#path(#(#decodes),*))
+		}
+		Fields::Named(fields) => {
+			let names = fields.named.iter().map(|field| &field.ident);
+			quote!(#path { #(#names: #decode),* })
+		}
+	}
+}
+
+/// Returns the expression that sums the maximum size of every field
+fn fields_size(fields: &Fields) -> TokenStream2 {
+	let types = fields.iter().map(|field| &field.ty);
+
+	quote!(0 #(+ <#types as ::car_transport::Field>::MAX_SIZE)*)
+}
+
+/// Generates the `Transport` implementation
+fn expand_transport(input: &DeriveInput) -> syn::Result<TokenStream2> {
+	let variants = variants(input)?;
+
+	let name = &input.ident;
+	let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+	let sizes = variants.iter().map(|variant| fields_size(variant.fields));
+	let id_patterns = variants.iter().map(Variant::pattern_ignoring_fields);
+	let patterns = variants.iter().map(Variant::pattern);
+	let ids = variants
+		.iter()
+		.map(|variant| variant.id)
+		.collect::<Vec<_>>();
+	let encodes = variants.iter().map(Variant::encode);
+	let constructs = variants.iter().map(Variant::construct);
+	let schemas = variants
+		.iter()
+		.map(Variant::schema)
+		.collect::<syn::Result<Vec<_>>>()?;
+
+	Ok(quote! {
+		impl #impl_generics ::car_transport::schema::Schema for #name #ty_generics #where_clause {
+			const VARIANTS: &'static [::car_transport::schema::Variant] = &[#(#schemas),*];
+		}
+
+		impl #impl_generics ::car_transport::Transport for #name #ty_generics #where_clause {
+			const MAX_PAYLOAD_SIZE: usize = ::car_transport::field::max_size(&[#(#sizes),*]);
+
+			fn id(&self) -> u8 {
+				match self {
+					#(#id_patterns => #ids,)*
+				}
+			}
+
+			fn encode(&self, buffer: &mut [u8]) -> usize {
+				let writer = &mut ::car_transport::field::Writer::new(buffer);
+
+				match self {
+					#(#patterns => { #encodes })*
+				}
+
+				writer.position()
+			}
+
+			fn deserialize(buffer: &[u8]) -> Result<Self, ::car_transport::TransportError> {
+				let reader = &mut ::car_transport::field::Reader::new(buffer);
+
+				let value = match reader.read_u8()? {
+					#(#ids => #constructs,)*
+					_ => return Err(::car_transport::TransportError::InvalidId),
+				};
+
+				reader.finish()?;
+
+				Ok(value)
+			}
+		}
+	})
+}
+
+/// Generates the `Field` implementation
+fn expand_field(input: &DeriveInput) -> syn::Result<TokenStream2> {
+	let name = &input.ident;
+	let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+	let type_name = name.to_string();
+	let doc = doc(&input.attrs);
+
+	let (max_size, encode, decode, shape) = match &input.data {
+		Data::Struct(data) => {
+			let unit = unit(parse_attributes(&input.attrs)?.unit.as_ref());
+			let members = members(&data.fields)?;
+
+			let accessors = data.fields.iter().enumerate().map(|(index, field)| {
+				field.ident.as_ref().map_or_else(
+					|| {
+						let index = syn::Index::from(index);
+						quote!(&self.#index)
+					},
+					|ident| quote!(&self.#ident),
+				)
+			});
+
+			(
+				fields_size(&data.fields),
+				quote!(#(::car_transport::Field::encode(#accessors, writer);)*),
+				construct(&quote!(Self), &data.fields),
+				quote! {
+					::car_transport::schema::Shape::Struct {
+						name: #type_name,
+						doc: #doc,
+						unit: #unit,
+						members: #members,
+					}
+				},
+			)
+		}
+		Data::Enum(_) => {
+			let variants = variants(input)?;
+
+			let sizes = variants.iter().map(|variant| fields_size(variant.fields));
+			let patterns = variants.iter().map(Variant::pattern);
+			let ids = variants
+				.iter()
+				.map(|variant| variant.id)
+				.collect::<Vec<_>>();
+			let encodes = variants.iter().map(Variant::encode);
+			let constructs = variants.iter().map(Variant::construct);
+			let schemas = variants
+				.iter()
+				.map(Variant::schema)
+				.collect::<syn::Result<Vec<_>>>()?;
+
+			(
+				quote!(1 + ::car_transport::field::max_size(&[#(#sizes),*])),
+				quote! {
+					match self {
+						#(#patterns => {
+							writer.write_u8(#ids);
+							#encodes
+						})*
+					}
+				},
+				quote! {
+					match reader.read_u8()? {
+						#(#ids => #constructs,)*
+						_ => return Err(::car_transport::TransportError::InvalidPayload),
+					}
+				},
+				quote! {
+					::car_transport::schema::Shape::Enum {
+						name: #type_name,
+						doc: #doc,
+						variants: &[#(#schemas),*],
+					}
+				},
+			)
+		}
+		Data::Union(_) => {
+			return Err(syn::Error::new_spanned(
+				name,
+				"`Field` cannot be derived for unions",
+			));
+		}
+	};
+
+	Ok(quote! {
+		impl #impl_generics ::car_transport::Field for #name #ty_generics #where_clause {
+			const MAX_SIZE: usize = #max_size;
+			const SHAPE: ::car_transport::schema::Shape = #shape;
+
+			fn encode(&self, writer: &mut ::car_transport::field::Writer<'_>) {
+				#encode
+			}
+
+			fn decode(
+				reader: &mut ::car_transport::field::Reader<'_>,
+			) -> Result<Self, ::car_transport::TransportError> {
+				Ok(#decode)
+			}
+		}
+	})
+}
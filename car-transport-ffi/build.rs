@@ -0,0 +1,16 @@
+//! Writes the C header of the bindings to `include/car_transport.h`
+
+use std::{env, path::PathBuf};
+
+fn main() {
+	let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("set by cargo"));
+	let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+		.expect("configuration is valid");
+
+	cbindgen::generate_with_config(&crate_dir, config)
+		.expect("bindings are generated")
+		.write_to_file(crate_dir.join("include/car_transport.h"));
+
+	println!("cargo::rerun-if-changed=src");
+	println!("cargo::rerun-if-changed=cbindgen.toml");
+}
@@ -0,0 +1,1058 @@
+/* C bindings of the car transport protocol, generated by the build of `car-transport-ffi`, do not edit. */
+
+#ifndef CAR_TRANSPORT_H
+#define CAR_TRANSPORT_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Longest frame, delimiter included
+ */
+#define CAR_MAX_FRAME_SIZE 68
+
+/**
+ * Byte ending every frame
+ */
+#define CAR_FRAME_DELIMITER 0
+
+/**
+ * Size of the authentication nonces
+ */
+#define CAR_NONCE_SIZE 8
+
+/**
+ * Size of the authentication tags
+ */
+#define CAR_TAG_SIZE 8
+
+/**
+ * Size of the firmware image hash
+ */
+#define CAR_HASH_SIZE 32
+
+/**
+ * Largest firmware chunk
+ */
+#define CAR_CHUNK_SIZE 32
+
+/**
+ * Longest log text, in bytes
+ */
+#define CAR_LOG_TEXT_SIZE 40
+
+/**
+ * Size of a log text buffer, null byte included
+ */
+#define CAR_LOG_TEXT_BUFFER_SIZE 41
+
+/**
+ * Telemetry speed bit
+ */
+#define CAR_TOPIC_SPEED (1 << 0)
+
+/**
+ * Telemetry direction bit
+ */
+#define CAR_TOPIC_DIRECTION (1 << 1)
+
+/**
+ * Telemetry distance bit
+ */
+#define CAR_TOPIC_DISTANCE (1 << 2)
+
+/**
+ * Telemetry battery bit
+ */
+#define CAR_TOPIC_BATTERY (1 << 3)
+
+/**
+ * Telemetry link counters bit
+ */
+#define CAR_TOPIC_LINK (1 << 4)
+
+/**
+ * Kind of a switch tunable
+ */
+#define CAR_PARAM_BOOL 0
+
+/**
+ * Kind of a whole number tunable
+ */
+#define CAR_PARAM_INTEGER 1
+
+/**
+ * Kind of a fixed point tunable
+ */
+#define CAR_PARAM_MILLI 2
+
+/**
+ * Number of `CarMessage` variants, valid tags are below it
+ */
+#define CAR_MESSAGE_TAGS 26
+
+/**
+ * Number of `CarAnswer` variants, valid tags are below it
+ */
+#define CAR_ANSWER_TAGS 29
+
+/**
+ * Outcome of the encoding and decoding functions
+ */
+typedef enum CarStatus {
+  /**
+   * Success
+   */
+  CarStatus_Ok,
+  /**
+   * There is no such id
+   */
+  CarStatus_InvalidId,
+  /**
+   * The payload is invalid for the given id
+   */
+  CarStatus_InvalidPayload,
+  /**
+   * The frame ends before the payload is complete
+   */
+  CarStatus_Truncated,
+  /**
+   * The frame contains bytes after the payload
+   */
+  CarStatus_TrailingBytes,
+  /**
+   * A value is outside of its valid range
+   */
+  CarStatus_OutOfRange,
+  /**
+   * The frame checksum does not match its content
+   */
+  CarStatus_InvalidChecksum,
+  /**
+   * The frame is malformed
+   */
+  CarStatus_InvalidFraming,
+  /**
+   * The frame is longer than the decoder buffer
+   */
+  CarStatus_FrameTooLong,
+  /**
+   * The output buffer is too small to hold the frame
+   */
+  CarStatus_BufferTooSmall,
+  /**
+   * The frame is not sealed with the session key
+   */
+  CarStatus_InvalidTag,
+  /**
+   * The frame counter was already used
+   */
+  CarStatus_Replayed,
+  /**
+   * The bytes do not end with a frame delimiter
+   */
+  CarStatus_Incomplete,
+  /**
+   * A pointer argument is null
+   */
+  CarStatus_NullPointer,
+} CarStatus;
+
+/**
+ * Revision of the protocol
+ */
+typedef struct CarVersion {
+  /**
+   * Incompatible revisions
+   */
+  uint8_t major;
+  /**
+   * Compatible additions
+   */
+  uint8_t minor;
+} CarVersion;
+
+/**
+ * Value of a tunable
+ */
+typedef struct CarParamValue {
+  /**
+   * Type of the value, see `CAR_PARAM_*`
+   */
+  uint8_t kind;
+  /**
+   * The value, 0 or 1 for a switch and thousandths for `CAR_PARAM_MILLI`
+   */
+  int32_t value;
+} CarParamValue;
+
+/**
+ * A tunable with its value
+ */
+typedef struct CarParam {
+  /**
+   * Id of the tunable
+   */
+  uint16_t id;
+  /**
+   * Its value
+   */
+  struct CarParamValue value;
+} CarParam;
+
+/**
+ * A chunk of firmware image
+ */
+typedef struct CarChunk {
+  /**
+   * Number of bytes used in `bytes`
+   */
+  uint8_t length;
+  /**
+   * Bytes of the chunk
+   */
+  uint8_t bytes[CAR_CHUNK_SIZE];
+} CarChunk;
+
+/**
+ * A message sent by the controller to the car, see `Message` in the protocol reference
+ *
+ * Commands carry the token of the lease granted by `CarMessage_AcquireControl`.
+ */
+enum CarMessage_Tag
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  /**
+   * Check that the car is reachable
+   */
+  CarMessage_Ping,
+  /**
+   * Open the session with the protocol revision of the controller
+   */
+  CarMessage_Hello,
+  /**
+   * Ask for the current speed
+   */
+  CarMessage_GetSpeed,
+  /**
+   * Ask for the current direction
+   */
+  CarMessage_GetDirection,
+  /**
+   * Ask for the battery voltage
+   */
+  CarMessage_GetBatteryLevel,
+  /**
+   * Ask for the distance to the closest obstacle
+   */
+  CarMessage_GetUltrasonicDistance,
+  /**
+   * Ask for the value of a tunable
+   */
+  CarMessage_GetParam,
+  /**
+   * Ask for the tunable at a position, to list them all
+   */
+  CarMessage_ListParams,
+  /**
+   * Ask for periodic telemetry snapshots
+   */
+  CarMessage_Subscribe,
+  /**
+   * Stop the telemetry snapshots
+   */
+  CarMessage_Unsubscribe,
+  /**
+   * Ask for the latched fault
+   */
+  CarMessage_GetFault,
+  /**
+   * Start the authentication with a random nonce
+   */
+  CarMessage_AuthChallenge,
+  /**
+   * Prove the knowledge of the pre-shared key
+   */
+  CarMessage_Authenticate,
+  /**
+   * Forward the log records from a level, or stop them
+   */
+  CarMessage_SetLogLevel,
+  /**
+   * Sample the car clock
+   */
+  CarMessage_TimeSync,
+  /**
+   * Set the current speed
+   */
+  CarMessage_SetSpeed,
+  /**
+   * Set the current direction
+   */
+  CarMessage_SetDirection,
+  /**
+   * Change a tunable
+   */
+  CarMessage_SetParam,
+  /**
+   * Stop the car and latch a fault
+   */
+  CarMessage_EmergencyStop,
+  /**
+   * Clear the latched fault
+   */
+  CarMessage_ClearFault,
+  /**
+   * Take or renew the lease on the car
+   */
+  CarMessage_AcquireControl,
+  /**
+   * Give the lease back
+   */
+  CarMessage_ReleaseControl,
+  /**
+   * Start a firmware update
+   */
+  CarMessage_BeginUpdate,
+  /**
+   * Write a chunk of the firmware image
+   */
+  CarMessage_WriteUpdate,
+  /**
+   * Check the hash of the written image
+   */
+  CarMessage_VerifyUpdate,
+  /**
+   * Boot the verified image
+   */
+  CarMessage_CommitUpdate,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum CarMessage_Tag CarMessage_Tag;
+#else
+typedef uint8_t CarMessage_Tag;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+typedef struct CarMessage_Hello_Body {
+  /**
+   * The revision spoken by the controller
+   */
+  struct CarVersion version;
+} CarMessage_Hello_Body;
+
+typedef struct CarMessage_GetParam_Body {
+  /**
+   * Id of the tunable
+   */
+  uint16_t id;
+} CarMessage_GetParam_Body;
+
+typedef struct CarMessage_ListParams_Body {
+  /**
+   * Position of the tunable
+   */
+  uint8_t index;
+} CarMessage_ListParams_Body;
+
+typedef struct CarMessage_Subscribe_Body {
+  /**
+   * Bits of the values to send, see `CAR_TOPIC_*`
+   */
+  uint8_t topics;
+  /**
+   * Time between two snapshots, in milliseconds
+   */
+  uint16_t period_ms;
+} CarMessage_Subscribe_Body;
+
+typedef struct CarMessage_AuthChallenge_Body {
+  /**
+   * Random bytes of the controller
+   */
+  uint8_t nonce[CAR_NONCE_SIZE];
+} CarMessage_AuthChallenge_Body;
+
+typedef struct CarMessage_Authenticate_Body {
+  /**
+   * Tag of the nonce of the car
+   */
+  uint8_t proof[CAR_TAG_SIZE];
+} CarMessage_Authenticate_Body;
+
+typedef struct CarMessage_SetLogLevel_Body {
+  /**
+   * Whether records are forwarded, 1 or 0
+   */
+  uint8_t has_level;
+  /**
+   * Lowest level forwarded, a `LogLevel` id
+   */
+  uint8_t level;
+} CarMessage_SetLogLevel_Body;
+
+typedef struct CarMessage_TimeSync_Body {
+  /**
+   * Time of the controller clock, in microseconds
+   */
+  uint64_t origin_us;
+} CarMessage_TimeSync_Body;
+
+typedef struct CarMessage_SetSpeed_Body {
+  /**
+   * Token of the lease
+   */
+  uint16_t lease;
+  /**
+   * Percentage between -100 and 100
+   */
+  int8_t throttle;
+} CarMessage_SetSpeed_Body;
+
+typedef struct CarMessage_SetDirection_Body {
+  /**
+   * Token of the lease
+   */
+  uint16_t lease;
+  /**
+   * Percentage between -100 and 100
+   */
+  int8_t steering;
+} CarMessage_SetDirection_Body;
+
+typedef struct CarMessage_SetParam_Body {
+  /**
+   * Token of the lease
+   */
+  uint16_t lease;
+  /**
+   * The tunable and its new value
+   */
+  struct CarParam param;
+} CarMessage_SetParam_Body;
+
+typedef struct CarMessage_ClearFault_Body {
+  /**
+   * Token of the lease
+   */
+  uint16_t lease;
+} CarMessage_ClearFault_Body;
+
+typedef struct CarMessage_AcquireControl_Body {
+  /**
+   * Whether the lease is renewed, 1 or 0
+   */
+  uint8_t has_renew;
+  /**
+   * Token of the renewed lease
+   */
+  uint16_t renew;
+  /**
+   * Duration of the lease, in milliseconds
+   */
+  uint16_t duration_ms;
+} CarMessage_AcquireControl_Body;
+
+typedef struct CarMessage_ReleaseControl_Body {
+  /**
+   * Token of the lease
+   */
+  uint16_t lease;
+} CarMessage_ReleaseControl_Body;
+
+typedef struct CarMessage_BeginUpdate_Body {
+  /**
+   * Token of the lease
+   */
+  uint16_t lease;
+  /**
+   * Size of the image, in bytes
+   */
+  uint32_t size;
+  /**
+   * SHA-256 hash of the image
+   */
+  uint8_t hash[CAR_HASH_SIZE];
+} CarMessage_BeginUpdate_Body;
+
+typedef struct CarMessage_WriteUpdate_Body {
+  /**
+   * Token of the lease
+   */
+  uint16_t lease;
+  /**
+   * Position of the chunk in the image
+   */
+  uint32_t offset;
+  /**
+   * Bytes of the chunk
+   */
+  struct CarChunk chunk;
+} CarMessage_WriteUpdate_Body;
+
+typedef struct CarMessage_VerifyUpdate_Body {
+  /**
+   * Token of the lease
+   */
+  uint16_t lease;
+} CarMessage_VerifyUpdate_Body;
+
+typedef struct CarMessage_CommitUpdate_Body {
+  /**
+   * Token of the lease
+   */
+  uint16_t lease;
+} CarMessage_CommitUpdate_Body;
+
+typedef struct CarMessage {
+  CarMessage_Tag tag;
+  union {
+    CarMessage_Hello_Body hello;
+    CarMessage_GetParam_Body get_param;
+    CarMessage_ListParams_Body list_params;
+    CarMessage_Subscribe_Body subscribe;
+    CarMessage_AuthChallenge_Body auth_challenge;
+    CarMessage_Authenticate_Body authenticate;
+    CarMessage_SetLogLevel_Body set_log_level;
+    CarMessage_TimeSync_Body time_sync;
+    CarMessage_SetSpeed_Body set_speed;
+    CarMessage_SetDirection_Body set_direction;
+    CarMessage_SetParam_Body set_param;
+    CarMessage_ClearFault_Body clear_fault;
+    CarMessage_AcquireControl_Body acquire_control;
+    CarMessage_ReleaseControl_Body release_control;
+    CarMessage_BeginUpdate_Body begin_update;
+    CarMessage_WriteUpdate_Body write_update;
+    CarMessage_VerifyUpdate_Body verify_update;
+    CarMessage_CommitUpdate_Body commit_update;
+  };
+} CarMessage;
+
+/**
+ * A message with its sequence number
+ */
+typedef struct CarMessageEnvelope {
+  /**
+   * Sequence number, answers echo it
+   */
+  uint8_t sequence;
+  /**
+   * The message
+   */
+  struct CarMessage message;
+} CarMessageEnvelope;
+
+/**
+ * Version of the firmware running on the car
+ */
+typedef struct CarFirmwareVersion {
+  /**
+   * Major version number
+   */
+  uint8_t major;
+  /**
+   * Minor version number
+   */
+  uint8_t minor;
+  /**
+   * Patch version number
+   */
+  uint8_t patch;
+} CarFirmwareVersion;
+
+/**
+ * A snapshot of the car state, values outside of `topics` are zero
+ */
+typedef struct CarTelemetry {
+  /**
+   * Bits of the values in the snapshot, see `CAR_TOPIC_*`
+   */
+  uint8_t topics;
+  /**
+   * Percentage between -100 and 100
+   */
+  int8_t speed;
+  /**
+   * Percentage between -100 and 100
+   */
+  int8_t direction;
+  /**
+   * Whether an echo came back, 1 or 0
+   */
+  uint8_t has_distance;
+  /**
+   * Distance in millimeters
+   */
+  uint16_t distance_mm;
+  /**
+   * Voltage in millivolts
+   */
+  uint16_t battery_mv;
+  /**
+   * Number of frames received by the car
+   */
+  uint16_t link_received;
+  /**
+   * Number of frames dropped by the car
+   */
+  uint16_t link_dropped;
+  /**
+   * Whether the car sent when the values were sampled, 1 or 0
+   */
+  uint8_t has_sampled_at;
+  /**
+   * When the values were sampled, in microseconds on the car clock
+   */
+  uint64_t sampled_at_us;
+} CarTelemetry;
+
+/**
+ * An answer sent by the car to the controller, see `Answer` in the protocol reference
+ */
+enum CarAnswer_Tag
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  /**
+   * Answer to a ping
+   */
+  CarAnswer_Pong,
+  /**
+   * Answer to a hello, describes the car
+   */
+  CarAnswer_Hello,
+  /**
+   * The current speed
+   */
+  CarAnswer_Speed,
+  /**
+   * The current direction
+   */
+  CarAnswer_Direction,
+  /**
+   * The battery voltage
+   */
+  CarAnswer_BatteryLevel,
+  /**
+   * The distance to the closest obstacle
+   */
+  CarAnswer_UltrasonicDistance,
+  /**
+   * The value of a tunable
+   */
+  CarAnswer_Param,
+  /**
+   * The tunable at a position
+   */
+  CarAnswer_ParamEntry,
+  /**
+   * The snapshots are sent
+   */
+  CarAnswer_Subscribed,
+  /**
+   * The snapshots are stopped
+   */
+  CarAnswer_Unsubscribed,
+  /**
+   * The latched fault
+   */
+  CarAnswer_Fault,
+  /**
+   * The nonce of the car and the proof of the controller nonce
+   */
+  CarAnswer_AuthChallenge,
+  /**
+   * The session is authenticated
+   */
+  CarAnswer_Authenticated,
+  /**
+   * The log level changed
+   */
+  CarAnswer_AckLogLevel,
+  /**
+   * The times of the car clock around a synchronization
+   */
+  CarAnswer_TimeSync,
+  /**
+   * The speed changed
+   */
+  CarAnswer_AckSpeed,
+  /**
+   * The direction changed
+   */
+  CarAnswer_AckDirection,
+  /**
+   * The tunable changed
+   */
+  CarAnswer_AckParam,
+  /**
+   * The car stopped
+   */
+  CarAnswer_AckEmergencyStop,
+  /**
+   * The fault was cleared
+   */
+  CarAnswer_AckClearFault,
+  /**
+   * The lease is granted
+   */
+  CarAnswer_ControlGranted,
+  /**
+   * The lease is released
+   */
+  CarAnswer_AckReleaseControl,
+  /**
+   * The update started
+   */
+  CarAnswer_UpdateReady,
+  /**
+   * The chunk was written
+   */
+  CarAnswer_AckWriteUpdate,
+  /**
+   * The written image matches its hash
+   */
+  CarAnswer_UpdateVerified,
+  /**
+   * The car boots the new image
+   */
+  CarAnswer_AckCommitUpdate,
+  /**
+   * A periodic snapshot
+   */
+  CarAnswer_Telemetry,
+  /**
+   * A log record of the firmware
+   */
+  CarAnswer_Log,
+  /**
+   * The message was refused
+   */
+  CarAnswer_Nack,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum CarAnswer_Tag CarAnswer_Tag;
+#else
+typedef uint8_t CarAnswer_Tag;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+typedef struct CarAnswer_Hello_Body {
+  /**
+   * The revision spoken by the car
+   */
+  struct CarVersion version;
+  /**
+   * Version of the firmware
+   */
+  struct CarFirmwareVersion firmware;
+  /**
+   * Bits of the components available on the car
+   */
+  uint16_t capabilities;
+} CarAnswer_Hello_Body;
+
+typedef struct CarAnswer_Speed_Body {
+  /**
+   * Percentage between -100 and 100
+   */
+  int8_t throttle;
+} CarAnswer_Speed_Body;
+
+typedef struct CarAnswer_Direction_Body {
+  /**
+   * Percentage between -100 and 100
+   */
+  int8_t steering;
+} CarAnswer_Direction_Body;
+
+typedef struct CarAnswer_BatteryLevel_Body {
+  /**
+   * Voltage in millivolts
+   */
+  uint16_t battery_mv;
+  /**
+   * Whether the car sent when the voltage was measured, 1 or 0
+   */
+  uint8_t has_sampled_at;
+  /**
+   * When the voltage was measured, in microseconds on the car clock
+   */
+  uint64_t sampled_at_us;
+} CarAnswer_BatteryLevel_Body;
+
+typedef struct CarAnswer_UltrasonicDistance_Body {
+  /**
+   * Whether an echo came back, 1 or 0
+   */
+  uint8_t has_distance;
+  /**
+   * Distance in millimeters
+   */
+  uint16_t distance_mm;
+  /**
+   * Whether the car sent when the distance was measured, 1 or 0
+   */
+  uint8_t has_sampled_at;
+  /**
+   * When the distance was measured, in microseconds on the car clock
+   */
+  uint64_t sampled_at_us;
+} CarAnswer_UltrasonicDistance_Body;
+
+typedef struct CarAnswer_Param_Body {
+  /**
+   * The tunable and its value
+   */
+  struct CarParam param;
+} CarAnswer_Param_Body;
+
+typedef struct CarAnswer_ParamEntry_Body {
+  /**
+   * Number of tunables
+   */
+  uint8_t count;
+  /**
+   * Whether there is a tunable at that position, 1 or 0
+   */
+  uint8_t has_param;
+  /**
+   * The tunable and its value
+   */
+  struct CarParam param;
+} CarAnswer_ParamEntry_Body;
+
+typedef struct CarAnswer_Subscribed_Body {
+  /**
+   * Time between two snapshots, in milliseconds
+   */
+  uint16_t period_ms;
+} CarAnswer_Subscribed_Body;
+
+typedef struct CarAnswer_Fault_Body {
+  /**
+   * Whether a fault is latched, 1 or 0
+   */
+  uint8_t has_fault;
+  /**
+   * The latched fault, a `Fault` id
+   */
+  uint8_t fault;
+} CarAnswer_Fault_Body;
+
+typedef struct CarAnswer_AuthChallenge_Body {
+  /**
+   * Random bytes of the car
+   */
+  uint8_t nonce[CAR_NONCE_SIZE];
+  /**
+   * Tag of the controller nonce
+   */
+  uint8_t proof[CAR_TAG_SIZE];
+} CarAnswer_AuthChallenge_Body;
+
+typedef struct CarAnswer_TimeSync_Body {
+  /**
+   * Time of the controller clock copied from the message, in microseconds
+   */
+  uint64_t origin_us;
+  /**
+   * When the car received the message, in microseconds on the car clock
+   */
+  uint64_t received_us;
+  /**
+   * When the car sent the answer, in microseconds on the car clock
+   */
+  uint64_t transmitted_us;
+} CarAnswer_TimeSync_Body;
+
+typedef struct CarAnswer_AckParam_Body {
+  /**
+   * The tunable and its value after clamping
+   */
+  struct CarParam param;
+} CarAnswer_AckParam_Body;
+
+typedef struct CarAnswer_AckClearFault_Body {
+  /**
+   * Whether a fault was latched, 1 or 0
+   */
+  uint8_t has_cleared;
+  /**
+   * The cleared fault, a `Fault` id
+   */
+  uint8_t cleared;
+} CarAnswer_AckClearFault_Body;
+
+typedef struct CarAnswer_ControlGranted_Body {
+  /**
+   * Token to put in the commands
+   */
+  uint16_t lease;
+  /**
+   * Duration of the lease, in milliseconds
+   */
+  uint16_t duration_ms;
+} CarAnswer_ControlGranted_Body;
+
+typedef struct CarAnswer_UpdateReady_Body {
+  /**
+   * Position of the first chunk to write
+   */
+  uint32_t offset;
+} CarAnswer_UpdateReady_Body;
+
+typedef struct CarAnswer_AckWriteUpdate_Body {
+  /**
+   * Position of the next chunk
+   */
+  uint32_t next;
+} CarAnswer_AckWriteUpdate_Body;
+
+typedef struct CarAnswer_Telemetry_Body {
+  /**
+   * The subscribed values
+   */
+  struct CarTelemetry telemetry;
+} CarAnswer_Telemetry_Body;
+
+typedef struct CarAnswer_Log_Body {
+  /**
+   * Level of the record, a `LogLevel` id
+   */
+  uint8_t level;
+  /**
+   * Number of records dropped before this one
+   */
+  uint16_t dropped;
+  /**
+   * UTF-8 text of the record, ends with a null byte
+   */
+  uint8_t text[CAR_LOG_TEXT_BUFFER_SIZE];
+} CarAnswer_Log_Body;
+
+typedef struct CarAnswer_Nack_Body {
+  /**
+   * Id of the refused message
+   */
+  uint8_t for_id;
+  /**
+   * Why it was refused, an `ErrorCode` id
+   */
+  uint8_t reason;
+} CarAnswer_Nack_Body;
+
+typedef struct CarAnswer {
+  CarAnswer_Tag tag;
+  union {
+    CarAnswer_Hello_Body hello;
+    CarAnswer_Speed_Body speed;
+    CarAnswer_Direction_Body direction;
+    CarAnswer_BatteryLevel_Body battery_level;
+    CarAnswer_UltrasonicDistance_Body ultrasonic_distance;
+    CarAnswer_Param_Body param;
+    CarAnswer_ParamEntry_Body param_entry;
+    CarAnswer_Subscribed_Body subscribed;
+    CarAnswer_Fault_Body fault;
+    CarAnswer_AuthChallenge_Body auth_challenge;
+    CarAnswer_TimeSync_Body time_sync;
+    CarAnswer_AckParam_Body ack_param;
+    CarAnswer_AckClearFault_Body ack_clear_fault;
+    CarAnswer_ControlGranted_Body control_granted;
+    CarAnswer_UpdateReady_Body update_ready;
+    CarAnswer_AckWriteUpdate_Body ack_write_update;
+    CarAnswer_Telemetry_Body telemetry;
+    CarAnswer_Log_Body log;
+    CarAnswer_Nack_Body nack;
+  };
+} CarAnswer;
+
+/**
+ * An answer with the sequence number of the message it answers
+ */
+typedef struct CarAnswerEnvelope {
+  /**
+   * Sequence number of the answered message
+   */
+  uint8_t sequence;
+  /**
+   * The answer
+   */
+  struct CarAnswer answer;
+} CarAnswerEnvelope;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Encodes the message into a frame, delimiter included
+ *
+ * The length of the frame is stored in `length`, a buffer of [`CAR_MAX_FRAME_SIZE`] bytes always
+ * fits.
+ *
+ * The tag of the message is checked to be below [`CAR_MESSAGE_TAGS`] and its `has_` flags to be 0 or 1, before
+ * the message is read.
+ *
+ * # Safety
+ * `envelope` and `length` must be valid pointers, and `frame` must be valid for `capacity` bytes.
+ */
+enum CarStatus car_encode_message(const struct CarMessageEnvelope *envelope,
+                                  uint8_t *frame,
+                                  size_t capacity,
+                                  size_t *length);
+
+/**
+ * Decodes a frame into a message
+ *
+ * `frame` holds the bytes received up to the first frame delimiter, included.
+ *
+ * # Safety
+ * `envelope` must be a valid pointer, and `frame` must be valid for `length` bytes.
+ */
+enum CarStatus car_decode_message(const uint8_t *frame,
+                                  size_t length,
+                                  struct CarMessageEnvelope *envelope);
+
+/**
+ * Encodes the answer into a frame, delimiter included
+ *
+ * The length of the frame is stored in `length`, a buffer of [`CAR_MAX_FRAME_SIZE`] bytes always
+ * fits.
+ *
+ * The tag of the answer is checked to be below [`CAR_ANSWER_TAGS`] and its `has_` flags to be 0 or 1, before
+ * the answer is read.
+ *
+ * # Safety
+ * `envelope` and `length` must be valid pointers, and `frame` must be valid for `capacity` bytes.
+ */
+enum CarStatus car_encode_answer(const struct CarAnswerEnvelope *envelope,
+                                 uint8_t *frame,
+                                 size_t capacity,
+                                 size_t *length);
+
+/**
+ * Decodes a frame into an answer
+ *
+ * `frame` holds the bytes received up to the first frame delimiter, included.
+ *
+ * # Safety
+ * `envelope` must be a valid pointer, and `frame` must be valid for `length` bytes.
+ */
+enum CarStatus car_decode_answer(const uint8_t *frame,
+                                 size_t length,
+                                 struct CarAnswerEnvelope *envelope);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CAR_TRANSPORT_H */
@@ -0,0 +1,540 @@
+//! C mirror of the answers sent by the car
+
+use car_transport::{
+	Answer, Capabilities, Envelope, LeaseToken, LogText, Millimeters, Millivolts, Nonce, Param,
+	Sequence, Steering, Tag, Telemetry, Throttle, Timestamp, Topics, Trailing, TransportError,
+	log::LOG_TEXT_SIZE, telemetry::LinkStats,
+};
+
+use crate::{
+	CAR_LOG_TEXT_BUFFER_SIZE, CAR_NONCE_SIZE, CAR_TAG_SIZE, CarFirmwareVersion, CarParam,
+	CarVersion, field_id, flag, from_field_id,
+};
+
+/// An answer with the sequence number of the message it answers
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CarAnswerEnvelope {
+	/// Sequence number of the answered message
+	pub sequence: u8,
+	/// The answer
+	pub answer: CarAnswer,
+}
+
+/// An answer sent by the car to the controller, see `Answer` in the protocol reference
+#[repr(C, u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CarAnswer {
+	/// Answer to a ping
+	Pong,
+	/// Answer to a hello, describes the car
+	Hello {
+		/// The revision spoken by the car
+		version: CarVersion,
+		/// Version of the firmware
+		firmware: CarFirmwareVersion,
+		/// Bits of the components available on the car
+		capabilities: u16,
+	},
+	/// The current speed
+	Speed {
+		/// Percentage between -100 and 100
+		throttle: i8,
+	},
+	/// The current direction
+	Direction {
+		/// Percentage between -100 and 100
+		steering: i8,
+	},
+	/// The battery voltage
+	BatteryLevel {
+		/// Voltage in millivolts
+		battery_mv: u16,
+		/// Whether the car sent when the voltage was measured, 1 or 0
+		has_sampled_at: u8,
+		/// When the voltage was measured, in microseconds on the car clock
+		sampled_at_us: u64,
+	},
+	/// The distance to the closest obstacle
+	UltrasonicDistance {
+		/// Whether an echo came back, 1 or 0
+		has_distance: u8,
+		/// Distance in millimeters
+		distance_mm: u16,
+		/// Whether the car sent when the distance was measured, 1 or 0
+		has_sampled_at: u8,
+		/// When the distance was measured, in microseconds on the car clock
+		sampled_at_us: u64,
+	},
+	/// The value of a tunable
+	Param {
+		/// The tunable and its value
+		param: CarParam,
+	},
+	/// The tunable at a position
+	ParamEntry {
+		/// Number of tunables
+		count: u8,
+		/// Whether there is a tunable at that position, 1 or 0
+		has_param: u8,
+		/// The tunable and its value
+		param: CarParam,
+	},
+	/// The snapshots are sent
+	Subscribed {
+		/// Time between two snapshots, in milliseconds
+		period_ms: u16,
+	},
+	/// The snapshots are stopped
+	Unsubscribed,
+	/// The latched fault
+	Fault {
+		/// Whether a fault is latched, 1 or 0
+		has_fault: u8,
+		/// The latched fault, a `Fault` id
+		fault: u8,
+	},
+	/// The nonce of the car and the proof of the controller nonce
+	AuthChallenge {
+		/// Random bytes of the car
+		nonce: [u8; CAR_NONCE_SIZE],
+		/// Tag of the controller nonce
+		proof: [u8; CAR_TAG_SIZE],
+	},
+	/// The session is authenticated
+	Authenticated,
+	/// The log level changed
+	AckLogLevel,
+	/// The times of the car clock around a synchronization
+	TimeSync {
+		/// Time of the controller clock copied from the message, in microseconds
+		origin_us: u64,
+		/// When the car received the message, in microseconds on the car clock
+		received_us: u64,
+		/// When the car sent the answer, in microseconds on the car clock
+		transmitted_us: u64,
+	},
+	/// The speed changed
+	AckSpeed,
+	/// The direction changed
+	AckDirection,
+	/// The tunable changed
+	AckParam {
+		/// The tunable and its value after clamping
+		param: CarParam,
+	},
+	/// The car stopped
+	AckEmergencyStop,
+	/// The fault was cleared
+	AckClearFault {
+		/// Whether a fault was latched, 1 or 0
+		has_cleared: u8,
+		/// The cleared fault, a `Fault` id
+		cleared: u8,
+	},
+	/// The lease is granted
+	ControlGranted {
+		/// Token to put in the commands
+		lease: u16,
+		/// Duration of the lease, in milliseconds
+		duration_ms: u16,
+	},
+	/// The lease is released
+	AckReleaseControl,
+	/// The update started
+	UpdateReady {
+		/// Position of the first chunk to write
+		offset: u32,
+	},
+	/// The chunk was written
+	AckWriteUpdate {
+		/// Position of the next chunk
+		next: u32,
+	},
+	/// The written image matches its hash
+	UpdateVerified,
+	/// The car boots the new image
+	AckCommitUpdate,
+	/// A periodic snapshot
+	Telemetry {
+		/// The subscribed values
+		telemetry: CarTelemetry,
+	},
+	/// A log record of the firmware
+	Log {
+		/// Level of the record, a `LogLevel` id
+		level: u8,
+		/// Number of records dropped before this one
+		dropped: u16,
+		/// UTF-8 text of the record, ends with a null byte
+		text: [u8; CAR_LOG_TEXT_BUFFER_SIZE],
+	},
+	/// The message was refused
+	Nack {
+		/// Id of the refused message
+		for_id: u8,
+		/// Why it was refused, an `ErrorCode` id
+		reason: u8,
+	},
+}
+
+/// A snapshot of the car state, values outside of `topics` are zero
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CarTelemetry {
+	/// Bits of the values in the snapshot, see `CAR_TOPIC_*`
+	pub topics: u8,
+	/// Percentage between -100 and 100
+	pub speed: i8,
+	/// Percentage between -100 and 100
+	pub direction: i8,
+	/// Whether an echo came back, 1 or 0
+	pub has_distance: u8,
+	/// Distance in millimeters
+	pub distance_mm: u16,
+	/// Voltage in millivolts
+	pub battery_mv: u16,
+	/// Number of frames received by the car
+	pub link_received: u16,
+	/// Number of frames dropped by the car
+	pub link_dropped: u16,
+	/// Whether the car sent when the values were sampled, 1 or 0
+	pub has_sampled_at: u8,
+	/// When the values were sampled, in microseconds on the car clock
+	pub sampled_at_us: u64,
+}
+
+impl From<&Telemetry> for CarTelemetry {
+	fn from(telemetry: &Telemetry) -> Self {
+		let mut topics = Topics::NONE;
+		for (topic, present) in [
+			(Topics::SPEED, telemetry.speed.is_some()),
+			(Topics::DIRECTION, telemetry.direction.is_some()),
+			(Topics::DISTANCE, telemetry.distance.is_some()),
+			(Topics::BATTERY, telemetry.battery.is_some()),
+			(Topics::LINK, telemetry.link.is_some()),
+		] {
+			if present {
+				topics = topics | topic;
+			}
+		}
+		let link = telemetry.link.unwrap_or_default();
+
+		Self {
+			topics: topics.0,
+			speed: telemetry.speed.map_or(0, Throttle::percent),
+			direction: telemetry.direction.map_or(0, Steering::percent),
+			has_distance: u8::from(matches!(telemetry.distance, Some(Some(_)))),
+			distance_mm: telemetry
+				.distance
+				.flatten()
+				.map_or(0, |distance| distance.0),
+			battery_mv: telemetry.battery.map_or(0, |battery| battery.0),
+			link_received: link.received,
+			link_dropped: link.dropped,
+			has_sampled_at: u8::from(!telemetry.sampled_at.is_none()),
+			sampled_at_us: sampled_at_us(telemetry.sampled_at),
+		}
+	}
+}
+
+impl TryFrom<&CarTelemetry> for Telemetry {
+	type Error = TransportError;
+
+	fn try_from(telemetry: &CarTelemetry) -> Result<Self, Self::Error> {
+		let topics = Topics(telemetry.topics);
+		if !Topics::ALL.contains(topics) {
+			return Err(TransportError::OutOfRange);
+		}
+
+		Ok(Self {
+			speed: topics
+				.contains(Topics::SPEED)
+				.then(|| telemetry.speed.try_into())
+				.transpose()?,
+			direction: topics
+				.contains(Topics::DIRECTION)
+				.then(|| telemetry.direction.try_into())
+				.transpose()?,
+			distance: topics
+				.contains(Topics::DISTANCE)
+				.then(|| {
+					flag(telemetry.has_distance).map(|has_distance| {
+						has_distance.then_some(Millimeters(telemetry.distance_mm))
+					})
+				})
+				.transpose()?,
+			battery: topics
+				.contains(Topics::BATTERY)
+				.then_some(Millivolts(telemetry.battery_mv)),
+			link: topics.contains(Topics::LINK).then_some(LinkStats {
+				received: telemetry.link_received,
+				dropped: telemetry.link_dropped,
+			}),
+			sampled_at: sampled_at(telemetry.has_sampled_at, telemetry.sampled_at_us)?,
+		})
+	}
+}
+
+/// Returns the microseconds of the time at which a value was sampled, 0 without one
+const fn sampled_at_us(sampled_at: Trailing<Timestamp>) -> u64 {
+	match sampled_at.0 {
+		Some(sampled_at) => sampled_at.0,
+		None => 0,
+	}
+}
+
+/// Returns the time at which a value was sampled from its C mirror
+fn sampled_at(
+	has_sampled_at: u8,
+	sampled_at_us: u64,
+) -> Result<Trailing<Timestamp>, TransportError> {
+	Ok(Trailing(
+		flag(has_sampled_at)?.then_some(Timestamp(sampled_at_us)),
+	))
+}
+
+impl From<&Envelope<Answer>> for CarAnswerEnvelope {
+	// A single arm per answer, splitting it would only scatter the mirror
+	#[allow(clippy::too_many_lines)]
+	fn from(envelope: &Envelope<Answer>) -> Self {
+		let answer = match &envelope.content {
+			Answer::Pong => CarAnswer::Pong,
+			Answer::Hello {
+				version,
+				firmware,
+				capabilities,
+			} => CarAnswer::Hello {
+				version: (*version).into(),
+				firmware: (*firmware).into(),
+				capabilities: capabilities.0,
+			},
+			Answer::Speed(throttle) => CarAnswer::Speed {
+				throttle: throttle.percent(),
+			},
+			Answer::Direction(steering) => CarAnswer::Direction {
+				steering: steering.percent(),
+			},
+			Answer::BatteryLevel { level, sampled_at } => CarAnswer::BatteryLevel {
+				battery_mv: level.0,
+				has_sampled_at: u8::from(!sampled_at.is_none()),
+				sampled_at_us: sampled_at_us(*sampled_at),
+			},
+			Answer::UltrasonicDistance {
+				distance,
+				sampled_at,
+			} => CarAnswer::UltrasonicDistance {
+				has_distance: u8::from(distance.is_some()),
+				distance_mm: distance.map_or(0, |distance| distance.0),
+				has_sampled_at: u8::from(!sampled_at.is_none()),
+				sampled_at_us: sampled_at_us(*sampled_at),
+			},
+			Answer::Param(param) => CarAnswer::Param {
+				param: (*param).into(),
+			},
+			Answer::ParamEntry { count, param } => CarAnswer::ParamEntry {
+				count: *count,
+				has_param: u8::from(param.is_some()),
+				param: param.map(CarParam::from).unwrap_or_default(),
+			},
+			Answer::Subscribed { period_ms } => CarAnswer::Subscribed {
+				period_ms: *period_ms,
+			},
+			Answer::Unsubscribed => CarAnswer::Unsubscribed,
+			Answer::Fault(fault) => CarAnswer::Fault {
+				has_fault: u8::from(fault.is_some()),
+				fault: fault.as_ref().map_or(0, field_id),
+			},
+			Answer::AuthChallenge { nonce, proof } => CarAnswer::AuthChallenge {
+				nonce: nonce.0,
+				proof: proof.0,
+			},
+			Answer::Authenticated => CarAnswer::Authenticated,
+			Answer::AckLogLevel => CarAnswer::AckLogLevel,
+			Answer::TimeSync {
+				origin,
+				received,
+				transmitted,
+			} => CarAnswer::TimeSync {
+				origin_us: origin.0,
+				received_us: received.0,
+				transmitted_us: transmitted.0,
+			},
+			Answer::AckSpeed => CarAnswer::AckSpeed,
+			Answer::AckDirection => CarAnswer::AckDirection,
+			Answer::AckParam(param) => CarAnswer::AckParam {
+				param: (*param).into(),
+			},
+			Answer::AckEmergencyStop => CarAnswer::AckEmergencyStop,
+			Answer::AckClearFault { cleared } => CarAnswer::AckClearFault {
+				has_cleared: u8::from(cleared.is_some()),
+				cleared: cleared.as_ref().map_or(0, field_id),
+			},
+			Answer::ControlGranted { lease, duration_ms } => CarAnswer::ControlGranted {
+				lease: lease.0,
+				duration_ms: *duration_ms,
+			},
+			Answer::AckReleaseControl => CarAnswer::AckReleaseControl,
+			Answer::UpdateReady { offset } => CarAnswer::UpdateReady { offset: *offset },
+			Answer::AckWriteUpdate { next } => CarAnswer::AckWriteUpdate { next: *next },
+			Answer::UpdateVerified => CarAnswer::UpdateVerified,
+			Answer::AckCommitUpdate => CarAnswer::AckCommitUpdate,
+			Answer::Telemetry(telemetry) => CarAnswer::Telemetry {
+				telemetry: telemetry.into(),
+			},
+			Answer::Log {
+				level,
+				dropped,
+				text,
+			} => CarAnswer::Log {
+				level: field_id(level),
+				dropped: *dropped,
+				text: log_text_bytes(text),
+			},
+			Answer::Nack { for_id, reason } => CarAnswer::Nack {
+				for_id: *for_id,
+				reason: field_id(reason),
+			},
+		};
+
+		Self {
+			sequence: envelope.sequence.0,
+			answer,
+		}
+	}
+}
+
+impl TryFrom<&CarAnswerEnvelope> for Envelope<Answer> {
+	type Error = TransportError;
+
+	// A single arm per answer, splitting it would only scatter the mirror
+	#[allow(clippy::too_many_lines)]
+	fn try_from(envelope: &CarAnswerEnvelope) -> Result<Self, Self::Error> {
+		let answer = match &envelope.answer {
+			CarAnswer::Pong => Answer::Pong,
+			CarAnswer::Hello {
+				version,
+				firmware,
+				capabilities,
+			} => Answer::Hello {
+				version: (*version).into(),
+				firmware: (*firmware).into(),
+				capabilities: Capabilities(*capabilities),
+			},
+			CarAnswer::Speed { throttle } => Answer::Speed((*throttle).try_into()?),
+			CarAnswer::Direction { steering } => Answer::Direction((*steering).try_into()?),
+			CarAnswer::BatteryLevel {
+				battery_mv,
+				has_sampled_at,
+				sampled_at_us,
+			} => Answer::BatteryLevel {
+				level: Millivolts(*battery_mv),
+				sampled_at: sampled_at(*has_sampled_at, *sampled_at_us)?,
+			},
+			CarAnswer::UltrasonicDistance {
+				has_distance,
+				distance_mm,
+				has_sampled_at,
+				sampled_at_us,
+			} => Answer::UltrasonicDistance {
+				distance: flag(*has_distance)?.then_some(Millimeters(*distance_mm)),
+				sampled_at: sampled_at(*has_sampled_at, *sampled_at_us)?,
+			},
+			CarAnswer::Param { param } => Answer::Param((*param).try_into()?),
+			CarAnswer::ParamEntry {
+				count,
+				has_param,
+				param,
+			} => Answer::ParamEntry {
+				count: *count,
+				param: flag(*has_param)?
+					.then(|| Param::try_from(*param))
+					.transpose()?,
+			},
+			CarAnswer::Subscribed { period_ms } => Answer::Subscribed {
+				period_ms: *period_ms,
+			},
+			CarAnswer::Unsubscribed => Answer::Unsubscribed,
+			CarAnswer::Fault { has_fault, fault } => Answer::Fault(
+				flag(*has_fault)?
+					.then(|| from_field_id(*fault))
+					.transpose()?,
+			),
+			CarAnswer::AuthChallenge { nonce, proof } => Answer::AuthChallenge {
+				nonce: Nonce(*nonce),
+				proof: Tag(*proof),
+			},
+			CarAnswer::Authenticated => Answer::Authenticated,
+			CarAnswer::AckLogLevel => Answer::AckLogLevel,
+			CarAnswer::TimeSync {
+				origin_us,
+				received_us,
+				transmitted_us,
+			} => Answer::TimeSync {
+				origin: Timestamp(*origin_us),
+				received: Timestamp(*received_us),
+				transmitted: Timestamp(*transmitted_us),
+			},
+			CarAnswer::AckSpeed => Answer::AckSpeed,
+			CarAnswer::AckDirection => Answer::AckDirection,
+			CarAnswer::AckParam { param } => Answer::AckParam((*param).try_into()?),
+			CarAnswer::AckEmergencyStop => Answer::AckEmergencyStop,
+			CarAnswer::AckClearFault {
+				has_cleared,
+				cleared,
+			} => Answer::AckClearFault {
+				cleared: flag(*has_cleared)?
+					.then(|| from_field_id(*cleared))
+					.transpose()?,
+			},
+			CarAnswer::ControlGranted { lease, duration_ms } => Answer::ControlGranted {
+				lease: LeaseToken(*lease),
+				duration_ms: *duration_ms,
+			},
+			CarAnswer::AckReleaseControl => Answer::AckReleaseControl,
+			CarAnswer::UpdateReady { offset } => Answer::UpdateReady { offset: *offset },
+			CarAnswer::AckWriteUpdate { next } => Answer::AckWriteUpdate { next: *next },
+			CarAnswer::UpdateVerified => Answer::UpdateVerified,
+			CarAnswer::AckCommitUpdate => Answer::AckCommitUpdate,
+			CarAnswer::Telemetry { telemetry } => Answer::Telemetry(telemetry.try_into()?),
+			CarAnswer::Log {
+				level,
+				dropped,
+				text,
+			} => Answer::Log {
+				level: from_field_id(*level)?,
+				dropped: *dropped,
+				text: log_text(text)?,
+			},
+			CarAnswer::Nack { for_id, reason } => Answer::Nack {
+				for_id: *for_id,
+				reason: from_field_id(*reason)?,
+			},
+		};
+
+		Ok(Self::new(Sequence(envelope.sequence), answer))
+	}
+}
+
+/// Returns the text of a log record followed by null bytes
+fn log_text_bytes(text: &LogText) -> [u8; CAR_LOG_TEXT_BUFFER_SIZE] {
+	let mut bytes = [0; CAR_LOG_TEXT_BUFFER_SIZE];
+	bytes[..text.as_str().len()].copy_from_slice(text.as_str().as_bytes());
+
+	bytes
+}
+
+/// Returns the text of a log record from its null terminated bytes
+fn log_text(bytes: &[u8; CAR_LOG_TEXT_BUFFER_SIZE]) -> Result<LogText, TransportError> {
+	let length = bytes
+		.iter()
+		.position(|&byte| byte == 0)
+		.ok_or(TransportError::InvalidPayload)?;
+	if length > LOG_TEXT_SIZE {
+		return Err(TransportError::OutOfRange);
+	}
+	let text =
+		core::str::from_utf8(&bytes[..length]).map_err(|_| TransportError::InvalidPayload)?;
+
+	Ok(LogText::truncated(text))
+}
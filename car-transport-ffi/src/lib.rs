@@ -0,0 +1,730 @@
+//! C bindings of the car transport protocol.
+//!
+//! The crate builds a static library, `libcar_transport_ffi.a`, and writes its header to
+//! `include/car_transport.h`. C programs fill a [`CarMessageEnvelope`] and encode it into a frame
+//! ready to be written on the serial link, then gather received bytes until the
+//! [`CAR_FRAME_DELIMITER`] and decode the frame into a [`CarAnswerEnvelope`]:
+//! ```c
+//! #include "car_transport.h"
+//!
+//! CarMessageEnvelope ping = { .sequence = 1, .message = { .tag = CarMessage_Ping } };
+//! uint8_t frame[CAR_MAX_FRAME_SIZE];
+//! size_t length;
+//! if (car_encode_message(&ping, frame, sizeof frame, &length) == CarStatus_Ok) {
+//!     Serial.write(frame, length);
+//! }
+//! ```
+//!
+//! Both sides are available, so C code can also stand in for the car. The encoding is done by
+//! `car-transport`, the types of this crate only mirror its values with plain C types: field
+//! enums such as `ErrorCode` are given by id, optional values have a `has_` flag set to 1 or 0, and
+//! the sizes of arrays are checked against `car-transport` at compile time. Frames are never sealed.
+//!
+//! C code may write any byte in the mirrors, so they only hold integers and the tag of
+//! [`CarMessage`] and [`CarAnswer`], which is checked before the value is read. Any other invalid
+//! value is refused with a [`CarStatus`].
+
+mod answer;
+mod message;
+
+use core::slice;
+
+use car_transport::{
+	Answer, Chunk, Envelope, Field, FirmwareVersion, FrameDecoder, FrameEncoder, Message, Param,
+	ParamId, ParamValue, ProtocolVersion, Transport, TransportError,
+	auth::{NONCE_SIZE, TAG_SIZE},
+	dfu::{CHUNK_SIZE, HASH_SIZE},
+	field::{Reader, Writer},
+	frame::{DEFAULT_FRAME_BUFFER_SIZE, FRAME_DELIMITER, max_frame_size},
+	log::LOG_TEXT_SIZE,
+	schema::Schema,
+	telemetry::Topics,
+};
+
+pub use answer::{CarAnswer, CarAnswerEnvelope, CarTelemetry};
+pub use message::{CarMessage, CarMessageEnvelope};
+
+/// Longest frame, delimiter included
+pub const CAR_MAX_FRAME_SIZE: usize = 68;
+/// Byte ending every frame
+pub const CAR_FRAME_DELIMITER: u8 = 0;
+/// Size of the authentication nonces
+pub const CAR_NONCE_SIZE: usize = 8;
+/// Size of the authentication tags
+pub const CAR_TAG_SIZE: usize = 8;
+/// Size of the firmware image hash
+pub const CAR_HASH_SIZE: usize = 32;
+/// Largest firmware chunk
+pub const CAR_CHUNK_SIZE: usize = 32;
+/// Longest log text, in bytes
+pub const CAR_LOG_TEXT_SIZE: usize = 40;
+/// Size of a log text buffer, null byte included
+pub const CAR_LOG_TEXT_BUFFER_SIZE: usize = 41;
+
+/// Telemetry speed bit
+pub const CAR_TOPIC_SPEED: u8 = 1 << 0;
+/// Telemetry direction bit
+pub const CAR_TOPIC_DIRECTION: u8 = 1 << 1;
+/// Telemetry distance bit
+pub const CAR_TOPIC_DISTANCE: u8 = 1 << 2;
+/// Telemetry battery bit
+pub const CAR_TOPIC_BATTERY: u8 = 1 << 3;
+/// Telemetry link counters bit
+pub const CAR_TOPIC_LINK: u8 = 1 << 4;
+
+/// Kind of a switch tunable
+pub const CAR_PARAM_BOOL: u8 = 0;
+/// Kind of a whole number tunable
+pub const CAR_PARAM_INTEGER: u8 = 1;
+/// Kind of a fixed point tunable
+pub const CAR_PARAM_MILLI: u8 = 2;
+
+/// Number of `CarMessage` variants, valid tags are below it
+pub const CAR_MESSAGE_TAGS: u8 = 26;
+/// Number of `CarAnswer` variants, valid tags are below it
+pub const CAR_ANSWER_TAGS: u8 = 29;
+
+// The header cannot refer to `car-transport`, its values are repeated and checked here
+const _: () = {
+	assert!(CAR_MAX_FRAME_SIZE == max_frame_size(DEFAULT_FRAME_BUFFER_SIZE));
+	assert!(CAR_FRAME_DELIMITER == FRAME_DELIMITER);
+	assert!(CAR_NONCE_SIZE == NONCE_SIZE);
+	assert!(CAR_TAG_SIZE == TAG_SIZE);
+	assert!(CAR_HASH_SIZE == HASH_SIZE);
+	assert!(CAR_CHUNK_SIZE == CHUNK_SIZE);
+	assert!(CAR_LOG_TEXT_SIZE == LOG_TEXT_SIZE);
+	assert!(CAR_LOG_TEXT_BUFFER_SIZE == LOG_TEXT_SIZE + 1);
+	assert!(CAR_TOPIC_SPEED == Topics::SPEED.0);
+	assert!(CAR_TOPIC_DIRECTION == Topics::DIRECTION.0);
+	assert!(CAR_TOPIC_DISTANCE == Topics::DISTANCE.0);
+	assert!(CAR_TOPIC_BATTERY == Topics::BATTERY.0);
+	assert!(CAR_TOPIC_LINK == Topics::LINK.0);
+	assert!(CAR_MESSAGE_TAGS as usize == Message::VARIANTS.len());
+	assert!(CAR_ANSWER_TAGS as usize == Answer::VARIANTS.len());
+};
+
+/// Outcome of the encoding and decoding functions
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CarStatus {
+	/// Success
+	Ok,
+	/// There is no such id
+	InvalidId,
+	/// The payload is invalid for the given id
+	InvalidPayload,
+	/// The frame ends before the payload is complete
+	Truncated,
+	/// The frame contains bytes after the payload
+	TrailingBytes,
+	/// A value is outside of its valid range
+	OutOfRange,
+	/// The frame checksum does not match its content
+	InvalidChecksum,
+	/// The frame is malformed
+	InvalidFraming,
+	/// The frame is longer than the decoder buffer
+	FrameTooLong,
+	/// The output buffer is too small to hold the frame
+	BufferTooSmall,
+	/// The frame is not sealed with the session key
+	InvalidTag,
+	/// The frame counter was already used
+	Replayed,
+	/// The bytes do not end with a frame delimiter
+	Incomplete,
+	/// A pointer argument is null
+	NullPointer,
+}
+
+impl From<TransportError> for CarStatus {
+	fn from(error: TransportError) -> Self {
+		match error {
+			TransportError::InvalidId => Self::InvalidId,
+			TransportError::InvalidPayload => Self::InvalidPayload,
+			TransportError::Truncated => Self::Truncated,
+			TransportError::TrailingBytes => Self::TrailingBytes,
+			TransportError::OutOfRange => Self::OutOfRange,
+			TransportError::InvalidChecksum => Self::InvalidChecksum,
+			TransportError::InvalidFraming => Self::InvalidFraming,
+			TransportError::FrameTooLong => Self::FrameTooLong,
+			TransportError::BufferTooSmall => Self::BufferTooSmall,
+			TransportError::InvalidTag => Self::InvalidTag,
+			TransportError::Replayed => Self::Replayed,
+		}
+	}
+}
+
+/// Revision of the protocol
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CarVersion {
+	/// Incompatible revisions
+	pub major: u8,
+	/// Compatible additions
+	pub minor: u8,
+}
+
+impl From<ProtocolVersion> for CarVersion {
+	fn from(version: ProtocolVersion) -> Self {
+		Self {
+			major: version.major,
+			minor: version.minor,
+		}
+	}
+}
+
+impl From<CarVersion> for ProtocolVersion {
+	fn from(version: CarVersion) -> Self {
+		Self {
+			major: version.major,
+			minor: version.minor,
+		}
+	}
+}
+
+/// Version of the firmware running on the car
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CarFirmwareVersion {
+	/// Major version number
+	pub major: u8,
+	/// Minor version number
+	pub minor: u8,
+	/// Patch version number
+	pub patch: u8,
+}
+
+impl From<FirmwareVersion> for CarFirmwareVersion {
+	fn from(version: FirmwareVersion) -> Self {
+		Self {
+			major: version.major,
+			minor: version.minor,
+			patch: version.patch,
+		}
+	}
+}
+
+impl From<CarFirmwareVersion> for FirmwareVersion {
+	fn from(version: CarFirmwareVersion) -> Self {
+		Self {
+			major: version.major,
+			minor: version.minor,
+			patch: version.patch,
+		}
+	}
+}
+
+/// Value of a tunable
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CarParamValue {
+	/// Type of the value, see `CAR_PARAM_*`
+	pub kind: u8,
+	/// The value, 0 or 1 for a switch and thousandths for `CAR_PARAM_MILLI`
+	pub value: i32,
+}
+
+/// A tunable with its value
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CarParam {
+	/// Id of the tunable
+	pub id: u16,
+	/// Its value
+	pub value: CarParamValue,
+}
+
+impl From<Param> for CarParam {
+	fn from(param: Param) -> Self {
+		let (kind, value) = match param.value {
+			ParamValue::Bool(value) => (CAR_PARAM_BOOL, value.into()),
+			ParamValue::Integer(value) => (CAR_PARAM_INTEGER, value),
+			ParamValue::Milli(value) => (CAR_PARAM_MILLI, value),
+		};
+
+		Self {
+			id: param.id.0,
+			value: CarParamValue { kind, value },
+		}
+	}
+}
+
+impl TryFrom<CarParam> for Param {
+	type Error = TransportError;
+
+	fn try_from(param: CarParam) -> Result<Self, Self::Error> {
+		let CarParamValue { kind, value } = param.value;
+
+		Ok(Self {
+			id: ParamId(param.id),
+			value: match kind {
+				CAR_PARAM_BOOL => ParamValue::Bool(flag(
+					u8::try_from(value).map_err(|_| TransportError::InvalidPayload)?,
+				)?),
+				CAR_PARAM_INTEGER => ParamValue::Integer(value),
+				CAR_PARAM_MILLI => ParamValue::Milli(value),
+				_ => return Err(TransportError::InvalidId),
+			},
+		})
+	}
+}
+
+/// A chunk of firmware image
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CarChunk {
+	/// Number of bytes used in `bytes`
+	pub length: u8,
+	/// Bytes of the chunk
+	pub bytes: [u8; CAR_CHUNK_SIZE],
+}
+
+impl From<&Chunk> for CarChunk {
+	fn from(chunk: &Chunk) -> Self {
+		let mut bytes = [0; CAR_CHUNK_SIZE];
+		bytes[..chunk.0.len()].copy_from_slice(&chunk.0);
+
+		Self {
+			// Cannot truncate, chunks hold at most `CAR_CHUNK_SIZE` bytes
+			#[allow(clippy::cast_possible_truncation)]
+			length: chunk.0.len() as u8,
+			bytes,
+		}
+	}
+}
+
+impl TryFrom<&CarChunk> for Chunk {
+	type Error = TransportError;
+
+	fn try_from(chunk: &CarChunk) -> Result<Self, Self::Error> {
+		let bytes = chunk
+			.bytes
+			.get(..usize::from(chunk.length))
+			.ok_or(TransportError::OutOfRange)?;
+
+		heapless::Vec::from_slice(bytes)
+			.map(Self)
+			.map_err(|_| TransportError::OutOfRange)
+	}
+}
+
+/// Returns the id of a field enum, such as an `ErrorCode`
+fn field_id<T: Field>(value: &T) -> u8 {
+	let mut id = [0];
+	value.encode(&mut Writer::new(&mut id));
+
+	id[0]
+}
+
+/// Returns the value of a `has_` flag, C code may write any byte in it
+const fn flag(value: u8) -> Result<bool, TransportError> {
+	match value {
+		0 => Ok(false),
+		1 => Ok(true),
+		_ => Err(TransportError::InvalidPayload),
+	}
+}
+
+/// Returns the field enum with the id, such as an `ErrorCode`
+fn from_field_id<T: Field>(id: u8) -> Result<T, TransportError> {
+	T::decode(&mut Reader::new(&[id]))
+}
+
+/// Encodes the message into a frame, delimiter included
+///
+/// The length of the frame is stored in `length`, a buffer of [`CAR_MAX_FRAME_SIZE`] bytes always
+/// fits.
+///
+/// The tag of the message is checked to be below [`CAR_MESSAGE_TAGS`] and its `has_` flags to be 0 or 1, before
+/// the message is read.
+///
+/// # Safety
+/// `envelope` and `length` must be valid pointers, and `frame` must be valid for `capacity` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn car_encode_message(
+	envelope: *const CarMessageEnvelope,
+	frame: *mut u8,
+	capacity: usize,
+	length: *mut usize,
+) -> CarStatus {
+	if envelope.is_null() {
+		return CarStatus::NullPointer;
+	}
+
+	// SAFETY: guaranteed by the caller, and checked not to be null
+	if unsafe { tag(&raw const (*envelope).message) } >= CAR_MESSAGE_TAGS {
+		return CarStatus::InvalidId;
+	}
+
+	// SAFETY: guaranteed by the caller, and the tag was checked, every other byte pattern is valid
+	let envelope = unsafe { &*envelope };
+
+	// SAFETY: guaranteed by the caller
+	unsafe { encode(Envelope::try_from(envelope), frame, capacity, length) }
+}
+
+/// Decodes a frame into a message
+///
+/// `frame` holds the bytes received up to the first frame delimiter, included.
+///
+/// # Safety
+/// `envelope` must be a valid pointer, and `frame` must be valid for `length` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn car_decode_message(
+	frame: *const u8,
+	length: usize,
+	envelope: *mut CarMessageEnvelope,
+) -> CarStatus {
+	// SAFETY: guaranteed by the caller
+	match unsafe { decode(frame, length) } {
+		Ok(decoded) if !envelope.is_null() => {
+			// SAFETY: guaranteed by the caller, and checked not to be null
+			unsafe { envelope.write(CarMessageEnvelope::from(&decoded)) };
+			CarStatus::Ok
+		}
+		Ok(_) => CarStatus::NullPointer,
+		Err(status) => status,
+	}
+}
+
+/// Encodes the answer into a frame, delimiter included
+///
+/// The length of the frame is stored in `length`, a buffer of [`CAR_MAX_FRAME_SIZE`] bytes always
+/// fits.
+///
+/// The tag of the answer is checked to be below [`CAR_ANSWER_TAGS`] and its `has_` flags to be 0 or 1, before
+/// the answer is read.
+///
+/// # Safety
+/// `envelope` and `length` must be valid pointers, and `frame` must be valid for `capacity` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn car_encode_answer(
+	envelope: *const CarAnswerEnvelope,
+	frame: *mut u8,
+	capacity: usize,
+	length: *mut usize,
+) -> CarStatus {
+	if envelope.is_null() {
+		return CarStatus::NullPointer;
+	}
+
+	// SAFETY: guaranteed by the caller, and checked not to be null
+	if unsafe { tag(&raw const (*envelope).answer) } >= CAR_ANSWER_TAGS {
+		return CarStatus::InvalidId;
+	}
+
+	// SAFETY: guaranteed by the caller, and the tag was checked, every other byte pattern is valid
+	let envelope = unsafe { &*envelope };
+
+	// SAFETY: guaranteed by the caller
+	unsafe { encode(Envelope::try_from(envelope), frame, capacity, length) }
+}
+
+/// Decodes a frame into an answer
+///
+/// `frame` holds the bytes received up to the first frame delimiter, included.
+///
+/// # Safety
+/// `envelope` must be a valid pointer, and `frame` must be valid for `length` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn car_decode_answer(
+	frame: *const u8,
+	length: usize,
+	envelope: *mut CarAnswerEnvelope,
+) -> CarStatus {
+	// SAFETY: guaranteed by the caller
+	match unsafe { decode(frame, length) } {
+		Ok(decoded) if !envelope.is_null() => {
+			// SAFETY: guaranteed by the caller, and checked not to be null
+			unsafe { envelope.write(CarAnswerEnvelope::from(&decoded)) };
+			CarStatus::Ok
+		}
+		Ok(_) => CarStatus::NullPointer,
+		Err(status) => status,
+	}
+}
+
+/// Returns the tag of a `#[repr(C, u8)]` enum, read as a byte so that C code may have written any
+/// value in it
+///
+/// # Safety
+/// `value` must be a valid pointer.
+const unsafe fn tag<T>(value: *const T) -> u8 {
+	// SAFETY: guaranteed by the caller, the tag is the first byte of such enums
+	unsafe { value.cast::<u8>().read() }
+}
+
+/// Encodes the converted envelope into `frame` and stores the length of the frame
+///
+/// # Safety
+/// `length` must be a valid pointer, and `frame` must be valid for `capacity` bytes.
+unsafe fn encode<T: Transport>(
+	envelope: Result<Envelope<T>, TransportError>,
+	frame: *mut u8,
+	capacity: usize,
+	length: *mut usize,
+) -> CarStatus {
+	if frame.is_null() || length.is_null() {
+		return CarStatus::NullPointer;
+	}
+
+	let envelope = match envelope {
+		Ok(envelope) => envelope,
+		Err(error) => return error.into(),
+	};
+
+	// SAFETY: guaranteed by the caller, and checked not to be null
+	let frame = unsafe { slice::from_raw_parts_mut(frame, capacity) };
+	match FrameEncoder::<DEFAULT_FRAME_BUFFER_SIZE>::new().encode(&envelope, frame) {
+		Ok(written) => {
+			// SAFETY: guaranteed by the caller, and checked not to be null
+			unsafe { length.write(written) };
+			CarStatus::Ok
+		}
+		Err(error) => error.into(),
+	}
+}
+
+/// Decodes the first frame of the bytes
+///
+/// # Safety
+/// `frame` must be valid for `length` bytes.
+unsafe fn decode<T: Transport>(frame: *const u8, length: usize) -> Result<Envelope<T>, CarStatus> {
+	if frame.is_null() {
+		return Err(CarStatus::NullPointer);
+	}
+
+	// SAFETY: guaranteed by the caller, and checked not to be null
+	let frame = unsafe { slice::from_raw_parts(frame, length) };
+	let mut decoder = FrameDecoder::<Envelope<T>>::new();
+	let mut envelopes = decoder.feed(frame);
+
+	match envelopes.next() {
+		Some(Ok(envelope)) => Ok(envelope),
+		Some(Err(error)) => Err(error.into()),
+		None => Err(CarStatus::Incomplete),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use core::mem::MaybeUninit;
+
+	use car_transport::{ErrorCode, LeaseToken, LogLevel, LogText, Sequence, Throttle};
+
+	use super::*;
+
+	#[test]
+	fn messages_cross_the_c_abi() {
+		let message = CarMessageEnvelope {
+			sequence: 7,
+			message: CarMessage::SetSpeed {
+				lease: 0x1234,
+				throttle: -50,
+			},
+		};
+
+		let mut frame = [0_u8; CAR_MAX_FRAME_SIZE];
+		let mut length = 0;
+		// SAFETY: the pointers come from references
+		let status = unsafe {
+			car_encode_message(
+				&raw const message,
+				frame.as_mut_ptr(),
+				frame.len(),
+				&raw mut length,
+			)
+		};
+		assert_eq!(status, CarStatus::Ok);
+
+		// The frame is the one written by `car-transport`
+		let expected = Envelope::new(
+			Sequence(7),
+			Message::SetSpeed {
+				lease: LeaseToken(0x1234),
+				throttle: Throttle::saturating(-50),
+			},
+		);
+		let mut reference = [0_u8; CAR_MAX_FRAME_SIZE];
+		let reference_length = FrameEncoder::<DEFAULT_FRAME_BUFFER_SIZE>::new()
+			.encode(&expected, &mut reference)
+			.expect("frame fits");
+		assert_eq!(frame[..length], reference[..reference_length]);
+
+		let mut decoded = CarMessageEnvelope {
+			sequence: 0,
+			message: CarMessage::Ping,
+		};
+		// SAFETY: the pointers come from references
+		let status = unsafe { car_decode_message(frame.as_ptr(), length, &raw mut decoded) };
+		assert_eq!(status, CarStatus::Ok);
+		assert_eq!(decoded, message);
+
+		// Values outside of their range are refused before encoding
+		let too_fast = CarMessageEnvelope {
+			sequence: 8,
+			message: CarMessage::SetSpeed {
+				lease: 0x1234,
+				throttle: 101,
+			},
+		};
+		// SAFETY: the pointers come from references
+		let status = unsafe {
+			car_encode_message(
+				&raw const too_fast,
+				frame.as_mut_ptr(),
+				frame.len(),
+				&raw mut length,
+			)
+		};
+		assert_eq!(status, CarStatus::OutOfRange);
+	}
+
+	#[test]
+	fn invalid_tags_and_flags_are_refused() {
+		let mut frame = [0_u8; CAR_MAX_FRAME_SIZE];
+		let mut length = 0;
+
+		// Kept uninitialized for Rust, as C code writes the invalid tags
+		let mut message = MaybeUninit::new(CarMessageEnvelope {
+			sequence: 1,
+			message: CarMessage::CommitUpdate { lease: 1 },
+		});
+		// The last variant has the last valid tag
+		// SAFETY: the pointer comes from a reference
+		assert_eq!(
+			unsafe { tag(&raw const (*message.as_ptr()).message) },
+			CAR_MESSAGE_TAGS - 1
+		);
+
+		// SAFETY: the pointer comes from a reference, the tag is written as a byte
+		unsafe {
+			(&raw mut (*message.as_mut_ptr()).message)
+				.cast::<u8>()
+				.write(CAR_MESSAGE_TAGS);
+		}
+		// SAFETY: the pointers come from references
+		let status = unsafe {
+			car_encode_message(
+				message.as_ptr(),
+				frame.as_mut_ptr(),
+				frame.len(),
+				&raw mut length,
+			)
+		};
+		assert_eq!(status, CarStatus::InvalidId);
+
+		let renew = CarMessageEnvelope {
+			sequence: 2,
+			message: CarMessage::AcquireControl {
+				has_renew: 2,
+				renew: 1,
+				duration_ms: 1000,
+			},
+		};
+		// SAFETY: the pointers come from references
+		let status = unsafe {
+			car_encode_message(
+				&raw const renew,
+				frame.as_mut_ptr(),
+				frame.len(),
+				&raw mut length,
+			)
+		};
+		assert_eq!(status, CarStatus::InvalidPayload);
+
+		let mut answer = MaybeUninit::new(CarAnswerEnvelope {
+			sequence: 3,
+			answer: CarAnswer::Param {
+				param: CarParam {
+					id: 1,
+					value: CarParamValue {
+						kind: CAR_PARAM_BOOL,
+						value: 2,
+					},
+				},
+			},
+		});
+		// SAFETY: the pointers come from references
+		let status = unsafe {
+			car_encode_answer(
+				answer.as_ptr(),
+				frame.as_mut_ptr(),
+				frame.len(),
+				&raw mut length,
+			)
+		};
+		assert_eq!(status, CarStatus::InvalidPayload);
+
+		// SAFETY: the pointer comes from a reference, the tag is written as a byte
+		unsafe {
+			(&raw mut (*answer.as_mut_ptr()).answer)
+				.cast::<u8>()
+				.write(u8::MAX);
+		}
+		// SAFETY: the pointers come from references
+		let status = unsafe {
+			car_encode_answer(
+				answer.as_ptr(),
+				frame.as_mut_ptr(),
+				frame.len(),
+				&raw mut length,
+			)
+		};
+		assert_eq!(status, CarStatus::InvalidId);
+	}
+
+	#[test]
+	fn answers_cross_the_c_abi() {
+		let answers = [
+			Answer::Log {
+				level: LogLevel::Warn,
+				dropped: 2,
+				text: LogText::truncated("battery low"),
+			},
+			Answer::Nack {
+				for_id: 100,
+				reason: ErrorCode::NotLeaseHolder,
+			},
+		];
+
+		for answer in answers {
+			let envelope = Envelope::new(Sequence(3), answer);
+			let mirror = CarAnswerEnvelope::from(&envelope);
+
+			let mut frame = [0_u8; CAR_MAX_FRAME_SIZE];
+			let mut length = 0;
+			// SAFETY: the pointers come from references
+			let status = unsafe {
+				car_encode_answer(
+					&raw const mirror,
+					frame.as_mut_ptr(),
+					frame.len(),
+					&raw mut length,
+				)
+			};
+			assert_eq!(status, CarStatus::Ok);
+
+			let mut decoded = CarAnswerEnvelope {
+				sequence: 0,
+				answer: CarAnswer::Pong,
+			};
+			// SAFETY: the pointers come from references
+			let status = unsafe { car_decode_answer(frame.as_ptr(), length, &raw mut decoded) };
+			assert_eq!(status, CarStatus::Ok);
+			assert_eq!(Envelope::try_from(&decoded), Ok(envelope));
+		}
+
+		let mut decoded = CarAnswerEnvelope {
+			sequence: 0,
+			answer: CarAnswer::Pong,
+		};
+		// SAFETY: the pointers come from references
+		let status = unsafe { car_decode_answer([0x02, 0x01].as_ptr(), 2, &raw mut decoded) };
+		assert_eq!(status, CarStatus::Incomplete);
+		// SAFETY: null pointers are checked
+		let status = unsafe { car_decode_answer(core::ptr::null(), 0, &raw mut decoded) };
+		assert_eq!(status, CarStatus::NullPointer);
+	}
+}
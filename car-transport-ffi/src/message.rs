@@ -0,0 +1,314 @@
+//! C mirror of the messages sent by the controller
+
+use car_transport::{
+	Chunk, Envelope, ImageHash, LeaseToken, Message, Nonce, ParamId, ProtocolVersion, Sequence,
+	Tag, Timestamp, Topics, TransportError,
+};
+
+use crate::{
+	CAR_HASH_SIZE, CAR_NONCE_SIZE, CAR_TAG_SIZE, CarChunk, CarParam, CarVersion, field_id, flag,
+	from_field_id,
+};
+
+/// A message with its sequence number
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CarMessageEnvelope {
+	/// Sequence number, answers echo it
+	pub sequence: u8,
+	/// The message
+	pub message: CarMessage,
+}
+
+/// A message sent by the controller to the car, see `Message` in the protocol reference
+///
+/// Commands carry the token of the lease granted by `CarMessage_AcquireControl`.
+#[repr(C, u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CarMessage {
+	/// Check that the car is reachable
+	Ping,
+	/// Open the session with the protocol revision of the controller
+	Hello {
+		/// The revision spoken by the controller
+		version: CarVersion,
+	},
+	/// Ask for the current speed
+	GetSpeed,
+	/// Ask for the current direction
+	GetDirection,
+	/// Ask for the battery voltage
+	GetBatteryLevel,
+	/// Ask for the distance to the closest obstacle
+	GetUltrasonicDistance,
+	/// Ask for the value of a tunable
+	GetParam {
+		/// Id of the tunable
+		id: u16,
+	},
+	/// Ask for the tunable at a position, to list them all
+	ListParams {
+		/// Position of the tunable
+		index: u8,
+	},
+	/// Ask for periodic telemetry snapshots
+	Subscribe {
+		/// Bits of the values to send, see `CAR_TOPIC_*`
+		topics: u8,
+		/// Time between two snapshots, in milliseconds
+		period_ms: u16,
+	},
+	/// Stop the telemetry snapshots
+	Unsubscribe,
+	/// Ask for the latched fault
+	GetFault,
+	/// Start the authentication with a random nonce
+	AuthChallenge {
+		/// Random bytes of the controller
+		nonce: [u8; CAR_NONCE_SIZE],
+	},
+	/// Prove the knowledge of the pre-shared key
+	Authenticate {
+		/// Tag of the nonce of the car
+		proof: [u8; CAR_TAG_SIZE],
+	},
+	/// Forward the log records from a level, or stop them
+	SetLogLevel {
+		/// Whether records are forwarded, 1 or 0
+		has_level: u8,
+		/// Lowest level forwarded, a `LogLevel` id
+		level: u8,
+	},
+	/// Sample the car clock
+	TimeSync {
+		/// Time of the controller clock, in microseconds
+		origin_us: u64,
+	},
+	/// Set the current speed
+	SetSpeed {
+		/// Token of the lease
+		lease: u16,
+		/// Percentage between -100 and 100
+		throttle: i8,
+	},
+	/// Set the current direction
+	SetDirection {
+		/// Token of the lease
+		lease: u16,
+		/// Percentage between -100 and 100
+		steering: i8,
+	},
+	/// Change a tunable
+	SetParam {
+		/// Token of the lease
+		lease: u16,
+		/// The tunable and its new value
+		param: CarParam,
+	},
+	/// Stop the car and latch a fault
+	EmergencyStop,
+	/// Clear the latched fault
+	ClearFault {
+		/// Token of the lease
+		lease: u16,
+	},
+	/// Take or renew the lease on the car
+	AcquireControl {
+		/// Whether the lease is renewed, 1 or 0
+		has_renew: u8,
+		/// Token of the renewed lease
+		renew: u16,
+		/// Duration of the lease, in milliseconds
+		duration_ms: u16,
+	},
+	/// Give the lease back
+	ReleaseControl {
+		/// Token of the lease
+		lease: u16,
+	},
+	/// Start a firmware update
+	BeginUpdate {
+		/// Token of the lease
+		lease: u16,
+		/// Size of the image, in bytes
+		size: u32,
+		/// SHA-256 hash of the image
+		hash: [u8; CAR_HASH_SIZE],
+	},
+	/// Write a chunk of the firmware image
+	WriteUpdate {
+		/// Token of the lease
+		lease: u16,
+		/// Position of the chunk in the image
+		offset: u32,
+		/// Bytes of the chunk
+		chunk: CarChunk,
+	},
+	/// Check the hash of the written image
+	VerifyUpdate {
+		/// Token of the lease
+		lease: u16,
+	},
+	/// Boot the verified image
+	CommitUpdate {
+		/// Token of the lease
+		lease: u16,
+	},
+}
+
+impl From<&Envelope<Message>> for CarMessageEnvelope {
+	fn from(envelope: &Envelope<Message>) -> Self {
+		let message = match &envelope.content {
+			Message::Ping => CarMessage::Ping,
+			Message::Hello { version } => CarMessage::Hello {
+				version: (*version).into(),
+			},
+			Message::GetSpeed => CarMessage::GetSpeed,
+			Message::GetDirection => CarMessage::GetDirection,
+			Message::GetBatteryLevel => CarMessage::GetBatteryLevel,
+			Message::GetUltrasonicDistance => CarMessage::GetUltrasonicDistance,
+			Message::GetParam(id) => CarMessage::GetParam { id: id.0 },
+			Message::ListParams { index } => CarMessage::ListParams { index: *index },
+			Message::Subscribe { topics, period_ms } => CarMessage::Subscribe {
+				topics: topics.0,
+				period_ms: *period_ms,
+			},
+			Message::Unsubscribe => CarMessage::Unsubscribe,
+			Message::GetFault => CarMessage::GetFault,
+			Message::AuthChallenge { nonce } => CarMessage::AuthChallenge { nonce: nonce.0 },
+			Message::Authenticate { proof } => CarMessage::Authenticate { proof: proof.0 },
+			Message::SetLogLevel { level } => CarMessage::SetLogLevel {
+				has_level: u8::from(level.is_some()),
+				level: level.as_ref().map_or(0, field_id),
+			},
+			Message::TimeSync { origin } => CarMessage::TimeSync {
+				origin_us: origin.0,
+			},
+			Message::SetSpeed { lease, throttle } => CarMessage::SetSpeed {
+				lease: lease.0,
+				throttle: throttle.percent(),
+			},
+			Message::SetDirection { lease, steering } => CarMessage::SetDirection {
+				lease: lease.0,
+				steering: steering.percent(),
+			},
+			Message::SetParam { lease, param } => CarMessage::SetParam {
+				lease: lease.0,
+				param: (*param).into(),
+			},
+			Message::EmergencyStop => CarMessage::EmergencyStop,
+			Message::ClearFault { lease } => CarMessage::ClearFault { lease: lease.0 },
+			Message::AcquireControl { renew, duration_ms } => CarMessage::AcquireControl {
+				has_renew: u8::from(renew.is_some()),
+				renew: renew.unwrap_or_default().0,
+				duration_ms: *duration_ms,
+			},
+			Message::ReleaseControl { lease } => CarMessage::ReleaseControl { lease: lease.0 },
+			Message::BeginUpdate { lease, size, hash } => CarMessage::BeginUpdate {
+				lease: lease.0,
+				size: *size,
+				hash: hash.0,
+			},
+			Message::WriteUpdate {
+				lease,
+				offset,
+				chunk,
+			} => CarMessage::WriteUpdate {
+				lease: lease.0,
+				offset: *offset,
+				chunk: chunk.into(),
+			},
+			Message::VerifyUpdate { lease } => CarMessage::VerifyUpdate { lease: lease.0 },
+			Message::CommitUpdate { lease } => CarMessage::CommitUpdate { lease: lease.0 },
+		};
+
+		Self {
+			sequence: envelope.sequence.0,
+			message,
+		}
+	}
+}
+
+impl TryFrom<&CarMessageEnvelope> for Envelope<Message> {
+	type Error = TransportError;
+
+	fn try_from(envelope: &CarMessageEnvelope) -> Result<Self, Self::Error> {
+		let message = match envelope.message {
+			CarMessage::Ping => Message::Ping,
+			CarMessage::Hello { version } => Message::Hello {
+				version: ProtocolVersion::from(version),
+			},
+			CarMessage::GetSpeed => Message::GetSpeed,
+			CarMessage::GetDirection => Message::GetDirection,
+			CarMessage::GetBatteryLevel => Message::GetBatteryLevel,
+			CarMessage::GetUltrasonicDistance => Message::GetUltrasonicDistance,
+			CarMessage::GetParam { id } => Message::GetParam(ParamId(id)),
+			CarMessage::ListParams { index } => Message::ListParams { index },
+			CarMessage::Subscribe { topics, period_ms } => Message::Subscribe {
+				topics: Topics(topics),
+				period_ms,
+			},
+			CarMessage::Unsubscribe => Message::Unsubscribe,
+			CarMessage::GetFault => Message::GetFault,
+			CarMessage::AuthChallenge { nonce } => Message::AuthChallenge {
+				nonce: Nonce(nonce),
+			},
+			CarMessage::Authenticate { proof } => Message::Authenticate { proof: Tag(proof) },
+			CarMessage::SetLogLevel { has_level, level } => Message::SetLogLevel {
+				level: flag(has_level)?.then(|| from_field_id(level)).transpose()?,
+			},
+			CarMessage::TimeSync { origin_us } => Message::TimeSync {
+				origin: Timestamp(origin_us),
+			},
+			CarMessage::SetSpeed { lease, throttle } => Message::SetSpeed {
+				lease: LeaseToken(lease),
+				throttle: throttle.try_into()?,
+			},
+			CarMessage::SetDirection { lease, steering } => Message::SetDirection {
+				lease: LeaseToken(lease),
+				steering: steering.try_into()?,
+			},
+			CarMessage::SetParam { lease, param } => Message::SetParam {
+				lease: LeaseToken(lease),
+				param: param.try_into()?,
+			},
+			CarMessage::EmergencyStop => Message::EmergencyStop,
+			CarMessage::ClearFault { lease } => Message::ClearFault {
+				lease: LeaseToken(lease),
+			},
+			CarMessage::AcquireControl {
+				has_renew,
+				renew,
+				duration_ms,
+			} => Message::AcquireControl {
+				renew: flag(has_renew)?.then_some(LeaseToken(renew)),
+				duration_ms,
+			},
+			CarMessage::ReleaseControl { lease } => Message::ReleaseControl {
+				lease: LeaseToken(lease),
+			},
+			CarMessage::BeginUpdate { lease, size, hash } => Message::BeginUpdate {
+				lease: LeaseToken(lease),
+				size,
+				hash: ImageHash(hash),
+			},
+			CarMessage::WriteUpdate {
+				lease,
+				offset,
+				chunk,
+			} => Message::WriteUpdate {
+				lease: LeaseToken(lease),
+				offset,
+				chunk: Chunk::try_from(&chunk)?,
+			},
+			CarMessage::VerifyUpdate { lease } => Message::VerifyUpdate {
+				lease: LeaseToken(lease),
+			},
+			CarMessage::CommitUpdate { lease } => Message::CommitUpdate {
+				lease: LeaseToken(lease),
+			},
+		};
+
+		Ok(Self::new(Sequence(envelope.sequence), message))
+	}
+}
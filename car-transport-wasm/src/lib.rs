@@ -0,0 +1,304 @@
+//! WebAssembly bindings of the car transport protocol.
+//!
+//! Built with `wasm-pack build --target web`, the crate lets a browser encode messages into frames
+//! and decode the answers of the car, with the same code as the firmware. Envelopes cross the
+//! boundary as JSON, in the shape of the `serde` representation of `car-transport`, so JavaScript
+//! only has to `JSON.stringify` and `JSON.parse` them:
+//! ```js
+//! import init, { AnswerDecoder, encodeMessage } from "./pkg/car_transport_wasm.js";
+//!
+//! await init();
+//! const frame = encodeMessage(JSON.stringify({ sequence: 1, content: "Ping" }));
+//! const decoder = new AnswerDecoder();
+//! for (const answer of decoder.feed(received)) {
+//!     console.log(JSON.parse(answer).content);
+//! }
+//! ```
+//!
+//! The `www` folder holds a controller page driving the car over Web Bluetooth. Frames are never
+//! sealed.
+
+use core::fmt;
+
+use car_transport::{
+	Answer, Envelope, FrameDecoder, FrameEncoder, Message, PROTOCOL_VERSION, Transport,
+	TransportError,
+	frame::{DEFAULT_FRAME_BUFFER_SIZE, max_frame_size},
+	handshake::{self, VersionMismatch},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use wasm_bindgen::prelude::*;
+
+/// Encodes the JSON of a message envelope into a frame
+///
+/// # Errors
+/// In case the JSON is not a valid message envelope
+#[wasm_bindgen(js_name = encodeMessage)]
+pub fn encode_message(envelope: &str) -> Result<Vec<u8>, JsError> {
+	Ok(encode::<Message>(envelope)?)
+}
+
+/// Decodes the first frame of the bytes into the JSON of a message envelope
+///
+/// # Errors
+/// In case the bytes do not hold a complete and valid message frame
+#[wasm_bindgen(js_name = decodeMessage)]
+pub fn decode_message(frame: &[u8]) -> Result<String, JsError> {
+	Ok(decode::<Message>(frame)?)
+}
+
+/// Encodes the JSON of an answer envelope into a frame
+///
+/// # Errors
+/// In case the JSON is not a valid answer envelope
+#[wasm_bindgen(js_name = encodeAnswer)]
+pub fn encode_answer(envelope: &str) -> Result<Vec<u8>, JsError> {
+	Ok(encode::<Answer>(envelope)?)
+}
+
+/// Decodes the first frame of the bytes into the JSON of an answer envelope
+///
+/// # Errors
+/// In case the bytes do not hold a complete and valid answer frame
+#[wasm_bindgen(js_name = decodeAnswer)]
+pub fn decode_answer(frame: &[u8]) -> Result<String, JsError> {
+	Ok(decode::<Answer>(frame)?)
+}
+
+/// Returns the JSON of the protocol revision spoken by the bindings, for `Hello`
+#[wasm_bindgen(js_name = protocolVersion)]
+#[must_use]
+pub fn protocol_version() -> String {
+	serde_json::to_string(&PROTOCOL_VERSION).unwrap_or_default()
+}
+
+/// Decodes the answers of the car from the bytes received in arbitrary chunks, such as Bluetooth
+/// notifications
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct AnswerDecoder {
+	/// Gathers the bytes of the current frame
+	decoder: FrameDecoder<Envelope<Answer>>,
+	/// Number of frames that could not be decoded
+	dropped: u32,
+}
+
+#[wasm_bindgen]
+impl AnswerDecoder {
+	/// Creates a new decoder
+	#[wasm_bindgen(constructor)]
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns the JSON of the answer envelopes completed by the chunk
+	///
+	/// Corrupted frames are skipped and counted in [`AnswerDecoder::dropped`].
+	pub fn feed(&mut self, chunk: &[u8]) -> Vec<String> {
+		let mut answers = Vec::new();
+		for envelope in self.decoder.feed(chunk) {
+			match envelope
+				.map_err(Error::from)
+				.and_then(|envelope| Ok(serde_json::to_string(&envelope)?))
+			{
+				Ok(json) => answers.push(json),
+				Err(_) => self.dropped = self.dropped.saturating_add(1),
+			}
+		}
+
+		answers
+	}
+
+	/// Returns the number of frames that could not be decoded
+	#[wasm_bindgen(getter)]
+	#[must_use]
+	// `wasm_bindgen` refuses `const` functions
+	#[allow(clippy::missing_const_for_fn)]
+	pub fn dropped(&self) -> u32 {
+		self.dropped
+	}
+}
+
+/// What the page and the car agreed on during the handshake, to leave out the messages the car
+/// cannot handle
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct Session(handshake::Session);
+
+#[wasm_bindgen]
+impl Session {
+	/// Negotiates the session from the JSON of the answer of the car to `Hello`
+	///
+	/// # Errors
+	/// In case the JSON is not a `Hello` answer, or the car speaks another major revision: the
+	/// bindings only encode frames of the current one
+	#[wasm_bindgen(constructor)]
+	pub fn new(hello: &str) -> Result<Self, JsError> {
+		Ok(Self(negotiate(hello)?))
+	}
+
+	/// Returns whether the car knows the message of the JSON and has the components it needs
+	///
+	/// # Errors
+	/// In case the JSON is not a valid message
+	pub fn supports(&self, message: &str) -> Result<bool, JsError> {
+		let message = serde_json::from_str::<Message>(message).map_err(Error::from)?;
+
+		Ok(self.0.supports(&message))
+	}
+}
+
+/// Negotiates a session of the current major revision from the JSON of a `Hello` answer
+fn negotiate(hello: &str) -> Result<handshake::Session, Error> {
+	let Answer::Hello {
+		version,
+		firmware,
+		capabilities,
+	} = serde_json::from_str(hello)?
+	else {
+		return Err(Error::NotHello);
+	};
+
+	let session = handshake::negotiate(version, firmware, capabilities).map_err(Error::Version)?;
+	if session.is_compat() {
+		return Err(Error::Version(VersionMismatch {
+			local: PROTOCOL_VERSION,
+			remote: version,
+		}));
+	}
+
+	Ok(session)
+}
+
+/// Encodes the JSON of an envelope into a frame
+fn encode<T: Transport + DeserializeOwned>(json: &str) -> Result<Vec<u8>, Error> {
+	let envelope = serde_json::from_str::<Envelope<T>>(json)?;
+
+	let mut frame = vec![0; max_frame_size(DEFAULT_FRAME_BUFFER_SIZE)];
+	let length = FrameEncoder::<DEFAULT_FRAME_BUFFER_SIZE>::new().encode(&envelope, &mut frame)?;
+	frame.truncate(length);
+
+	Ok(frame)
+}
+
+/// Decodes the first frame of the bytes into the JSON of its envelope
+fn decode<T: Transport + Serialize>(frame: &[u8]) -> Result<String, Error> {
+	let mut decoder = FrameDecoder::<Envelope<T>>::new();
+	let envelope = decoder.feed(frame).next().ok_or(Error::Incomplete)??;
+
+	Ok(serde_json::to_string(&envelope)?)
+}
+
+/// Reasons why an envelope cannot cross the boundary, given to JavaScript as the message of the
+/// thrown error
+#[derive(Debug)]
+enum Error {
+	/// The JSON does not describe an envelope
+	Json(serde_json::Error),
+	/// The envelope could not be encoded or decoded
+	Transport(TransportError),
+	/// The bytes end before the frame delimiter
+	Incomplete,
+	/// The answer given to negotiate a session is not `Hello`
+	NotHello,
+	/// The car speaks a revision the bindings cannot encode
+	Version(VersionMismatch),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Json(error) => write!(f, "invalid envelope: {error}"),
+			Self::Transport(error) => error.fmt(f),
+			Self::Incomplete => f.write_str("incomplete frame"),
+			Self::NotHello => f.write_str("the answer is not Hello"),
+			Self::Version(error) => error.fmt(f),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+impl From<serde_json::Error> for Error {
+	fn from(error: serde_json::Error) -> Self {
+		Self::Json(error)
+	}
+}
+
+impl From<TransportError> for Error {
+	fn from(error: TransportError) -> Self {
+		Self::Transport(error)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn envelopes_cross_as_json() {
+		let json = r#"{"sequence":7,"content":{"SetSpeed":{"lease":4660,"throttle":-50}}}"#;
+		let frame = encode::<Message>(json).expect("the envelope is valid");
+
+		assert_eq!(frame.last(), Some(&0));
+		assert_eq!(decode::<Message>(&frame).expect("the frame is valid"), json);
+	}
+
+	#[test]
+	fn invalid_envelopes_are_refused() {
+		let out_of_range = r#"{"sequence":1,"content":{"SetSpeed":{"lease":1,"throttle":101}}}"#;
+		assert!(matches!(
+			encode::<Message>(out_of_range),
+			Err(Error::Json(_))
+		));
+
+		let frame = encode::<Answer>(r#"{"sequence":1,"content":"Pong"}"#).expect("Pong is valid");
+		assert!(matches!(
+			decode::<Answer>(&frame[..frame.len() - 1]),
+			Err(Error::Incomplete)
+		));
+	}
+
+	#[test]
+	fn answers_are_decoded_across_chunks() {
+		let mut stream = encode::<Answer>(r#"{"sequence":1,"content":"Pong"}"#).expect("valid");
+		stream.extend([0x03, 0xFF, 0xFF, 0x00]);
+		stream.extend(encode::<Answer>(r#"{"sequence":2,"content":"AckSpeed"}"#).expect("valid"));
+
+		let mut decoder = AnswerDecoder::new();
+		let (first, second) = stream.split_at(3);
+		let mut answers = decoder.feed(first);
+		answers.extend(decoder.feed(second));
+
+		assert_eq!(
+			answers,
+			[
+				r#"{"sequence":1,"content":"Pong"}"#,
+				r#"{"sequence":2,"content":"AckSpeed"}"#
+			]
+		);
+		assert_eq!(decoder.dropped(), 1);
+	}
+
+	#[test]
+	fn sessions_leave_out_missing_components() {
+		let hello = r#"{"Hello":{"version":{"major":4,"minor":4},"firmware":{"major":0,"minor":1,"patch":0},"capabilities":1}}"#;
+		let session = Session::new(hello).expect("the car speaks the current revision");
+
+		assert!(
+			session
+				.supports(r#"{"SetSpeed":{"lease":1,"throttle":50}}"#)
+				.expect("valid")
+		);
+		assert!(
+			!session
+				.supports(r#"{"SetDirection":{"lease":1,"steering":50}}"#)
+				.expect("valid")
+		);
+
+		let older = r#"{"Hello":{"version":{"major":3,"minor":1},"firmware":{"major":0,"minor":1,"patch":0},"capabilities":15}}"#;
+		assert!(matches!(negotiate(older), Err(Error::Version(_))));
+		assert!(matches!(negotiate(r#""Pong""#), Err(Error::NotHello)));
+	}
+}
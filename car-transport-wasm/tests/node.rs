@@ -0,0 +1,49 @@
+//! Runs the exported functions headless under Node, with `wasm-pack test --node`
+
+#![cfg(target_arch = "wasm32")]
+
+use car_transport_wasm::{
+	AnswerDecoder, decode_answer, decode_message, encode_answer, encode_message, protocol_version,
+};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn messages_are_encoded_into_frames() {
+	let json = r#"{"sequence":1,"content":{"SetDirection":{"lease":4660,"steering":25}}}"#;
+	let frame = encode_message(json).expect("the envelope is valid");
+
+	assert_eq!(frame.last(), Some(&0));
+	assert_eq!(decode_message(&frame).expect("the frame is valid"), json);
+}
+
+#[wasm_bindgen_test]
+fn answers_are_decoded_from_frames() {
+	let json = r#"{"sequence":3,"content":{"ControlGranted":{"lease":4660,"duration_ms":5000}}}"#;
+	let frame = encode_answer(json).expect("the envelope is valid");
+
+	assert_eq!(decode_answer(&frame).expect("the frame is valid"), json);
+	assert!(decode_answer(&frame[..frame.len() - 1]).is_err());
+	assert!(encode_message(r#"{"sequence":1,"content":"Nothing"}"#).is_err());
+}
+
+#[wasm_bindgen_test]
+fn notifications_are_decoded_in_chunks() {
+	let mut stream = encode_answer(r#"{"sequence":1,"content":"AckSpeed"}"#).expect("valid");
+	stream.extend(encode_answer(r#"{"sequence":2,"content":"AckDirection"}"#).expect("valid"));
+
+	let mut decoder = AnswerDecoder::new();
+	let answers = stream
+		.chunks(3)
+		.flat_map(|chunk| decoder.feed(chunk))
+		.collect::<Vec<_>>();
+
+	assert_eq!(
+		answers,
+		[
+			r#"{"sequence":1,"content":"AckSpeed"}"#,
+			r#"{"sequence":2,"content":"AckDirection"}"#
+		]
+	);
+	assert_eq!(decoder.dropped(), 0);
+	assert_eq!(protocol_version(), r#"{"major":4,"minor":4}"#);
+}
@@ -0,0 +1,244 @@
+// Drives the car from a browser over Web Bluetooth, the frames are encoded by `car-transport-wasm`
+
+import init, { AnswerDecoder, Session, encodeMessage, protocolVersion } from "./pkg/car_transport_wasm.js";
+
+// Service and characteristic of the serial bridge of the `HM-10`
+const SERIAL_SERVICE = 0xffe0;
+const SERIAL_CHARACTERISTIC = 0xffe1;
+// Largest write accepted by the `HM-10`
+const MAX_WRITE_SIZE = 20;
+
+// The car stops when no command is received for that long
+const LEASE_DURATION_MS = 1000;
+// Time between two checks of the joystick
+const TICK_MS = 100;
+// Commands are sent again after that long, to keep the lease
+const KEEP_ALIVE_MS = 300;
+// Distance from the center to the edge of the joystick, in percents of the knob size
+const KNOB_TRAVEL = (35 / 30) * 100;
+
+const status = document.getElementById("status");
+const joystick = document.getElementById("joystick");
+const knob = document.getElementById("knob");
+const connectButton = document.getElementById("connect");
+const stopButton = document.getElementById("stop");
+
+await init();
+
+// The link to the car, `null` when disconnected
+let characteristic = null;
+// Writes are chained, GATT operations cannot overlap
+let writes = Promise.resolve();
+let decoder = new AnswerDecoder();
+let sequence = 0;
+// What the car agreed on in its `Hello` answer, `null` until then
+let session = null;
+// The token of the control lease, `null` until granted
+let lease = null;
+// Whether the emergency stop was sent, until the fault is cleared
+let stopped = false;
+
+// Position of the joystick, percentages between -100 and 100
+const position = { throttle: 0, steering: 0 };
+// Last position sent to the car and when
+const sent = { throttle: 0, steering: 0, at: 0 };
+
+// Encodes the message in a frame and queues its writes
+function send(content) {
+	sequence = (sequence + 1) % 256;
+	const frame = encodeMessage(JSON.stringify({ sequence, content }));
+
+	writes = writes
+		.then(async () => {
+			for (let offset = 0; offset < frame.length; offset += MAX_WRITE_SIZE) {
+				await characteristic.writeValueWithoutResponse(
+					frame.subarray(offset, offset + MAX_WRITE_SIZE),
+				);
+			}
+		})
+		.catch((error) => (status.textContent = `Write failed: ${error.message}`));
+}
+
+// Reacts to an answer of the car
+function receive(envelope) {
+	const answer = envelope.content;
+	const name = typeof answer === "string" ? answer : Object.keys(answer)[0];
+
+	switch (name) {
+		case "Hello":
+			try {
+				session = new Session(JSON.stringify(answer));
+			} catch (error) {
+				status.textContent = `Cannot drive this car: ${error.message}`;
+				break;
+			}
+			send({ AcquireControl: { renew: null, duration_ms: LEASE_DURATION_MS } });
+			break;
+		case "ControlGranted":
+			lease = answer.ControlGranted.lease;
+			status.textContent = `Driving with lease ${lease}`;
+			break;
+		case "AckEmergencyStop":
+			status.textContent = "Stopped, press again to drive";
+			break;
+		case "AckClearFault":
+			stopped = false;
+			stopButton.textContent = "Stop";
+			status.textContent = `Driving with lease ${lease}`;
+			break;
+		case "AckSpeed":
+		case "AckDirection":
+		case "AckReleaseControl":
+			break;
+		case "Nack":
+			status.textContent = `Refused: ${JSON.stringify(answer.Nack.reason)}`;
+			// The lease expired, renewing it with the same token also works when the car still
+			// holds it, other refusals keep the lease
+			if (answer.Nack.reason === "NotLeaseHolder" && lease !== null) {
+				send({ AcquireControl: { renew: lease, duration_ms: LEASE_DURATION_MS } });
+				lease = null;
+			}
+			break;
+		default:
+			status.textContent = JSON.stringify(answer);
+	}
+}
+
+async function connect() {
+	const device = await navigator.bluetooth.requestDevice({
+		filters: [{ services: [SERIAL_SERVICE] }],
+	});
+	device.addEventListener("gattserverdisconnected", disconnected);
+
+	const server = await device.gatt.connect();
+	const service = await server.getPrimaryService(SERIAL_SERVICE);
+	characteristic = await service.getCharacteristic(SERIAL_CHARACTERISTIC);
+
+	decoder = new AnswerDecoder();
+	characteristic.addEventListener("characteristicvaluechanged", (event) => {
+		const value = event.target.value;
+		const chunk = new Uint8Array(value.buffer, value.byteOffset, value.byteLength);
+		for (const answer of decoder.feed(chunk)) {
+			receive(JSON.parse(answer));
+		}
+	});
+	await characteristic.startNotifications();
+
+	status.textContent = `Connected to ${device.name ?? "the car"}`;
+	connectButton.textContent = "Disconnect";
+	stopButton.disabled = false;
+	send({ Hello: { version: JSON.parse(protocolVersion()) } });
+}
+
+function disconnected() {
+	characteristic = null;
+	session = null;
+	lease = null;
+	stopped = false;
+	status.textContent = "Disconnected";
+	connectButton.textContent = "Connect";
+	stopButton.textContent = "Stop";
+	stopButton.disabled = true;
+}
+
+connectButton.addEventListener("click", async () => {
+	if (characteristic !== null) {
+		if (lease !== null) {
+			send({ ReleaseControl: { lease } });
+			await writes;
+		}
+		characteristic.service.device.gatt.disconnect();
+		return;
+	}
+
+	if (!navigator.bluetooth) {
+		status.textContent = "Web Bluetooth is not available in this browser";
+		return;
+	}
+
+	try {
+		await connect();
+	} catch (error) {
+		status.textContent = `Connection failed: ${error.message}`;
+	}
+});
+
+stopButton.addEventListener("click", () => {
+	if (stopped) {
+		if (lease !== null) {
+			send({ ClearFault: { lease } });
+		}
+		return;
+	}
+
+	stopped = true;
+	stopButton.textContent = "Clear fault";
+	send("EmergencyStop");
+});
+
+// Moves the knob under the pointer, inside the joystick
+function move(event) {
+	const area = joystick.getBoundingClientRect();
+	const radius = area.width / 2;
+	let x = (event.clientX - area.left - radius) / radius;
+	let y = (event.clientY - area.top - radius) / radius;
+
+	const distance = Math.hypot(x, y);
+	if (distance > 1) {
+		x /= distance;
+		y /= distance;
+	}
+
+	place(x, y);
+}
+
+// Places the knob and sets the position, `x` and `y` between -1 and 1
+function place(x, y) {
+	knob.style.transform = `translate(${x * KNOB_TRAVEL}%, ${y * KNOB_TRAVEL}%)`;
+	position.steering = Math.round(x * 100);
+	// Up is forward
+	position.throttle = Math.round(-y * 100);
+}
+
+joystick.addEventListener("pointerdown", (event) => {
+	joystick.setPointerCapture(event.pointerId);
+	move(event);
+});
+joystick.addEventListener("pointermove", (event) => {
+	if (joystick.hasPointerCapture(event.pointerId)) {
+		move(event);
+	}
+});
+// Releasing the joystick brings the car to a halt
+joystick.addEventListener("pointerup", () => place(0, 0));
+joystick.addEventListener("pointercancel", () => place(0, 0));
+
+// Returns whether the car can handle the message, the car refuses the others
+function supports(content) {
+	return session !== null && session.supports(JSON.stringify(content));
+}
+
+setInterval(() => {
+	if (characteristic === null || lease === null || stopped) {
+		return;
+	}
+
+	const now = performance.now();
+	const keepAlive = now - sent.at >= KEEP_ALIVE_MS;
+	const throttle =
+		supports({ SetSpeed: { lease, throttle: 0 } }) &&
+		(keepAlive || position.throttle !== sent.throttle);
+	const steering =
+		supports({ SetDirection: { lease, steering: 0 } }) &&
+		(keepAlive || position.steering !== sent.steering);
+
+	if (throttle) {
+		send({ SetSpeed: { lease, throttle: position.throttle } });
+	}
+	if (steering) {
+		send({ SetDirection: { lease, steering: position.steering } });
+	}
+	if (throttle || steering) {
+		Object.assign(sent, { ...position, at: now });
+	}
+}, TICK_MS);
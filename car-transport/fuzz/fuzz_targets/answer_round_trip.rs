@@ -0,0 +1,13 @@
+//! Checks that every [`Answer`] survives a serialization round trip
+
+#![no_main]
+
+use car_transport::{Answer, Transport};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|answer: Answer| {
+	let mut buffer = [0_u8; Answer::BUFFER_SIZE];
+	let length = answer.serialize(&mut buffer);
+
+	assert_eq!(Answer::deserialize(&buffer[..length]), Ok(answer));
+});
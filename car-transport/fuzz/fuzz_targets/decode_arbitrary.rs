@@ -0,0 +1,14 @@
+//! Checks that decoding arbitrary bytes never panics
+
+#![no_main]
+
+use car_transport::{Answer, FrameDecoder, Message, Transport};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	let _ = Message::deserialize(data);
+	let _ = Answer::deserialize(data);
+
+	FrameDecoder::<Message>::new().feed(data).for_each(drop);
+	FrameDecoder::<Answer>::new().feed(data).for_each(drop);
+});
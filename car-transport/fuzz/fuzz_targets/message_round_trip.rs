@@ -0,0 +1,13 @@
+//! Checks that every [`Message`] survives a serialization round trip
+
+#![no_main]
+
+use car_transport::{Message, Transport};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|message: Message| {
+	let mut buffer = [0_u8; Message::BUFFER_SIZE];
+	let length = message.serialize(&mut buffer);
+
+	assert_eq!(Message::deserialize(&buffer[..length]), Ok(message));
+});
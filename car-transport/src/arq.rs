@@ -0,0 +1,429 @@
+//! Reliable delivery over lossy links
+//!
+//! Bluetooth writes without response can be silently dropped. Every [`Message`](crate::Message)
+//! is answered, so the answer doubles as the acknowledgement of its [`Envelope`]:
+//! - the sender keeps [`Delivery::Reliable`] envelopes in a [`Retransmitter`] and sends them
+//!   again with the same sequence number until they are answered or run out of retries,
+//! - the receiver runs every envelope through a [`Deduplicator`], which recognizes the
+//!   retransmissions and hands back the answer that was already sent instead of executing
+//!   the message twice.
+//!
+//! [`Delivery::BestEffort`] envelopes are never sent again, which suits high-rate control
+//! frames that are superseded by the next one anyway.
+//!
+//! Time is given by the caller as wrapping milliseconds, so that it works with any clock.
+
+use crate::envelope::{Arrival, Envelope, Sequence, SequenceWindow};
+
+/// How hard the sender tries to deliver an envelope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub enum Delivery {
+	/// Sent once, the next envelope supersedes it
+	BestEffort,
+	/// Sent again until it is answered or runs out of retries
+	Reliable,
+}
+
+/// Something the sender needs to do, see [`Retransmitter::poll`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub enum Event<T> {
+	/// The envelope was not answered in time and should be sent again
+	Retransmit(Envelope<T>),
+	/// The envelope was not answered in time and is no longer tracked
+	Expired(Envelope<T>),
+}
+
+/// An envelope waiting for its answer
+#[derive(Debug, Clone)]
+struct Outstanding<T> {
+	/// The envelope to send again
+	envelope: Envelope<T>,
+	/// Number of retransmissions left, always 0 for best effort envelopes
+	retries: u8,
+	/// Time at which the envelope is considered lost
+	deadline: u32,
+}
+
+/// Keeps the sent envelopes until they are answered and tells when to send them again
+///
+/// `N` is the maximum number of envelopes waiting for their answer.
+#[derive(Debug, Clone)]
+pub struct Retransmitter<T, const N: usize> {
+	/// The envelopes waiting for their answer
+	outstanding: [Option<Outstanding<T>>; N],
+
+	/// Time to wait for an answer before sending the envelope again, in milliseconds
+	pub timeout_ms: u32,
+	/// Number of times a reliable envelope is sent again before giving up
+	pub retries: u8,
+}
+
+impl<T: Clone, const N: usize> Retransmitter<T, N> {
+	/// Creates a retransmitter without outstanding envelopes
+	#[must_use]
+	pub const fn new(timeout_ms: u32, retries: u8) -> Self {
+		Self {
+			outstanding: [const { None }; N],
+			timeout_ms,
+			retries,
+		}
+	}
+
+	/// Starts waiting for the answer of an envelope that was just sent
+	///
+	/// Returns `false` when there are already `N` outstanding envelopes.
+	#[must_use]
+	pub fn send(&mut self, envelope: &Envelope<T>, delivery: Delivery, now: u32) -> bool {
+		let Some(slot) = self.outstanding.iter_mut().find(|slot| slot.is_none()) else {
+			return false;
+		};
+
+		*slot = Some(Outstanding {
+			envelope: envelope.clone(),
+			retries: match delivery {
+				Delivery::BestEffort => 0,
+				Delivery::Reliable => self.retries,
+			},
+			deadline: now.wrapping_add(self.timeout_ms),
+		});
+
+		true
+	}
+
+	/// Stops waiting for the envelope with this sequence number, returns whether it was outstanding
+	pub fn acknowledge(&mut self, sequence: Sequence) -> bool {
+		let slot = self.outstanding.iter_mut().find(|slot| {
+			slot.as_ref()
+				.is_some_and(|outstanding| outstanding.envelope.sequence == sequence)
+		});
+
+		slot.is_some_and(|slot| slot.take().is_some())
+	}
+
+	/// Returns the next envelope to send again or to give up on, call it until it returns [`None`]
+	pub fn poll(&mut self, now: u32) -> Option<Event<T>> {
+		let slot = self.outstanding.iter_mut().find(|slot| {
+			slot.as_ref()
+				.is_some_and(|outstanding| is_reached(outstanding.deadline, now))
+		})?;
+
+		let outstanding = slot.as_mut()?;
+		if outstanding.retries == 0 {
+			return slot
+				.take()
+				.map(|outstanding| Event::Expired(outstanding.envelope));
+		}
+
+		outstanding.retries -= 1;
+		outstanding.deadline = now.wrapping_add(self.timeout_ms);
+
+		Some(Event::Retransmit(outstanding.envelope.clone()))
+	}
+
+	/// Returns the time left until the next call to [`Retransmitter::poll`] has something to do
+	#[must_use]
+	pub fn remaining_ms(&self, now: u32) -> Option<u32> {
+		self.outstanding
+			.iter()
+			.flatten()
+			.map(|outstanding| {
+				if is_reached(outstanding.deadline, now) {
+					0
+				} else {
+					outstanding.deadline.wrapping_sub(now)
+				}
+			})
+			.min()
+	}
+
+	/// Returns whether the envelope with this sequence number is waiting for its answer
+	#[must_use]
+	pub fn is_outstanding(&self, sequence: Sequence) -> bool {
+		self.outstanding
+			.iter()
+			.flatten()
+			.any(|outstanding| outstanding.envelope.sequence == sequence)
+	}
+}
+
+/// Returns whether the wrapping `deadline` is at or before `now`
+const fn is_reached(deadline: u32, now: u32) -> bool {
+	now.wrapping_sub(deadline) < 1 << 31
+}
+
+/// What to do with a received envelope, see [`Deduplicator::observe`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub enum Incoming<'a, T> {
+	/// The envelope was not received before and should be handled
+	Fresh,
+	/// The envelope is a retransmission, its answer should be sent again when it is still known
+	Duplicate(Option<&'a Envelope<T>>),
+}
+
+/// Recognizes retransmitted envelopes and remembers the last answers sent
+///
+/// `N` is the number of answers kept to answer retransmissions.
+#[derive(Debug, Clone)]
+pub struct Deduplicator<T, const N: usize> {
+	/// The received sequence numbers
+	window: SequenceWindow,
+	/// The last answers sent, in a ring
+	replies: [Option<Envelope<T>>; N],
+	/// The slot of `replies` to overwrite next
+	next: usize,
+}
+
+impl<T: Clone, const N: usize> Default for Deduplicator<T, N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: Clone, const N: usize> Deduplicator<T, N> {
+	/// Creates a deduplicator that did not receive anything yet
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			window: SequenceWindow::new(),
+			replies: [const { None }; N],
+			next: 0,
+		}
+	}
+
+	/// Forgets every received envelope and answer, e.g. when a new controller connects
+	pub fn reset(&mut self) {
+		self.window.reset();
+		self.replies = [const { None }; N];
+	}
+
+	/// Records the sequence number of a received envelope and tells whether to handle it
+	pub fn observe(&mut self, sequence: Sequence) -> Incoming<'_, T> {
+		match self.window.observe(sequence) {
+			Arrival::InOrder | Arrival::Gap(_) | Arrival::Reordered => Incoming::Fresh,
+			Arrival::Duplicate => Incoming::Duplicate(
+				self.replies
+					.iter()
+					.flatten()
+					.find(|reply| reply.sequence == sequence),
+			),
+		}
+	}
+
+	/// Remembers the answer sent to a fresh envelope, to send it again on retransmissions
+	pub fn record(&mut self, reply: &Envelope<T>) {
+		if N == 0 {
+			return;
+		}
+
+		self.replies[self.next] = Some(reply.clone());
+		self.next = (self.next + 1) % N;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Answer, LeaseToken, Message, Throttle};
+
+	/// A link that drops the frames whose position is in the pattern, so that tests are repeatable
+	struct LossyChannel {
+		/// Whether each sent frame is dropped, repeated over and over
+		pattern: &'static [bool],
+		/// Number of frames sent so far
+		sent: usize,
+	}
+
+	impl LossyChannel {
+		/// Returns the frame when it makes it through
+		fn carry<T>(&mut self, frame: T) -> Option<T> {
+			let dropped = self.pattern[self.sent % self.pattern.len()];
+			self.sent += 1;
+
+			(!dropped).then_some(frame)
+		}
+	}
+
+	/// The car side, counting how many times it executed each message
+	struct Car {
+		/// Recognizes the retransmissions
+		deduplicator: Deduplicator<Answer, 4>,
+		/// Number of executed messages
+		executed: usize,
+	}
+
+	impl Car {
+		/// Handles a message and returns the answer to send
+		fn handle(&mut self, message: &Envelope<Message>) -> Option<Envelope<Answer>> {
+			match self.deduplicator.observe(message.sequence) {
+				Incoming::Fresh => {}
+				Incoming::Duplicate(reply) => return reply.cloned(),
+			}
+
+			self.executed += 1;
+			let reply = message.reply(Answer::AckSpeed);
+			self.deduplicator.record(&reply);
+
+			Some(reply)
+		}
+	}
+
+	/// Sends the message over the lossy channels, returns the answer and the number of frames sent
+	fn deliver(
+		message: &Envelope<Message>,
+		delivery: Delivery,
+		uplink: &mut LossyChannel,
+		downlink: &mut LossyChannel,
+		car: &mut Car,
+	) -> (Option<Envelope<Answer>>, usize) {
+		let mut retransmitter = Retransmitter::<Message, 2>::new(100, 3);
+		assert!(retransmitter.send(message, delivery, 0));
+
+		let mut in_flight = Some(message.clone());
+		let mut frames = 0;
+		for now in (0..1000).step_by(10) {
+			if let Some(sent) = in_flight.take() {
+				frames += 1;
+
+				let answer = uplink
+					.carry(sent)
+					.and_then(|message| car.handle(&message))
+					.and_then(|answer| downlink.carry(answer));
+				if let Some(answer) = answer {
+					assert!(retransmitter.acknowledge(answer.sequence));
+					return (Some(answer), frames);
+				}
+			}
+
+			match retransmitter.poll(now) {
+				Some(Event::Retransmit(envelope)) => in_flight = Some(envelope),
+				Some(Event::Expired(_)) => return (None, frames),
+				None => {}
+			}
+		}
+
+		unreachable!("the retransmitter gives up before")
+	}
+
+	/// A channel that carries every frame
+	const RELIABLE: &[bool] = &[false];
+
+	#[test]
+	fn reliable_messages_survive_losses_without_being_executed_twice() {
+		let message = Envelope::new(
+			Sequence(0),
+			Message::SetSpeed {
+				lease: LeaseToken(1),
+				throttle: Throttle::ZERO,
+			},
+		);
+		let mut car = Car {
+			deduplicator: Deduplicator::new(),
+			executed: 0,
+		};
+
+		// The first message is lost, then the first answer
+		let (answer, frames) = deliver(
+			&message,
+			Delivery::Reliable,
+			&mut LossyChannel {
+				pattern: &[true, false, false],
+				sent: 0,
+			},
+			&mut LossyChannel {
+				pattern: &[true, false],
+				sent: 0,
+			},
+			&mut car,
+		);
+
+		assert_eq!(answer, Some(message.reply(Answer::AckSpeed)));
+		assert_eq!(frames, 3);
+		assert_eq!(car.executed, 1);
+	}
+
+	#[test]
+	fn best_effort_messages_are_sent_once() {
+		let message = Envelope::new(
+			Sequence(0),
+			Message::SetSpeed {
+				lease: LeaseToken(1),
+				throttle: Throttle::MAX,
+			},
+		);
+		let mut car = Car {
+			deduplicator: Deduplicator::new(),
+			executed: 0,
+		};
+
+		let (answer, frames) = deliver(
+			&message,
+			Delivery::BestEffort,
+			&mut LossyChannel {
+				pattern: &[true],
+				sent: 0,
+			},
+			&mut LossyChannel {
+				pattern: RELIABLE,
+				sent: 0,
+			},
+			&mut car,
+		);
+
+		assert_eq!(answer, None);
+		assert_eq!(frames, 1);
+		assert_eq!(car.executed, 0);
+	}
+
+	#[test]
+	fn retransmissions_are_bounded() {
+		let message = Envelope::new(
+			Sequence(0),
+			Message::SetSpeed {
+				lease: LeaseToken(1),
+				throttle: Throttle::ZERO,
+			},
+		);
+		let mut car = Car {
+			deduplicator: Deduplicator::new(),
+			executed: 0,
+		};
+
+		// Every answer is lost
+		let (answer, frames) = deliver(
+			&message,
+			Delivery::Reliable,
+			&mut LossyChannel {
+				pattern: RELIABLE,
+				sent: 0,
+			},
+			&mut LossyChannel {
+				pattern: &[true],
+				sent: 0,
+			},
+			&mut car,
+		);
+
+		assert_eq!(answer, None);
+		assert_eq!(frames, 4);
+		assert_eq!(car.executed, 1);
+	}
+
+	#[test]
+	fn deadlines_wrap_around() {
+		let mut retransmitter = Retransmitter::<Message, 1>::new(100, 1);
+		let message = Envelope::new(Sequence(0), Message::Ping);
+		assert!(retransmitter.send(&message, Delivery::Reliable, u32::MAX - 10));
+
+		assert_eq!(retransmitter.poll(50), None);
+		assert_eq!(retransmitter.remaining_ms(50), Some(39));
+		assert_eq!(retransmitter.poll(89), Some(Event::Retransmit(message)));
+		assert!(!retransmitter.send(
+			&Envelope::new(Sequence(1), Message::Ping),
+			Delivery::Reliable,
+			89
+		));
+	}
+}
@@ -0,0 +1,490 @@
+//! Authenticated sessions with a pre-shared key
+//!
+//! Anyone in range of the bluetooth module can send messages, so the car can require the
+//! controller to prove that it knows a [`Key`] shared beforehand. The handshake is a mutual
+//! challenge and response, where proofs are truncated `HMAC-SHA256`s of the nonces:
+//! ```text
+//! controller                                                 car
+//!     | -- AuthChallenge { nonce: Nc } -----------------------> |
+//!     | <- AuthChallenge { nonce: Nr, proof: P(car, Nc, Nr) } - |
+//!     | -- Authenticate { proof: P(controller, Nr, Nc) } -----> |
+//!     | <- Authenticated -------------------------------------- |
+//! ```
+//!
+//! Both sides then derive a session key from the nonces and seal every frame:
+//! ```text
+//! +---------------------------+------------------+----------+
+//! | serialized value (varies) | counter (4b, BE) | tag (8b) |
+//! +---------------------------+------------------+----------+
+//! ```
+//! The tag authenticates the sender, the counter and the value. Counters only go up, a frame
+//! whose counter is not above the last accepted one is a replay.
+//!
+//! Handshake messages travel in plain frames, as there is no session yet. The car refuses
+//! every other plain message with [`ErrorCode::Unauthenticated`](crate::ErrorCode::Unauthenticated)
+//! and answers sealed messages with sealed frames.
+//!
+//! The [`Nonce`] and [`Tag`] payloads are always available, the cryptography needs the `auth` feature.
+
+#[cfg(feature = "auth")]
+use core::fmt;
+
+#[cfg(feature = "auth")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "auth")]
+use sha2::Sha256;
+
+#[cfg(feature = "auth")]
+use crate::{Answer, Message, TransportError};
+use crate::{Field, ProtocolVersion};
+
+/// First protocol revision with authenticated sessions
+pub const SINCE: ProtocolVersion = ProtocolVersion { major: 4, minor: 1 };
+
+/// Size of a [`Nonce`] in bytes
+pub const NONCE_SIZE: usize = 8;
+/// Size of a [`Tag`] in bytes
+pub const TAG_SIZE: usize = 8;
+/// Size of a [`Key`] in bytes
+pub const KEY_SIZE: usize = 32;
+/// Size of the frame counter in bytes
+const COUNTER_SIZE: usize = 4;
+/// Number of bytes appended to a sealed value
+pub const SEAL_SIZE: usize = COUNTER_SIZE + TAG_SIZE;
+
+/// Number used once to make each handshake unique
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct Nonce(pub [u8; NONCE_SIZE]);
+
+/// Truncated `HMAC-SHA256` proving the knowledge of a key
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct Tag(pub [u8; TAG_SIZE]);
+
+/// Key shared by the car and its controllers
+#[cfg(feature = "auth")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key([u8; KEY_SIZE]);
+
+#[cfg(feature = "auth")]
+impl Key {
+	/// Wraps the raw key bytes
+	#[must_use]
+	pub const fn new(bytes: [u8; KEY_SIZE]) -> Self {
+		Self(bytes)
+	}
+
+	/// Parses a key written as 64 hex digits, usually `env!("CAR_PSK")`
+	///
+	/// Returns [`None`] when the text has another length or a non hex digit.
+	#[must_use]
+	pub const fn from_hex(hex: &str) -> Option<Self> {
+		let hex = hex.as_bytes();
+		if hex.len() != KEY_SIZE * 2 {
+			return None;
+		}
+
+		let mut bytes = [0_u8; KEY_SIZE];
+		let mut index = 0;
+		while index < KEY_SIZE {
+			let (Some(high), Some(low)) =
+				(hex_digit(hex[index * 2]), hex_digit(hex[index * 2 + 1]))
+			else {
+				return None;
+			};
+			bytes[index] = high << 4 | low;
+			index += 1;
+		}
+
+		Some(Self(bytes))
+	}
+
+	/// Computes the `HMAC-SHA256` of the parts, one after the other
+	fn mac(&self, parts: &[&[u8]]) -> Hmac<Sha256> {
+		let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC takes keys of any size");
+		for part in parts {
+			mac.update(part);
+		}
+
+		mac
+	}
+
+	/// Computes the truncated `HMAC-SHA256` of the parts
+	fn tag(&self, parts: &[&[u8]]) -> Tag {
+		let mut tag = Tag::default();
+		tag.0
+			.copy_from_slice(&self.mac(parts).finalize().into_bytes()[..TAG_SIZE]);
+
+		tag
+	}
+
+	/// Checks the truncated `HMAC-SHA256` of the parts in constant time
+	fn verify(&self, parts: &[&[u8]], tag: Tag) -> bool {
+		self.mac(parts).verify_truncated_left(&tag.0).is_ok()
+	}
+
+	/// Derives the key of the session started with the two nonces
+	fn session(&self, controller: Nonce, car: Nonce) -> Self {
+		Self(
+			self.mac(&[b"session", &controller.0, &car.0])
+				.finalize()
+				.into_bytes()
+				.into(),
+		)
+	}
+}
+
+// The key never shows up in logs
+#[cfg(feature = "auth")]
+impl fmt::Debug for Key {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("Key(..)")
+	}
+}
+
+/// Returns the value of a hex digit
+#[cfg(feature = "auth")]
+const fn hex_digit(digit: u8) -> Option<u8> {
+	match digit {
+		b'0'..=b'9' => Some(digit - b'0'),
+		b'a'..=b'f' => Some(digit - b'a' + 10),
+		b'A'..=b'F' => Some(digit - b'A' + 10),
+		_ => None,
+	}
+}
+
+/// Side of the link, so that a frame cannot be reflected to its sender
+#[cfg(feature = "auth")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub enum Role {
+	/// Sends messages
+	Controller,
+	/// Sends answers
+	Car,
+}
+
+#[cfg(feature = "auth")]
+impl Role {
+	/// Returns the label mixed in the tags of this side
+	const fn label(self) -> &'static [u8] {
+		match self {
+			Self::Controller => b"controller",
+			Self::Car => b"car",
+		}
+	}
+
+	/// Returns the other side of the link
+	const fn peer(self) -> Self {
+		match self {
+			Self::Controller => Self::Car,
+			Self::Car => Self::Controller,
+		}
+	}
+}
+
+/// Keys and counters of an authenticated session, seals sent frames and opens received ones
+#[cfg(feature = "auth")]
+#[derive(Debug, Clone)]
+pub struct Session {
+	/// Key derived during the handshake
+	key: Key,
+	/// The side of the link this session runs on
+	role: Role,
+	/// Counter of the next sealed frame
+	sent: u32,
+	/// Counter of the last opened frame
+	received: Option<u32>,
+}
+
+#[cfg(feature = "auth")]
+impl Session {
+	/// Starts a session with the key derived during the handshake
+	const fn new(key: Key, role: Role) -> Self {
+		Self {
+			key,
+			role,
+			sent: 0,
+			received: None,
+		}
+	}
+
+	/// Appends the counter and the tag to the serialized value at the start of the buffer,
+	/// returns the sealed length
+	///
+	/// # Errors
+	/// [`TransportError::BufferTooSmall`] when the buffer cannot hold [`SEAL_SIZE`] more bytes,
+	/// [`TransportError::Replayed`] when the session sealed so many frames that its counter is
+	/// exhausted, a new handshake is needed
+	pub fn seal(&mut self, buffer: &mut [u8], length: usize) -> Result<usize, TransportError> {
+		if buffer.len() < length + SEAL_SIZE {
+			return Err(TransportError::BufferTooSmall);
+		}
+
+		let counter = self.sent.to_be_bytes();
+		self.sent = self.sent.checked_add(1).ok_or(TransportError::Replayed)?;
+
+		let (value, seal) = buffer.split_at_mut(length);
+		let tag = self.key.tag(&[self.role.label(), &counter, value]);
+		seal[..COUNTER_SIZE].copy_from_slice(&counter);
+		seal[COUNTER_SIZE..SEAL_SIZE].copy_from_slice(&tag.0);
+
+		Ok(length + SEAL_SIZE)
+	}
+
+	/// Checks a sealed value sent by the other side and returns the serialized value
+	///
+	/// # Errors
+	/// [`TransportError::InvalidTag`] when the frame is not sealed with the session key,
+	/// [`TransportError::Replayed`] when its counter was already used
+	pub fn open<'a>(&mut self, sealed: &'a [u8]) -> Result<&'a [u8], TransportError> {
+		let (value, seal) = sealed
+			.split_at_checked(sealed.len().wrapping_sub(SEAL_SIZE))
+			.ok_or(TransportError::InvalidTag)?;
+		let (counter, tag) = seal.split_at(COUNTER_SIZE);
+
+		let mut expected = Tag::default();
+		expected.0.copy_from_slice(tag);
+		if !self
+			.key
+			.verify(&[self.role.peer().label(), counter, value], expected)
+		{
+			return Err(TransportError::InvalidTag);
+		}
+
+		let mut bytes = [0_u8; COUNTER_SIZE];
+		bytes.copy_from_slice(counter);
+		let counter = u32::from_be_bytes(bytes);
+		if self.received.is_some_and(|received| counter <= received) {
+			return Err(TransportError::Replayed);
+		}
+		self.received = Some(counter);
+
+		Ok(value)
+	}
+}
+
+/// The controller side of the handshake
+#[cfg(feature = "auth")]
+#[derive(Debug, Clone)]
+pub struct ControllerHandshake {
+	/// The pre-shared key
+	key: Key,
+	/// The nonce sent in the challenge, it must never be reused with the same key
+	nonce: Nonce,
+}
+
+#[cfg(feature = "auth")]
+impl ControllerHandshake {
+	/// Starts a handshake with a random nonce
+	#[must_use]
+	pub const fn new(key: Key, nonce: Nonce) -> Self {
+		Self { key, nonce }
+	}
+
+	/// Returns the message that starts the handshake
+	#[must_use]
+	pub const fn challenge(&self) -> Message {
+		Message::AuthChallenge { nonce: self.nonce }
+	}
+
+	/// Checks the proof of the car and returns the message proving the controller knows the key,
+	/// with the session to use once the car answered [`Answer::Authenticated`]
+	///
+	/// # Errors
+	/// [`TransportError::InvalidTag`] when the car does not know the key
+	pub fn respond(&self, nonce: Nonce, proof: Tag) -> Result<(Message, Session), TransportError> {
+		if !self
+			.key
+			.verify(&[Role::Car.label(), &self.nonce.0, &nonce.0], proof)
+		{
+			return Err(TransportError::InvalidTag);
+		}
+
+		let proof = self
+			.key
+			.tag(&[Role::Controller.label(), &nonce.0, &self.nonce.0]);
+		let session = Session::new(self.key.session(self.nonce, nonce), Role::Controller);
+
+		Ok((Message::Authenticate { proof }, session))
+	}
+}
+
+/// The car side of the handshake
+#[cfg(feature = "auth")]
+#[derive(Debug, Clone)]
+pub struct CarHandshake {
+	/// The pre-shared key
+	key: Key,
+	/// The nonce of the controller
+	controller: Nonce,
+	/// The nonce of the car
+	car: Nonce,
+}
+
+#[cfg(feature = "auth")]
+impl CarHandshake {
+	/// Answers the challenge of a controller
+	///
+	/// The nonce of the car is derived from the key, the nonce of the controller and `entropy`.
+	/// Without the key nobody can predict it, but `entropy` must differ between handshakes so
+	/// that a recorded handshake cannot be replayed, e.g. a timestamp and a handshake counter.
+	#[must_use]
+	pub fn new(key: Key, controller: Nonce, entropy: &[u8]) -> Self {
+		let car = Nonce(key.tag(&[b"nonce", &controller.0, entropy]).0);
+
+		Self {
+			key,
+			controller,
+			car,
+		}
+	}
+
+	/// Returns the answer proving the car knows the key
+	#[must_use]
+	pub fn challenge(&self) -> Answer {
+		Answer::AuthChallenge {
+			nonce: self.car,
+			proof: self
+				.key
+				.tag(&[Role::Car.label(), &self.controller.0, &self.car.0]),
+		}
+	}
+
+	/// Checks the proof of the controller and returns the session to use from now on
+	///
+	/// # Errors
+	/// [`TransportError::InvalidTag`] when the controller does not know the key
+	pub fn verify(&self, proof: Tag) -> Result<Session, TransportError> {
+		if !self.key.verify(
+			&[Role::Controller.label(), &self.car.0, &self.controller.0],
+			proof,
+		) {
+			return Err(TransportError::InvalidTag);
+		}
+
+		Ok(Session::new(
+			self.key.session(self.controller, self.car),
+			Role::Car,
+		))
+	}
+}
+
+/// A received value, with whether its frame was sealed
+#[cfg(feature = "auth")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub enum Opened<T> {
+	/// The frame was sealed with the session key and was not a replay
+	Sealed(T),
+	/// The frame was not sealed, or there is no session
+	Plain(T),
+}
+
+#[cfg(feature = "auth")]
+impl<T> Opened<T> {
+	/// Returns the received value
+	pub fn into_inner(self) -> T {
+		match self {
+			Self::Sealed(value) | Self::Plain(value) => value,
+		}
+	}
+
+	/// Returns whether the frame was sealed
+	#[must_use]
+	pub const fn is_sealed(&self) -> bool {
+		matches!(self, Self::Sealed(_))
+	}
+}
+
+#[cfg(all(test, feature = "auth"))]
+mod tests {
+	use super::*;
+
+	const KEY: Option<Key> =
+		Key::from_hex("000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F");
+
+	/// Runs a handshake and returns the sessions of the controller and of the car
+	fn handshake(key: &Key) -> Result<(Session, Session), TransportError> {
+		let controller = ControllerHandshake::new(key.clone(), Nonce([1; NONCE_SIZE]));
+		let Message::AuthChallenge { nonce } = controller.challenge() else {
+			unreachable!("the handshake starts with a challenge");
+		};
+
+		let car = CarHandshake::new(key.clone(), nonce, &42_u32.to_be_bytes());
+		let Answer::AuthChallenge { nonce, proof } = car.challenge() else {
+			unreachable!("the car answers with a challenge");
+		};
+
+		let (Message::Authenticate { proof }, controller) = controller.respond(nonce, proof)?
+		else {
+			unreachable!("the controller answers with its proof");
+		};
+
+		Ok((controller, car.verify(proof)?))
+	}
+
+	#[test]
+	fn can_parse_hex_keys() {
+		assert!(KEY.is_some());
+		assert!(Key::from_hex("00").is_none());
+		assert!(Key::from_hex(&"0g".repeat(KEY_SIZE)).is_none());
+	}
+
+	#[test]
+	fn sealed_frames_are_opened_once() -> Result<(), TransportError> {
+		let (mut controller, mut car) = handshake(&KEY.expect("valid key"))?;
+
+		let mut buffer = [0_u8; 3 + SEAL_SIZE];
+		buffer[..3].copy_from_slice(&[7, 100, 42]);
+		let length = controller.seal(&mut buffer, 3)?;
+		assert_eq!(length, buffer.len());
+
+		assert_eq!(car.open(&buffer)?, [7, 100, 42]);
+		assert_eq!(car.open(&buffer), Err(TransportError::Replayed));
+
+		// The counter is authenticated
+		controller.seal(&mut buffer, 3)?;
+		buffer[3] ^= 1;
+		assert_eq!(car.open(&buffer), Err(TransportError::InvalidTag));
+
+		// A frame cannot be sent back to its sender
+		controller.seal(&mut buffer, 3)?;
+		assert_eq!(controller.open(&buffer), Err(TransportError::InvalidTag));
+
+		Ok(())
+	}
+
+	#[test]
+	fn handshake_fails_with_another_key() {
+		let key = KEY.expect("valid key");
+		let other = ControllerHandshake::new(Key::new([0xAA; KEY_SIZE]), Nonce([2; NONCE_SIZE]));
+
+		let car = CarHandshake::new(key, Nonce([2; NONCE_SIZE]), &[]);
+		let Answer::AuthChallenge { nonce, proof } = car.challenge() else {
+			unreachable!("the car answers with a challenge");
+		};
+
+		assert_eq!(
+			other.respond(nonce, proof).map(|_| ()),
+			Err(TransportError::InvalidTag)
+		);
+		assert_eq!(
+			car.verify(Tag::default()).map(|_| ()),
+			Err(TransportError::InvalidTag)
+		);
+	}
+}
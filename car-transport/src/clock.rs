@@ -0,0 +1,437 @@
+//! Clock synchronization between the controller and the car
+//!
+//! The car counts time in microseconds since it booted. Sensor answers and telemetry snapshots
+//! carry the [`Timestamp`] at which they were sampled, and the controller relates those to its
+//! own clock with [`Message::TimeSync`](crate::Message::TimeSync) exchanges, in the way of NTP:
+//! - the controller sends the time of its clock as the origin,
+//! - the car answers with the origin, the time at which the message was received and the time at
+//!   which the answer is transmitted,
+//! - the controller notes when the answer arrives and feeds the four times to a
+//!   [`ClockEstimator`] as a [`SyncSample`].
+//!
+//! The estimator keeps the last samples and fits the offset of the car clock and its drift
+//! against the controller clock, preferring the exchanges that were answered the fastest. Its
+//! [`Estimate`] converts the timestamps of the car to the time of the controller.
+//!
+//! Timestamps are only sent to controllers speaking at least the [`SINCE`] revision, they are
+//! trailing members that older revisions would refuse.
+
+use core::fmt;
+
+use crate::{Field, ProtocolVersion};
+
+/// First protocol revision with clock synchronization and timestamped answers
+pub const SINCE: ProtocolVersion = ProtocolVersion { major: 4, minor: 4 };
+
+/// Exchanges that took less than that are kept by the [`ClockEstimator`], in microseconds
+const DELAY_FLOOR_US: i64 = 1000;
+
+/// A time in microseconds (`us`), since the car booted for the car clock
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+#[transport(unit = "us")]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+	/// Returns the signed number of microseconds from `earlier` to this time
+	#[must_use]
+	pub const fn since(self, earlier: Self) -> i64 {
+		// The difference of two's complement values, timestamps are far from `i64::MAX`
+		#[allow(clippy::cast_possible_wrap)]
+		let elapsed = self.0.wrapping_sub(earlier.0) as i64;
+		elapsed
+	}
+
+	/// Returns the time moved by a signed number of microseconds, saturating at the bounds
+	#[must_use]
+	pub const fn offset(self, micros: i64) -> Self {
+		Self(self.0.saturating_add_signed(micros))
+	}
+}
+
+impl fmt::Display for Timestamp {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}us", self.0)
+	}
+}
+
+/// The four times of a [`Message::TimeSync`](crate::Message::TimeSync) exchange
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SyncSample {
+	/// When the controller sent the message, on its clock
+	pub origin: Timestamp,
+	/// When the car received the message, on its clock
+	pub received: Timestamp,
+	/// When the car sent the answer, on its clock
+	pub transmitted: Timestamp,
+	/// When the controller received the answer, on its clock
+	pub answered: Timestamp,
+}
+
+impl SyncSample {
+	/// Returns how far the car clock is ahead of the controller clock, in microseconds
+	///
+	/// The link is assumed to take as long both ways.
+	#[must_use]
+	pub const fn offset_us(&self) -> i64 {
+		i64::midpoint(
+			self.received.since(self.origin),
+			self.transmitted.since(self.answered),
+		)
+	}
+
+	/// Returns the time spent on the link both ways, without the time spent on the car
+	#[must_use]
+	pub const fn delay_us(&self) -> i64 {
+		self.answered.since(self.origin) - self.transmitted.since(self.received)
+	}
+
+	/// Returns the controller time halfway through the exchange
+	#[must_use]
+	pub const fn midpoint(&self) -> Timestamp {
+		self.origin.offset(self.answered.since(self.origin) / 2)
+	}
+}
+
+/// Relation between the car clock and the controller clock, see [`ClockEstimator::estimate`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+	/// Controller time at which the offset was estimated
+	pub at: Timestamp,
+	/// How far the car clock is ahead of the controller clock at [`Estimate::at`], in microseconds
+	pub offset_us: f64,
+	/// How much faster the car clock runs, in microseconds per second of the controller clock
+	pub drift_ppm: f64,
+}
+
+impl Estimate {
+	/// Returns how far the car clock is ahead of the controller clock at a controller time
+	#[must_use]
+	pub fn offset_at(&self, host: Timestamp) -> f64 {
+		self.offset_us + self.drift_ppm / 1e6 * micros(host.since(self.at))
+	}
+
+	/// Converts a time of the car clock to the controller clock
+	#[must_use]
+	pub fn to_host(&self, car: Timestamp) -> Timestamp {
+		// `car = host + offset + drift * (host - at)`, solved for `host`
+		let elapsed = (micros(car.since(self.at)) - self.offset_us) / (1.0 + self.drift_ppm / 1e6);
+		self.at.offset(round(elapsed))
+	}
+
+	/// Converts a time of the controller clock to the car clock
+	#[must_use]
+	pub fn to_car(&self, host: Timestamp) -> Timestamp {
+		host.offset(round(self.offset_at(host)))
+	}
+}
+
+/// Estimates the offset and drift of the car clock from the last `N` synchronizations
+///
+/// Exchanges delayed on the link are asymmetric more often than not, so only the samples that
+/// took at most twice as long as the fastest one, or less than a millisecond, are fitted.
+#[derive(Debug, Clone, Default)]
+pub struct ClockEstimator<const N: usize = 8> {
+	/// The last samples, oldest first
+	samples: heapless::Deque<SyncSample, N>,
+}
+
+impl<const N: usize> ClockEstimator<N> {
+	/// Creates an estimator without samples
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			samples: heapless::Deque::new(),
+		}
+	}
+
+	/// Adds the sample of an exchange, forgetting the oldest one when full
+	pub fn add(&mut self, sample: SyncSample) {
+		if self.samples.is_full() {
+			self.samples.pop_front();
+		}
+		// There is room since the oldest sample was removed
+		let _ = self.samples.push_back(sample);
+	}
+
+	/// Forgets every sample, when the car restarted for instance
+	pub fn clear(&mut self) {
+		self.samples.clear();
+	}
+
+	/// Returns the number of samples kept
+	#[must_use]
+	pub const fn len(&self) -> usize {
+		self.samples.len()
+	}
+
+	/// Returns whether no sample was added
+	#[must_use]
+	pub fn is_empty(&self) -> bool {
+		self.samples.is_empty()
+	}
+
+	/// Fits the offset and drift of the car clock, [`None`] without samples
+	///
+	/// The drift stays at zero until the samples span some time.
+	#[must_use]
+	pub fn estimate(&self) -> Option<Estimate> {
+		let fastest = self.samples.iter().map(SyncSample::delay_us).min()?;
+		let limit = fastest.saturating_mul(2).max(DELAY_FLOOR_US);
+		let kept = || {
+			self.samples
+				.iter()
+				.filter(move |sample| sample.delay_us() <= limit)
+		};
+
+		// Relative to the latest sample, which keeps the values small
+		let at = kept().next_back()?.midpoint();
+		let count = micros(i64::try_from(kept().count()).unwrap_or(i64::MAX));
+		let point = |sample: &SyncSample| {
+			(
+				micros(sample.midpoint().since(at)),
+				micros(sample.offset_us()),
+			)
+		};
+
+		let (sum_x, sum_y) = kept()
+			.map(point)
+			.fold((0.0, 0.0), |(sum_x, sum_y), (x, y)| (sum_x + x, sum_y + y));
+		let (mean_x, mean_y) = (sum_x / count, sum_y / count);
+		let (covariance, variance) = kept().map(point).fold((0.0, 0.0), |(xy, xx), (x, y)| {
+			(
+				xy + (x - mean_x) * (y - mean_y),
+				xx + (x - mean_x) * (x - mean_x),
+			)
+		});
+
+		let slope = if variance > 0.0 {
+			covariance / variance
+		} else {
+			0.0
+		};
+
+		Some(Estimate {
+			at,
+			offset_us: mean_y - slope * mean_x,
+			drift_ppm: slope * 1e6,
+		})
+	}
+}
+
+/// Converts microseconds to a float, exact below 2^53 which is more than 280 years
+#[allow(clippy::cast_precision_loss)]
+const fn micros(value: i64) -> f64 {
+	value as f64
+}
+
+/// Rounds microseconds to the nearest integer, `core` has no `f64::round`
+fn round(value: f64) -> i64 {
+	let rounded = if value < 0.0 {
+		value - 0.5
+	} else {
+		value + 0.5
+	};
+
+	// Truncated towards zero and saturated, `NaN` becomes 0
+	#[allow(clippy::cast_possible_truncation)]
+	let integer = rounded as i64;
+	integer
+}
+
+/// (De)serializes a sensor value with the [`Trailing`] time at which it was sampled
+///
+/// Without a time the value is represented alone, as before timestamps were added, otherwise as
+/// a `value` and `sampled_at` structure.
+#[cfg(feature = "serde")]
+pub(crate) mod sampled {
+	use core::{fmt, marker::PhantomData};
+
+	use serde::{
+		Deserialize, Deserializer, Serialize, Serializer,
+		de::{self, IntoDeserializer, MapAccess, Visitor, value},
+		ser::SerializeStruct,
+	};
+
+	use super::Timestamp;
+	use crate::field::Trailing;
+
+	/// Serializes the value, with its time when present
+	pub fn serialize<T: Serialize, S: Serializer>(
+		value: &T,
+		sampled_at: &Trailing<Timestamp>,
+		serializer: S,
+	) -> Result<S::Ok, S::Error> {
+		match sampled_at.0 {
+			None => value.serialize(serializer),
+			Some(sampled_at) => {
+				let mut structure = serializer.serialize_struct("Sampled", 2)?;
+				structure.serialize_field("value", value)?;
+				structure.serialize_field("sampled_at", &sampled_at)?;
+				structure.end()
+			}
+		}
+	}
+
+	/// Deserializes the value alone or with its time
+	pub fn deserialize<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<(T, Trailing<Timestamp>), D::Error> {
+		deserializer.deserialize_any(SampledVisitor(PhantomData))
+	}
+
+	/// Members of the structure with a time
+	#[derive(Deserialize)]
+	#[serde(field_identifier, rename_all = "snake_case")]
+	enum Key {
+		/// The sampled value
+		Value,
+		/// When the value was sampled
+		SampledAt,
+	}
+
+	/// Tells a lone value from the structure with a time
+	struct SampledVisitor<T>(PhantomData<T>);
+
+	impl<'de, T: Deserialize<'de>> Visitor<'de> for SampledVisitor<T> {
+		type Value = (T, Trailing<Timestamp>);
+
+		fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+			formatter.write_str("a value, or a value with the time it was sampled at")
+		}
+
+		fn visit_u64<E: de::Error>(self, number: u64) -> Result<Self::Value, E> {
+			let value = T::deserialize(Present(number.into_deserializer()))?;
+			Ok((value, Trailing::NONE))
+		}
+
+		fn visit_i64<E: de::Error>(self, number: i64) -> Result<Self::Value, E> {
+			let value = T::deserialize(Present(number.into_deserializer()))?;
+			Ok((value, Trailing::NONE))
+		}
+
+		fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+			let value = T::deserialize(value::UnitDeserializer::new())?;
+			Ok((value, Trailing::NONE))
+		}
+
+		fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+			self.visit_unit()
+		}
+
+		fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+			let (mut value, mut sampled_at) = (None, None);
+			while let Some(key) = map.next_key()? {
+				match key {
+					Key::Value if value.is_none() => value = Some(map.next_value()?),
+					Key::SampledAt if sampled_at.is_none() => {
+						sampled_at = Some(map.next_value()?);
+					}
+					Key::Value => return Err(de::Error::duplicate_field("value")),
+					Key::SampledAt => return Err(de::Error::duplicate_field("sampled_at")),
+				}
+			}
+
+			let value = value.ok_or_else(|| de::Error::missing_field("value"))?;
+			Ok((value, Trailing(sampled_at)))
+		}
+	}
+
+	/// Forwards to a deserializer of a number, which is present when an option is expected
+	struct Present<D>(D);
+
+	impl<'de, D: Deserializer<'de>> Deserializer<'de> for Present<D> {
+		type Error = D::Error;
+
+		fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+			self.0.deserialize_any(visitor)
+		}
+
+		fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+			visitor.visit_some(self.0)
+		}
+
+		serde::forward_to_deserialize_any! {
+			bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+			unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+			ignored_any
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// An exchange with a car clock `offset` ahead, over a link taking `delay` each way
+	fn exchange(origin: u64, offset: i64, delay: u64) -> SyncSample {
+		let received = Timestamp(origin + delay).offset(offset);
+		SyncSample {
+			origin: Timestamp(origin),
+			received,
+			transmitted: received.offset(200),
+			answered: Timestamp(origin + 2 * delay + 200),
+		}
+	}
+
+	#[test]
+	fn samples_measure_offset_and_delay() {
+		let sample = exchange(1_000_000, 5_000_000, 15_000);
+
+		assert_eq!(sample.offset_us(), 5_000_000);
+		assert_eq!(sample.delay_us(), 30_000);
+		assert_eq!(sample.midpoint(), Timestamp(1_015_100));
+
+		// The car booted after the controller
+		assert_eq!(exchange(9_000_000, -8_000_000, 0).offset_us(), -8_000_000);
+	}
+
+	#[test]
+	fn estimates_prefer_fast_exchanges() {
+		let mut estimator = ClockEstimator::<4>::new();
+		assert_eq!(estimator.estimate(), None);
+
+		estimator.add(exchange(1_000_000, 5_000_000, 10_000));
+		// Delayed on the way back only, which skews the offset
+		let mut late = exchange(2_000_000, 5_000_000, 10_000);
+		late.answered = late.answered.offset(80_000);
+		estimator.add(late);
+
+		let estimate = estimator.estimate().expect("there are samples");
+		assert!((estimate.offset_us - 5_000_000.0).abs() < 1.0);
+		assert!(estimate.drift_ppm.abs() < f64::EPSILON);
+		assert_eq!(estimate.to_host(Timestamp(6_010_100)), Timestamp(1_010_100));
+
+		for origin in 3..8 {
+			estimator.add(exchange(origin * 1_000_000, 5_000_000, 10_000));
+		}
+		assert_eq!(estimator.len(), 4);
+	}
+
+	#[test]
+	fn estimates_follow_drift() {
+		let mut estimator = ClockEstimator::<8>::new();
+		// The car clock runs 50 microseconds per second faster
+		for second in 1..=8 {
+			estimator.add(exchange(
+				second * 1_000_000,
+				3_000 + 50 * second.cast_signed(),
+				5_000,
+			));
+		}
+
+		let estimate = estimator.estimate().expect("there are samples");
+		assert!((estimate.drift_ppm - 50.0).abs() < 0.5);
+
+		let host = Timestamp(20_000_000);
+		let car = estimate.to_car(host);
+		assert_eq!(car, Timestamp(20_004_000));
+		assert_eq!(estimate.to_host(car), host);
+	}
+}
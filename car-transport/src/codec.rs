@@ -0,0 +1,193 @@
+//! [`tokio_util::codec`] adapters for the host side
+//!
+//! A [`Codec`] frames [`Transport`] values over any `AsyncRead` and `AsyncWrite`, such as a
+//! serial port, a TCP or a Unix socket. Wrapped in a [`Framed`](tokio_util::codec::Framed),
+//! the byte stream becomes a `Sink` of sent values and a `Stream` of received values.
+//!
+//! Corrupted frames are yielded as [`TransportError`]s instead of ending the stream, so that
+//! a noisy link keeps going with the following frames.
+//!
+//! With the `auth` feature, a codec given a [`Session`] seals every encoded value and opens the
+//! received ones. Plain values are still decoded, as the car refuses commands in plain frames.
+
+use std::{io, marker::PhantomData};
+
+use tokio_util::{
+	bytes::{Buf, BytesMut},
+	codec::{Decoder, Encoder},
+};
+
+#[cfg(feature = "auth")]
+use crate::auth::{Opened, Session};
+use crate::{
+	Answer, Envelope, FrameDecoder, FrameEncoder, Message, Transport, TransportError,
+	auth::SEAL_SIZE,
+	frame::{DEFAULT_FRAME_BUFFER_SIZE, max_frame_size},
+};
+
+/// The controller side of a codec, encodes messages and decodes answers
+pub type ControllerCodec = Codec<Envelope<Message>, Envelope<Answer>>;
+
+/// The car side of a codec, encodes answers and decodes messages
+pub type CarCodec = Codec<Envelope<Answer>, Envelope<Message>>;
+
+/// Encodes `Tx` values and decodes `Rx` values as frames
+#[derive(Debug, Default)]
+pub struct Codec<Tx: Transport, Rx: Transport> {
+	/// Frames the encoded values
+	encoder: FrameEncoder,
+	/// Extracts the values from the received bytes
+	decoder: FrameDecoder<Rx>,
+	/// The authenticated session, if any
+	#[cfg(feature = "auth")]
+	session: Option<Session>,
+
+	/// The type of encoded values
+	_transport: PhantomData<fn(Tx)>,
+}
+
+impl<Tx: Transport, Rx: Transport> Codec<Tx, Rx> {
+	/// Creates a new codec
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			encoder: FrameEncoder::new(),
+			decoder: FrameDecoder::new(),
+			#[cfg(feature = "auth")]
+			session: None,
+			_transport: PhantomData,
+		}
+	}
+
+	/// Replaces the session used to seal and open frames, [`None`] goes back to plain frames
+	#[cfg(feature = "auth")]
+	pub const fn set_session(&mut self, session: Option<Session>) {
+		self.session = session;
+	}
+
+	/// Returns the serialized value of the last decoded frame, see [`FrameDecoder::last_frame`]
+	#[must_use]
+	pub fn last_frame(&self) -> &[u8] {
+		self.decoder.last_frame()
+	}
+}
+
+impl<Tx: Transport, Rx: Transport> Encoder<Tx> for Codec<Tx, Rx> {
+	type Error = io::Error;
+
+	fn encode(&mut self, value: Tx, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		self.encode(&value, dst)
+	}
+}
+
+impl<Tx: Transport, Rx: Transport> Encoder<&Tx> for Codec<Tx, Rx> {
+	type Error = io::Error;
+
+	fn encode(&mut self, value: &Tx, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		let mut frame = [0_u8; max_frame_size(DEFAULT_FRAME_BUFFER_SIZE + SEAL_SIZE)];
+
+		#[cfg(feature = "auth")]
+		let length = match &mut self.session {
+			Some(session) => self.encoder.encode_sealed(value, session, &mut frame),
+			None => self.encoder.encode(value, &mut frame),
+		};
+		#[cfg(not(feature = "auth"))]
+		let length = self.encoder.encode(value, &mut frame);
+
+		let length = length.map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+
+		dst.extend_from_slice(&frame[..length]);
+
+		Ok(())
+	}
+}
+
+impl<Tx: Transport, Rx: Transport> Decoder for Codec<Tx, Rx> {
+	type Item = Result<Rx, TransportError>;
+	type Error = io::Error;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		// Bytes are consumed as they are pushed, the decoder keeps the partial frame
+		while src.has_remaining() {
+			#[cfg(feature = "auth")]
+			let value = self
+				.decoder
+				.push_sealed(src.get_u8(), self.session.as_mut())
+				.map(|value| value.map(Opened::into_inner));
+			#[cfg(not(feature = "auth"))]
+			let value = self.decoder.push(src.get_u8());
+
+			if let Some(value) = value {
+				return Ok(Some(value));
+			}
+		}
+
+		Ok(None)
+	}
+
+	fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		let value = self.decode(src)?;
+
+		// A partial frame at the end of the stream is never completed
+		if value.is_none() {
+			self.decoder.reset();
+		}
+
+		Ok(value)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{LeaseToken, Sequence, Throttle};
+
+	#[test]
+	fn frames_cross_the_codec_in_chunks() {
+		let message = Envelope::new(
+			Sequence(7),
+			Message::SetSpeed {
+				lease: LeaseToken(1),
+				throttle: Throttle::MIN,
+			},
+		);
+
+		let mut wire = BytesMut::new();
+		let mut controller = ControllerCodec::new();
+		controller
+			.encode(&message, &mut wire)
+			.expect("frame fits in the buffer");
+		controller
+			.encode(message.clone(), &mut wire)
+			.expect("frame fits in the buffer");
+
+		let mut car = CarCodec::new();
+		let mut chunk = wire.split_to(3);
+		assert_eq!(car.decode(&mut chunk).expect("infallible"), None);
+
+		assert_eq!(
+			car.decode(&mut wire).expect("infallible"),
+			Some(Ok(message.clone()))
+		);
+		assert_eq!(
+			car.decode_eof(&mut wire).expect("infallible"),
+			Some(Ok(message))
+		);
+		assert_eq!(car.decode_eof(&mut wire).expect("infallible"), None);
+	}
+
+	#[test]
+	fn corrupted_frames_do_not_end_the_stream() {
+		let mut wire = BytesMut::from(&[1, 2, 3, 0][..]);
+		Encoder::encode(
+			&mut ControllerCodec::new(),
+			Envelope::new(Sequence(0), Message::Ping),
+			&mut wire,
+		)
+		.expect("frame fits in the buffer");
+
+		let mut car = CarCodec::new();
+		assert!(matches!(car.decode(&mut wire), Ok(Some(Err(_)))));
+		assert!(matches!(car.decode(&mut wire), Ok(Some(Ok(_)))));
+	}
+}
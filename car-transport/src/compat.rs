@@ -0,0 +1,381 @@
+//! Decoding of the previous revision of the protocol
+//!
+//! Cars that were not updated speak [`VERSION`], they tell it in their
+//! [`Answer::Hello`](crate::Answer::Hello). Frames and envelopes did not change since, and its
+//! answers are a subset of the current ones, but the commands that drive or configure the car did
+//! not carry a [`LeaseToken`] yet.
+//!
+//! A controller drives such cars once [`negotiate`](crate::handshake::negotiate) returned a
+//! session that [`is_compat`](crate::handshake::Session::is_compat): its messages are converted
+//! with [`Message::downgrade`], and the answers of the car decode as current answers.
+//!
+//! Frames of that revision are decoded with the [`Message`] and [`Answer`] of this module, then
+//! upgraded to the current types:
+//! ```
+//! use car_transport::{Envelope, FrameDecoder, LeaseToken, Throttle, compat};
+//!
+//! // `SetSpeed` to 100% written by a controller speaking `3.1`
+//! let frame = [0x05, 0x03, 0x64, 0x64, 0x7e, 0x01, 0x00];
+//!
+//! let mut decoder = FrameDecoder::<Envelope<compat::Message>>::new();
+//! let envelope = decoder.feed(&frame).next().expect("frame is complete")?;
+//!
+//! assert_eq!(
+//!     envelope.upgrade(LeaseToken(7)).content,
+//!     car_transport::Message::SetSpeed { lease: LeaseToken(7), throttle: Throttle::MAX }
+//! );
+//! # Ok::<(), car_transport::TransportError>(())
+//! ```
+
+use crate::{
+	Envelope, LeaseToken, Param, ParamId, ProtocolVersion, Steering, Throttle, Topics, Transport,
+	TransportError,
+};
+
+/// Revision of the protocol decoded by this module
+pub const VERSION: ProtocolVersion = ProtocolVersion { major: 3, minor: 1 };
+
+/// Ids of the answers that exist in [`VERSION`], the others were added since
+const ANSWER_IDS: [u8; 18] = [
+	0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 100, 101, 102, 103, 104, 200, 255,
+];
+
+/// Messages sent by a controller speaking [`VERSION`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Transport)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Message {
+	/// See [`Message::Ping`](crate::Message::Ping)
+	#[transport(id = 0)]
+	Ping,
+	/// See [`Message::Hello`](crate::Message::Hello)
+	#[transport(id = 5)]
+	Hello {
+		/// The revision spoken by the controller
+		version: ProtocolVersion,
+	},
+
+	/// See [`Message::GetSpeed`](crate::Message::GetSpeed)
+	#[transport(id = 1)]
+	GetSpeed,
+	/// See [`Message::GetDirection`](crate::Message::GetDirection)
+	#[transport(id = 2)]
+	GetDirection,
+	/// See [`Message::GetBatteryLevel`](crate::Message::GetBatteryLevel)
+	#[transport(id = 3)]
+	GetBatteryLevel,
+	/// See [`Message::GetUltrasonicDistance`](crate::Message::GetUltrasonicDistance)
+	#[transport(id = 4)]
+	GetUltrasonicDistance,
+	/// See [`Message::GetParam`](crate::Message::GetParam)
+	#[transport(id = 6)]
+	GetParam(ParamId),
+	/// See [`Message::ListParams`](crate::Message::ListParams)
+	#[transport(id = 7)]
+	ListParams {
+		/// Position of the tunable
+		index: u8,
+	},
+	/// See [`Message::Subscribe`](crate::Message::Subscribe)
+	#[transport(id = 8)]
+	Subscribe {
+		/// The values to send
+		topics: Topics,
+		/// Time between two snapshots
+		#[transport(unit = "ms")]
+		period_ms: u16,
+	},
+	/// See [`Message::Unsubscribe`](crate::Message::Unsubscribe)
+	#[transport(id = 9)]
+	Unsubscribe,
+	/// See [`Message::GetFault`](crate::Message::GetFault)
+	#[transport(id = 10)]
+	GetFault,
+
+	/// Set the current speed, without lease
+	#[transport(id = 100)]
+	SetSpeed(Throttle),
+	/// Set the current direction, without lease
+	#[transport(id = 101)]
+	SetDirection(Steering),
+	/// Change a tunable, without lease
+	#[transport(id = 102)]
+	SetParam(Param),
+	/// See [`Message::EmergencyStop`](crate::Message::EmergencyStop)
+	#[transport(id = 103)]
+	EmergencyStop,
+	/// Clear the latched fault, without lease
+	#[transport(id = 104)]
+	ClearFault,
+}
+
+impl Message {
+	/// Converts a message of the current revision for a car speaking [`VERSION`], [`None`] when
+	/// the message was added since
+	///
+	/// The commands lose their lease token, see [`Message::upgrade`].
+	#[must_use]
+	pub const fn downgrade(message: &crate::Message) -> Option<Self> {
+		Some(match *message {
+			crate::Message::Ping => Self::Ping,
+			crate::Message::Hello { version } => Self::Hello { version },
+			crate::Message::GetSpeed => Self::GetSpeed,
+			crate::Message::GetDirection => Self::GetDirection,
+			crate::Message::GetBatteryLevel => Self::GetBatteryLevel,
+			crate::Message::GetUltrasonicDistance => Self::GetUltrasonicDistance,
+			crate::Message::GetParam(id) => Self::GetParam(id),
+			crate::Message::ListParams { index } => Self::ListParams { index },
+			crate::Message::Subscribe { topics, period_ms } => {
+				Self::Subscribe { topics, period_ms }
+			}
+			crate::Message::Unsubscribe => Self::Unsubscribe,
+			crate::Message::GetFault => Self::GetFault,
+			crate::Message::SetSpeed { throttle, .. } => Self::SetSpeed(throttle),
+			crate::Message::SetDirection { steering, .. } => Self::SetDirection(steering),
+			crate::Message::SetParam { param, .. } => Self::SetParam(param),
+			crate::Message::EmergencyStop => Self::EmergencyStop,
+			crate::Message::ClearFault { .. } => Self::ClearFault,
+			crate::Message::AuthChallenge { .. }
+			| crate::Message::Authenticate { .. }
+			| crate::Message::SetLogLevel { .. }
+			| crate::Message::TimeSync { .. }
+			| crate::Message::AcquireControl { .. }
+			| crate::Message::ReleaseControl { .. }
+			| crate::Message::BeginUpdate { .. }
+			| crate::Message::WriteUpdate { .. }
+			| crate::Message::VerifyUpdate { .. }
+			| crate::Message::CommitUpdate { .. } => return None,
+		})
+	}
+
+	/// Converts the message into the current revision, the commands carry `lease`
+	///
+	/// Controllers speaking [`VERSION`] cannot acquire the lease, the car usually gives them the
+	/// token of the current holder when nobody else is connected.
+	#[must_use]
+	pub const fn upgrade(self, lease: LeaseToken) -> crate::Message {
+		match self {
+			Self::Ping => crate::Message::Ping,
+			Self::Hello { version } => crate::Message::Hello { version },
+			Self::GetSpeed => crate::Message::GetSpeed,
+			Self::GetDirection => crate::Message::GetDirection,
+			Self::GetBatteryLevel => crate::Message::GetBatteryLevel,
+			Self::GetUltrasonicDistance => crate::Message::GetUltrasonicDistance,
+			Self::GetParam(id) => crate::Message::GetParam(id),
+			Self::ListParams { index } => crate::Message::ListParams { index },
+			Self::Subscribe { topics, period_ms } => {
+				crate::Message::Subscribe { topics, period_ms }
+			}
+			Self::Unsubscribe => crate::Message::Unsubscribe,
+			Self::GetFault => crate::Message::GetFault,
+			Self::SetSpeed(throttle) => crate::Message::SetSpeed { lease, throttle },
+			Self::SetDirection(steering) => crate::Message::SetDirection { lease, steering },
+			Self::SetParam(param) => crate::Message::SetParam { lease, param },
+			Self::EmergencyStop => crate::Message::EmergencyStop,
+			Self::ClearFault => crate::Message::ClearFault { lease },
+		}
+	}
+}
+
+/// Answers sent by a car speaking [`VERSION`]
+///
+/// They are encoded like the current [`Answer`](crate::Answer)s, but answers added since are
+/// refused with [`TransportError::InvalidId`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct Answer(crate::Answer);
+
+impl Answer {
+	/// Converts the answer into the current revision
+	#[must_use]
+	pub fn upgrade(self) -> crate::Answer {
+		self.0
+	}
+}
+
+impl Transport for Answer {
+	const MAX_PAYLOAD_SIZE: usize = crate::Answer::MAX_PAYLOAD_SIZE;
+
+	fn id(&self) -> u8 {
+		self.0.id()
+	}
+
+	fn encode(&self, buffer: &mut [u8]) -> usize {
+		self.0.encode(buffer)
+	}
+
+	fn deserialize(buffer: &[u8]) -> Result<Self, TransportError> {
+		let answer = crate::Answer::deserialize(buffer)?;
+
+		if !ANSWER_IDS.contains(&answer.id()) {
+			return Err(TransportError::InvalidId);
+		}
+
+		Ok(Self(answer))
+	}
+}
+
+impl Envelope<Message> {
+	/// Converts the message for a car speaking [`VERSION`], see [`Message::downgrade`]
+	#[must_use]
+	pub const fn downgrade(envelope: &Envelope<crate::Message>) -> Option<Self> {
+		match Message::downgrade(&envelope.content) {
+			Some(content) => Some(Self::new(envelope.sequence, content)),
+			None => None,
+		}
+	}
+
+	/// Converts the message into the current revision, see [`Message::upgrade`]
+	#[must_use]
+	pub const fn upgrade(self, lease: LeaseToken) -> Envelope<crate::Message> {
+		Envelope::new(self.sequence, self.content.upgrade(lease))
+	}
+}
+
+impl Envelope<Answer> {
+	/// Converts the answer into the current revision
+	#[must_use]
+	pub fn upgrade(self) -> Envelope<crate::Answer> {
+		Envelope::new(self.sequence, self.content.upgrade())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		FrameDecoder, FrameEncoder, LogLevel, LogText, PROTOCOL_VERSION, Sequence,
+		frame::{DEFAULT_FRAME_BUFFER_SIZE, max_frame_size},
+		handshake::negotiate,
+	};
+
+	#[test]
+	fn previous_messages_are_upgraded() -> Result<(), TransportError> {
+		// `ClearFault` and `SetDirection` to -100% with sequence numbers 4 and 5
+		let frames = [
+			0x05, 0x04, 0x68, 0x3c, 0x65, 0x00, 0x06, 0x05, 0x65, 0x9c, 0x91, 0x86, 0x00,
+		];
+		let mut decoder = FrameDecoder::<Envelope<Message>>::new();
+		let mut envelopes = decoder.feed(&frames);
+
+		let lease = LeaseToken(0x1234);
+		assert_eq!(
+			envelopes.next().expect("first frame")?.upgrade(lease),
+			Envelope::new(Sequence(4), crate::Message::ClearFault { lease })
+		);
+		assert_eq!(
+			envelopes.next().expect("second frame")?.upgrade(lease),
+			Envelope::new(
+				Sequence(5),
+				crate::Message::SetDirection {
+					lease,
+					steering: Steering::MIN
+				}
+			)
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn older_cars_are_driven_through_this_revision() -> Result<(), TransportError> {
+		let mut encoder = FrameEncoder::<DEFAULT_FRAME_BUFFER_SIZE>::new();
+		let mut frame = [0_u8; max_frame_size(DEFAULT_FRAME_BUFFER_SIZE)];
+		let mut car = FrameDecoder::<Envelope<Message>>::new();
+		let mut controller = FrameDecoder::<Envelope<crate::Answer>>::new();
+
+		// The car understands the hello of the current revision
+		let hello = Envelope::new(
+			Sequence(1),
+			crate::Message::Hello {
+				version: PROTOCOL_VERSION,
+			},
+		);
+		let length = encoder.encode(
+			&Envelope::downgrade(&hello).expect("hello exists"),
+			&mut frame,
+		)?;
+		let received = car
+			.feed(&frame[..length])
+			.next()
+			.expect("frame is complete")?;
+		assert_eq!(received.upgrade(LeaseToken(0)), hello);
+
+		// `Hello` answer of a car speaking `3.1`, from its vectors
+		let answer = [
+			0x05, 0x01, 0x05, 0x03, 0x01, 0x02, 0x01, 0x01, 0x04, 0x0f, 0xa1, 0x96, 0x00,
+		];
+		let crate::Answer::Hello {
+			version,
+			firmware,
+			capabilities,
+		} = controller
+			.feed(&answer)
+			.next()
+			.expect("frame is complete")?
+			.content
+		else {
+			panic!("the car answers the hello");
+		};
+		let session = negotiate(version, firmware, capabilities).expect("3.1 is still driven");
+		assert!(session.is_compat());
+
+		// Commands lose their lease, the frame is the one written by a `3.1` controller
+		let speed = Envelope::new(
+			Sequence(3),
+			crate::Message::SetSpeed {
+				lease: LeaseToken(7),
+				throttle: Throttle::MAX,
+			},
+		);
+		assert!(session.supports(&speed.content));
+		let length = encoder.encode(
+			&Envelope::downgrade(&speed).expect("speed exists"),
+			&mut frame,
+		)?;
+		assert_eq!(&frame[..length], [0x05, 0x03, 0x64, 0x64, 0x7e, 0x01, 0x00]);
+		let received = car
+			.feed(&frame[..length])
+			.next()
+			.expect("frame is complete")?;
+		assert_eq!(received.upgrade(LeaseToken(7)), speed);
+
+		// Messages added since are not sent
+		let acquire = crate::Message::AcquireControl {
+			renew: None,
+			duration_ms: 1000,
+		};
+		assert!(!session.supports(&acquire));
+		assert_eq!(Message::downgrade(&acquire), None);
+
+		Ok(())
+	}
+
+	#[test]
+	fn newer_answers_are_refused() {
+		let mut buffer = [0_u8; crate::Answer::BUFFER_SIZE];
+
+		let length = crate::Answer::AckSpeed.serialize(&mut buffer);
+		assert_eq!(
+			Answer::deserialize(&buffer[..length]).map(Answer::upgrade),
+			Ok(crate::Answer::AckSpeed)
+		);
+
+		let log = crate::Answer::Log {
+			level: LogLevel::Info,
+			dropped: 0,
+			text: LogText::truncated("started"),
+		};
+		let length = log.serialize(&mut buffer);
+		assert_eq!(
+			Answer::deserialize(&buffer[..length]),
+			Err(TransportError::InvalidId)
+		);
+	}
+}
@@ -0,0 +1,554 @@
+//! Firmware updates over the link
+//!
+//! The controller streams a new firmware image in chunks, which the car writes to a spare flash
+//! partition. Once the image is complete and its hash checked, the car asks the bootloader to
+//! swap it in and reboots:
+//! ```text
+//! controller                                              car
+//!     | -- BeginUpdate { size, hash } ----------------------> | erases the spare partition
+//!     | <- UpdateReady { offset } --------------------------- |
+//!     | -- WriteUpdate { offset, chunk } -------------------> | until the image is complete
+//!     | <- AckWriteUpdate { next } -------------------------- |
+//!     | -- VerifyUpdate ------------------------------------> | hashes the written image
+//!     | <- UpdateVerified ----------------------------------- |
+//!     | -- CommitUpdate ------------------------------------> | marks the image to be swapped in
+//!     | <- AckCommitUpdate ---------------------------------- | and reboots
+//! ```
+//! Beginning the same image again resumes where the car stopped, so that an upload interrupted
+//! by a lost link does not start over. Chunks are only written at the offset the car expects,
+//! the answer tells the controller where to continue.
+//!
+//! The car handles these messages with an [`Updater`] over its [`UpdateFlash`], the controller
+//! sends them with an [`Uploader`]. The payloads are always available, hashing and both state
+//! machines need the `dfu` feature.
+
+use core::fmt;
+
+#[cfg(feature = "dfu")]
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "dfu")]
+use crate::{Answer, ErrorCode, LeaseToken, Message};
+use crate::{Field, ProtocolVersion};
+
+/// First protocol revision with firmware updates
+pub const SINCE: ProtocolVersion = ProtocolVersion { major: 4, minor: 2 };
+
+/// Maximum number of image bytes in a [`Chunk`]
+pub const CHUNK_SIZE: usize = 32;
+/// Size of an [`ImageHash`] in bytes
+pub const HASH_SIZE: usize = 32;
+
+/// `SHA-256` of a firmware image
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct ImageHash(pub [u8; HASH_SIZE]);
+
+impl ImageHash {
+	/// Hashes a whole image
+	#[cfg(feature = "dfu")]
+	#[must_use]
+	pub fn of(image: &[u8]) -> Self {
+		Self(Sha256::digest(image).into())
+	}
+}
+
+impl fmt::Display for ImageHash {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+	}
+}
+
+/// Part of a firmware image, at most [`CHUNK_SIZE`] bytes
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct Chunk(pub heapless::Vec<u8, CHUNK_SIZE>);
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Chunk {
+	fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+		let length = u.int_in_range(0..=CHUNK_SIZE)?;
+		let bytes = heapless::Vec::from_slice(u.bytes(length)?)
+			.map_err(|_| arbitrary::Error::IncorrectFormat)?;
+
+		Ok(Self(bytes))
+	}
+}
+
+/// The spare flash partition receiving the image, usually managed by the bootloader
+#[cfg(feature = "dfu")]
+pub trait UpdateFlash {
+	/// Why the flash could not be accessed
+	type Error;
+
+	/// Returns the size of the partition in bytes
+	fn capacity(&self) -> u32;
+
+	/// Erases the partition before a new image is written
+	///
+	/// # Errors
+	/// In case the flash cannot be erased
+	fn erase(&mut self) -> Result<(), Self::Error>;
+
+	/// Writes part of the image, offsets only go up and no byte is written twice
+	///
+	/// # Errors
+	/// In case the flash cannot be written
+	fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+	/// Hashes the first `size` bytes of the partition
+	///
+	/// # Errors
+	/// In case the flash cannot be read
+	fn hash(&mut self, size: u32) -> Result<ImageHash, Self::Error>;
+
+	/// Asks the bootloader to swap the image in on the next reset
+	///
+	/// # Errors
+	/// In case the bootloader state cannot be written
+	fn mark_updated(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Progress of the update on the car
+#[cfg(feature = "dfu")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+	/// No image is being received
+	Idle,
+	/// Chunks of the image are being written
+	Receiving {
+		/// Size of the whole image
+		size: u32,
+		/// Expected hash of the whole image
+		hash: ImageHash,
+		/// Number of bytes written so far
+		written: u32,
+	},
+	/// The image is complete and matches its hash
+	Verified {
+		/// Size of the whole image
+		size: u32,
+		/// Hash of the whole image
+		hash: ImageHash,
+	},
+}
+
+/// The car side of an update, writes the received image to the flash
+#[cfg(feature = "dfu")]
+#[derive(Debug)]
+pub struct Updater<F> {
+	/// The partition receiving the image
+	flash: F,
+	/// Progress of the update
+	state: State,
+}
+
+#[cfg(feature = "dfu")]
+impl<F: UpdateFlash> Updater<F> {
+	/// Creates an updater that is not receiving any image
+	pub const fn new(flash: F) -> Self {
+		Self {
+			flash,
+			state: State::Idle,
+		}
+	}
+
+	/// Handles the update messages, returns [`None`] for the other ones
+	pub fn handle(&mut self, message: &Message) -> Option<Result<Answer, ErrorCode>> {
+		let answer = match message {
+			Message::BeginUpdate { size, hash, .. } => self
+				.begin(*size, *hash)
+				.map(|offset| Answer::UpdateReady { offset }),
+			Message::WriteUpdate { offset, chunk, .. } => self
+				.write(*offset, &chunk.0)
+				.map(|next| Answer::AckWriteUpdate { next }),
+			Message::VerifyUpdate { .. } => self.verify().map(|()| Answer::UpdateVerified),
+			Message::CommitUpdate { .. } => self.commit().map(|()| Answer::AckCommitUpdate),
+			_ => return None,
+		};
+
+		Some(answer)
+	}
+
+	/// Prepares the flash for an image and returns the offset of the first chunk to send
+	///
+	/// The offset is not zero when the same image was already partly received.
+	///
+	/// # Errors
+	/// [`ErrorCode::OutOfRange`] when the image does not fit in the partition,
+	/// [`ErrorCode::FlashFailed`] when the partition cannot be erased
+	pub fn begin(&mut self, size: u32, hash: ImageHash) -> Result<u32, ErrorCode> {
+		if size > self.flash.capacity() {
+			return Err(ErrorCode::OutOfRange);
+		}
+
+		match self.state {
+			State::Receiving {
+				size: current,
+				hash: expected,
+				written,
+			} if (current, expected) == (size, hash) => return Ok(written),
+			State::Verified {
+				size: current,
+				hash: expected,
+			} if (current, expected) == (size, hash) => return Ok(size),
+			_ => {}
+		}
+
+		self.state = State::Idle;
+		self.flash.erase().map_err(|_| ErrorCode::FlashFailed)?;
+		self.state = State::Receiving {
+			size,
+			hash,
+			written: 0,
+		};
+
+		Ok(0)
+	}
+
+	/// Writes a chunk and returns the offset of the next one
+	///
+	/// A chunk that is not at the expected offset, such as a retransmitted one, is ignored.
+	///
+	/// # Errors
+	/// [`ErrorCode::UpdateIncomplete`] when no image is being received,
+	/// [`ErrorCode::OutOfRange`] when the chunk goes past the end of the image,
+	/// [`ErrorCode::FlashFailed`] when the chunk cannot be written, the update must begin again
+	pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<u32, ErrorCode> {
+		let State::Receiving { size, written, .. } = &mut self.state else {
+			return Err(ErrorCode::UpdateIncomplete);
+		};
+		if offset != *written {
+			return Ok(*written);
+		}
+
+		let end = u32::try_from(data.len())
+			.ok()
+			.and_then(|length| offset.checked_add(length))
+			.filter(|end| end <= size)
+			.ok_or(ErrorCode::OutOfRange)?;
+
+		if self.flash.write(offset, data).is_err() {
+			// The partly written chunk cannot be written again without erasing
+			self.state = State::Idle;
+			return Err(ErrorCode::FlashFailed);
+		}
+		*written = end;
+
+		Ok(end)
+	}
+
+	/// Checks that the image is complete and matches its hash
+	///
+	/// # Errors
+	/// [`ErrorCode::UpdateIncomplete`] when chunks are missing, [`ErrorCode::ImageMismatch`]
+	/// when the written image does not match its hash, the update must then begin again
+	pub fn verify(&mut self) -> Result<(), ErrorCode> {
+		let (size, hash) = match self.state {
+			State::Verified { .. } => return Ok(()),
+			State::Receiving {
+				size,
+				hash,
+				written,
+			} if written == size => (size, hash),
+			_ => return Err(ErrorCode::UpdateIncomplete),
+		};
+
+		let written = self.flash.hash(size).map_err(|_| ErrorCode::FlashFailed)?;
+		if written != hash {
+			self.state = State::Idle;
+			return Err(ErrorCode::ImageMismatch);
+		}
+
+		self.state = State::Verified { size, hash };
+		Ok(())
+	}
+
+	/// Asks the bootloader to swap the verified image in, the car must then reboot
+	///
+	/// # Errors
+	/// [`ErrorCode::UpdateIncomplete`] when the image was not verified,
+	/// [`ErrorCode::FlashFailed`] when the bootloader state cannot be written
+	pub fn commit(&mut self) -> Result<(), ErrorCode> {
+		if !matches!(self.state, State::Verified { .. }) {
+			return Err(ErrorCode::UpdateIncomplete);
+		}
+
+		self.flash
+			.mark_updated()
+			.map_err(|_| ErrorCode::FlashFailed)
+	}
+
+	/// Returns the partition receiving the image
+	pub const fn flash(&self) -> &F {
+		&self.flash
+	}
+}
+
+/// The controller side of an update, cuts the image into [`Message::WriteUpdate`] chunks
+#[cfg(feature = "dfu")]
+#[derive(Debug, Clone)]
+pub struct Uploader<'a> {
+	/// The whole image
+	image: &'a [u8],
+	/// Size of the whole image
+	size: u32,
+	/// Hash of the whole image
+	hash: ImageHash,
+	/// Offset of the next chunk to send
+	offset: u32,
+}
+
+#[cfg(feature = "dfu")]
+impl<'a> Uploader<'a> {
+	/// Hashes the image to upload, returns [`None`] when it is larger than 4 GiB
+	#[must_use]
+	pub fn new(image: &'a [u8]) -> Option<Self> {
+		Some(Self {
+			image,
+			size: u32::try_from(image.len()).ok()?,
+			hash: ImageHash::of(image),
+			offset: 0,
+		})
+	}
+
+	/// Returns the size of the image
+	#[must_use]
+	pub const fn size(&self) -> u32 {
+		self.size
+	}
+
+	/// Returns the hash of the image
+	#[must_use]
+	pub const fn hash(&self) -> ImageHash {
+		self.hash
+	}
+
+	/// Returns the offset of the next chunk to send
+	#[must_use]
+	pub const fn offset(&self) -> u32 {
+		self.offset
+	}
+
+	/// Returns whether every chunk was acknowledged
+	#[must_use]
+	pub const fn is_complete(&self) -> bool {
+		self.offset == self.size
+	}
+
+	/// Returns the message that starts, or resumes, the update
+	#[must_use]
+	pub const fn begin(&self, lease: LeaseToken) -> Message {
+		Message::BeginUpdate {
+			lease,
+			size: self.size,
+			hash: self.hash,
+		}
+	}
+
+	/// Returns the message carrying the next chunk, [`None`] when the image is complete
+	#[must_use]
+	pub fn next_write(&self, lease: LeaseToken) -> Option<Message> {
+		let rest = self.image.get(self.offset as usize..)?;
+		if rest.is_empty() {
+			return None;
+		}
+
+		let chunk = &rest[..rest.len().min(CHUNK_SIZE)];
+		Some(Message::WriteUpdate {
+			lease,
+			offset: self.offset,
+			chunk: Chunk(heapless::Vec::from_slice(chunk).ok()?),
+		})
+	}
+
+	/// Continues at the offset given by [`Answer::UpdateReady`] or [`Answer::AckWriteUpdate`]
+	pub fn seek(&mut self, offset: u32) {
+		self.offset = offset.min(self.size);
+	}
+}
+
+#[cfg(all(test, feature = "dfu"))]
+mod tests {
+	use super::*;
+
+	/// Size of the simulated partition
+	const CAPACITY: u32 = 256;
+
+	/// A flash partition in memory, which refuses to write a byte twice like a NOR flash
+	struct SimulatedFlash {
+		/// Content of the partition
+		memory: [u8; CAPACITY as usize],
+		/// Whether the image was marked to be swapped in
+		updated: bool,
+	}
+
+	impl SimulatedFlash {
+		/// Value of an erased byte
+		const ERASED: u8 = 0xFF;
+
+		/// Creates a partition holding a previous image
+		const fn new() -> Self {
+			Self {
+				memory: [0; CAPACITY as usize],
+				updated: false,
+			}
+		}
+	}
+
+	impl UpdateFlash for SimulatedFlash {
+		type Error = ();
+
+		fn capacity(&self) -> u32 {
+			CAPACITY
+		}
+
+		fn erase(&mut self) -> Result<(), ()> {
+			self.memory.fill(Self::ERASED);
+			Ok(())
+		}
+
+		fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+			let target = self
+				.memory
+				.get_mut(offset as usize..offset as usize + data.len())
+				.ok_or(())?;
+			if target.iter().any(|byte| *byte != Self::ERASED) {
+				return Err(());
+			}
+
+			target.copy_from_slice(data);
+			Ok(())
+		}
+
+		fn hash(&mut self, size: u32) -> Result<ImageHash, ()> {
+			Ok(ImageHash::of(&self.memory[..size as usize]))
+		}
+
+		fn mark_updated(&mut self) -> Result<(), ()> {
+			self.updated = true;
+			Ok(())
+		}
+	}
+
+	/// Sends the message to the car and returns the answer
+	fn exchange(car: &mut Updater<SimulatedFlash>, message: &Message) -> Result<Answer, ErrorCode> {
+		car.handle(message).expect("update message")
+	}
+
+	/// Sends the chunks until the image is complete or `limit` chunks were sent
+	fn upload(
+		car: &mut Updater<SimulatedFlash>,
+		uploader: &mut Uploader<'_>,
+		limit: usize,
+	) -> Result<(), ErrorCode> {
+		for _ in 0..limit {
+			let Some(message) = uploader.next_write(LeaseToken(1)) else {
+				break;
+			};
+			let Answer::AckWriteUpdate { next } = exchange(car, &message)? else {
+				unreachable!("writes are acknowledged");
+			};
+			uploader.seek(next);
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn interrupted_update_resumes() -> Result<(), ErrorCode> {
+		let image: [u8; 100] =
+			core::array::from_fn(|index| u8::try_from(index).unwrap_or_default());
+		let mut car = Updater::new(SimulatedFlash::new());
+
+		let mut uploader = Uploader::new(&image).expect("image is small");
+		let answer = exchange(&mut car, &uploader.begin(LeaseToken(1)))?;
+		assert_eq!(answer, Answer::UpdateReady { offset: 0 });
+		upload(&mut car, &mut uploader, 2)?;
+		assert_eq!(
+			exchange(
+				&mut car,
+				&Message::VerifyUpdate {
+					lease: LeaseToken(1)
+				}
+			),
+			Err(ErrorCode::UpdateIncomplete)
+		);
+
+		// The link was lost, a new upload of the same image resumes after the written chunks
+		let mut uploader = Uploader::new(&image).expect("image is small");
+		let answer = exchange(&mut car, &uploader.begin(LeaseToken(1)))?;
+		assert_eq!(answer, Answer::UpdateReady { offset: 64 });
+
+		// A retransmitted chunk is not written twice
+		let stale = Uploader::new(&image).expect("image is small");
+		let stale = stale.next_write(LeaseToken(1)).expect("image is not empty");
+		assert_eq!(
+			exchange(&mut car, &stale),
+			Ok(Answer::AckWriteUpdate { next: 64 })
+		);
+
+		uploader.seek(64);
+		upload(&mut car, &mut uploader, usize::MAX)?;
+		assert!(uploader.is_complete());
+
+		let verify = Message::VerifyUpdate {
+			lease: LeaseToken(1),
+		};
+		assert_eq!(exchange(&mut car, &verify), Ok(Answer::UpdateVerified));
+		let commit = Message::CommitUpdate {
+			lease: LeaseToken(1),
+		};
+		assert_eq!(exchange(&mut car, &commit), Ok(Answer::AckCommitUpdate));
+
+		assert!(car.flash().updated);
+		assert_eq!(car.flash().memory[..image.len()], image);
+
+		Ok(())
+	}
+
+	#[test]
+	fn corrupted_image_is_not_committed() -> Result<(), ErrorCode> {
+		let image = [0x42; 40];
+		let mut car = Updater::new(SimulatedFlash::new());
+
+		let mut uploader = Uploader::new(&image).expect("image is small");
+		exchange(&mut car, &uploader.begin(LeaseToken(1)))?;
+		upload(&mut car, &mut uploader, usize::MAX)?;
+		car.flash.memory[3] = 0;
+
+		let verify = Message::VerifyUpdate {
+			lease: LeaseToken(1),
+		};
+		assert_eq!(exchange(&mut car, &verify), Err(ErrorCode::ImageMismatch));
+		let commit = Message::CommitUpdate {
+			lease: LeaseToken(1),
+		};
+		assert_eq!(
+			exchange(&mut car, &commit),
+			Err(ErrorCode::UpdateIncomplete)
+		);
+		assert!(!car.flash().updated);
+
+		// Images that do not fit are refused before erasing anything
+		let large = Message::BeginUpdate {
+			lease: LeaseToken(1),
+			size: CAPACITY + 1,
+			hash: ImageHash::default(),
+		};
+		assert_eq!(exchange(&mut car, &large), Err(ErrorCode::OutOfRange));
+
+		Ok(())
+	}
+}
@@ -0,0 +1,413 @@
+//! Sequence numbers to correlate answers with the messages that caused them
+//!
+//! Every [`Message`] is sent inside an [`Envelope`] carrying a wrapping [`Sequence`] number,
+//! the car echoes that number in the envelope of the matching [`Answer`].
+//!
+//! The controller keeps track of its outstanding messages with [`Requests`], while the car
+//! uses a [`SequenceWindow`] to notice duplicated, reordered or missing messages.
+
+use crate::{Answer, Message, Transport, TransportError, field::Reader};
+
+/// Wrapping sequence number of an [`Envelope`]
+///
+/// Sequence numbers are compared with serial number arithmetic, a number
+/// is considered newer than the 127 numbers before it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct Sequence(pub u8);
+
+impl Sequence {
+	/// Returns the sequence number that follows this one
+	#[must_use]
+	pub const fn next(self) -> Self {
+		Self(self.0.wrapping_add(1))
+	}
+
+	/// Returns how far ahead this sequence number is from `other`, negative if it is behind
+	#[must_use]
+	pub const fn distance(self, other: Self) -> i8 {
+		i8::from_ne_bytes([self.0.wrapping_sub(other.0)])
+	}
+}
+
+/// A [`Transport`] value tagged with a [`Sequence`] number
+///
+/// It is transported in the following format:
+/// ```text
+/// +----------------+-------------------------------------------+
+/// | sequence (1b)  | sub-type id (1b) | payload (varies)       |
+/// +----------------+-------------------------------------------+
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope<T> {
+	/// The sequence number of the message, echoed by the answer
+	pub sequence: Sequence,
+	/// The wrapped value
+	pub content: T,
+}
+
+impl<T> Envelope<T> {
+	/// Wraps the content with the given sequence number
+	pub const fn new(sequence: Sequence, content: T) -> Self {
+		Self { sequence, content }
+	}
+
+	/// Reads the sequence number and content id of a serialized envelope without decoding its payload
+	///
+	/// This identifies envelopes whose content is invalid, see [`FrameDecoder::last_frame`](crate::FrameDecoder::last_frame).
+	#[must_use]
+	pub const fn peek(serialized: &[u8]) -> Option<(Sequence, u8)> {
+		match serialized {
+			[sequence, id, ..] => Some((Sequence(*sequence), *id)),
+			_ => None,
+		}
+	}
+}
+
+impl Envelope<Message> {
+	/// Wraps the answer to this message in an envelope with the same sequence number
+	#[must_use]
+	pub const fn reply(&self, answer: Answer) -> Envelope<Answer> {
+		Envelope::new(self.sequence, answer)
+	}
+}
+
+impl<T: Transport> Transport for Envelope<T> {
+	const MAX_PAYLOAD_SIZE: usize = T::BUFFER_SIZE;
+
+	/// The sequence number takes the place of the sub-type id
+	fn id(&self) -> u8 {
+		self.sequence.0
+	}
+
+	fn encode(&self, buffer: &mut [u8]) -> usize {
+		self.content.serialize(buffer)
+	}
+
+	fn deserialize(buffer: &[u8]) -> Result<Self, TransportError> {
+		let mut reader = Reader::new(buffer);
+		let sequence = Sequence(reader.read_u8()?);
+
+		let content = T::deserialize(reader.remaining())?;
+
+		Ok(Self { sequence, content })
+	}
+}
+
+/// How a received sequence number relates to the previous ones, see [`SequenceWindow`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub enum Arrival {
+	/// The sequence number directly follows the newest one
+	InOrder,
+	/// The sequence number skipped this many numbers, which are missing for now
+	Gap(u8),
+	/// The sequence number is older than the newest one but was not received yet
+	Reordered,
+	/// The sequence number was already received
+	Duplicate,
+}
+
+/// Keeps track of the last received sequence numbers
+///
+/// Numbers older than the window size (32) are reported as duplicates.
+#[derive(Debug, Default, Clone)]
+pub struct SequenceWindow {
+	/// The newest received sequence number
+	newest: Option<Sequence>,
+	/// Bit `n` is set when the number `n` steps before the newest one was received
+	received: u32,
+}
+
+impl SequenceWindow {
+	/// Creates a window that did not receive anything yet
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			newest: None,
+			received: 0,
+		}
+	}
+
+	/// Forgets every received sequence number, e.g. when a new controller connects
+	pub const fn reset(&mut self) {
+		self.newest = None;
+		self.received = 0;
+	}
+
+	/// Records the sequence number and tells how it relates to the previous ones
+	pub fn observe(&mut self, sequence: Sequence) -> Arrival {
+		let Some(newest) = self.newest else {
+			self.newest = Some(sequence);
+			self.received = 1;
+			return Arrival::InOrder;
+		};
+
+		let distance = sequence.distance(newest);
+
+		if distance > 0 {
+			let shift = distance.unsigned_abs();
+			self.received = self.received.checked_shl(shift.into()).unwrap_or(0) | 1;
+			self.newest = Some(sequence);
+
+			return if shift == 1 {
+				Arrival::InOrder
+			} else {
+				Arrival::Gap(shift - 1)
+			};
+		}
+
+		let age = u32::from(distance.unsigned_abs());
+		if age >= u32::BITS || self.received & (1 << age) != 0 {
+			return Arrival::Duplicate;
+		}
+
+		self.received |= 1 << age;
+		Arrival::Reordered
+	}
+}
+
+/// What a received answer corresponds to, see [`Requests::resolve`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub enum Reply {
+	/// The answer matches an outstanding message, which is no longer tracked
+	Matched,
+	/// The answer is for a message that was already answered or given up on
+	Duplicate,
+	/// The answer sequence number matches an outstanding message of another kind
+	Mismatched,
+	/// The answer sequence number was never sent
+	Unsolicited,
+}
+
+/// A message waiting for its answer
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+	/// The sequence number the message was sent with
+	sequence: Sequence,
+	/// The id of the message, its answer shares the same id
+	id: u8,
+}
+
+/// Keeps track of the messages sent by the controller that were not answered yet
+///
+/// `N` is the maximum number of outstanding messages.
+#[derive(Debug, Clone)]
+pub struct Requests<const N: usize> {
+	/// The sequence number of the next message
+	next: Sequence,
+	/// The outstanding messages
+	pending: [Option<Pending>; N],
+}
+
+impl<const N: usize> Default for Requests<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<const N: usize> Requests<N> {
+	/// Creates a tracker without outstanding messages
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			next: Sequence(0),
+			pending: [None; N],
+		}
+	}
+
+	/// Wraps the message in an envelope with a fresh sequence number and tracks it
+	///
+	/// Returns [`None`] when there are already `N` outstanding messages.
+	pub fn track(&mut self, message: Message) -> Option<Envelope<Message>> {
+		let slot = self.pending.iter_mut().find(|slot| slot.is_none())?;
+
+		let sequence = self.next;
+		self.next = sequence.next();
+
+		*slot = Some(Pending {
+			sequence,
+			id: message.id(),
+		});
+
+		Some(Envelope::new(sequence, message))
+	}
+
+	/// Stops waiting for the answer of the given message, e.g. after a timeout
+	pub fn forget(&mut self, sequence: Sequence) {
+		self.pending
+			.iter_mut()
+			.filter(|slot| slot.is_some_and(|pending| pending.sequence == sequence))
+			.for_each(|slot| *slot = None);
+	}
+
+	/// Returns whether the given message is still waiting for its answer
+	#[must_use]
+	pub fn is_pending(&self, sequence: Sequence) -> bool {
+		self.pending
+			.iter()
+			.flatten()
+			.any(|pending| pending.sequence == sequence)
+	}
+
+	/// Returns the outstanding messages that were overtaken by an answer to a newer message
+	///
+	/// Over an ordered link, their answers are most likely lost.
+	pub fn overtaken(&self, answered: Sequence) -> impl Iterator<Item = Sequence> + '_ {
+		self.pending
+			.iter()
+			.flatten()
+			.map(|pending| pending.sequence)
+			.filter(move |&sequence| sequence.distance(answered) < 0)
+	}
+
+	/// Matches a received answer against the outstanding messages
+	pub fn resolve(&mut self, answer: &Envelope<Answer>) -> Reply {
+		let slot = self
+			.pending
+			.iter_mut()
+			.find(|slot| slot.is_some_and(|pending| pending.sequence == answer.sequence));
+
+		let Some(slot) = slot else {
+			return if answer.sequence.distance(self.next) < 0 {
+				Reply::Duplicate
+			} else {
+				Reply::Unsolicited
+			};
+		};
+
+		if slot.is_some_and(|pending| pending.id == answer.content.answered_id()) {
+			*slot = None;
+			Reply::Matched
+		} else {
+			Reply::Mismatched
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{LeaseToken, Throttle};
+
+	#[test]
+	fn can_serialize_envelope() -> Result<(), TransportError> {
+		let envelope = Envelope::new(
+			Sequence(42),
+			Message::SetSpeed {
+				lease: LeaseToken(1),
+				throttle: Throttle::saturating(-5),
+			},
+		);
+		let mut buffer = [0_u8; Envelope::<Message>::BUFFER_SIZE];
+
+		let length = envelope.serialize(&mut buffer);
+		assert_eq!(
+			&buffer[..length],
+			&[42, 100, 0, 1, (-5_i8).to_be_bytes()[0]]
+		);
+
+		let deserialized = Envelope::<Message>::deserialize(&buffer[..length])?;
+		assert_eq!(deserialized, envelope);
+
+		Ok(())
+	}
+
+	#[test]
+	fn sequence_distance_wraps() {
+		assert_eq!(Sequence(1).distance(Sequence(255)), 2);
+		assert_eq!(Sequence(255).distance(Sequence(1)), -2);
+	}
+
+	#[test]
+	fn window_detects_duplicates_and_reordering() {
+		let mut window = SequenceWindow::new();
+
+		assert_eq!(window.observe(Sequence(254)), Arrival::InOrder);
+		assert_eq!(window.observe(Sequence(255)), Arrival::InOrder);
+		assert_eq!(window.observe(Sequence(2)), Arrival::Gap(2));
+		assert_eq!(window.observe(Sequence(0)), Arrival::Reordered);
+		assert_eq!(window.observe(Sequence(0)), Arrival::Duplicate);
+		assert_eq!(window.observe(Sequence(255)), Arrival::Duplicate);
+		assert_eq!(window.observe(Sequence(200)), Arrival::Duplicate);
+	}
+
+	#[test]
+	fn requests_match_answers() {
+		let mut requests = Requests::<4>::new();
+
+		let speed = requests.track(Message::GetSpeed).expect("room for message");
+		let ping = requests.track(Message::Ping).expect("room for message");
+		assert_eq!(ping.sequence, Sequence(1));
+
+		assert_eq!(
+			requests.resolve(&ping.reply(Answer::Speed(Throttle::ZERO))),
+			Reply::Mismatched
+		);
+		assert_eq!(requests.resolve(&ping.reply(Answer::Pong)), Reply::Matched);
+		assert_eq!(
+			requests.resolve(&ping.reply(Answer::Pong)),
+			Reply::Duplicate
+		);
+		assert_eq!(
+			requests.resolve(&Envelope::new(Sequence(9), Answer::Pong)),
+			Reply::Unsolicited
+		);
+
+		assert!(requests.overtaken(ping.sequence).eq([speed.sequence]));
+
+		requests.forget(speed.sequence);
+		assert!(!requests.is_pending(speed.sequence));
+		assert_eq!(
+			requests.resolve(&speed.reply(Answer::Speed(Throttle::ZERO))),
+			Reply::Duplicate
+		);
+	}
+
+	#[test]
+	fn nacks_match_the_refused_message() {
+		let mut requests = Requests::<2>::new();
+		let envelope = requests
+			.track(Message::SetSpeed {
+				lease: LeaseToken(1),
+				throttle: Throttle::MAX,
+			})
+			.expect("there is room");
+
+		let nack = Answer::Nack {
+			for_id: 0,
+			reason: crate::ErrorCode::MissingComponent,
+		};
+		assert_eq!(
+			requests.resolve(&Envelope::new(envelope.sequence, nack)),
+			Reply::Mismatched
+		);
+
+		let nack = Answer::Nack {
+			for_id: envelope.content.id(),
+			reason: crate::ErrorCode::MissingComponent,
+		};
+		assert_eq!(
+			requests.resolve(&Envelope::new(envelope.sequence, nack)),
+			Reply::Matched
+		);
+	}
+
+	#[test]
+	fn requests_are_bounded() {
+		let mut requests = Requests::<1>::new();
+
+		assert!(requests.track(Message::Ping).is_some());
+		assert!(requests.track(Message::Ping).is_none());
+	}
+}
@@ -0,0 +1,32 @@
+//! Faults that stop the car until they are cleared
+//!
+//! A [`Message::EmergencyStop`](crate::Message::EmergencyStop) brakes the motors and latches a
+//! [`Fault`]. While a fault is latched, the car refuses every
+//! [`Message::SetSpeed`](crate::Message::SetSpeed) with [`ErrorCode::Faulted`](crate::ErrorCode::Faulted)
+//! until the controller sends an explicit [`Message::ClearFault`](crate::Message::ClearFault).
+
+use core::fmt;
+
+use crate::{Field, ProtocolVersion};
+
+/// First protocol revision with the emergency stop and latched faults
+pub const SINCE: ProtocolVersion = ProtocolVersion { major: 3, minor: 1 };
+
+/// Why the car stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Fault {
+	/// The controller asked for an emergency stop
+	#[transport(id = 0)]
+	EmergencyStop,
+}
+
+impl fmt::Display for Fault {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::EmergencyStop => "emergency stop",
+		})
+	}
+}
@@ -0,0 +1,524 @@
+//! Encoding of the values carried in payloads
+//!
+//! Every field of a [`Transport`](crate::Transport) variant implements [`Field`].
+//! Integers are encoded in big endian, `bool` as a single `0` or `1` byte and
+//! `Option` as a `0` or `1` tag byte followed by the value when present.
+//! Byte arrays are copied as they are.
+//!
+//! A [`Trailing`] value ends a payload, it is encoded as the value alone when present and takes no
+//! byte otherwise. Members added to an existing message this way keep the frames of older
+//! revisions valid.
+//!
+//! Bounded [`heapless::Vec`] and [`heapless::String`] are prefixed with their length, on one
+//! byte when their capacity is below 256 and on two bytes otherwise. Their capacity is checked
+//! when decoded, so that payloads stay allocation-free.
+
+use crate::{TransportError, schema::Shape};
+
+/// A value that can be carried in a payload
+///
+/// Implementations for structs and enums can be derived with [`Field`](derive@crate::Field),
+/// fields are encoded one after the other. Enum variants are prefixed with their
+/// `#[transport(id = N)]` tag byte.
+pub trait Field: Sized {
+	/// Maximum size of the encoded value in bytes
+	const MAX_SIZE: usize;
+
+	/// How the value is encoded, see the [`schema`](crate::schema) module
+	const SHAPE: Shape;
+
+	/// Encodes the value into the writer
+	fn encode(&self, writer: &mut Writer<'_>);
+
+	/// Decodes a value from the reader
+	///
+	/// # Errors
+	/// In case the bytes do not hold a valid value
+	fn decode(reader: &mut Reader<'_>) -> Result<Self, TransportError>;
+}
+
+/// Returns the largest of the given sizes, used to compute maximum payload sizes at compile time
+#[must_use]
+pub const fn max_size(sizes: &[usize]) -> usize {
+	let mut max = 0;
+	let mut index = 0;
+
+	while index < sizes.len() {
+		if sizes[index] > max {
+			max = sizes[index];
+		}
+		index += 1;
+	}
+
+	max
+}
+
+/// Reads a serialized payload while checking every access against the buffer bounds
+#[derive(Debug)]
+pub struct Reader<'a> {
+	/// The bytes that were not read yet
+	buffer: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	/// Creates a reader over the given buffer
+	#[must_use]
+	pub const fn new(buffer: &'a [u8]) -> Self {
+		Self { buffer }
+	}
+
+	/// Reads a single byte
+	///
+	/// # Errors
+	/// In case the buffer is exhausted
+	pub fn read_u8(&mut self) -> Result<u8, TransportError> {
+		let [byte] = self.read_array()?;
+
+		Ok(byte)
+	}
+
+	/// Reads a fixed amount of bytes
+	///
+	/// # Errors
+	/// In case the buffer is exhausted
+	pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], TransportError> {
+		let (bytes, rest) = self
+			.buffer
+			.split_first_chunk()
+			.ok_or(TransportError::Truncated)?;
+		self.buffer = rest;
+
+		Ok(*bytes)
+	}
+
+	/// Reads the given amount of bytes without copying them
+	///
+	/// # Errors
+	/// In case the buffer is exhausted
+	pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], TransportError> {
+		let (bytes, rest) = self
+			.buffer
+			.split_at_checked(length)
+			.ok_or(TransportError::Truncated)?;
+		self.buffer = rest;
+
+		Ok(bytes)
+	}
+
+	/// Returns the bytes that were not read yet
+	#[must_use]
+	pub const fn remaining(&self) -> &'a [u8] {
+		self.buffer
+	}
+
+	/// Checks that the whole buffer was read
+	///
+	/// # Errors
+	/// In case there are bytes left in the buffer
+	pub const fn finish(&self) -> Result<(), TransportError> {
+		if self.buffer.is_empty() {
+			Ok(())
+		} else {
+			Err(TransportError::TrailingBytes)
+		}
+	}
+}
+
+/// Writes a payload into a buffer sized for the maximum payload size
+#[derive(Debug)]
+pub struct Writer<'a> {
+	/// The buffer to write into
+	buffer: &'a mut [u8],
+	/// Number of bytes written so far
+	position: usize,
+}
+
+impl<'a> Writer<'a> {
+	/// Creates a writer at the start of the given buffer
+	pub const fn new(buffer: &'a mut [u8]) -> Self {
+		Self {
+			buffer,
+			position: 0,
+		}
+	}
+
+	/// Writes a single byte
+	///
+	/// # Panics
+	/// In case the buffer is full, which cannot happen with a buffer of the maximum size
+	pub fn write_u8(&mut self, byte: u8) {
+		self.write_bytes(&[byte]);
+	}
+
+	/// Writes the bytes one after the other
+	///
+	/// # Panics
+	/// In case the buffer is full, which cannot happen with a buffer of the maximum size
+	pub fn write_bytes(&mut self, bytes: &[u8]) {
+		self.buffer[self.position..self.position + bytes.len()].copy_from_slice(bytes);
+		self.position += bytes.len();
+	}
+
+	/// Returns the number of bytes written so far
+	#[must_use]
+	pub const fn position(&self) -> usize {
+		self.position
+	}
+}
+
+/// Implements [`Field`] for integers in big endian
+macro_rules! impl_field_for_integers {
+	($($kind:ident: $($integer:ty),*;)*) => {
+		$($(
+			impl Field for $integer {
+				const MAX_SIZE: usize = size_of::<Self>();
+				const SHAPE: Shape = Shape::$kind {
+					size: Self::MAX_SIZE,
+				};
+
+				fn encode(&self, writer: &mut Writer<'_>) {
+					writer.write_bytes(&self.to_be_bytes());
+				}
+
+				fn decode(reader: &mut Reader<'_>) -> Result<Self, TransportError> {
+					Ok(Self::from_be_bytes(reader.read_array()?))
+				}
+			}
+		)*)*
+	};
+}
+
+impl_field_for_integers! {
+	Unsigned: u8, u16, u32, u64;
+	Signed: i8, i16, i32, i64;
+}
+
+impl Field for bool {
+	const MAX_SIZE: usize = 1;
+	const SHAPE: Shape = Shape::Bool;
+
+	fn encode(&self, writer: &mut Writer<'_>) {
+		writer.write_u8(u8::from(*self));
+	}
+
+	fn decode(reader: &mut Reader<'_>) -> Result<Self, TransportError> {
+		match reader.read_u8()? {
+			0 => Ok(false),
+			1 => Ok(true),
+			_ => Err(TransportError::InvalidPayload),
+		}
+	}
+}
+
+impl<const N: usize> Field for [u8; N] {
+	const MAX_SIZE: usize = N;
+	const SHAPE: Shape = Shape::Bytes { size: N };
+
+	fn encode(&self, writer: &mut Writer<'_>) {
+		writer.write_bytes(self);
+	}
+
+	fn decode(reader: &mut Reader<'_>) -> Result<Self, TransportError> {
+		reader.read_array()
+	}
+}
+
+impl<T: Field> Field for Option<T> {
+	const MAX_SIZE: usize = 1 + T::MAX_SIZE;
+	const SHAPE: Shape = Shape::Option { value: &T::SHAPE };
+
+	fn encode(&self, writer: &mut Writer<'_>) {
+		match self {
+			None => writer.write_u8(0),
+			Some(value) => {
+				writer.write_u8(1);
+				value.encode(writer);
+			}
+		}
+	}
+
+	fn decode(reader: &mut Reader<'_>) -> Result<Self, TransportError> {
+		match reader.read_u8()? {
+			0 => Ok(None),
+			1 => Ok(Some(T::decode(reader)?)),
+			_ => Err(TransportError::InvalidPayload),
+		}
+	}
+}
+
+/// An optional value at the end of a payload, nothing is encoded when it is [`None`]
+///
+/// The value is decoded when bytes are left, so it must be the last member of its payload: of the
+/// variant, and of every struct it is nested in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct Trailing<T>(pub Option<T>);
+
+impl<T> Trailing<T> {
+	/// No value, nothing is encoded
+	pub const NONE: Self = Self(None);
+
+	/// Returns whether there is no value
+	#[must_use]
+	pub const fn is_none(&self) -> bool {
+		self.0.is_none()
+	}
+}
+
+impl<T> From<Option<T>> for Trailing<T> {
+	fn from(value: Option<T>) -> Self {
+		Self(value)
+	}
+}
+
+impl<T: Field> Field for Trailing<T> {
+	const MAX_SIZE: usize = T::MAX_SIZE;
+	const SHAPE: Shape = Shape::Trailing { value: &T::SHAPE };
+
+	fn encode(&self, writer: &mut Writer<'_>) {
+		if let Some(value) = &self.0 {
+			value.encode(writer);
+		}
+	}
+
+	fn decode(reader: &mut Reader<'_>) -> Result<Self, TransportError> {
+		if reader.remaining().is_empty() {
+			return Ok(Self(None));
+		}
+
+		Ok(Self(Some(T::decode(reader)?)))
+	}
+}
+
+/// Size of the length prefix of a collection with the given capacity
+const fn length_prefix_size(capacity: usize) -> usize {
+	if capacity <= u8::MAX as usize { 1 } else { 2 }
+}
+
+/// Writes the length prefix of a collection with the given capacity
+fn encode_length(length: usize, capacity: usize, writer: &mut Writer<'_>) {
+	if length_prefix_size(capacity) == 1 {
+		writer.write_u8(u8::try_from(length).expect("capacity fits in the prefix"));
+	} else {
+		u16::try_from(length)
+			.expect("capacity fits in the prefix")
+			.encode(writer);
+	}
+}
+
+/// Reads the length prefix of a collection and checks it against its capacity
+fn decode_length(capacity: usize, reader: &mut Reader<'_>) -> Result<usize, TransportError> {
+	let length = if length_prefix_size(capacity) == 1 {
+		usize::from(reader.read_u8()?)
+	} else {
+		usize::from(u16::decode(reader)?)
+	};
+
+	if length > capacity {
+		return Err(TransportError::OutOfRange);
+	}
+
+	Ok(length)
+}
+
+impl<T: Field, const N: usize> Field for heapless::Vec<T, N> {
+	const MAX_SIZE: usize = {
+		assert!(
+			N <= u16::MAX as usize,
+			"capacity does not fit in the length prefix"
+		);
+		length_prefix_size(N) + N * T::MAX_SIZE
+	};
+	const SHAPE: Shape = Shape::List {
+		capacity: N,
+		prefix: length_prefix_size(N),
+		item: &T::SHAPE,
+	};
+
+	fn encode(&self, writer: &mut Writer<'_>) {
+		encode_length(self.len(), N, writer);
+
+		for item in self {
+			item.encode(writer);
+		}
+	}
+
+	fn decode(reader: &mut Reader<'_>) -> Result<Self, TransportError> {
+		let length = decode_length(N, reader)?;
+
+		let mut items = Self::new();
+		for _ in 0..length {
+			items
+				.push(T::decode(reader)?)
+				.map_err(|_| TransportError::OutOfRange)?;
+		}
+
+		Ok(items)
+	}
+}
+
+impl<const N: usize> Field for heapless::String<N> {
+	const MAX_SIZE: usize = heapless::Vec::<u8, N>::MAX_SIZE;
+	const SHAPE: Shape = Shape::Text {
+		capacity: N,
+		prefix: length_prefix_size(N),
+	};
+
+	fn encode(&self, writer: &mut Writer<'_>) {
+		encode_length(self.len(), N, writer);
+		writer.write_bytes(self.as_bytes());
+	}
+
+	fn decode(reader: &mut Reader<'_>) -> Result<Self, TransportError> {
+		let length = decode_length(N, reader)?;
+		let text = core::str::from_utf8(reader.read_bytes(length)?)
+			.map_err(|_| TransportError::InvalidPayload)?;
+
+		text.try_into().map_err(|_| TransportError::OutOfRange)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Field;
+
+	#[derive(Debug, PartialEq, Eq, Field)]
+	struct Reading {
+		distance: Option<u16>,
+		valid: bool,
+	}
+
+	#[derive(Debug, PartialEq, Eq, Field)]
+	enum Sensor {
+		#[transport(id = 3)]
+		Ultrasonic(Reading),
+		#[transport(id = 7)]
+		Battery { level: i32 },
+	}
+
+	/// Encodes and decodes the value back
+	fn round_trip<T: Field>(value: &T) -> Result<T, TransportError> {
+		let mut buffer = [0; 16];
+		let mut writer = Writer::new(&mut buffer);
+		value.encode(&mut writer);
+		let length = writer.position();
+
+		let mut reader = Reader::new(&buffer[..length]);
+		let decoded = T::decode(&mut reader)?;
+		reader.finish()?;
+
+		Ok(decoded)
+	}
+
+	#[test]
+	fn integers_are_big_endian() {
+		let mut buffer = [0; 4];
+		let mut writer = Writer::new(&mut buffer);
+		0x0102_i16.encode(&mut writer);
+		0xAB_u8.encode(&mut writer);
+
+		assert_eq!(buffer, [1, 2, 0xAB, 0]);
+	}
+
+	#[test]
+	fn derived_fields_round_trip() -> Result<(), TransportError> {
+		let sensor = Sensor::Ultrasonic(Reading {
+			distance: Some(1200),
+			valid: true,
+		});
+		assert_eq!(round_trip(&sensor)?, sensor);
+
+		let sensor = Sensor::Battery { level: -3 };
+		assert_eq!(round_trip(&sensor)?, sensor);
+
+		assert_eq!(Reading::MAX_SIZE, 4);
+		assert_eq!(Sensor::MAX_SIZE, 5);
+
+		Ok(())
+	}
+
+	#[test]
+	fn collections_are_length_prefixed() -> Result<(), TransportError> {
+		let mut distances = heapless::Vec::<u16, 4>::new();
+		distances.extend([300, 1200]);
+		assert_eq!(round_trip(&distances)?, distances);
+		assert_eq!(heapless::Vec::<u16, 4>::MAX_SIZE, 9);
+
+		let name = heapless::String::<8>::try_from("clio").expect("name fits");
+		assert_eq!(round_trip(&name)?, name);
+
+		let mut reader = Reader::new(&[2, b'o', b'k']);
+		assert_eq!(
+			heapless::String::<1>::decode(&mut reader),
+			Err(TransportError::OutOfRange)
+		);
+		let mut reader = Reader::new(&[1, 0xFF]);
+		assert_eq!(
+			heapless::String::<1>::decode(&mut reader),
+			Err(TransportError::InvalidPayload)
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn large_collections_have_two_bytes_prefixes() {
+		let bytes = heapless::Vec::<u8, 300>::from_slice(&[7; 3]).expect("bytes fit");
+		let mut buffer = [0; 8];
+		let mut writer = Writer::new(&mut buffer);
+		bytes.encode(&mut writer);
+		let length = writer.position();
+
+		assert_eq!(buffer[..length], [0, 3, 7, 7, 7]);
+	}
+
+	#[test]
+	fn trailing_values_may_be_missing() -> Result<(), TransportError> {
+		#[derive(Debug, PartialEq, Eq, Field)]
+		struct Stamped {
+			level: u16,
+			at: Trailing<u32>,
+		}
+
+		let stamped = Stamped {
+			level: 7400,
+			at: Trailing(Some(12)),
+		};
+		assert_eq!(round_trip(&stamped)?, stamped);
+		assert_eq!(Stamped::MAX_SIZE, 6);
+
+		// Written by a revision without the trailing value
+		let mut reader = Reader::new(&[0x1C, 0xE8]);
+		assert_eq!(
+			Stamped::decode(&mut reader)?,
+			Stamped {
+				level: 7400,
+				at: Trailing::NONE
+			}
+		);
+
+		let mut reader = Reader::new(&[0x1C, 0xE8, 0]);
+		assert_eq!(Stamped::decode(&mut reader), Err(TransportError::Truncated));
+
+		Ok(())
+	}
+
+	#[test]
+	fn rejects_invalid_tags() {
+		assert_eq!(
+			Sensor::decode(&mut Reader::new(&[4])),
+			Err(TransportError::InvalidPayload)
+		);
+		assert_eq!(
+			bool::decode(&mut Reader::new(&[2])),
+			Err(TransportError::InvalidPayload)
+		);
+	}
+}
@@ -0,0 +1,588 @@
+//! Framing of [`Transport`] values over a raw byte stream
+//!
+//! The serial link and the bluetooth notifications only carry a stream of bytes,
+//! frames delimit the serialized values and protect them against corruption.
+//!
+//! Each frame has the following format:
+//! ```text
+//! +-------------------------------------------------------+-----------+
+//! | COBS( serialized value (varies) | CRC-16 (2b, BE) )   | 0x00 (1b) |
+//! +-------------------------------------------------------+-----------+
+//! ```
+//!
+//! [COBS](https://en.wikipedia.org/wiki/Consistent_Overhead_Byte_Stuffing) removes every
+//! zero byte from the frame content, so that a zero byte always marks the end of a frame.
+//! When a byte is dropped or corrupted, the checksum does not match and the decoder
+//! discards everything up to the next delimiter, which resynchronizes the stream.
+//!
+//! In an authenticated session, the serialized value is sealed before the checksum is
+//! computed, see [`auth`](crate::auth).
+
+use core::marker::PhantomData;
+
+#[cfg(feature = "auth")]
+use crate::auth::{Opened, SEAL_SIZE, Session};
+use crate::{Transport, TransportError};
+
+/// Byte that marks the end of a frame
+pub const FRAME_DELIMITER: u8 = 0x00;
+
+/// Size of the checksum appended to the serialized value
+pub(crate) const CHECKSUM_SIZE: usize = 2;
+
+/// Default size of the internal buffers, enough for every [`Message`](crate::Message)
+/// and [`Answer`](crate::Answer)
+pub const DEFAULT_FRAME_BUFFER_SIZE: usize = 64;
+
+/// Returns the maximum size of a frame carrying `serialized_size` bytes, delimiter included
+#[must_use]
+pub const fn max_frame_size(serialized_size: usize) -> usize {
+	let content_size = serialized_size + CHECKSUM_SIZE;
+
+	// One overhead byte every 254 bytes, plus the first code byte and the delimiter
+	content_size + content_size / 254 + 2
+}
+
+/// Computes the `CRC-16/CCITT-FALSE` checksum of the given bytes
+#[must_use]
+pub fn crc16(bytes: &[u8]) -> u16 {
+	let mut crc = 0xFFFF_u16;
+
+	for &byte in bytes {
+		crc ^= u16::from(byte) << 8;
+
+		for _ in 0..8 {
+			crc = if crc & 0x8000 == 0 {
+				crc << 1
+			} else {
+				(crc << 1) ^ 0x1021
+			};
+		}
+	}
+
+	crc
+}
+
+/// Encodes [`Transport`] values into delimited frames
+///
+/// `N` is the size of the internal buffer used to serialize values.
+#[derive(Debug)]
+pub struct FrameEncoder<const N: usize = DEFAULT_FRAME_BUFFER_SIZE> {
+	/// Holds the serialized value and its checksum before stuffing
+	buffer: [u8; N],
+}
+
+impl<const N: usize> Default for FrameEncoder<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<const N: usize> FrameEncoder<N> {
+	/// Creates a new encoder
+	#[must_use]
+	pub const fn new() -> Self {
+		Self { buffer: [0; N] }
+	}
+
+	/// Encodes the value as a complete frame into `output` and returns the frame length
+	///
+	/// `output` should be at least [`max_frame_size`] of the value's [`Transport::BUFFER_SIZE`].
+	///
+	/// # Errors
+	/// In case the internal buffer or `output` is too small to hold the frame
+	pub fn encode<T: Transport>(
+		&mut self,
+		value: &T,
+		output: &mut [u8],
+	) -> Result<usize, TransportError> {
+		if T::BUFFER_SIZE + CHECKSUM_SIZE > N {
+			return Err(TransportError::BufferTooSmall);
+		}
+
+		let length = value.serialize(&mut self.buffer[..T::BUFFER_SIZE]);
+
+		self.finish(length, output)
+	}
+
+	/// Seals the value with the session key and encodes it as a complete frame into `output`,
+	/// returns the frame length
+	///
+	/// `output` should be at least [`max_frame_size`] of the value's [`Transport::BUFFER_SIZE`]
+	/// plus [`SEAL_SIZE`].
+	///
+	/// # Errors
+	/// In case the internal buffer or `output` is too small to hold the frame, or the session
+	/// cannot seal more frames
+	#[cfg(feature = "auth")]
+	pub fn encode_sealed<T: Transport>(
+		&mut self,
+		value: &T,
+		session: &mut Session,
+		output: &mut [u8],
+	) -> Result<usize, TransportError> {
+		if T::BUFFER_SIZE + SEAL_SIZE + CHECKSUM_SIZE > N {
+			return Err(TransportError::BufferTooSmall);
+		}
+
+		let length = value.serialize(&mut self.buffer[..T::BUFFER_SIZE]);
+		let length = session.seal(&mut self.buffer[..T::BUFFER_SIZE + SEAL_SIZE], length)?;
+
+		self.finish(length, output)
+	}
+
+	/// Appends the checksum to the first `length` bytes of the buffer and stuffs them into `output`
+	fn finish(&mut self, length: usize, output: &mut [u8]) -> Result<usize, TransportError> {
+		let checksum = crc16(&self.buffer[..length]);
+		self.buffer[length..length + CHECKSUM_SIZE].copy_from_slice(&checksum.to_be_bytes());
+
+		let content = &self.buffer[..length + CHECKSUM_SIZE];
+		if output.len() < max_frame_size(length) {
+			return Err(TransportError::BufferTooSmall);
+		}
+
+		let mut size = cobs_encode(content, output);
+		output[size] = FRAME_DELIMITER;
+		size += 1;
+
+		Ok(size)
+	}
+}
+
+/// Decodes [`Transport`] values from a stream of bytes cut in arbitrary chunks
+///
+/// `N` is the size of the internal buffer, frames longer than that are dropped.
+#[derive(Debug)]
+pub struct FrameDecoder<T: Transport, const N: usize = DEFAULT_FRAME_BUFFER_SIZE> {
+	/// Holds the bytes of the current frame
+	buffer: [u8; N],
+	/// Number of bytes of the current frame
+	length: usize,
+	/// Whether the current frame exceeded the buffer and should be dropped
+	overflowed: bool,
+	/// Length of the serialized value of the last frame with a valid checksum
+	checked: usize,
+
+	/// The type of decoded values
+	_transport: PhantomData<fn() -> T>,
+}
+
+impl<T: Transport, const N: usize> Default for FrameDecoder<T, N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: Transport, const N: usize> FrameDecoder<T, N> {
+	/// Creates a new decoder
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			buffer: [0; N],
+			length: 0,
+			overflowed: false,
+			checked: 0,
+			_transport: PhantomData,
+		}
+	}
+
+	/// Discards the bytes of the current partial frame
+	pub const fn reset(&mut self) {
+		self.length = 0;
+		self.overflowed = false;
+	}
+
+	/// Pushes a single byte into the decoder
+	///
+	/// Returns a value when the byte completes a frame. Errors are returned
+	/// for corrupted frames, the decoder is ready for the next frame either way.
+	pub fn push(&mut self, byte: u8) -> Option<Result<T, TransportError>> {
+		let length = self.push_byte(byte)?;
+
+		Some(
+			length
+				.and_then(|length| self.check_frame(length))
+				.and_then(|length| T::deserialize(&self.buffer[..length])),
+		)
+	}
+
+	/// Pushes a single byte into the decoder and opens the frames sealed with the session key
+	///
+	/// Frames that are not sealed with the session key are decoded as plain frames, so that
+	/// the caller can decide which values are allowed without a session. Replayed frames are
+	/// returned as [`TransportError::Replayed`].
+	#[cfg(feature = "auth")]
+	pub fn push_sealed(
+		&mut self,
+		byte: u8,
+		session: Option<&mut Session>,
+	) -> Option<Result<Opened<T>, TransportError>> {
+		let length = match self
+			.push_byte(byte)?
+			.and_then(|length| self.check_frame(length))
+		{
+			Ok(length) => length,
+			Err(error) => return Some(Err(error)),
+		};
+
+		let frame = &self.buffer[..length];
+		let opened = match session.map(|session| session.open(frame)) {
+			Some(Ok(value)) => T::deserialize(value).map(Opened::Sealed),
+			Some(Err(TransportError::Replayed)) => Err(TransportError::Replayed),
+			_ => T::deserialize(frame).map(Opened::Plain),
+		};
+
+		Some(opened)
+	}
+
+	/// Pushes a single byte into the buffer and returns the length of the frame it completes
+	const fn push_byte(&mut self, byte: u8) -> Option<Result<usize, TransportError>> {
+		self.checked = 0;
+
+		if byte != FRAME_DELIMITER {
+			if self.length < N {
+				self.buffer[self.length] = byte;
+				self.length += 1;
+			} else {
+				self.overflowed = true;
+			}
+
+			return None;
+		}
+
+		let (length, overflowed) = (self.length, self.overflowed);
+		self.reset();
+
+		// Consecutive delimiters are used to flush the line
+		if length == 0 && !overflowed {
+			return None;
+		}
+
+		if overflowed {
+			return Some(Err(TransportError::FrameTooLong));
+		}
+
+		Some(Ok(length))
+	}
+
+	/// Returns the serialized value of the last frame that had a valid checksum
+	///
+	/// It is only available right after [`FrameDecoder::push`] completed a frame, and is empty
+	/// when that frame was corrupted. This tells the sender which value could not be deserialized.
+	#[must_use]
+	pub fn last_frame(&self) -> &[u8] {
+		&self.buffer[..self.checked]
+	}
+
+	/// Feeds a chunk of bytes into the decoder
+	///
+	/// The returned iterator yields every frame completed by the chunk.
+	/// Bytes of an incomplete frame at the end are kept for the next chunk.
+	pub fn feed<'a>(&'a mut self, chunk: &'a [u8]) -> Frames<'a, T, N> {
+		Frames {
+			decoder: self,
+			chunk: chunk.iter(),
+		}
+	}
+
+	/// Unstuffs and checks the frame of the given length in the buffer, returns the length of
+	/// its serialized value
+	fn check_frame(&mut self, length: usize) -> Result<usize, TransportError> {
+		let length = cobs_decode_in_place(&mut self.buffer[..length])?;
+
+		if length <= CHECKSUM_SIZE {
+			return Err(TransportError::InvalidFraming);
+		}
+
+		let (serialized, checksum) = self.buffer[..length].split_at(length - CHECKSUM_SIZE);
+		if crc16(serialized).to_be_bytes() != checksum {
+			return Err(TransportError::InvalidChecksum);
+		}
+
+		self.checked = serialized.len();
+		Ok(self.checked)
+	}
+}
+
+/// Iterator over the frames completed by a chunk, see [`FrameDecoder::feed`]
+#[derive(Debug)]
+pub struct Frames<'a, T: Transport, const N: usize> {
+	/// The decoder to push bytes into
+	decoder: &'a mut FrameDecoder<T, N>,
+	/// The remaining bytes of the chunk
+	chunk: core::slice::Iter<'a, u8>,
+}
+
+impl<T: Transport, const N: usize> Iterator for Frames<'_, T, N> {
+	type Item = Result<T, TransportError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.chunk
+			.by_ref()
+			.find_map(|&byte| self.decoder.push(byte))
+	}
+}
+
+/// COBS encodes `input` into `output` and returns the encoded length, without delimiter
+///
+/// `output` must be at least `input.len() + input.len() / 254 + 1` long.
+fn cobs_encode(input: &[u8], output: &mut [u8]) -> usize {
+	let mut code_index = 0;
+	let mut write_index = 1;
+	let mut code = 1_u8;
+
+	for &byte in input {
+		if byte == 0 {
+			output[code_index] = code;
+			code_index = write_index;
+			write_index += 1;
+			code = 1;
+		} else {
+			output[write_index] = byte;
+			write_index += 1;
+			code += 1;
+
+			if code == 0xFF {
+				output[code_index] = code;
+				code_index = write_index;
+				write_index += 1;
+				code = 1;
+			}
+		}
+	}
+
+	output[code_index] = code;
+
+	write_index
+}
+
+/// COBS decodes the buffer in place and returns the decoded length
+///
+/// The buffer must not contain the delimiter.
+fn cobs_decode_in_place(buffer: &mut [u8]) -> Result<usize, TransportError> {
+	let mut read_index = 0;
+	let mut write_index = 0;
+
+	while read_index < buffer.len() {
+		let code = usize::from(buffer[read_index]);
+		if code == 0 || read_index + code > buffer.len() {
+			return Err(TransportError::InvalidFraming);
+		}
+		read_index += 1;
+
+		for _ in 1..code {
+			buffer[write_index] = buffer[read_index];
+			write_index += 1;
+			read_index += 1;
+		}
+
+		// A full block does not stand for a zero, neither does the last block
+		if code != 0xFF && read_index != buffer.len() {
+			buffer[write_index] = 0;
+			write_index += 1;
+		}
+	}
+
+	Ok(write_index)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Answer, Envelope, LeaseToken, Message, Sequence, Steering, Throttle};
+
+	/// Encodes the value into a fresh frame
+	fn frame<T: Transport>(value: &T) -> ([u8; DEFAULT_FRAME_BUFFER_SIZE], usize) {
+		let mut output = [0; DEFAULT_FRAME_BUFFER_SIZE];
+		let length = FrameEncoder::<DEFAULT_FRAME_BUFFER_SIZE>::new()
+			.encode(value, &mut output)
+			.expect("frame fits in buffer");
+		(output, length)
+	}
+
+	#[test]
+	fn crc16_matches_reference() {
+		assert_eq!(crc16(b"123456789"), 0x29B1);
+	}
+
+	#[test]
+	fn cobs_round_trips() {
+		let input = [0, 1, 0, 0, 2, 3, 0];
+		let mut encoded = [0; 16];
+
+		let length = cobs_encode(&input, &mut encoded);
+		assert!(!encoded[..length].contains(&0));
+
+		let decoded = cobs_decode_in_place(&mut encoded[..length]);
+		assert_eq!(decoded, Ok(input.len()));
+		assert_eq!(&encoded[..input.len()], &input);
+	}
+
+	#[test]
+	fn cobs_round_trips_long_runs() {
+		let input = [0xAA; 300];
+		let mut encoded = [0; 310];
+
+		let length = cobs_encode(&input, &mut encoded);
+		assert!(length < max_frame_size(input.len() - CHECKSUM_SIZE));
+		assert!(!encoded[..length].contains(&0));
+
+		let decoded = cobs_decode_in_place(&mut encoded[..length]);
+		assert_eq!(decoded, Ok(input.len()));
+		assert_eq!(&encoded[..input.len()], &input);
+	}
+
+	#[test]
+	fn can_decode_frames_split_in_chunks() {
+		let (first, first_length) = frame(&Message::SetSpeed {
+			lease: LeaseToken(1),
+			throttle: Throttle::ZERO,
+		});
+		let (second, second_length) = frame(&Message::GetSpeed);
+
+		let mut stream = [0; 32];
+		stream[..first_length].copy_from_slice(&first[..first_length]);
+		stream[first_length..first_length + second_length]
+			.copy_from_slice(&second[..second_length]);
+		let stream = &stream[..first_length + second_length];
+
+		let mut decoder = FrameDecoder::<Message>::new();
+		let mut messages = [None, None];
+		let mut count = 0;
+
+		for chunk in stream.chunks(3) {
+			for message in decoder.feed(chunk) {
+				messages[count] = Some(message);
+				count += 1;
+			}
+		}
+
+		assert_eq!(
+			messages,
+			[
+				Some(Ok(Message::SetSpeed {
+					lease: LeaseToken(1),
+					throttle: Throttle::ZERO,
+				})),
+				Some(Ok(Message::GetSpeed))
+			]
+		);
+	}
+
+	#[test]
+	fn resynchronizes_after_dropped_byte() {
+		let (first, first_length) = frame(&Answer::Speed(Throttle::saturating(-20)));
+		let (second, second_length) = frame(&Answer::AckSpeed);
+
+		let mut decoder = FrameDecoder::<Answer>::new();
+
+		// Drop the second byte of the first frame
+		assert_eq!(decoder.feed(&first[..1]).next(), None);
+		let mut frames = decoder.feed(&first[2..first_length]);
+		assert!(matches!(frames.next(), Some(Err(_))));
+		assert_eq!(frames.next(), None);
+
+		let mut frames = decoder.feed(&second[..second_length]);
+		assert_eq!(frames.next(), Some(Ok(Answer::AckSpeed)));
+	}
+
+	#[test]
+	fn rejects_corrupted_checksum() {
+		let (mut encoded, length) = frame(&Answer::Direction(Steering::saturating(42)));
+		encoded[2] ^= 0x01;
+
+		let mut decoder = FrameDecoder::<Answer>::new();
+		let mut frames = decoder.feed(&encoded[..length]);
+		assert_eq!(frames.next(), Some(Err(TransportError::InvalidChecksum)));
+	}
+
+	#[test]
+	fn keeps_last_frame_of_invalid_values() {
+		let answer = Envelope::new(Sequence(9), Answer::Unsubscribed);
+		let (encoded, length) = frame(&answer);
+
+		let mut decoder = FrameDecoder::<Envelope<Message>>::new();
+		let mut frames = decoder.feed(&encoded[..length]);
+		assert_eq!(
+			frames.next(),
+			Some(Ok(Envelope::new(Sequence(9), Message::Unsubscribe)))
+		);
+
+		let answer = Envelope::new(
+			Sequence(10),
+			Answer::Nack {
+				for_id: 0,
+				reason: crate::ErrorCode::UnknownMessage,
+			},
+		);
+		let (encoded, length) = frame(&answer);
+
+		assert_eq!(
+			decoder.feed(&encoded[..length]).next(),
+			Some(Err(TransportError::InvalidId))
+		);
+		assert_eq!(
+			Envelope::<Message>::peek(decoder.last_frame()),
+			Some((Sequence(10), 255))
+		);
+
+		decoder.push(1);
+		assert_eq!(decoder.last_frame(), [0_u8; 0]);
+	}
+
+	#[test]
+	fn drops_frames_longer_than_buffer() {
+		let mut decoder = FrameDecoder::<Message, 4>::new();
+		let mut frames = decoder.feed(&[1, 2, 3, 4, 5, 6, 0]);
+		assert_eq!(frames.next(), Some(Err(TransportError::FrameTooLong)));
+
+		let (encoded, length) = frame(&Message::Ping);
+		let mut frames = decoder.feed(&encoded[..length]);
+		assert_eq!(frames.next(), Some(Ok(Message::Ping)));
+	}
+
+	#[test]
+	#[cfg(feature = "auth")]
+	fn sealed_frames_are_opened() -> Result<(), TransportError> {
+		use crate::auth::{CarHandshake, ControllerHandshake, Key, Nonce};
+
+		let key = Key::new([7; 32]);
+		let controller = ControllerHandshake::new(key.clone(), Nonce([1; 8]));
+		let car = CarHandshake::new(key, Nonce([1; 8]), &[]);
+		let crate::Answer::AuthChallenge { nonce, proof } = car.challenge() else {
+			unreachable!("the car answers with a challenge");
+		};
+		let (Message::Authenticate { proof }, mut controller) = controller.respond(nonce, proof)?
+		else {
+			unreachable!("the controller answers with its proof");
+		};
+		let mut car = car.verify(proof)?;
+
+		let mut output = [0; DEFAULT_FRAME_BUFFER_SIZE];
+		let length = FrameEncoder::<DEFAULT_FRAME_BUFFER_SIZE>::new().encode_sealed(
+			&Message::GetSpeed,
+			&mut controller,
+			&mut output,
+		)?;
+		let mut decoder = FrameDecoder::<Message>::new();
+
+		let sealed = &output[..length];
+		let mut received = sealed
+			.iter()
+			.filter_map(|&byte| decoder.push_sealed(byte, Some(&mut car)));
+		assert_eq!(received.next(), Some(Ok(Opened::Sealed(Message::GetSpeed))));
+
+		let mut replayed = sealed
+			.iter()
+			.filter_map(|&byte| decoder.push_sealed(byte, Some(&mut car)));
+		assert_eq!(replayed.next(), Some(Err(TransportError::Replayed)));
+
+		let (plain, length) = frame(&Message::Ping);
+		let mut received = plain[..length]
+			.iter()
+			.filter_map(|&byte| decoder.push_sealed(byte, Some(&mut car)));
+		assert_eq!(received.next(), Some(Ok(Opened::Plain(Message::Ping))));
+
+		Ok(())
+	}
+}
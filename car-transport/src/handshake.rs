@@ -0,0 +1,347 @@
+//! Protocol version and capability handshake
+//!
+//! The controller starts every session with a [`Message::Hello`], the car answers with an
+//! [`Answer::Hello`](crate::Answer::Hello) that describes its firmware. Both sides can then
+//! [`negotiate`] a common protocol revision and the set of usable components.
+
+use core::{fmt, ops};
+
+use crate::{Field, Message, compat};
+
+/// First protocol revision, with the handshake and the messages that drive the car
+pub const SINCE: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
+
+/// Revision of the protocol spoken by this crate
+///
+/// The major number changes when the wire format of existing messages changes,
+/// the minor number when messages are added.
+///
+/// Revisions:
+/// - `1.0`: sequence number envelopes, `Hello` handshake
+/// - `2.0`: typed units, distances and battery levels widened to 16 bits
+/// - `2.1`: parameter registry with `GetParam`, `SetParam` and `ListParams`
+/// - `2.2`: telemetry subscriptions with `Subscribe`, `Unsubscribe` and `Telemetry`
+/// - `3.0`: `Nack` answers, parameter answers always carry a value
+/// - `3.1`: emergency stop with `EmergencyStop`, `ClearFault` and `GetFault`
+/// - `4.0`: control lease with `AcquireControl` and `ReleaseControl`, commands carry its token
+/// - `4.1`: authenticated sessions with `AuthChallenge` and `Authenticate`
+/// - `4.2`: firmware updates with `BeginUpdate`, `WriteUpdate`, `VerifyUpdate` and `CommitUpdate`
+/// - `4.3`: log forwarding with `SetLogLevel` and `Log`
+/// - `4.4`: clock synchronization with `TimeSync`, sensor answers and telemetry carry when they
+///   were sampled
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 4, minor: 4 };
+
+/// A revision of the protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProtocolVersion {
+	/// Incremented on incompatible changes
+	pub major: u8,
+	/// Incremented on backward compatible additions
+	pub minor: u8,
+}
+
+impl fmt::Display for ProtocolVersion {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}.{}", self.major, self.minor)
+	}
+}
+
+/// Version of the firmware running on the car
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FirmwareVersion {
+	/// Major version number
+	pub major: u8,
+	/// Minor version number
+	pub minor: u8,
+	/// Patch version number
+	pub patch: u8,
+}
+
+impl FirmwareVersion {
+	/// Parses a `major.minor.patch` version, usually `env!("CARGO_PKG_VERSION")`
+	///
+	/// Pre-release and build metadata are ignored.
+	///
+	/// # Panics
+	/// In case the version is malformed, which fails the build when used in a constant
+	#[must_use]
+	pub const fn parse(version: &str) -> Self {
+		let bytes = version.as_bytes();
+		let mut numbers = [0_u8; 3];
+		let mut current = 0;
+		let mut index = 0;
+
+		while index < bytes.len() {
+			match bytes[index] {
+				b'.' if current < 2 => current += 1,
+				digit @ b'0'..=b'9' => {
+					numbers[current] = numbers[current] * 10 + (digit - b'0');
+				}
+				b'-' | b'+' => break,
+				_ => panic!("malformed firmware version"),
+			}
+			index += 1;
+		}
+
+		assert!(current == 2, "firmware version needs three numbers");
+
+		Self {
+			major: numbers[0],
+			minor: numbers[1],
+			patch: numbers[2],
+		}
+	}
+}
+
+impl fmt::Display for FirmwareVersion {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+	}
+}
+
+/// Set of components available on the car
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct Capabilities(pub u16);
+
+impl Capabilities {
+	/// No component is available
+	pub const NONE: Self = Self(0);
+
+	/// The `L298N` motor driver, required by the speed messages
+	pub const MOTORS: Self = Self(1 << 0);
+	/// The `SG90` servo motor, required by the direction messages
+	pub const SERVO: Self = Self(1 << 1);
+	/// The `HC-SR04` ultrasonic sensor, required by the distance messages
+	pub const ULTRASONIC: Self = Self(1 << 2);
+	/// The battery voltage sense, required by the battery messages
+	pub const BATTERY: Self = Self(1 << 3);
+	/// Not a component, the car refuses plain messages until a session is authenticated
+	pub const AUTHENTICATION: Self = Self(1 << 4);
+	/// A bootloader with a spare flash partition, required by the update messages
+	pub const UPDATE: Self = Self(1 << 5);
+
+	/// Returns whether every component of `other` is available
+	#[must_use]
+	pub const fn contains(self, other: Self) -> bool {
+		self.0 & other.0 == other.0
+	}
+}
+
+impl ops::BitOr for Capabilities {
+	type Output = Self;
+
+	fn bitor(self, other: Self) -> Self {
+		Self(self.0 | other.0)
+	}
+}
+
+impl ops::BitAnd for Capabilities {
+	type Output = Self;
+
+	fn bitand(self, other: Self) -> Self {
+		Self(self.0 & other.0)
+	}
+}
+
+/// What both sides agreed on after the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub struct Session {
+	/// The revision both sides understand, the oldest of the two
+	pub version: ProtocolVersion,
+	/// The firmware running on the car
+	pub firmware: FirmwareVersion,
+	/// The components available on the car
+	pub capabilities: Capabilities,
+}
+
+impl Session {
+	/// Returns whether the session runs an older revision than [`PROTOCOL_VERSION`]
+	///
+	/// Messages added after the negotiated revision should not be sent, see [`Session::supports`].
+	#[must_use]
+	pub fn is_degraded(&self) -> bool {
+		self.version < PROTOCOL_VERSION
+	}
+
+	/// Returns whether the car speaks the previous major revision, messages are then converted
+	/// with [`compat::Message::downgrade`]
+	#[must_use]
+	pub const fn is_compat(&self) -> bool {
+		self.version.major != PROTOCOL_VERSION.major
+	}
+
+	/// Returns whether the car knows the message and has the components it needs
+	#[must_use]
+	pub fn supports(&self, message: &Message) -> bool {
+		message.since() <= self.version
+			&& self.capabilities.contains(message.required_capabilities())
+	}
+}
+
+/// The two sides speak incompatible revisions of the protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub struct VersionMismatch {
+	/// The revision spoken locally
+	pub local: ProtocolVersion,
+	/// The revision spoken by the other side
+	pub remote: ProtocolVersion,
+}
+
+impl fmt::Display for VersionMismatch {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"protocol version {} is incompatible with local version {}",
+			self.remote, self.local
+		)
+	}
+}
+
+impl core::error::Error for VersionMismatch {}
+
+/// Agrees on a common revision with the car that answered the handshake
+///
+/// Revisions with the same major number are compatible, the session then uses the oldest one.
+/// Cars speaking the previous major number are still driven through the [`compat`] module, up to
+/// [`compat::VERSION`].
+///
+/// # Errors
+/// In case the major number of the car is neither the current nor the previous one
+pub fn negotiate(
+	remote: ProtocolVersion,
+	firmware: FirmwareVersion,
+	capabilities: Capabilities,
+) -> Result<Session, VersionMismatch> {
+	let version = if remote.major == PROTOCOL_VERSION.major {
+		remote.min(PROTOCOL_VERSION)
+	} else if remote.major == compat::VERSION.major {
+		remote.min(compat::VERSION)
+	} else {
+		return Err(VersionMismatch {
+			local: PROTOCOL_VERSION,
+			remote,
+		});
+	};
+
+	Ok(Session {
+		version,
+		firmware,
+		capabilities,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const FIRMWARE: FirmwareVersion = FirmwareVersion::parse("0.12.3-beta+build");
+
+	#[test]
+	fn can_parse_firmware_version() {
+		assert_eq!(
+			FIRMWARE,
+			FirmwareVersion {
+				major: 0,
+				minor: 12,
+				patch: 3
+			}
+		);
+	}
+
+	#[test]
+	fn older_minor_version_degrades_session() -> Result<(), VersionMismatch> {
+		let older = ProtocolVersion {
+			major: PROTOCOL_VERSION.major,
+			minor: 0,
+		};
+		let newer = ProtocolVersion {
+			major: PROTOCOL_VERSION.major,
+			minor: u8::MAX,
+		};
+
+		let session = negotiate(newer, FIRMWARE, Capabilities::SERVO)?;
+		assert_eq!(session.version, PROTOCOL_VERSION);
+		assert!(!session.is_degraded());
+
+		let session = negotiate(older, FIRMWARE, Capabilities::SERVO)?;
+		assert_eq!(session.version, older);
+		assert_eq!(session.is_degraded(), older < PROTOCOL_VERSION);
+		assert!(session.capabilities.contains(Capabilities::SERVO));
+		assert!(!session.capabilities.contains(Capabilities::MOTORS));
+
+		// Messages added since the negotiated revision are not sent
+		let session = negotiate(
+			ProtocolVersion { major: 4, minor: 3 },
+			FIRMWARE,
+			Capabilities::SERVO,
+		)?;
+		assert!(session.supports(&Message::SetLogLevel { level: None }));
+		assert!(!session.supports(&Message::TimeSync {
+			origin: crate::Timestamp(0)
+		}));
+		assert!(!session.supports(&Message::GetSpeed));
+
+		Ok(())
+	}
+
+	#[test]
+	fn other_major_version_is_refused() {
+		for remote in [
+			ProtocolVersion {
+				major: PROTOCOL_VERSION.major + 1,
+				minor: 0,
+			},
+			ProtocolVersion {
+				major: compat::VERSION.major - 1,
+				minor: 2,
+			},
+		] {
+			assert_eq!(
+				negotiate(remote, FIRMWARE, Capabilities::NONE),
+				Err(VersionMismatch {
+					local: PROTOCOL_VERSION,
+					remote
+				})
+			);
+		}
+	}
+
+	#[test]
+	fn previous_major_version_goes_through_compat() -> Result<(), VersionMismatch> {
+		let session = negotiate(compat::VERSION, FIRMWARE, Capabilities::MOTORS)?;
+		assert_eq!(session.version, compat::VERSION);
+		assert!(session.is_compat() && session.is_degraded());
+
+		let lease = crate::LeaseToken(1);
+		assert!(session.supports(&Message::ClearFault { lease }));
+		assert!(!session.supports(&Message::ReleaseControl { lease }));
+
+		let older = negotiate(
+			ProtocolVersion {
+				major: compat::VERSION.major,
+				minor: 0,
+			},
+			FIRMWARE,
+			Capabilities::MOTORS,
+		)?;
+		assert!(older.is_compat());
+		assert!(!older.supports(&Message::EmergencyStop));
+
+		Ok(())
+	}
+}
@@ -0,0 +1,232 @@
+//! Arbitration of the car between several controllers
+//!
+//! A controller sends [`Message::AcquireControl`](crate::Message::AcquireControl) to get a
+//! [`LeaseToken`], which every command that drives or configures the car must carry, see
+//! [`Message::lease`](crate::Message::lease). Commands with another token are refused with
+//! [`ErrorCode::NotLeaseHolder`]. Queries and emergency stops never need the lease.
+//!
+//! Each accepted command renews the lease, which expires after a period of silence so that a
+//! crashed controller does not keep the car to itself. Tokens only tell controllers apart,
+//! they do not authenticate them.
+//!
+//! Time is given by the caller as wrapping milliseconds, like in the [`arq`](crate::arq) module.
+
+use crate::{ErrorCode, Field, ProtocolVersion};
+
+/// First protocol revision with the control lease
+pub const SINCE: ProtocolVersion = ProtocolVersion { major: 4, minor: 0 };
+
+/// Token of the controller holding the lease
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct LeaseToken(pub u16);
+
+/// The controller currently holding the lease
+#[derive(Debug, Clone, Copy)]
+struct Holder {
+	/// The token handed to the controller
+	token: LeaseToken,
+	/// Time of silence after which the lease expires
+	duration_ms: u16,
+	/// Time at which the lease expires if it is not renewed
+	deadline: u32,
+}
+
+/// Keeps track of the controller allowed to drive the car
+#[derive(Debug, Clone)]
+pub struct ControlLease {
+	/// The controller holding the lease, if any
+	holder: Option<Holder>,
+	/// The last token handed out
+	last_token: LeaseToken,
+}
+
+impl ControlLease {
+	/// Shortest lease, so that controllers have the time to send a command before it expires
+	pub const MIN_DURATION_MS: u16 = 200;
+	/// Longest lease, so that a crashed controller does not lock the car for long
+	pub const MAX_DURATION_MS: u16 = 10_000;
+
+	/// Creates a lease that nobody holds, `seed` is the origin of the handed out tokens
+	#[must_use]
+	pub const fn new(seed: u16) -> Self {
+		Self {
+			holder: None,
+			last_token: LeaseToken(seed),
+		}
+	}
+
+	/// Grants the lease, or renews it when `renew` is the token of the current holder
+	///
+	/// The duration is clamped between [`ControlLease::MIN_DURATION_MS`] and
+	/// [`ControlLease::MAX_DURATION_MS`], the token and the duration actually used are returned.
+	///
+	/// # Errors
+	/// [`ErrorCode::LeaseTaken`] when another controller holds the lease
+	pub fn acquire(
+		&mut self,
+		renew: Option<LeaseToken>,
+		duration_ms: u16,
+		now: u32,
+	) -> Result<(LeaseToken, u16), ErrorCode> {
+		let token = match self.holder(now) {
+			Some(token) if renew == Some(token) => token,
+			Some(_) => return Err(ErrorCode::LeaseTaken),
+			None => {
+				// Skipping a time dependent number of tokens makes it unlikely that a controller
+				// gets the token another one held before the car was reset
+				let skip = u16::from(now.to_le_bytes()[0]) + 1;
+				self.last_token = LeaseToken(self.last_token.0.wrapping_add(skip));
+				self.last_token
+			}
+		};
+
+		let duration_ms = duration_ms.clamp(Self::MIN_DURATION_MS, Self::MAX_DURATION_MS);
+		self.holder = Some(Holder {
+			token,
+			duration_ms,
+			deadline: now.wrapping_add(duration_ms.into()),
+		});
+
+		Ok((token, duration_ms))
+	}
+
+	/// Gives the lease up so that another controller can acquire it
+	///
+	/// # Errors
+	/// [`ErrorCode::NotLeaseHolder`] when the token is not the one of the current holder
+	pub fn release(&mut self, token: LeaseToken, now: u32) -> Result<(), ErrorCode> {
+		if self.holder(now) != Some(token) {
+			return Err(ErrorCode::NotLeaseHolder);
+		}
+
+		self.holder = None;
+		Ok(())
+	}
+
+	/// Checks that a command carries the token of the current holder and renews the lease
+	///
+	/// # Errors
+	/// [`ErrorCode::NotLeaseHolder`] when the token is not the one of the current holder
+	pub fn authorize(&mut self, token: LeaseToken, now: u32) -> Result<(), ErrorCode> {
+		match &mut self.holder {
+			Some(holder) if holder.token == token && !is_reached(holder.deadline, now) => {
+				holder.deadline = now.wrapping_add(holder.duration_ms.into());
+				Ok(())
+			}
+			_ => Err(ErrorCode::NotLeaseHolder),
+		}
+	}
+
+	/// Returns the token of the current holder, [`None`] when the lease is free
+	#[must_use]
+	pub fn holder(&self, now: u32) -> Option<LeaseToken> {
+		self.holder
+			.filter(|holder| !is_reached(holder.deadline, now))
+			.map(|holder| holder.token)
+	}
+
+	/// Frees the lease when it expired and returns the token of the controller that lost it
+	pub fn expire(&mut self, now: u32) -> Option<LeaseToken> {
+		self.holder
+			.take_if(|holder| is_reached(holder.deadline, now))
+			.map(|holder| holder.token)
+	}
+
+	/// Returns the time left until the lease expires, [`None`] when the lease is free
+	#[must_use]
+	pub fn remaining_ms(&self, now: u32) -> Option<u32> {
+		self.holder.map(|holder| {
+			if is_reached(holder.deadline, now) {
+				0
+			} else {
+				holder.deadline.wrapping_sub(now)
+			}
+		})
+	}
+}
+
+/// Returns whether the wrapping `deadline` is at or before `now`
+const fn is_reached(deadline: u32, now: u32) -> bool {
+	now.wrapping_sub(deadline) < 1 << 31
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn commands_need_the_token_of_the_holder() -> Result<(), ErrorCode> {
+		let mut lease = ControlLease::new(0);
+		assert_eq!(
+			lease.authorize(LeaseToken(1), 0),
+			Err(ErrorCode::NotLeaseHolder)
+		);
+
+		let (token, duration_ms) = lease.acquire(None, 1000, 0)?;
+		assert_eq!(duration_ms, 1000);
+		assert_eq!(lease.authorize(token, 10), Ok(()));
+		assert_eq!(
+			lease.authorize(LeaseToken(token.0.wrapping_add(1)), 10),
+			Err(ErrorCode::NotLeaseHolder)
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn only_one_controller_holds_the_lease() -> Result<(), ErrorCode> {
+		let mut lease = ControlLease::new(0);
+		let (first, _) = lease.acquire(None, 1000, 0)?;
+
+		assert_eq!(lease.acquire(None, 1000, 10), Err(ErrorCode::LeaseTaken));
+		assert_eq!(lease.acquire(Some(first), 2000, 10), Ok((first, 2000)));
+
+		assert_eq!(
+			lease.release(LeaseToken(first.0.wrapping_add(1)), 20),
+			Err(ErrorCode::NotLeaseHolder)
+		);
+		assert_eq!(lease.release(first, 20), Ok(()));
+
+		let (second, _) = lease.acquire(None, 1000, 30)?;
+		assert_ne!(first, second);
+		assert_eq!(lease.authorize(first, 40), Err(ErrorCode::NotLeaseHolder));
+
+		Ok(())
+	}
+
+	#[test]
+	fn lease_expires_on_silence() -> Result<(), ErrorCode> {
+		let mut lease = ControlLease::new(u16::MAX);
+		let start = u32::MAX - 100;
+		let (token, duration_ms) = lease.acquire(None, 0, start)?;
+		assert_eq!(duration_ms, ControlLease::MIN_DURATION_MS);
+
+		// Every command pushes the expiry back
+		let renewed = start.wrapping_add(150);
+		assert_eq!(lease.authorize(token, renewed), Ok(()));
+		assert_eq!(lease.remaining_ms(renewed), Some(200));
+		assert_eq!(lease.expire(renewed.wrapping_add(199)), None);
+
+		let expiry = renewed.wrapping_add(200);
+		assert_eq!(lease.holder(expiry), None);
+		assert_eq!(lease.expire(expiry), Some(token));
+		assert_eq!(lease.remaining_ms(expiry), None);
+		assert_eq!(
+			lease.authorize(token, expiry),
+			Err(ErrorCode::NotLeaseHolder)
+		);
+
+		// An expired token cannot renew, a new one is handed out
+		let (next, _) = lease.acquire(Some(token), 1000, expiry)?;
+		assert_ne!(next, token);
+
+		Ok(())
+	}
+}
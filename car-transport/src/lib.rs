@@ -2,6 +2,62 @@
 
 #![no_std]
 
+// The `Arbitrary` derive refers to `std`
+#[cfg(any(feature = "std", feature = "arbitrary"))]
+extern crate std;
+
+// The derive macros refer to this crate by name
+extern crate self as car_transport;
+
+use core::fmt;
+
+pub mod arq;
+pub mod auth;
+pub mod clock;
+#[cfg(feature = "tokio")]
+pub mod codec;
+pub mod compat;
+pub mod dfu;
+pub mod envelope;
+pub mod fault;
+pub mod field;
+pub mod frame;
+pub mod handshake;
+pub mod lease;
+#[cfg(feature = "link")]
+pub mod link;
+pub mod log;
+pub mod nack;
+pub mod params;
+pub mod schema;
+pub mod telemetry;
+pub mod text;
+pub mod units;
+#[cfg(all(test, feature = "serde"))]
+mod vectors;
+
+pub use arq::Delivery;
+pub use auth::{Nonce, Tag};
+pub use car_transport_derive::{Field, Transport};
+pub use clock::Timestamp;
+#[cfg(feature = "tokio")]
+pub use codec::{CarCodec, Codec, ControllerCodec};
+pub use dfu::{Chunk, ImageHash};
+pub use envelope::{Envelope, Sequence};
+pub use fault::Fault;
+pub use field::{Field, Trailing};
+pub use frame::{FrameDecoder, FrameEncoder};
+pub use handshake::{Capabilities, FirmwareVersion, PROTOCOL_VERSION, ProtocolVersion};
+pub use lease::LeaseToken;
+#[cfg(feature = "link")]
+pub use link::{CarLink, ControllerLink, Link, LinkError};
+pub use log::{LogLevel, LogText};
+pub use nack::ErrorCode;
+pub use params::{Param, ParamId, ParamValue};
+pub use telemetry::{Telemetry, Topics};
+pub use text::{Encoding, Text};
+pub use units::{Millimeters, Millivolts, Steering, Throttle};
+
 /// A light custom transport protocol template that comes on top of bluetooth or serial communication.
 ///
 /// It transport data in the following format:
@@ -10,6 +66,34 @@
 /// | sub-type id (1b) | payload (varies) |
 /// +------------------+------------------+
 /// ```
+///
+/// It is usually derived on an enum, the payload holds the [`Field`]s of the variant:
+/// ```
+/// use car_transport::Transport;
+///
+/// #[derive(Transport)]
+/// enum Command {
+///     #[transport(id = 0)]
+///     Stop,
+///     #[transport(id = 1)]
+///     Drive { speed: i8, boost: Option<u16> },
+/// }
+///
+/// assert_eq!(Command::MAX_PAYLOAD_SIZE, 4);
+/// ```
+///
+/// Every variant needs a unique id:
+/// ```compile_fail
+/// use car_transport::Transport;
+///
+/// #[derive(Transport)]
+/// enum Command {
+///     #[transport(id = 0)]
+///     Stop,
+///     #[transport(id = 0)]
+///     Start,
+/// }
+/// ```
 pub trait Transport: Sized {
 	/// Size to allocate for a buffer
 	const BUFFER_SIZE: usize = Self::MAX_PAYLOAD_SIZE + 1;
@@ -20,18 +104,16 @@ pub trait Transport: Sized {
 	/// The unique id of the transport sub-type
 	fn id(&self) -> u8;
 
-	/// Encodes the payload into the buffer
-	fn encode(&self, buffer: &mut [u8]) -> u8;
+	/// Encodes the payload into the buffer and returns its length
+	fn encode(&self, buffer: &mut [u8]) -> usize;
 
 	/// Serializes the full message with the sub-type id into the buffer
 	fn serialize(&self, buffer: &mut [u8]) -> usize {
-		assert!(buffer.len() <= Self::MAX_PAYLOAD_SIZE + 1);
+		assert!(buffer.len() >= Self::BUFFER_SIZE);
 
 		buffer[0] = self.id();
 
-		let size = self.encode(&mut buffer[1..]);
-
-		usize::from(size) + 1
+		self.encode(&mut buffer[1..]) + 1
 	}
 
 	/// Deserializes the full message from the buffer
@@ -42,216 +124,673 @@ pub trait Transport: Sized {
 }
 
 /// A [`Transport`] related error
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
 pub enum TransportError {
 	/// There is no such unique id
 	InvalidId,
 	/// The payload length is invalid for the given id
 	InvalidPayload,
+	/// The buffer ends before the payload is complete
+	Truncated,
+	/// The buffer contains bytes after the end of the payload
+	TrailingBytes,
+	/// A payload value is outside of its valid range
+	OutOfRange,
+
+	/// The frame checksum does not match its content
+	InvalidChecksum,
+	/// The frame is not properly byte-stuffed or is too short
+	InvalidFraming,
+	/// The frame is longer than the decoder buffer
+	FrameTooLong,
+	/// The provided buffer is too small to hold the frame
+	BufferTooSmall,
+
+	/// The frame is not sealed with the session key
+	InvalidTag,
+	/// The frame counter was already used
+	Replayed,
+}
+
+impl fmt::Display for TransportError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let description = match self {
+			Self::InvalidId => "there is no such unique id",
+			Self::InvalidPayload => "the payload is invalid for the given id",
+			Self::Truncated => "the buffer ends before the payload is complete",
+			Self::TrailingBytes => "the buffer contains bytes after the payload",
+			Self::OutOfRange => "a payload value is outside of its valid range",
+			Self::InvalidChecksum => "the frame checksum does not match its content",
+			Self::InvalidFraming => "the frame is malformed",
+			Self::FrameTooLong => "the frame is longer than the decoder buffer",
+			Self::BufferTooSmall => "the buffer is too small to hold the frame",
+			Self::InvalidTag => "the frame is not sealed with the session key",
+			Self::Replayed => "the frame counter was already used",
+		};
+
+		f.write_str(description)
+	}
 }
 
+impl core::error::Error for TransportError {}
+
 /// Messages sent by the controller
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Transport)]
 #[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Message {
 	/// Ping the car
 	///
 	/// Car should answer with [`Answer::Pong`]
+	#[transport(id = 0)]
 	Ping,
+	/// Start a session by telling the car which protocol revision the controller speaks
+	///
+	/// Car should answer with [`Answer::Hello`]
+	#[transport(id = 5)]
+	Hello {
+		/// The protocol revision of the controller
+		version: ProtocolVersion,
+	},
 
 	/// Get the current speed
 	///
 	/// Car should answer with [`Answer::Speed`]
+	#[transport(id = 1)]
 	GetSpeed,
 	/// Get the current direction
 	///
 	/// Car should answer with [`Answer::Direction`]
+	#[transport(id = 2)]
 	GetDirection,
 	/// Get the current battery level
 	///
 	/// Car should answer with [`Answer::BatteryLevel`]
+	#[transport(id = 3)]
 	GetBatteryLevel,
 	/// Get the last measured distance with the ultrasonic sensor
 	///
 	/// Car should answer with [`Answer::UltrasonicDistance`]
+	#[transport(id = 4)]
 	GetUltrasonicDistance,
+	/// Get the current value of a tunable
+	///
+	/// Car should answer with [`Answer::Param`]
+	#[transport(id = 6)]
+	GetParam(ParamId),
+	/// Get the tunable at the given position, starting at 0
+	///
+	/// Car should answer with [`Answer::ParamEntry`]
+	#[transport(id = 7)]
+	ListParams {
+		/// Position of the tunable in the car table
+		index: u8,
+	},
+	/// Ask the car to push [`Answer::Telemetry`] snapshots periodically, replacing any previous subscription
+	///
+	/// Car should answer with [`Answer::Subscribed`]
+	#[transport(id = 8)]
+	Subscribe {
+		/// The values to carry in the snapshots
+		topics: Topics,
+		/// Time between two snapshots in milliseconds
+		#[transport(unit = "ms")]
+		period_ms: u16,
+	},
+	/// Stop the [`Answer::Telemetry`] snapshots
+	///
+	/// Car should answer with [`Answer::Unsubscribed`]
+	#[transport(id = 9)]
+	Unsubscribe,
+	/// Get the latched fault
+	///
+	/// Car should answer with [`Answer::Fault`]
+	#[transport(id = 10)]
+	GetFault,
+	/// Start an authenticated session, see [`auth`]
+	///
+	/// Car should answer with [`Answer::AuthChallenge`]
+	#[transport(id = 11)]
+	AuthChallenge {
+		/// The nonce of the controller
+		nonce: Nonce,
+	},
+	/// Prove that the controller knows the key, see [`auth`]
+	///
+	/// Car should answer with [`Answer::Authenticated`]
+	#[transport(id = 12)]
+	Authenticate {
+		/// Proof computed from both nonces
+		proof: Tag,
+	},
+	/// Ask the car to push its [`Answer::Log`] records of a level and above, see [`log`]
+	///
+	/// Car should answer with [`Answer::AckLogLevel`]
+	#[transport(id = 13)]
+	SetLogLevel {
+		/// Least important level to forward, [`None`] to stop forwarding
+		level: Option<LogLevel>,
+	},
+	/// Sample the car clock to synchronize with it, see [`clock`]
+	///
+	/// Car should answer with [`Answer::TimeSync`]
+	#[transport(id = 14)]
+	TimeSync {
+		/// The time of the controller clock when sending
+		origin: Timestamp,
+	},
 
 	/// Set the current speed
 	///
 	/// Car should answer with [`Answer::AckSpeed`]
-	SetSpeed(i8),
+	#[transport(id = 100)]
+	SetSpeed {
+		/// The token of the control lease
+		lease: LeaseToken,
+		/// The power sent to the motors
+		throttle: Throttle,
+	},
 	/// Set the current direction
 	///
 	/// Car should answer with [`Answer::AckDirection`]
-	SetDirection(i8),
+	#[transport(id = 101)]
+	SetDirection {
+		/// The token of the control lease
+		lease: LeaseToken,
+		/// The position of the front wheels
+		steering: Steering,
+	},
+	/// Change a tunable
+	///
+	/// Car should answer with [`Answer::AckParam`]
+	#[transport(id = 102)]
+	SetParam {
+		/// The token of the control lease
+		lease: LeaseToken,
+		/// The tunable and its new value
+		param: Param,
+	},
+	/// Brake the motors immediately and latch [`Fault::EmergencyStop`], handled before any other message
+	///
+	/// Car should answer with [`Answer::AckEmergencyStop`]
+	#[transport(id = 103)]
+	EmergencyStop,
+	/// Clear the latched fault, so that the car accepts speed changes again
+	///
+	/// Car should answer with [`Answer::AckClearFault`]
+	#[transport(id = 104)]
+	ClearFault {
+		/// The token of the control lease
+		lease: LeaseToken,
+	},
+	/// Get or renew the control lease, needed by the commands that drive or configure the car
+	///
+	/// Car should answer with [`Answer::ControlGranted`]
+	#[transport(id = 105)]
+	AcquireControl {
+		/// The token of the lease to renew, [`None`] to acquire a new one
+		renew: Option<LeaseToken>,
+		/// Time of silence after which the lease expires, in milliseconds
+		#[transport(unit = "ms")]
+		duration_ms: u16,
+	},
+	/// Give the control lease up, the car stops
+	///
+	/// Car should answer with [`Answer::AckReleaseControl`]
+	#[transport(id = 106)]
+	ReleaseControl {
+		/// The token of the control lease
+		lease: LeaseToken,
+	},
+	/// Prepare the car to receive a firmware image, or resume a partly received one, see [`dfu`]
+	///
+	/// Car should answer with [`Answer::UpdateReady`]
+	#[transport(id = 107)]
+	BeginUpdate {
+		/// The token of the control lease
+		lease: LeaseToken,
+		/// Size of the whole image in bytes
+		size: u32,
+		/// Hash of the whole image
+		hash: ImageHash,
+	},
+	/// Write part of the firmware image
+	///
+	/// Car should answer with [`Answer::AckWriteUpdate`]
+	#[transport(id = 108)]
+	WriteUpdate {
+		/// The token of the control lease
+		lease: LeaseToken,
+		/// Position of the chunk in the image
+		offset: u32,
+		/// The image bytes
+		chunk: Chunk,
+	},
+	/// Check that the firmware image is complete and matches its hash
+	///
+	/// Car should answer with [`Answer::UpdateVerified`]
+	#[transport(id = 109)]
+	VerifyUpdate {
+		/// The token of the control lease
+		lease: LeaseToken,
+	},
+	/// Boot on the verified firmware image, the car reboots after answering
+	///
+	/// Car should answer with [`Answer::AckCommitUpdate`]
+	#[transport(id = 110)]
+	CommitUpdate {
+		/// The token of the control lease
+		lease: LeaseToken,
+	},
 }
 
-impl Transport for Message {
-	const MAX_PAYLOAD_SIZE: usize = 1;
-
-	fn id(&self) -> u8 {
+impl Message {
+	/// Returns the components the car needs to handle this message
+	#[must_use]
+	pub const fn required_capabilities(&self) -> Capabilities {
 		match self {
-			Self::Ping => 0,
-			Self::GetSpeed => 1,
-			Self::GetDirection => 2,
-			Self::GetBatteryLevel => 3,
-			Self::GetUltrasonicDistance => 4,
-
-			Self::SetSpeed(_) => 100,
-			Self::SetDirection(_) => 101,
+			Self::GetSpeed | Self::SetSpeed { .. } => Capabilities::MOTORS,
+			Self::GetDirection | Self::SetDirection { .. } => Capabilities::SERVO,
+			Self::GetBatteryLevel => Capabilities::BATTERY,
+			Self::GetUltrasonicDistance => Capabilities::ULTRASONIC,
+			Self::BeginUpdate { .. }
+			| Self::WriteUpdate { .. }
+			| Self::VerifyUpdate { .. }
+			| Self::CommitUpdate { .. } => Capabilities::UPDATE,
+			Self::Ping
+			| Self::Hello { .. }
+			| Self::GetParam(_)
+			| Self::ListParams { .. }
+			| Self::Subscribe { .. }
+			| Self::Unsubscribe
+			| Self::GetFault
+			| Self::AuthChallenge { .. }
+			| Self::Authenticate { .. }
+			| Self::SetLogLevel { .. }
+			| Self::TimeSync { .. }
+			| Self::SetParam { .. }
+			| Self::EmergencyStop
+			| Self::ClearFault { .. }
+			| Self::AcquireControl { .. }
+			| Self::ReleaseControl { .. } => Capabilities::NONE,
 		}
 	}
 
-	fn encode(&self, buffer: &mut [u8]) -> u8 {
+	/// Returns the first revision of the protocol with this message
+	///
+	/// The payload may have changed since, when the major number did. A car speaking an older
+	/// revision does not know the message, see [`Session::supports`](handshake::Session::supports).
+	#[must_use]
+	pub const fn since(&self) -> ProtocolVersion {
 		match self {
 			Self::Ping
+			| Self::Hello { .. }
 			| Self::GetSpeed
 			| Self::GetDirection
 			| Self::GetBatteryLevel
-			| Self::GetUltrasonicDistance => 0,
-
-			Self::SetSpeed(speed) => {
-				buffer[0] = speed.to_be_bytes()[0];
-				1
-			}
-			Self::SetDirection(direction) => {
-				buffer[0] = direction.to_be_bytes()[0];
-				1
-			}
+			| Self::GetUltrasonicDistance
+			| Self::SetSpeed { .. }
+			| Self::SetDirection { .. } => handshake::SINCE,
+			Self::GetParam(_) | Self::ListParams { .. } | Self::SetParam { .. } => params::SINCE,
+			Self::Subscribe { .. } | Self::Unsubscribe => telemetry::SINCE,
+			Self::EmergencyStop | Self::ClearFault { .. } | Self::GetFault => fault::SINCE,
+			Self::AcquireControl { .. } | Self::ReleaseControl { .. } => lease::SINCE,
+			Self::AuthChallenge { .. } | Self::Authenticate { .. } => auth::SINCE,
+			Self::BeginUpdate { .. }
+			| Self::WriteUpdate { .. }
+			| Self::VerifyUpdate { .. }
+			| Self::CommitUpdate { .. } => dfu::SINCE,
+			Self::SetLogLevel { .. } => log::SINCE,
+			Self::TimeSync { .. } => clock::SINCE,
 		}
 	}
 
-	fn deserialize(buffer: &[u8]) -> Result<Self, TransportError> {
-		let message = match buffer[0] {
-			0 => Self::Ping,
-			1 => Self::GetSpeed,
-			2 => Self::GetDirection,
-			3 => Self::GetBatteryLevel,
-			4 => Self::GetUltrasonicDistance,
+	/// Returns whether the message belongs to the handshake, which travels in plain frames
+	#[must_use]
+	pub const fn is_handshake(&self) -> bool {
+		matches!(
+			self,
+			Self::Hello { .. } | Self::AuthChallenge { .. } | Self::Authenticate { .. }
+		)
+	}
 
-			100 => Self::SetSpeed(i8::from_be_bytes([buffer[1]])),
-			101 => Self::SetDirection(i8::from_be_bytes([buffer[1]])),
+	/// Returns whether the car accepts this message in a plain frame when it requires
+	/// authenticated sessions
+	///
+	/// Besides the handshake, emergency stops are always accepted: braking is safe to honour
+	/// whoever asks, and lines typed in a serial terminal are never sealed.
+	#[must_use]
+	pub const fn is_accepted_plain(&self) -> bool {
+		self.is_handshake() || matches!(self, Self::EmergencyStop)
+	}
 
-			_ => return Err(TransportError::InvalidId),
-		};
+	/// Returns the token carried by the commands that need the control lease
+	///
+	/// Queries, emergency stops and lease messages can be sent by any controller.
+	#[must_use]
+	pub const fn lease(&self) -> Option<LeaseToken> {
+		match self {
+			Self::SetSpeed { lease, .. }
+			| Self::SetDirection { lease, .. }
+			| Self::SetParam { lease, .. }
+			| Self::ClearFault { lease }
+			| Self::BeginUpdate { lease, .. }
+			| Self::WriteUpdate { lease, .. }
+			| Self::VerifyUpdate { lease }
+			| Self::CommitUpdate { lease } => Some(*lease),
+			_ => None,
+		}
+	}
 
-		Ok(message)
+	/// Returns how hard the controller should try to deliver this message
+	///
+	/// Steering and non-zero speeds are sent at a high rate and superseded by the next
+	/// message, stopping the car and every other message must not be lost. Clock samples are
+	/// only meaningful when answered right away, a late one is replaced by a new exchange.
+	#[must_use]
+	pub const fn delivery(&self) -> Delivery {
+		match self {
+			Self::SetSpeed { throttle, .. } if throttle.percent() != 0 => Delivery::BestEffort,
+			Self::SetDirection { .. } | Self::TimeSync { .. } => Delivery::BestEffort,
+			_ => Delivery::Reliable,
+		}
 	}
 }
 
 /// Messages sent by the car microcontroller
-#[derive(Debug, PartialEq, Eq)]
+///
+/// Most answers share the id of the [`Message`] they answer, but [`Answer::Nack`] answers any
+/// message, and [`Answer::Telemetry`] and [`Answer::Log`] are sent unsolicited. Answers are
+/// matched to their message through the sequence number of the [`Envelope`], not their id.
+#[derive(Debug, Clone, PartialEq, Eq, Transport)]
 #[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Answer {
 	/// Acknowledge a ping from the controller
 	/// Can also be used to check if there is still a controller connected
 	///
 	/// Answer to [`Message::Ping`]
+	#[transport(id = 0)]
 	Pong,
+	/// Describe the car firmware and its components
+	///
+	/// Answer to [`Message::Hello`]
+	#[transport(id = 5)]
+	Hello {
+		/// The protocol revision of the car
+		version: ProtocolVersion,
+		/// The firmware running on the car
+		firmware: FirmwareVersion,
+		/// The components available on the car
+		capabilities: Capabilities,
+	},
 
 	/// Send the current speed
 	///
 	/// Answer to [`Message::GetSpeed`]
-	Speed(i8),
+	#[transport(id = 1)]
+	Speed(Throttle),
 	/// Send the current direction
 	///
 	/// Answer to [`Message::GetDirection`]
-	Direction(i8),
+	#[transport(id = 2)]
+	Direction(Steering),
 	/// Send the current battery level
 	///
 	/// Answer to [`Message::GetBatteryLevel`]
-	BatteryLevel(u8),
+	#[transport(id = 3)]
+	#[cfg_attr(
+		feature = "serde",
+		serde(
+			serialize_with = "clock::sampled::serialize",
+			deserialize_with = "clock::sampled::deserialize"
+		)
+	)]
+	BatteryLevel {
+		/// The measured level
+		level: Millivolts,
+		/// When the level was measured on the car clock, since revision 4.4
+		sampled_at: Trailing<Timestamp>,
+	},
 	/// Send the last measured distance with the ultrasonic sensor
 	///
 	/// Answer to [`Message::GetUltrasonicDistance`]
-	UltrasonicDistance(Option<u8>),
+	#[transport(id = 4)]
+	#[cfg_attr(
+		feature = "serde",
+		serde(
+			serialize_with = "clock::sampled::serialize",
+			deserialize_with = "clock::sampled::deserialize"
+		)
+	)]
+	UltrasonicDistance {
+		/// The measured distance, [`None`] when no obstacle is in range
+		distance: Option<Millimeters>,
+		/// When the distance was measured on the car clock, since revision 4.4
+		sampled_at: Trailing<Timestamp>,
+	},
+	/// Send the value of a tunable
+	///
+	/// Answer to [`Message::GetParam`]
+	#[transport(id = 6)]
+	Param(Param),
+	/// Send a tunable with the number of tunables, [`None`] past the last one
+	///
+	/// Answer to [`Message::ListParams`]
+	#[transport(id = 7)]
+	ParamEntry {
+		/// Number of tunables on the car
+		count: u8,
+		/// The tunable at the requested position
+		param: Option<Param>,
+	},
+
+	/// Acknowledge the subscription with the period actually used by the car
+	///
+	/// Answer to [`Message::Subscribe`]
+	#[transport(id = 8)]
+	Subscribed {
+		/// Time between two snapshots in milliseconds, clamped to what the car supports
+		#[transport(unit = "ms")]
+		period_ms: u16,
+	},
+	/// Acknowledge the end of the subscription
+	///
+	/// Answer to [`Message::Unsubscribe`]
+	#[transport(id = 9)]
+	Unsubscribed,
+	/// Send the latched fault, [`None`] when the car runs normally
+	///
+	/// Answer to [`Message::GetFault`]
+	#[transport(id = 10)]
+	Fault(Option<Fault>),
+	/// Prove that the car knows the key, see [`auth`]
+	///
+	/// Answer to [`Message::AuthChallenge`]
+	#[transport(id = 11)]
+	AuthChallenge {
+		/// The nonce of the car
+		nonce: Nonce,
+		/// Proof computed from both nonces
+		proof: Tag,
+	},
+	/// Acknowledge the proof of the controller, the following frames are sealed
+	///
+	/// Answer to [`Message::Authenticate`]
+	#[transport(id = 12)]
+	Authenticated,
+	/// Acknowledge the forwarded log level
+	///
+	/// Answer to [`Message::SetLogLevel`]
+	#[transport(id = 13)]
+	AckLogLevel,
+	/// Send the times of the car clock around the synchronization, see [`clock`]
+	///
+	/// Answer to [`Message::TimeSync`]
+	#[transport(id = 14)]
+	TimeSync {
+		/// The time of the controller clock, copied from the message
+		origin: Timestamp,
+		/// The time of the car clock when the message was received
+		received: Timestamp,
+		/// The time of the car clock when the answer is sent
+		transmitted: Timestamp,
+	},
 
 	/// Acknowledge the speed change
 	///
 	/// Answer to [`Message::SetSpeed`]
+	#[transport(id = 100)]
 	AckSpeed,
 	/// Acknowledge the direction change
 	///
 	/// Answer to [`Message::SetDirection`]
+	#[transport(id = 101)]
 	AckDirection,
-}
-
-impl Transport for Answer {
-	const MAX_PAYLOAD_SIZE: usize = 2;
-
-	fn id(&self) -> u8 {
-		match self {
-			Self::Pong => 0,
-
-			Self::Speed(_) => 1,
-			Self::Direction(_) => 2,
-			Self::BatteryLevel(_) => 3,
-			Self::UltrasonicDistance(_) => 4,
+	/// Acknowledge the tunable change with the value that was stored after clamping
+	///
+	/// Answer to [`Message::SetParam`]
+	#[transport(id = 102)]
+	AckParam(Param),
+	/// Acknowledge the emergency stop, the motors are braking
+	///
+	/// Answer to [`Message::EmergencyStop`]
+	#[transport(id = 103)]
+	AckEmergencyStop,
+	/// Acknowledge that no fault is latched anymore
+	///
+	/// Answer to [`Message::ClearFault`]
+	#[transport(id = 104)]
+	AckClearFault {
+		/// The fault that was latched, [`None`] when there was none
+		cleared: Option<Fault>,
+	},
+	/// Grant the control lease
+	///
+	/// Answer to [`Message::AcquireControl`]
+	#[transport(id = 105)]
+	ControlGranted {
+		/// The token to put in the commands
+		lease: LeaseToken,
+		/// Time of silence after which the lease expires, clamped to what the car supports
+		#[transport(unit = "ms")]
+		duration_ms: u16,
+	},
+	/// Acknowledge that the control lease is free
+	///
+	/// Answer to [`Message::ReleaseControl`]
+	#[transport(id = 106)]
+	AckReleaseControl,
+	/// Tell where the firmware image should start or resume
+	///
+	/// Answer to [`Message::BeginUpdate`]
+	#[transport(id = 107)]
+	UpdateReady {
+		/// Offset of the first chunk to send
+		offset: u32,
+	},
+	/// Acknowledge a chunk of the firmware image
+	///
+	/// Answer to [`Message::WriteUpdate`]
+	#[transport(id = 108)]
+	AckWriteUpdate {
+		/// Offset of the next chunk to send, the chunk was ignored when it is not right after it
+		next: u32,
+	},
+	/// Confirm that the firmware image is complete and matches its hash
+	///
+	/// Answer to [`Message::VerifyUpdate`]
+	#[transport(id = 109)]
+	UpdateVerified,
+	/// Acknowledge that the car reboots on the new firmware
+	///
+	/// Answer to [`Message::CommitUpdate`]
+	#[transport(id = 110)]
+	AckCommitUpdate,
 
-			Self::AckSpeed => 100,
-			Self::AckDirection => 101,
-		}
-	}
+	/// Send a snapshot of the subscribed values
+	///
+	/// Sent without being asked after a [`Message::Subscribe`], with the sequence number of
+	/// the subscription
+	#[transport(id = 200)]
+	Telemetry(Telemetry),
+	/// Send a log record of the car
+	///
+	/// Sent without being asked after a [`Message::SetLogLevel`], with the sequence number of
+	/// that message
+	#[transport(id = 201)]
+	Log {
+		/// Importance of the record
+		level: LogLevel,
+		/// Number of records dropped since the previous one
+		dropped: u16,
+		/// What happened
+		text: LogText,
+	},
+
+	/// Refuse a message that could not be decoded or executed
+	///
+	/// Answer to any [`Message`], with its sequence number when it could be read
+	#[transport(id = 255)]
+	Nack {
+		/// Id of the refused message
+		for_id: u8,
+		/// Why the message was refused
+		reason: ErrorCode,
+	},
+}
 
-	fn encode(&self, buffer: &mut [u8]) -> u8 {
+impl Answer {
+	/// Returns the id of the message this answers, which differs from [`Transport::id`] for [`Answer::Nack`]
+	#[must_use]
+	pub fn answered_id(&self) -> u8 {
 		match self {
-			Self::Speed(speed) => {
-				buffer[0] = speed.to_be_bytes()[0];
-				1
-			}
-			Self::Direction(direction) => {
-				buffer[0] = direction.to_be_bytes()[0];
-				1
-			}
-			Self::BatteryLevel(voltage) => {
-				buffer[0] = *voltage;
-				1
-			}
-			Self::UltrasonicDistance(distance) => {
-				if let Some(distance) = distance {
-					buffer[0] = 1;
-					buffer[1] = *distance;
-				}
-
-				2
-			}
-
-			Self::Pong | Self::AckSpeed | Self::AckDirection => 0,
+			Self::Nack { for_id, .. } => *for_id,
+			answer => answer.id(),
 		}
 	}
-
-	fn deserialize(buffer: &[u8]) -> Result<Self, TransportError> {
-		let answer = match buffer[0] {
-			0 => Self::Pong,
-			1 => Self::Speed(i8::from_be_bytes([buffer[1]])),
-			2 => Self::Direction(i8::from_be_bytes([buffer[1]])),
-			3 => Self::BatteryLevel(buffer[1]),
-			4 => Self::UltrasonicDistance(match buffer[1] {
-				0 => None,
-				1 => Some(buffer[2]),
-				_ => return Err(TransportError::InvalidPayload),
-			}),
-
-			100 => Self::AckSpeed,
-			101 => Self::AckDirection,
-
-			_ => return Err(TransportError::InvalidId),
-		};
-
-		Ok(answer)
-	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
 
+	/// A tunable with a value of the largest type
+	const PARAM: Param = Param {
+		id: ParamId(0),
+		value: ParamValue::Integer(0),
+	};
+
+	/// A lease token for the commands
+	const LEASE: LeaseToken = LeaseToken(1);
+
+	/// A snapshot with every topic
+	const TELEMETRY: Telemetry = Telemetry {
+		speed: Some(Throttle::ZERO),
+		direction: Some(Steering::ZERO),
+		distance: Some(Some(Millimeters(0))),
+		battery: Some(Millivolts(0)),
+		link: Some(telemetry::LinkStats {
+			received: 0,
+			dropped: 0,
+		}),
+		sampled_at: Trailing(Some(Timestamp(0))),
+	};
+
+	/// A time on the car clock
+	const AT: Trailing<Timestamp> = Trailing(Some(Timestamp(0)));
+
 	#[test]
 	fn message_max_payload_size_is_right() {
+		let chunk = Chunk(heapless::Vec::from_slice(&[0; dfu::CHUNK_SIZE]).expect("chunk is full"));
+
 		#[rustfmt::skip]
 		let messages ={
 			use Message::*;
-			[GetSpeed, GetDirection, GetBatteryLevel, GetUltrasonicDistance, SetSpeed(0), SetDirection(0)]
+			[Hello { version: PROTOCOL_VERSION }, GetSpeed, GetDirection, GetBatteryLevel, GetUltrasonicDistance, GetParam(ParamId(0)), ListParams { index: 0 }, Subscribe { topics: Topics::ALL, period_ms: 0 }, Unsubscribe, GetFault, AuthChallenge { nonce: Nonce::default() }, Authenticate { proof: Tag::default() }, SetLogLevel { level: Some(LogLevel::Error) }, TimeSync { origin: Timestamp(0) }, SetSpeed { lease: LEASE, throttle: Throttle::ZERO }, SetDirection { lease: LEASE, steering: Steering::ZERO }, SetParam { lease: LEASE, param: PARAM }, EmergencyStop, ClearFault { lease: LEASE }, AcquireControl { renew: Some(LEASE), duration_ms: 0 }, ReleaseControl { lease: LEASE }, BeginUpdate { lease: LEASE, size: 0, hash: ImageHash::default() }, WriteUpdate { lease: LEASE, offset: 0, chunk }, VerifyUpdate { lease: LEASE }, CommitUpdate { lease: LEASE }]
 		};
 
 		let mut buffer = [0u8; Message::MAX_PAYLOAD_SIZE + 1];
@@ -260,17 +799,19 @@ mod tests {
 			.iter()
 			.map(|message| message.encode(&mut buffer))
 			.max()
-			.unwrap();
+			.expect("list is not empty");
 
-		assert_eq!(usize::from(max_length), Message::MAX_PAYLOAD_SIZE);
+		assert_eq!(max_length, Message::MAX_PAYLOAD_SIZE);
 	}
 
 	#[test]
 	fn answer_max_payload_size_is_right() {
+		let text = LogText::truncated("a log text that is longer than forty bytes");
+
 		#[rustfmt::skip]
 		let messages ={
 			use Answer::*;
-			[Speed(0), Direction(0), BatteryLevel(0), UltrasonicDistance(None), AckSpeed, AckDirection]
+			[Hello { version: PROTOCOL_VERSION, firmware: FirmwareVersion::parse("0.1.0"), capabilities: Capabilities::NONE }, Speed(Throttle::ZERO), Direction(Steering::ZERO), BatteryLevel { level: Millivolts(0), sampled_at: AT }, UltrasonicDistance { distance: Some(Millimeters(0)), sampled_at: AT }, Param(PARAM), ParamEntry { count: 0, param: Some(PARAM) }, Subscribed { period_ms: 0 }, Unsubscribed, Fault(Some(fault::Fault::EmergencyStop)), AuthChallenge { nonce: Nonce::default(), proof: Tag::default() }, Authenticated, AckLogLevel, TimeSync { origin: Timestamp(0), received: Timestamp(0), transmitted: Timestamp(0) }, Telemetry(TELEMETRY), Log { level: LogLevel::Error, dropped: 0, text }, AckSpeed, AckDirection, AckParam(PARAM), AckEmergencyStop, AckClearFault { cleared: Some(fault::Fault::EmergencyStop) }, ControlGranted { lease: LEASE, duration_ms: 0 }, AckReleaseControl, UpdateReady { offset: 0 }, AckWriteUpdate { next: 0 }, UpdateVerified, AckCommitUpdate, Nack { for_id: 0, reason: ErrorCode::UnknownMessage }]
 		};
 
 		let mut buffer = [0u8; Answer::MAX_PAYLOAD_SIZE];
@@ -279,9 +820,9 @@ mod tests {
 			.iter()
 			.map(|message| message.encode(&mut buffer))
 			.max()
-			.unwrap();
+			.expect("list is not empty");
 
-		assert_eq!(usize::from(max_length), Answer::MAX_PAYLOAD_SIZE);
+		assert_eq!(max_length, Answer::MAX_PAYLOAD_SIZE);
 	}
 
 	#[test]
@@ -301,15 +842,18 @@ mod tests {
 
 	#[test]
 	fn can_serialize_message_with_data() -> Result<(), TransportError> {
-		let message = Message::SetSpeed(100);
+		let message = Message::SetSpeed {
+			lease: LeaseToken(0x0102),
+			throttle: Throttle::MAX,
+		};
 		let mut buffer = [0u8; Message::MAX_PAYLOAD_SIZE + 1];
 
 		let length = message.serialize(&mut buffer);
-		assert_eq!(length, 2);
-		assert_eq!(&buffer[..length], &[100u8, 100i8.to_be_bytes()[0]]);
+		assert_eq!(length, 4);
+		assert_eq!(&buffer[..length], &[100u8, 1, 2, 100i8.to_be_bytes()[0]]);
 
-		let message = Message::deserialize(&buffer[..length])?;
-		assert_eq!(message, Message::SetSpeed(100));
+		let deserialized = Message::deserialize(&buffer[..length])?;
+		assert_eq!(deserialized, message);
 
 		Ok(())
 	}
@@ -331,7 +875,7 @@ mod tests {
 
 	#[test]
 	fn can_serialize_answer_with_data() -> Result<(), TransportError> {
-		let answer = Answer::Direction(100);
+		let answer = Answer::Direction(Steering::MAX);
 		let mut buffer = [0u8; Answer::MAX_PAYLOAD_SIZE + 1];
 
 		let length = answer.serialize(&mut buffer);
@@ -346,7 +890,7 @@ mod tests {
 
 	#[test]
 	fn can_serialize_message_with_signed_integer() -> Result<(), TransportError> {
-		let answer = Answer::Direction(-100);
+		let answer = Answer::Direction(Steering::MIN);
 		let mut buffer = [0u8; Answer::MAX_PAYLOAD_SIZE + 1];
 
 		let length = answer.serialize(&mut buffer);
@@ -358,4 +902,131 @@ mod tests {
 
 		Ok(())
 	}
+
+	#[test]
+	fn deserialize_rejects_truncated_buffers() {
+		assert_eq!(Message::deserialize(&[]), Err(TransportError::Truncated));
+		assert_eq!(Message::deserialize(&[100]), Err(TransportError::Truncated));
+		assert_eq!(Answer::deserialize(&[4, 1]), Err(TransportError::Truncated));
+		// A partial timestamp after the distance
+		assert_eq!(
+			Answer::deserialize(&[4, 0, 0]),
+			Err(TransportError::Truncated)
+		);
+	}
+
+	#[test]
+	fn deserialize_rejects_trailing_bytes() {
+		assert_eq!(
+			Message::deserialize(&[0, 0]),
+			Err(TransportError::TrailingBytes)
+		);
+		assert_eq!(
+			Answer::deserialize(&[1, 0, 0]),
+			Err(TransportError::TrailingBytes)
+		);
+	}
+
+	#[test]
+	fn can_serialize_answer_without_distance() -> Result<(), TransportError> {
+		let answer = Answer::UltrasonicDistance {
+			distance: None,
+			sampled_at: Trailing::NONE,
+		};
+		let mut buffer = [0xFFu8; Answer::MAX_PAYLOAD_SIZE + 1];
+
+		let length = answer.serialize(&mut buffer);
+		assert_eq!(&buffer[..length], &[answer.id(), 0]);
+
+		let deserialized = Answer::deserialize(&buffer[..length])?;
+		assert_eq!(deserialized, answer);
+
+		Ok(())
+	}
+
+	#[test]
+	fn sensor_answers_may_carry_their_timestamp() -> Result<(), TransportError> {
+		let answer = Answer::BatteryLevel {
+			level: Millivolts(7400),
+			sampled_at: Trailing(Some(Timestamp(0x0102))),
+		};
+		let mut buffer = [0u8; Answer::MAX_PAYLOAD_SIZE + 1];
+
+		let length = answer.serialize(&mut buffer);
+		assert_eq!(&buffer[..length], &[3, 0x1C, 0xE8, 0, 0, 0, 0, 0, 0, 1, 2]);
+		assert_eq!(Answer::deserialize(&buffer[..length])?, answer);
+
+		// Sent to controllers older than the timestamps
+		assert_eq!(
+			Answer::deserialize(&buffer[..3])?,
+			Answer::BatteryLevel {
+				level: Millivolts(7400),
+				sampled_at: Trailing::NONE,
+			}
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	#[cfg(feature = "serde")]
+	fn json_representation_is_stable() {
+		let message = Envelope::new(
+			Sequence(3),
+			Message::SetSpeed {
+				lease: LEASE,
+				throttle: Throttle::MAX,
+			},
+		);
+		let json = r#"{"sequence":3,"content":{"SetSpeed":{"lease":1,"throttle":100}}}"#;
+
+		assert_eq!(serde_json::to_string(&message).expect("serializable"), json);
+		assert_eq!(
+			serde_json::from_str::<Envelope<Message>>(json).expect("valid message"),
+			message
+		);
+		assert_eq!(
+			serde_json::to_string(&Answer::Nack {
+				for_id: 100,
+				reason: ErrorCode::OutOfRange
+			})
+			.expect("serializable"),
+			r#"{"Nack":{"for_id":100,"reason":"OutOfRange"}}"#
+		);
+
+		// Sensor values stand alone unless they carry their timestamp
+		let distance = Answer::UltrasonicDistance {
+			distance: None,
+			sampled_at: Trailing::NONE,
+		};
+		let stamped = Answer::UltrasonicDistance {
+			distance: Some(Millimeters(250)),
+			sampled_at: Trailing(Some(Timestamp(1_500))),
+		};
+		for (answer, json) in [
+			(distance, r#"{"UltrasonicDistance":null}"#),
+			(
+				stamped,
+				r#"{"UltrasonicDistance":{"value":250,"sampled_at":1500}}"#,
+			),
+		] {
+			assert_eq!(serde_json::to_string(&answer).expect("serializable"), json);
+			assert_eq!(
+				serde_json::from_str::<Answer>(json).expect("valid answer"),
+				answer
+			);
+		}
+		assert_eq!(
+			serde_json::from_str::<Answer>(r#"{"BatteryLevel":7400}"#).expect("valid answer"),
+			Answer::BatteryLevel {
+				level: Millivolts(7400),
+				sampled_at: Trailing::NONE
+			}
+		);
+
+		// Ranges are checked like on the wire
+		assert!(
+			serde_json::from_str::<Message>(r#"{"SetSpeed":{"lease":1,"throttle":101}}"#).is_err()
+		);
+	}
 }
@@ -0,0 +1,437 @@
+//! Framed link over any asynchronous byte stream
+//!
+//! A [`Link`] sends and receives [`Transport`] values over an [`embedded_io_async`] reader and
+//! writer, such as an UART, a serial port or an in-memory pipe. The controller uses a
+//! [`ControllerLink`] and the car a [`CarLink`], both share the same framing and error handling.
+//!
+//! With the `auth` feature, a link can seal and open frames with an authenticated [`Session`].
+//!
+//! Links also understand the [`text`] encoding typed in serial terminals. The encoding
+//! is detected on the first byte of every received value, and values are sent in the encoding of
+//! the last received one, so binary and text peers can take turns on the same link.
+
+use core::{fmt, marker::PhantomData, ops::Range};
+
+use embedded_io_async::{Read, Write};
+
+#[cfg(feature = "auth")]
+use crate::auth::{Opened, SEAL_SIZE, Session};
+use crate::{
+	Answer, Encoding, Envelope, FrameDecoder, FrameEncoder, Message, Text, Transport,
+	TransportError,
+	field::max_size,
+	frame::{DEFAULT_FRAME_BUFFER_SIZE, max_frame_size},
+	telemetry::LinkStats,
+	text::{self, LineDecoder, MAX_LINE_SIZE, TextContext},
+};
+
+/// Size of the buffer holding received bytes that were not decoded yet
+const RECEIVE_BUFFER_SIZE: usize = 32;
+
+/// Size of the buffer holding a sent frame or line
+const SEND_BUFFER_SIZE: usize =
+	max_size(&[max_frame_size(DEFAULT_FRAME_BUFFER_SIZE), MAX_LINE_SIZE]);
+
+/// The controller side of a link, sends messages and receives answers
+pub type ControllerLink<R, W> = Link<R, W, Envelope<Message>, Envelope<Answer>>;
+
+/// The car side of a link, sends answers and receives messages
+pub type CarLink<R, W> = Link<R, W, Envelope<Answer>, Envelope<Message>>;
+
+/// Sends `Tx` values and receives `Rx` values as frames or lines over a byte stream
+#[derive(Debug)]
+pub struct Link<R, W, Tx: Transport, Rx: Transport> {
+	/// Where received bytes come from
+	reader: R,
+	/// Where sent frames go to
+	writer: W,
+
+	/// Frames the sent values
+	encoder: FrameEncoder,
+	/// Extracts the values from the received bytes
+	decoder: FrameDecoder<Rx>,
+	/// Gathers the received lines
+	lines: LineDecoder,
+	/// What the received and sent lines leave implicit
+	context: TextContext,
+	/// Encoding of the value being received, [`None`] between values
+	receiving: Option<Encoding>,
+	/// Encoding of the last received value, used to send
+	encoding: Encoding,
+
+	/// Bytes read from `reader`
+	received: [u8; RECEIVE_BUFFER_SIZE],
+	/// Range of `received` that was not pushed into the decoder yet
+	pending: Range<usize>,
+	/// Counts the received and dropped frames
+	stats: LinkStats,
+	/// The authenticated session, if any
+	#[cfg(feature = "auth")]
+	session: Option<Session>,
+
+	/// The type of sent values
+	_transport: PhantomData<fn(Tx)>,
+}
+
+// The futures are `Send` when the reader and writer futures are
+#[allow(clippy::future_not_send)]
+impl<R: Read, W: Write, Tx: Transport + Text, Rx: Transport + Text> Link<R, W, Tx, Rx> {
+	/// Creates a link over the two halves of a byte stream
+	pub const fn new(reader: R, writer: W) -> Self {
+		Self {
+			reader,
+			writer,
+			encoder: FrameEncoder::new(),
+			decoder: FrameDecoder::new(),
+			lines: LineDecoder::new(),
+			context: TextContext::new(),
+			receiving: None,
+			encoding: Encoding::Binary,
+			received: [0; RECEIVE_BUFFER_SIZE],
+			pending: 0..0,
+			stats: LinkStats {
+				received: 0,
+				dropped: 0,
+			},
+			#[cfg(feature = "auth")]
+			session: None,
+			_transport: PhantomData,
+		}
+	}
+
+	/// Sends the value as a single frame, or line in the [`encoding`](Self::encoding) of the peer
+	///
+	/// # Errors
+	/// In case the value does not fit in a frame or the writer fails
+	pub async fn send(&mut self, value: &Tx) -> Result<(), LinkError<W::Error>> {
+		let mut frame = [0_u8; SEND_BUFFER_SIZE];
+		let length = match self.encoding {
+			Encoding::Binary => self.encoder.encode(value, &mut frame)?,
+			Encoding::Text => text::encode_line(value, &mut self.context, &mut frame)?,
+		};
+
+		self.writer
+			.write_all(&frame[..length])
+			.await
+			.map_err(LinkError::Io)?;
+		self.writer.flush().await.map_err(LinkError::Io)
+	}
+
+	/// Seals the value with the session key and sends it as a single frame
+	///
+	/// Lines cannot be sealed, they are sent plain when the peer types text.
+	///
+	/// # Errors
+	/// In case there is no session, the value does not fit in a frame or the writer fails
+	#[cfg(feature = "auth")]
+	pub async fn send_sealed(&mut self, value: &Tx) -> Result<(), LinkError<W::Error>> {
+		let session = self.session.as_mut().ok_or(TransportError::InvalidTag)?;
+		if self.encoding == Encoding::Text {
+			return self.send(value).await;
+		}
+
+		let mut frame = [0_u8; max_frame_size(DEFAULT_FRAME_BUFFER_SIZE + SEAL_SIZE)];
+		let length = self.encoder.encode_sealed(value, session, &mut frame)?;
+
+		self.writer
+			.write_all(&frame[..length])
+			.await
+			.map_err(LinkError::Io)?;
+		self.writer.flush().await.map_err(LinkError::Io)
+	}
+
+	/// Waits for the next complete value
+	///
+	/// Corrupted frames are reported as errors, the next call resumes with the following frame.
+	/// Cancelling the call is safe, bytes that were already read are kept.
+	///
+	/// # Errors
+	/// In case a frame is corrupted, the reader fails or reaches its end
+	pub async fn recv(&mut self) -> Result<Rx, LinkError<R::Error>> {
+		self.recv_with(|link, byte| link.decoder.push(byte), |value| value)
+			.await
+	}
+
+	/// Waits for the next complete value and tells whether it was sealed with the session key
+	///
+	/// See [`FrameDecoder::push_sealed`], every value is plain when there is no session, and lines
+	/// are always plain.
+	///
+	/// # Errors
+	/// In case a frame is corrupted or replayed, the reader fails or reaches its end
+	#[cfg(feature = "auth")]
+	pub async fn recv_opened(&mut self) -> Result<Opened<Rx>, LinkError<R::Error>> {
+		self.recv_with(
+			|link, byte| link.decoder.push_sealed(byte, link.session.as_mut()),
+			Opened::Plain,
+		)
+		.await
+	}
+
+	/// Replaces the session used to seal and open frames, [`None`] goes back to plain frames
+	#[cfg(feature = "auth")]
+	pub const fn set_session(&mut self, session: Option<Session>) {
+		self.session = session;
+	}
+
+	/// Returns whether frames can be sealed
+	#[cfg(feature = "auth")]
+	#[must_use]
+	pub const fn has_session(&self) -> bool {
+		self.session.is_some()
+	}
+
+	/// Returns the encoding of the last received value, the one used to send
+	#[must_use]
+	pub const fn encoding(&self) -> Encoding {
+		self.encoding
+	}
+
+	/// Pushes the received bytes with `push` until it completes a frame, or into the line decoder
+	/// until it completes a line parsed into a value by `plain`
+	async fn recv_with<V>(
+		&mut self,
+		push: fn(&mut Self, u8) -> Option<Result<V, TransportError>>,
+		plain: fn(Rx) -> V,
+	) -> Result<V, LinkError<R::Error>> {
+		loop {
+			for index in self.pending.clone() {
+				self.pending.start = index + 1;
+				let byte = self.received[index];
+
+				let Some(encoding) = self.receiving.or_else(|| self.encoding.detect(byte)) else {
+					continue;
+				};
+				self.receiving = Some(encoding);
+
+				let value = match encoding {
+					Encoding::Binary => push(self, byte),
+					Encoding::Text => self.lines.push(byte).map(|line| match line {
+						Ok(line) => Rx::parse_text(line, &mut self.context).map(plain),
+						Err(error) => {
+							self.context.header = None;
+							Err(error)
+						}
+					}),
+				};
+
+				if let Some(value) = value {
+					self.receiving = None;
+					self.encoding = encoding;
+
+					if value.is_ok() {
+						self.stats.received = self.stats.received.wrapping_add(1);
+					} else {
+						self.stats.dropped = self.stats.dropped.wrapping_add(1);
+					}
+
+					return value.map_err(LinkError::Transport);
+				}
+			}
+
+			let length = self
+				.reader
+				.read(&mut self.received)
+				.await
+				.map_err(LinkError::Io)?;
+			if length == 0 {
+				return Err(LinkError::Closed);
+			}
+
+			self.pending = 0..length;
+		}
+	}
+
+	/// Returns the serialized value of the last received frame, see [`FrameDecoder::last_frame`]
+	///
+	/// After a line, only its envelope header is available, see [`TextContext::last_line`].
+	#[must_use]
+	pub fn last_frame(&self) -> &[u8] {
+		match self.encoding {
+			Encoding::Binary => self.decoder.last_frame(),
+			Encoding::Text => self.context.last_line(),
+		}
+	}
+
+	/// Returns the counters of received and dropped frames
+	#[must_use]
+	pub const fn stats(&self) -> LinkStats {
+		self.stats
+	}
+
+	/// Returns the two halves of the byte stream
+	pub fn into_inner(self) -> (R, W) {
+		(self.reader, self.writer)
+	}
+}
+
+/// Errors that can occur on a [`Link`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub enum LinkError<E> {
+	/// A frame could not be encoded or decoded
+	Transport(TransportError),
+	/// The underlying byte stream failed
+	Io(E),
+	/// The underlying byte stream reached its end
+	Closed,
+}
+
+impl<E> From<TransportError> for LinkError<E> {
+	fn from(error: TransportError) -> Self {
+		Self::Transport(error)
+	}
+}
+
+impl<E: fmt::Debug> fmt::Display for LinkError<E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Transport(error) => write!(f, "{error}"),
+			Self::Io(error) => write!(f, "the byte stream failed: {error:?}"),
+			Self::Closed => f.write_str("the byte stream is closed"),
+		}
+	}
+}
+
+impl<E: fmt::Debug> core::error::Error for LinkError<E> {}
+
+#[cfg(test)]
+mod tests {
+	use core::{
+		pin::pin,
+		task::{Context, Poll, Waker},
+	};
+
+	use super::*;
+	use crate::{LeaseToken, Sequence, Throttle};
+
+	/// Polls the future until it completes, in-memory streams never wait
+	fn block_on<F: Future>(future: F) -> F::Output {
+		let mut future = pin!(future);
+		let mut context = Context::from_waker(Waker::noop());
+
+		loop {
+			if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+				return output;
+			}
+		}
+	}
+
+	#[test]
+	fn messages_and_answers_cross_the_link() {
+		let mut wire = [0_u8; 64];
+
+		let mut writer = &mut wire[..];
+		let mut controller = ControllerLink::new(&[][..], &mut writer);
+		let message = Envelope::new(
+			Sequence(3),
+			Message::SetSpeed {
+				lease: LeaseToken(1),
+				throttle: Throttle::MAX,
+			},
+		);
+		block_on(controller.send(&message)).expect("frame fits on the wire");
+		block_on(controller.send(&message)).expect("frame fits on the wire");
+		let written = 64 - writer.len();
+
+		let mut car = CarLink::new(&wire[..written], &mut [][..]);
+		assert_eq!(block_on(car.recv()), Ok(message.clone()));
+		assert_eq!(block_on(car.recv()), Ok(message));
+		assert_eq!(block_on(car.recv()), Err(LinkError::Closed));
+		assert_eq!(car.stats().received, 2);
+	}
+
+	#[test]
+	fn typed_lines_are_answered_with_lines() {
+		let mut wire = [0_u8; 64];
+		let mut frame = [0_u8; 16];
+		let ping = Envelope::new(Sequence(9), Message::Ping);
+		let length = FrameEncoder::<DEFAULT_FRAME_BUFFER_SIZE>::new()
+			.encode(&ping, &mut frame)
+			.expect("frame fits");
+
+		let mut received = [0_u8; 64];
+		let typed = b"acquire 5000\r\nSPEED 50\r\n";
+		received[..typed.len()].copy_from_slice(typed);
+		received[typed.len()..typed.len() + length].copy_from_slice(&frame[..length]);
+
+		let mut writer = &mut wire[..];
+		let mut car = CarLink::new(&received[..typed.len() + length], &mut writer);
+
+		let acquire = block_on(car.recv()).expect("line is a message");
+		assert_eq!(car.encoding(), Encoding::Text);
+		block_on(car.send(&acquire.reply(Answer::ControlGranted {
+			lease: LeaseToken(4660),
+			duration_ms: 5000,
+		})))
+		.expect("line fits on the wire");
+
+		let speed = block_on(car.recv()).expect("line is a message");
+		assert_eq!(
+			speed,
+			Envelope::new(
+				Sequence(2),
+				Message::SetSpeed {
+					lease: LeaseToken(4660),
+					throttle: Throttle::saturating(50),
+				}
+			)
+		);
+		block_on(car.send(&speed.reply(Answer::AckSpeed))).expect("line fits on the wire");
+
+		// The controller takes over with frames
+		assert_eq!(block_on(car.recv()), Ok(ping));
+		assert_eq!(car.encoding(), Encoding::Binary);
+		assert_eq!(car.stats().received, 3);
+
+		let written = 64 - writer.len();
+		assert_eq!(&wire[..written], b"OK ACQUIRE 4660 5000ms\r\nOK SPEED\r\n");
+	}
+
+	#[cfg(feature = "auth")]
+	#[test]
+	fn plain_stops_are_accepted_with_a_session() -> Result<(), TransportError> {
+		use crate::auth::{CarHandshake, ControllerHandshake, Key, Nonce, Opened};
+
+		let key = Key::new([7; 32]);
+		let controller = ControllerHandshake::new(key.clone(), Nonce([1; 8]));
+		let car = CarHandshake::new(key, Nonce([1; 8]), &[2]);
+		let Answer::AuthChallenge { nonce, proof } = car.challenge() else {
+			unreachable!("the car answers with a challenge");
+		};
+		let (Message::Authenticate { proof }, _) = controller.respond(nonce, proof)? else {
+			unreachable!("the controller answers with its proof");
+		};
+
+		let mut wire = [0_u8; 16];
+		let mut writer = &mut wire[..];
+		let mut link = CarLink::new(&b"STOP\r\nPING\r\n"[..], &mut writer);
+		link.set_session(Some(car.verify(proof)?));
+
+		// A serial terminal cannot seal its lines, it can still stop the car
+		let Ok(Opened::Plain(stop)) = block_on(link.recv_opened()) else {
+			panic!("lines are plain messages");
+		};
+		assert_eq!(stop.content, Message::EmergencyStop);
+		assert!(stop.content.is_accepted_plain());
+		block_on(link.send(&stop.reply(Answer::AckEmergencyStop))).expect("line fits");
+
+		let Ok(Opened::Plain(ping)) = block_on(link.recv_opened()) else {
+			panic!("lines are plain messages");
+		};
+		assert!(!ping.content.is_accepted_plain());
+
+		let written = 16 - writer.len();
+		assert_eq!(&wire[..written], b"OK STOP\r\n");
+
+		Ok(())
+	}
+
+	#[test]
+	fn corrupted_frames_are_counted() {
+		let mut car = CarLink::new(&[1, 2, 3, 0][..], &mut [][..]);
+
+		assert!(matches!(block_on(car.recv()), Err(LinkError::Transport(_))));
+		assert_eq!(car.stats().dropped, 1);
+	}
+}
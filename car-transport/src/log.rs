@@ -0,0 +1,332 @@
+//! Log records forwarded from the car to the controller
+//!
+//! Once the car is unplugged from the probe, its `defmt` logs are lost. The controller can ask
+//! the car to mirror its log records of a given level and above with
+//! [`Message::SetLogLevel`](crate::Message::SetLogLevel), they are then pushed as
+//! [`Answer::Log`](crate::Answer::Log) with the sequence number of that message.
+//!
+//! A [`LogForwarder`] rate limits the records so that they do not saturate the link, the
+//! records it drops are counted in the next forwarded one.
+//!
+//! Time is given by the caller as wrapping milliseconds, like in the [`arq`](crate::arq) module.
+
+use core::{fmt, str::FromStr};
+
+use crate::{Answer, Envelope, Field, ProtocolVersion, Sequence};
+
+/// First protocol revision with log forwarding
+pub const SINCE: ProtocolVersion = ProtocolVersion { major: 4, minor: 3 };
+
+/// Maximum number of bytes of text in a [`LogText`]
+pub const LOG_TEXT_SIZE: usize = 40;
+
+/// Importance of a log record, from the most verbose to the most important
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LogLevel {
+	/// Detailed tracing of the car internals
+	#[transport(id = 0)]
+	Trace,
+	/// Information useful when debugging
+	#[transport(id = 1)]
+	Debug,
+	/// Normal events, such as a controller connecting
+	#[transport(id = 2)]
+	Info,
+	/// Unexpected events the car recovered from
+	#[transport(id = 3)]
+	Warn,
+	/// Failures
+	#[transport(id = 4)]
+	Error,
+}
+
+impl LogLevel {
+	/// Every level, from the most verbose to the most important
+	pub const ALL: [Self; 5] = [
+		Self::Trace,
+		Self::Debug,
+		Self::Info,
+		Self::Warn,
+		Self::Error,
+	];
+
+	/// Returns the lowercase name of the level
+	#[must_use]
+	pub const fn name(self) -> &'static str {
+		match self {
+			Self::Trace => "trace",
+			Self::Debug => "debug",
+			Self::Info => "info",
+			Self::Warn => "warn",
+			Self::Error => "error",
+		}
+	}
+}
+
+impl fmt::Display for LogLevel {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+impl FromStr for LogLevel {
+	type Err = UnknownLogLevel;
+
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|level| level.name().eq_ignore_ascii_case(name))
+			.ok_or(UnknownLogLevel)
+	}
+}
+
+/// The name is not one of a [`LogLevel`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownLogLevel;
+
+impl fmt::Display for UnknownLogLevel {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("expected one of trace, debug, info, warn or error")
+	}
+}
+
+impl core::error::Error for UnknownLogLevel {}
+
+/// Text of a log record, at most [`LOG_TEXT_SIZE`] bytes
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct LogText(pub heapless::String<LOG_TEXT_SIZE>);
+
+impl LogText {
+	/// Copies the text, cut at a character boundary when it is too long
+	#[must_use]
+	pub fn truncated(text: &str) -> Self {
+		let mut truncated = heapless::String::new();
+		// Cannot fail, the prefix fits
+		let _ = truncated.push_str(prefix(text, LOG_TEXT_SIZE));
+
+		Self(truncated)
+	}
+
+	/// Returns the text
+	#[must_use]
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+}
+
+/// Formatting writes as much as fits and silently drops the rest
+impl fmt::Write for LogText {
+	fn write_str(&mut self, text: &str) -> fmt::Result {
+		// Cannot fail, the prefix fits
+		let _ = self.0.push_str(prefix(text, LOG_TEXT_SIZE - self.0.len()));
+
+		Ok(())
+	}
+}
+
+impl fmt::Display for LogText {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+/// Returns the longest start of `text` that fits in `size` bytes without cutting a character
+fn prefix(text: &str, size: usize) -> &str {
+	let mut end = text.len().min(size);
+	while !text.is_char_boundary(end) {
+		end -= 1;
+	}
+
+	&text[..end]
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for LogText {
+	fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+		Ok(Self::truncated(u.arbitrary()?))
+	}
+}
+
+/// Rate limits the log records sent to the controller and counts the dropped ones
+///
+/// Records are sent in bursts of up to `burst` records, then one every `period_ms`.
+#[derive(Debug, Clone)]
+pub struct LogForwarder {
+	/// Least important level forwarded, [`None`] when forwarding is off
+	level: Option<LogLevel>,
+	/// Sequence number of the message that turned forwarding on
+	sequence: Sequence,
+
+	/// Number of records that can be sent in a row
+	burst: u8,
+	/// Time to earn the right to send one more record
+	period_ms: u32,
+	/// Number of records that can be sent right now
+	tokens: u8,
+	/// Time at which `tokens` was last updated
+	refilled: u32,
+
+	/// Number of records dropped since the last forwarded one
+	dropped: u16,
+}
+
+impl LogForwarder {
+	/// Creates a forwarder that is off
+	#[must_use]
+	pub const fn new(burst: u8, period_ms: u32) -> Self {
+		Self {
+			level: None,
+			sequence: Sequence(0),
+			burst,
+			period_ms,
+			tokens: burst,
+			refilled: 0,
+			dropped: 0,
+		}
+	}
+
+	/// Forwards the records of `level` and above with the given sequence number, [`None`]
+	/// turns forwarding off
+	///
+	/// The count of dropped records starts over.
+	pub const fn set_level(&mut self, level: Option<LogLevel>, sequence: Sequence) {
+		self.level = level;
+		self.sequence = sequence;
+		self.dropped = 0;
+	}
+
+	/// Returns the least important level forwarded, [`None`] when forwarding is off
+	#[must_use]
+	pub const fn level(&self) -> Option<LogLevel> {
+		self.level
+	}
+
+	/// Counts records that were lost before reaching the forwarder, such as in a full queue
+	///
+	/// Nothing is counted while forwarding is off.
+	pub const fn drop_records(&mut self, count: u16) {
+		if self.level.is_some() {
+			self.dropped = self.dropped.saturating_add(count);
+		}
+	}
+
+	/// Returns the answer to send for the record, [`None`] when it is filtered out or dropped
+	pub fn forward(
+		&mut self,
+		level: LogLevel,
+		text: LogText,
+		now: u32,
+	) -> Option<Envelope<Answer>> {
+		if self.level.is_none_or(|forwarded| level < forwarded) {
+			return None;
+		}
+
+		self.refill(now);
+		if self.tokens == 0 {
+			self.drop_records(1);
+			return None;
+		}
+		self.tokens -= 1;
+
+		let answer = Answer::Log {
+			level,
+			dropped: core::mem::take(&mut self.dropped),
+			text,
+		};
+		Some(Envelope::new(self.sequence, answer))
+	}
+
+	/// Earns the tokens for the time elapsed since the last refill
+	fn refill(&mut self, now: u32) {
+		let earned = now.wrapping_sub(self.refilled) / self.period_ms.max(1);
+		let tokens = u32::from(self.tokens).saturating_add(earned);
+
+		if tokens >= u32::from(self.burst) {
+			self.tokens = self.burst;
+			self.refilled = now;
+		} else if earned != 0 {
+			// Cannot truncate, the tokens are below the burst
+			self.tokens = u8::try_from(tokens).unwrap_or(self.burst);
+			self.refilled = self.refilled.wrapping_add(earned * self.period_ms.max(1));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use core::fmt::Write;
+
+	use super::*;
+
+	#[test]
+	fn records_are_rate_limited() {
+		let mut forwarder = LogForwarder::new(2, 100);
+		let text = || LogText::truncated("lease expired");
+
+		assert_eq!(forwarder.forward(LogLevel::Error, text(), 0), None);
+		forwarder.drop_records(7);
+		forwarder.set_level(Some(LogLevel::Info), Sequence(4));
+		assert_eq!(forwarder.forward(LogLevel::Debug, text(), 0), None);
+
+		let start = u32::MAX - 10;
+		assert!(forwarder.forward(LogLevel::Warn, text(), start).is_some());
+		assert!(forwarder.forward(LogLevel::Warn, text(), start).is_some());
+		assert_eq!(forwarder.forward(LogLevel::Warn, text(), start), None);
+		forwarder.drop_records(2);
+
+		// The next record tells how many were dropped, then the count starts over
+		let answer = forwarder.forward(LogLevel::Info, text(), start.wrapping_add(100));
+		assert_eq!(
+			answer,
+			Some(Envelope::new(
+				Sequence(4),
+				Answer::Log {
+					level: LogLevel::Info,
+					dropped: 3,
+					text: text(),
+				}
+			))
+		);
+		assert_eq!(
+			forwarder.forward(LogLevel::Info, text(), start.wrapping_add(150)),
+			None
+		);
+
+		let answer = forwarder.forward(LogLevel::Info, text(), start.wrapping_add(200));
+		assert!(matches!(
+			answer,
+			Some(Envelope {
+				content: Answer::Log { dropped: 1, .. },
+				..
+			})
+		));
+	}
+
+	#[test]
+	fn long_texts_are_truncated() -> fmt::Result {
+		assert_eq!("WARN".parse(), Ok(LogLevel::Warn));
+		assert_eq!("loud".parse::<LogLevel>(), Err(UnknownLogLevel));
+
+		// Two bytes characters are not cut in half
+		let text = LogText::truncated("ééééééééééééééééééééé");
+		assert_eq!(text.as_str().len(), LOG_TEXT_SIZE);
+		assert_eq!(text.as_str().chars().count(), LOG_TEXT_SIZE / 2);
+
+		let mut text = LogText::default();
+		let (fits, cut) = ("a", 'é');
+		write!(text, "{fits:>39} {cut}")?;
+		assert_eq!(text.as_str().len(), LOG_TEXT_SIZE);
+		assert!(text.as_str().ends_with("a "));
+
+		Ok(())
+	}
+}
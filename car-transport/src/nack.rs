@@ -0,0 +1,103 @@
+//! Reasons the car refuses a message, sent in [`Answer::Nack`](crate::Answer::Nack)
+//!
+//! Instead of staying silent and letting the controller time out, the car answers every
+//! message it cannot execute with a [`ErrorCode`]. Codes are stable, new ones are a minor
+//! protocol revision.
+
+use core::fmt;
+
+use crate::{Field, TransportError, params::ParamError};
+
+/// Why the car refused a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorCode {
+	/// The message id is unknown to the car
+	#[transport(id = 0)]
+	UnknownMessage,
+	/// The payload could not be decoded
+	#[transport(id = 1)]
+	MalformedPayload,
+	/// A payload value is outside of its valid range
+	#[transport(id = 2)]
+	OutOfRange,
+	/// The component needed by the message is missing on the car
+	#[transport(id = 3)]
+	MissingComponent,
+	/// The car knows the message but cannot execute it
+	#[transport(id = 4)]
+	Unsupported,
+	/// No tunable has this identifier
+	#[transport(id = 5)]
+	UnknownParam,
+	/// The value does not have the type of the tunable
+	#[transport(id = 6)]
+	WrongParamType,
+	/// A latched fault stops the car, see [`Fault`](crate::Fault)
+	#[transport(id = 7)]
+	Faulted,
+	/// The command needs the control lease, see [`lease`](crate::lease)
+	#[transport(id = 8)]
+	NotLeaseHolder,
+	/// Another controller holds the control lease
+	#[transport(id = 9)]
+	LeaseTaken,
+	/// The car only accepts this message in an authenticated session, see [`auth`](crate::auth)
+	#[transport(id = 10)]
+	Unauthenticated,
+	/// The firmware image was not completely received, see [`dfu`](crate::dfu)
+	#[transport(id = 11)]
+	UpdateIncomplete,
+	/// The received firmware image does not match its hash
+	#[transport(id = 12)]
+	ImageMismatch,
+	/// The flash could not be erased or written
+	#[transport(id = 13)]
+	FlashFailed,
+}
+
+impl From<TransportError> for ErrorCode {
+	fn from(error: TransportError) -> Self {
+		match error {
+			TransportError::InvalidId => Self::UnknownMessage,
+			TransportError::OutOfRange => Self::OutOfRange,
+			TransportError::InvalidTag | TransportError::Replayed => Self::Unauthenticated,
+			_ => Self::MalformedPayload,
+		}
+	}
+}
+
+impl From<ParamError> for ErrorCode {
+	fn from(error: ParamError) -> Self {
+		match error {
+			ParamError::Unknown => Self::UnknownParam,
+			ParamError::WrongType => Self::WrongParamType,
+			ParamError::Duplicate | ParamError::Full => Self::Unsupported,
+		}
+	}
+}
+
+impl fmt::Display for ErrorCode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::UnknownMessage => "unknown message",
+			Self::MalformedPayload => "malformed payload",
+			Self::OutOfRange => "value out of range",
+			Self::MissingComponent => "missing component",
+			Self::Unsupported => "unsupported message",
+			Self::UnknownParam => "unknown parameter",
+			Self::WrongParamType => "wrong parameter type",
+			Self::Faulted => "the car is stopped by a fault",
+			Self::NotLeaseHolder => "the controller does not hold the control lease",
+			Self::LeaseTaken => "another controller holds the control lease",
+			Self::Unauthenticated => "the message needs an authenticated session",
+			Self::UpdateIncomplete => "the firmware image is not complete",
+			Self::ImageMismatch => "the firmware image does not match its hash",
+			Self::FlashFailed => "the flash could not be written",
+		})
+	}
+}
+
+impl core::error::Error for ErrorCode {}
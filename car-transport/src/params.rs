@@ -0,0 +1,407 @@
+//! Runtime tunables exchanged with [`Message::GetParam`](crate::Message::GetParam),
+//! [`Message::SetParam`](crate::Message::SetParam) and [`Message::ListParams`](crate::Message::ListParams)
+//!
+//! Every tunable is keyed by a stable [`ParamId`] and holds a typed [`ParamValue`]. The car keeps
+//! them in a [`ParamTable`] that components register into, so that new tunables do not need new
+//! messages.
+
+use core::fmt;
+
+use crate::{Field, ProtocolVersion};
+
+/// First protocol revision with the parameter registry
+pub const SINCE: ProtocolVersion = ProtocolVersion { major: 2, minor: 1 };
+
+/// Stable identifier of a tunable
+///
+/// Identifiers are never reused, so that an older controller can still edit a newer car.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct ParamId(pub u16);
+
+impl ParamId {
+	/// Offset of the servo neutral position, in degrees
+	pub const SERVO_TRIM: Self = Self(1);
+	/// Throttle below which the motors do not turn, in percent
+	pub const MOTOR_DEADBAND: Self = Self(2);
+	/// Frequency of the motors `PWM`, in hertz
+	pub const PWM_FREQUENCY: Self = Self(3);
+	/// Distance under which the car stops in front of an obstacle, in millimeters
+	pub const COLLISION_DISTANCE: Self = Self(4);
+	/// Proportional gain of the speed controller
+	pub const PID_KP: Self = Self(5);
+	/// Integral gain of the speed controller
+	pub const PID_KI: Self = Self(6);
+	/// Derivative gain of the speed controller
+	pub const PID_KD: Self = Self(7);
+
+	/// Every tunable with a name
+	pub const WELL_KNOWN: [Self; 7] = [
+		Self::SERVO_TRIM,
+		Self::MOTOR_DEADBAND,
+		Self::PWM_FREQUENCY,
+		Self::COLLISION_DISTANCE,
+		Self::PID_KP,
+		Self::PID_KI,
+		Self::PID_KD,
+	];
+
+	/// Returns the well-known tunable with this name
+	#[must_use]
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::WELL_KNOWN
+			.into_iter()
+			.find(|id| id.name() == Some(name))
+	}
+
+	/// Returns the name of a well-known tunable
+	#[must_use]
+	pub const fn name(self) -> Option<&'static str> {
+		Some(match self {
+			Self::SERVO_TRIM => "servo_trim",
+			Self::MOTOR_DEADBAND => "motor_deadband",
+			Self::PWM_FREQUENCY => "pwm_frequency",
+			Self::COLLISION_DISTANCE => "collision_distance",
+			Self::PID_KP => "pid_kp",
+			Self::PID_KI => "pid_ki",
+			Self::PID_KD => "pid_kd",
+			_ => return None,
+		})
+	}
+}
+
+impl fmt::Display for ParamId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.name() {
+			Some(name) => f.write_str(name),
+			None => write!(f, "#{}", self.0),
+		}
+	}
+}
+
+/// Value of a tunable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParamValue {
+	/// A switch
+	#[transport(id = 0)]
+	Bool(bool),
+	/// A whole number
+	#[transport(id = 1)]
+	Integer(i32),
+	/// A fixed point number in thousandths, used for gains
+	#[transport(id = 2)]
+	Milli(i32),
+}
+
+impl ParamValue {
+	/// Returns whether both values have the same type
+	#[must_use]
+	pub const fn same_type(self, other: Self) -> bool {
+		matches!(
+			(self, other),
+			(Self::Bool(_), Self::Bool(_))
+				| (Self::Integer(_), Self::Integer(_))
+				| (Self::Milli(_), Self::Milli(_))
+		)
+	}
+
+	/// Returns the value clamped between `min` and `max`, booleans are left as is
+	#[must_use]
+	pub fn clamp(self, min: i32, max: i32) -> Self {
+		match self {
+			Self::Bool(_) => self,
+			Self::Integer(value) => Self::Integer(value.clamp(min, max)),
+			Self::Milli(value) => Self::Milli(value.clamp(min, max)),
+		}
+	}
+}
+
+impl fmt::Display for ParamValue {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Bool(value) => write!(f, "{value}"),
+			Self::Integer(value) => write!(f, "{value}"),
+			Self::Milli(value) => {
+				let sign = if *value < 0 { "-" } else { "" };
+				let value = value.unsigned_abs();
+				write!(f, "{sign}{}.{:03}", value / 1000, value % 1000)
+			}
+		}
+	}
+}
+
+/// A tunable with its current value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Param {
+	/// Identifier of the tunable
+	pub id: ParamId,
+	/// Current value of the tunable
+	pub value: ParamValue,
+}
+
+/// Description of a tunable registered by a component
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub struct ParamSpec {
+	/// Identifier of the tunable
+	pub id: ParamId,
+	/// Value before any change, which also fixes the type of the tunable
+	pub default: ParamValue,
+	/// Lowest accepted value, ignored for booleans
+	pub min: i32,
+	/// Highest accepted value, ignored for booleans
+	pub max: i32,
+}
+
+impl ParamSpec {
+	/// Describes a switch
+	#[must_use]
+	pub const fn bool(id: ParamId, default: bool) -> Self {
+		Self {
+			id,
+			default: ParamValue::Bool(default),
+			min: 0,
+			max: 1,
+		}
+	}
+
+	/// Describes a whole number within `min..=max`
+	#[must_use]
+	pub const fn integer(id: ParamId, default: i32, min: i32, max: i32) -> Self {
+		Self {
+			id,
+			default: ParamValue::Integer(default),
+			min,
+			max,
+		}
+	}
+
+	/// Describes a fixed point number in thousandths within `min..=max`
+	#[must_use]
+	pub const fn milli(id: ParamId, default: i32, min: i32, max: i32) -> Self {
+		Self {
+			id,
+			default: ParamValue::Milli(default),
+			min,
+			max,
+		}
+	}
+}
+
+/// Reasons a tunable could not be registered, read or changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub enum ParamError {
+	/// No tunable has this identifier
+	Unknown,
+	/// The value does not have the type of the tunable
+	WrongType,
+	/// A tunable with this identifier is already registered
+	Duplicate,
+	/// The table has no room left
+	Full,
+}
+
+impl fmt::Display for ParamError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::Unknown => "unknown parameter",
+			Self::WrongType => "wrong parameter type",
+			Self::Duplicate => "parameter is already registered",
+			Self::Full => "parameter table is full",
+		})
+	}
+}
+
+impl core::error::Error for ParamError {}
+
+/// A registered tunable with its current value
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+	/// How the tunable was registered
+	spec: ParamSpec,
+	/// Current value, always of the type of the default value and within range
+	value: ParamValue,
+}
+
+/// Fixed capacity table of the tunables of the car
+#[derive(Debug)]
+pub struct ParamTable<const N: usize> {
+	/// Registered tunables, in registration order
+	entries: [Option<Entry>; N],
+	/// Number of registered tunables
+	len: usize,
+}
+
+impl<const N: usize> Default for ParamTable<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<const N: usize> ParamTable<N> {
+	/// Creates an empty table
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			entries: [None; N],
+			len: 0,
+		}
+	}
+
+	/// Adds a tunable set to its default value
+	///
+	/// # Errors
+	/// In case the identifier is already used or the table is full
+	pub fn register(&mut self, spec: ParamSpec) -> Result<(), ParamError> {
+		if self.entry(spec.id).is_some() {
+			return Err(ParamError::Duplicate);
+		}
+
+		let slot = self.entries.get_mut(self.len).ok_or(ParamError::Full)?;
+		*slot = Some(Entry {
+			spec,
+			value: spec.default,
+		});
+		self.len += 1;
+
+		Ok(())
+	}
+
+	/// Adds every tunable of a component
+	///
+	/// # Errors
+	/// In case an identifier is already used or the table is full
+	pub fn register_all(&mut self, specs: &[ParamSpec]) -> Result<(), ParamError> {
+		specs.iter().try_for_each(|spec| self.register(*spec))
+	}
+
+	/// Returns the number of registered tunables
+	#[must_use]
+	pub const fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Returns whether no tunable is registered
+	#[must_use]
+	pub const fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// Returns the current value of a tunable
+	///
+	/// # Errors
+	/// In case no tunable has this identifier
+	pub fn get(&self, id: ParamId) -> Result<ParamValue, ParamError> {
+		self.entry(id)
+			.map(|entry| entry.value)
+			.ok_or(ParamError::Unknown)
+	}
+
+	/// Changes a tunable and returns the value that was stored, clamped to its range
+	///
+	/// # Errors
+	/// In case no tunable has this identifier or the value has the wrong type
+	pub fn set(&mut self, id: ParamId, value: ParamValue) -> Result<ParamValue, ParamError> {
+		let entry = self
+			.entries
+			.iter_mut()
+			.flatten()
+			.find(|entry| entry.spec.id == id)
+			.ok_or(ParamError::Unknown)?;
+
+		if !entry.spec.default.same_type(value) {
+			return Err(ParamError::WrongType);
+		}
+
+		entry.value = value.clamp(entry.spec.min, entry.spec.max);
+
+		Ok(entry.value)
+	}
+
+	/// Returns the tunable at the given position, used to list them one by one
+	#[must_use]
+	pub fn at(&self, index: usize) -> Option<Param> {
+		let entry = self.entries.get(index)?.as_ref()?;
+
+		Some(Param {
+			id: entry.spec.id,
+			value: entry.value,
+		})
+	}
+
+	/// Iterates over every tunable with its current value
+	pub fn iter(&self) -> impl Iterator<Item = Param> + '_ {
+		(0..self.len).filter_map(|index| self.at(index))
+	}
+
+	/// Returns the entry of a tunable
+	fn entry(&self, id: ParamId) -> Option<&Entry> {
+		self.entries
+			.iter()
+			.flatten()
+			.find(|entry| entry.spec.id == id)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn table_registers_and_clamps() -> Result<(), ParamError> {
+		let mut table = ParamTable::<2>::new();
+		table.register_all(&[
+			ParamSpec::integer(ParamId::SERVO_TRIM, 0, -20, 20),
+			ParamSpec::milli(ParamId::PID_KP, 1500, 0, 10_000),
+		])?;
+
+		assert_eq!(
+			table.set(ParamId::SERVO_TRIM, ParamValue::Integer(-35))?,
+			ParamValue::Integer(-20)
+		);
+		assert_eq!(table.get(ParamId::PID_KP)?, ParamValue::Milli(1500));
+		assert_eq!(
+			table.set(ParamId::PID_KP, ParamValue::Bool(true)),
+			Err(ParamError::WrongType)
+		);
+		assert_eq!(table.get(ParamId::PWM_FREQUENCY), Err(ParamError::Unknown));
+
+		assert_eq!(
+			table.register(ParamSpec::bool(ParamId::SERVO_TRIM, false)),
+			Err(ParamError::Duplicate)
+		);
+		assert_eq!(
+			table.register(ParamSpec::bool(ParamId(42), false)),
+			Err(ParamError::Full)
+		);
+
+		assert_eq!(table.iter().count(), 2);
+		assert_eq!(table.at(2), None);
+
+		Ok(())
+	}
+
+	#[test]
+	fn values_are_displayed() {
+		extern crate std;
+		use std::string::ToString;
+
+		assert_eq!(ParamValue::Milli(-1050).to_string(), "-1.050");
+		assert_eq!(ParamId::PID_KD.to_string(), "pid_kd");
+		assert_eq!(ParamId(300).to_string(), "#300");
+		assert_eq!(ParamId::from_name("pid_kd"), Some(ParamId::PID_KD));
+	}
+}
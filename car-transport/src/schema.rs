@@ -0,0 +1,310 @@
+//! Machine readable description of the protocol
+//!
+//! Every [`Field`](crate::Field) describes its encoding with a [`Shape`], and every enum deriving
+//! [`Transport`](derive@crate::Transport) lists its [`Variant`]s in [`Schema`]. Both are generated
+//! by the derive macros from the Rust definitions, with their documentation comments and the units
+//! given by `#[transport(unit = "...")]` attributes, so the description cannot drift from the code.
+//!
+//! [`PROTOCOL`] gathers the whole protocol, it serializes to JSON with the `serde` feature:
+//! ```json
+//! {
+//!   "version": { "major": 4, "minor": 3 },
+//!   "framing": { "stuffing": "COBS", "delimiter": 0, ... },
+//!   "messages": [{ "name": "Ping", "id": 0, "doc": "...", "members": [] }, ...],
+//!   "answers": [...]
+//! }
+//! ```
+
+use crate::{
+	Answer, Message, ProtocolVersion,
+	auth::SEAL_SIZE,
+	frame::{CHECKSUM_SIZE, FRAME_DELIMITER},
+	handshake::PROTOCOL_VERSION,
+};
+
+/// The variants of an enum deriving [`Transport`](derive@crate::Transport)
+pub trait Schema {
+	/// Every variant, in declaration order
+	const VARIANTS: &'static [Variant];
+}
+
+/// How a [`Field`](crate::Field) is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize),
+	serde(tag = "kind", rename_all = "snake_case")
+)]
+pub enum Shape {
+	/// Unsigned integer in big endian
+	Unsigned {
+		/// Size in bytes
+		size: usize,
+	},
+	/// Two's complement integer in big endian
+	Signed {
+		/// Size in bytes
+		size: usize,
+	},
+	/// A `0` or `1` byte
+	Bool,
+	/// Bytes copied as they are
+	Bytes {
+		/// Number of bytes
+		size: usize,
+	},
+	/// A `0` or `1` tag byte followed by the value when present
+	Option {
+		/// Shape of the value
+		value: &'static Self,
+	},
+	/// The value when present, nothing otherwise, only at the end of a payload
+	Trailing {
+		/// Shape of the value
+		value: &'static Self,
+	},
+	/// Items prefixed with their number
+	List {
+		/// Maximum number of items
+		capacity: usize,
+		/// Size of the length prefix in bytes
+		prefix: usize,
+		/// Shape of every item
+		item: &'static Self,
+	},
+	/// `UTF-8` text prefixed with its length in bytes
+	Text {
+		/// Maximum length in bytes
+		capacity: usize,
+		/// Size of the length prefix in bytes
+		prefix: usize,
+	},
+	/// Members encoded one after the other
+	Struct {
+		/// Name of the Rust type
+		name: &'static str,
+		/// Documentation of the Rust type
+		doc: &'static str,
+		/// Unit of the value, for single member structs
+		#[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+		unit: Option<&'static str>,
+		/// The members, in encoding order
+		members: &'static [Member],
+	},
+	/// A tag byte with the id of the variant followed by its members
+	Enum {
+		/// Name of the Rust type
+		name: &'static str,
+		/// Documentation of the Rust type
+		doc: &'static str,
+		/// Every variant
+		variants: &'static [Variant],
+	},
+}
+
+impl Shape {
+	/// Returns the maximum size of the encoded value in bytes
+	#[must_use]
+	pub const fn max_size(&self) -> usize {
+		match *self {
+			Self::Unsigned { size } | Self::Signed { size } | Self::Bytes { size } => size,
+			Self::Bool => 1,
+			Self::Option { value } => 1 + value.max_size(),
+			Self::Trailing { value } => value.max_size(),
+			Self::List {
+				capacity,
+				prefix,
+				item,
+			} => prefix + capacity * item.max_size(),
+			Self::Text { capacity, prefix } => prefix + capacity,
+			Self::Struct { members, .. } => members_size(members),
+			Self::Enum { variants, .. } => 1 + max_variant_size(variants),
+		}
+	}
+}
+
+/// A variant of an enum, with the id that tags it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Variant {
+	/// Name of the Rust variant
+	pub name: &'static str,
+	/// Tag byte of the variant
+	pub id: u8,
+	/// Documentation of the Rust variant
+	pub doc: &'static str,
+	/// The members, in encoding order
+	pub members: &'static [Member],
+}
+
+impl Variant {
+	/// Returns the maximum size of the encoded members in bytes
+	#[must_use]
+	pub const fn max_size(&self) -> usize {
+		members_size(self.members)
+	}
+}
+
+/// A field of a struct or of a variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Member {
+	/// Name of the Rust field, its position for tuples
+	pub name: &'static str,
+	/// Documentation of the Rust field
+	pub doc: &'static str,
+	/// Unit of the value, when not carried by its type
+	#[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+	pub unit: Option<&'static str>,
+	/// How the value is encoded
+	pub shape: &'static Shape,
+}
+
+/// How envelopes are cut into frames, see the [`frame`](crate::frame) and [`auth`](crate::auth)
+/// modules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Framing {
+	/// Byte stuffing algorithm that removes the delimiter from the content
+	pub stuffing: &'static str,
+	/// Byte that ends every frame
+	pub delimiter: u8,
+	/// Checksum of the serialized envelope, appended in big endian before stuffing
+	pub checksum: &'static str,
+	/// Size of the checksum in bytes
+	pub checksum_size: usize,
+	/// Size of the counter and tag appended to sealed envelopes
+	pub seal_size: usize,
+}
+
+/// The whole protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Protocol {
+	/// Revision of the protocol
+	pub version: ProtocolVersion,
+	/// How envelopes are cut into frames
+	pub framing: Framing,
+	/// Variants of [`Message`], sent by the controller
+	pub messages: &'static [Variant],
+	/// Variants of [`Answer`], sent by the car
+	pub answers: &'static [Variant],
+}
+
+/// The protocol spoken by this crate
+pub const PROTOCOL: Protocol = Protocol {
+	version: PROTOCOL_VERSION,
+	framing: Framing {
+		stuffing: "COBS",
+		delimiter: FRAME_DELIMITER,
+		checksum: "CRC-16/CCITT-FALSE",
+		checksum_size: CHECKSUM_SIZE,
+		seal_size: SEAL_SIZE,
+	},
+	messages: Message::VARIANTS,
+	answers: Answer::VARIANTS,
+};
+
+/// Returns the sum of the maximum sizes of the members
+const fn members_size(members: &[Member]) -> usize {
+	let mut size = 0;
+	let mut index = 0;
+
+	while index < members.len() {
+		size += members[index].shape.max_size();
+		index += 1;
+	}
+
+	size
+}
+
+/// Returns the largest maximum size of the variants
+const fn max_variant_size(variants: &[Variant]) -> usize {
+	let mut max = 0;
+	let mut index = 0;
+
+	while index < variants.len() {
+		let size = variants[index].max_size();
+		if size > max {
+			max = size;
+		}
+		index += 1;
+	}
+
+	max
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Field, LogLevel, LogText, Millimeters, Transport, log::LOG_TEXT_SIZE};
+
+	#[test]
+	fn schema_matches_the_encoding() {
+		assert_eq!(
+			max_variant_size(PROTOCOL.messages),
+			Message::MAX_PAYLOAD_SIZE
+		);
+		assert_eq!(max_variant_size(PROTOCOL.answers), Answer::MAX_PAYLOAD_SIZE);
+		assert_eq!(LogLevel::SHAPE.max_size(), LogLevel::MAX_SIZE);
+
+		let log = PROTOCOL
+			.answers
+			.iter()
+			.find(|variant| variant.name == "Log")
+			.expect("log answer is described");
+		assert_eq!(log.id, 201);
+		assert!(
+			log.members
+				.iter()
+				.map(|member| member.name)
+				.eq(["level", "dropped", "text"])
+		);
+		assert_eq!(
+			log.members[1].doc,
+			"Number of records dropped since the previous one"
+		);
+
+		let Shape::Struct { members, .. } = LogText::SHAPE else {
+			panic!("log text is a struct");
+		};
+		assert_eq!(
+			*members[0].shape,
+			Shape::Text {
+				capacity: LOG_TEXT_SIZE,
+				prefix: 1
+			}
+		);
+		assert!(matches!(
+			Millimeters::SHAPE,
+			Shape::Struct {
+				unit: Some("mm"),
+				..
+			}
+		));
+	}
+
+	#[test]
+	#[cfg(feature = "serde")]
+	fn schema_serializes_to_json() -> Result<(), serde_json::Error> {
+		let schema = serde_json::to_value(PROTOCOL)?;
+
+		assert_eq!(schema["framing"]["seal_size"], SEAL_SIZE);
+		assert_eq!(schema["messages"][0]["name"], "Ping");
+		assert_eq!(
+			schema["messages"][0]["doc"],
+			"Ping the car\n\nCar should answer with [`Answer::Pong`]"
+		);
+		let speed = schema["answers"]
+			.as_array()
+			.and_then(|answers| answers.iter().find(|answer| answer["name"] == "Speed"))
+			.expect("speed answer is described");
+		assert_eq!(speed["members"][0]["shape"]["unit"], "%");
+		assert_eq!(
+			speed["members"][0]["shape"]["members"][0]["shape"],
+			serde_json::json!({ "kind": "signed", "size": 1 })
+		);
+
+		Ok(())
+	}
+}
@@ -0,0 +1,222 @@
+//! Periodic snapshots pushed by the car
+//!
+//! After a [`Message::Subscribe`](crate::Message::Subscribe), the car sends an
+//! [`Answer::Telemetry`](crate::Answer::Telemetry) every period without being asked, until a
+//! [`Message::Unsubscribe`](crate::Message::Unsubscribe). Snapshots only carry the subscribed
+//! [`Topics`], which keeps them small on the link.
+
+use core::ops;
+
+use crate::{
+	Field, Millimeters, Millivolts, ProtocolVersion, Steering, Throttle, Timestamp, field::Trailing,
+};
+
+/// First protocol revision with telemetry subscriptions
+pub const SINCE: ProtocolVersion = ProtocolVersion { major: 2, minor: 2 };
+
+/// Set of values carried in a [`Telemetry`] snapshot
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+pub struct Topics(pub u8);
+
+impl Topics {
+	/// No value
+	pub const NONE: Self = Self(0);
+	/// The current speed
+	pub const SPEED: Self = Self(1 << 0);
+	/// The current direction
+	pub const DIRECTION: Self = Self(1 << 1);
+	/// The last measured distance with the ultrasonic sensor
+	pub const DISTANCE: Self = Self(1 << 2);
+	/// The current battery level
+	pub const BATTERY: Self = Self(1 << 3);
+	/// Statistics about the link with the controller
+	pub const LINK: Self = Self(1 << 4);
+	/// Every value
+	pub const ALL: Self = Self(0b1_1111);
+
+	/// Returns whether every topic of `other` is in the set
+	#[must_use]
+	pub const fn contains(self, other: Self) -> bool {
+		self.0 & other.0 == other.0
+	}
+}
+
+impl ops::BitOr for Topics {
+	type Output = Self;
+
+	fn bitor(self, other: Self) -> Self {
+		Self(self.0 | other.0)
+	}
+}
+
+impl ops::BitAnd for Topics {
+	type Output = Self;
+
+	fn bitand(self, other: Self) -> Self {
+		Self(self.0 & other.0)
+	}
+}
+
+/// Counters about the frames received by the car, they wrap around
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkStats {
+	/// Number of valid frames received
+	pub received: u16,
+	/// Number of frames dropped because they were corrupted or malformed
+	pub dropped: u16,
+}
+
+/// A snapshot of the car state, values outside of the subscribed [`Topics`] are [`None`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Telemetry {
+	/// The current speed, see [`Topics::SPEED`]
+	pub speed: Option<Throttle>,
+	/// The current direction, see [`Topics::DIRECTION`]
+	pub direction: Option<Steering>,
+	/// The last measured distance, itself [`None`] when no obstacle is in range, see [`Topics::DISTANCE`]
+	#[cfg_attr(
+		feature = "serde",
+		serde(
+			default,
+			skip_serializing_if = "Option::is_none",
+			with = "sampled_distance"
+		)
+	)]
+	pub distance: Option<Option<Millimeters>>,
+	/// The current battery level, see [`Topics::BATTERY`]
+	pub battery: Option<Millivolts>,
+	/// Statistics about the link, see [`Topics::LINK`]
+	pub link: Option<LinkStats>,
+	/// When the values were sampled on the car clock, since revision 4.4
+	#[cfg_attr(
+		feature = "serde",
+		serde(default, skip_serializing_if = "Trailing::is_none")
+	)]
+	pub sampled_at: Trailing<Timestamp>,
+}
+
+impl Telemetry {
+	/// Keeps only the values of the given topics
+	#[must_use]
+	pub fn filter(self, topics: Topics) -> Self {
+		Self {
+			speed: self.speed.filter(|_| topics.contains(Topics::SPEED)),
+			direction: self
+				.direction
+				.filter(|_| topics.contains(Topics::DIRECTION)),
+			distance: self.distance.filter(|_| topics.contains(Topics::DISTANCE)),
+			battery: self.battery.filter(|_| topics.contains(Topics::BATTERY)),
+			link: self.link.filter(|_| topics.contains(Topics::LINK)),
+			sampled_at: self.sampled_at,
+		}
+	}
+}
+
+/// Represents a sampled distance by the distance alone, so that a distance that was not sampled
+/// leaves the field out instead of being written `null` like a distance without obstacle
+#[cfg(feature = "serde")]
+mod sampled_distance {
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+	use crate::Millimeters;
+
+	/// Serializes a sampled distance, `null` when no obstacle is in range
+	// `serde` gives a reference to the field, whose type is fixed
+	#[allow(
+		clippy::ref_option,
+		clippy::option_option,
+		clippy::trivially_copy_pass_by_ref
+	)]
+	pub fn serialize<S: Serializer>(
+		distance: &Option<Option<Millimeters>>,
+		serializer: S,
+	) -> Result<S::Ok, S::Error> {
+		distance.flatten().serialize(serializer)
+	}
+
+	/// Deserializes a present field as a sampled distance
+	// The type of the field is fixed
+	#[allow(clippy::option_option)]
+	pub fn deserialize<'de, D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Option<Option<Millimeters>>, D::Error> {
+		Option::deserialize(deserializer).map(Some)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Answer, Transport};
+
+	#[test]
+	fn snapshots_only_carry_subscribed_topics() {
+		let snapshot = Telemetry {
+			speed: Some(Throttle::MAX),
+			direction: Some(Steering::ZERO),
+			distance: Some(None),
+			battery: Some(Millivolts(7400)),
+			link: Some(LinkStats::default()),
+			sampled_at: Trailing(Some(Timestamp(1_000))),
+		};
+
+		let filtered = snapshot.filter(Topics::SPEED | Topics::DISTANCE);
+		assert_eq!(filtered.speed, Some(Throttle::MAX));
+		assert_eq!(filtered.distance, Some(None));
+		assert_eq!(filtered.battery, None);
+		assert_eq!(filtered.sampled_at, snapshot.sampled_at);
+
+		let mut full = [0; Answer::BUFFER_SIZE];
+		let mut small = [0; Answer::BUFFER_SIZE];
+		assert!(
+			Answer::Telemetry(filtered).serialize(&mut small)
+				< Answer::Telemetry(snapshot).serialize(&mut full)
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "serde")]
+	fn unsampled_distances_are_told_apart_from_missing_obstacles() {
+		for (distance, json) in [
+			(
+				None,
+				r#"{"speed":null,"direction":null,"battery":null,"link":null}"#,
+			),
+			(
+				Some(None),
+				r#"{"speed":null,"direction":null,"distance":null,"battery":null,"link":null}"#,
+			),
+			(
+				Some(Some(Millimeters(480))),
+				r#"{"speed":null,"direction":null,"distance":480,"battery":null,"link":null}"#,
+			),
+		] {
+			let snapshot = Telemetry {
+				distance,
+				..Telemetry::default()
+			};
+
+			assert_eq!(
+				serde_json::to_string(&snapshot).expect("serializable"),
+				json
+			);
+			assert_eq!(
+				serde_json::from_str::<Telemetry>(json).expect("valid snapshot"),
+				snapshot
+			);
+		}
+	}
+}
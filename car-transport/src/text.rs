@@ -0,0 +1,1256 @@
+//! Human typeable line encoding of messages and answers
+//!
+//! Besides binary frames, a [`Link`](crate::Link) understands lines of ASCII words, so that the car
+//! can be driven and inspected from a serial terminal such as `screen`, `minicom` or a phone
+//! bluetooth terminal app:
+//! ```text
+//! > ACQUIRE 5000ms
+//! < OK ACQUIRE 4660 5000ms
+//! > SPEED 50
+//! < OK SPEED
+//! > DIST?
+//! < DIST 420mm
+//! ```
+//!
+//! Lines end with `\r`, `\n` or both, words are separated by spaces and keywords are case
+//! insensitive. Queries end with `?` and their answers repeat the keyword, acknowledgements start
+//! with `OK` and refusals with `ERR` followed by the [`ErrorCode`](crate::ErrorCode) id. Missing
+//! values are written `-` and units are optional.
+//!
+//! | Message | Line | Answer |
+//! |---|---|---|
+//! | `Ping` | `PING` | `PONG` |
+//! | `Hello` | `HELLO 4.4` | `HELLO 4.4 0.1.0 15` with the firmware and capabilities |
+//! | `GetSpeed` | `SPEED?` | `SPEED 50%` |
+//! | `GetDirection` | `DIR?` | `DIR -20%` |
+//! | `GetBatteryLevel` | `BATT?` | `BATT 7400mV`, or `BATT 7400mV 1500us` with the sample time |
+//! | `GetUltrasonicDistance` | `DIST?` | `DIST 420mm` or `DIST -`, then the sample time if any |
+//! | `GetParam` | `PARAM? 1` | `PARAM 1 0.250` |
+//! | `ListParams` | `PARAMS? 0` | `PARAMS 4 0 true` with the count, or `PARAMS 4 -` |
+//! | `Subscribe` | `SUB SPEED,DIST 500ms` | `OK SUB 500ms` |
+//! | `Unsubscribe` | `UNSUB` | `OK UNSUB` |
+//! | `GetFault` | `FAULT?` | `FAULT 0 emergency stop` or `FAULT -` |
+//! | `AuthChallenge` | `CHALLENGE <hex>` | `CHALLENGE <hex> <hex>` |
+//! | `Authenticate` | `AUTH <hex>` | `OK AUTH` |
+//! | `SetLogLevel` | `LOG INFO` or `LOG OFF` | `OK LOG`, then `LOG WARN 0 <text>` records |
+//! | `TimeSync` | `TIME 1500us` | `TIME 1500us 2000us 2100us` with the car times |
+//! | `SetSpeed` | `SPEED 50` | `OK SPEED` |
+//! | `SetDirection` | `DIR -20` | `OK DIR` |
+//! | `SetParam` | `PARAM 1 0.250` | `OK PARAM 1 0.250` |
+//! | `EmergencyStop` | `STOP` | `OK STOP` |
+//! | `ClearFault` | `CLEAR` | `OK CLEAR 0 emergency stop` or `OK CLEAR -` |
+//! | `AcquireControl` | `ACQUIRE 5000ms` | `OK ACQUIRE 4660 5000ms` with the token |
+//! | `ReleaseControl` | `RELEASE` | `OK RELEASE` |
+//! | `BeginUpdate` | `UPDATE <size> <hex>` | `OK UPDATE <offset>` |
+//! | `WriteUpdate` | `CHUNK <offset> <hex>` | `OK CHUNK <next>` |
+//! | `VerifyUpdate` | `VERIFY` | `OK VERIFY` |
+//! | `CommitUpdate` | `COMMIT` | `OK COMMIT` |
+//!
+//! Telemetry snapshots are written `TELEMETRY SPEED=50% DIR=-20% DIST=420mm BATT=7400mV LINK=12/0`
+//! with the subscribed topics only, followed by `AT=1500us` when they carry their sample time.
+//! Refusals are written `ERR SPEED 8 <description>`, with `#` and the id instead of the keyword
+//! for messages of other revisions, such as `ERR #120 0 <description>`, and `?` for lines that
+//! were not understood.
+//!
+//! Lines carry neither sequence number nor lease token, the [`TextContext`] of the link numbers
+//! the received lines and puts the token of the last granted lease in the commands. Lines cannot
+//! be sealed, a car that requires an authenticated session refuses the commands typed in a terminal.
+
+use core::{fmt, str::FromStr};
+
+use crate::{
+	Answer, Chunk, Envelope, Field, FirmwareVersion, ImageHash, LeaseToken, LogLevel, LogText,
+	Message, Millimeters, Millivolts, Nonce, Param, ParamId, ParamValue, ProtocolVersion, Sequence,
+	Tag, Telemetry, Timestamp, Topics, Transport, TransportError,
+	field::{Reader, Trailing, Writer},
+	frame::FRAME_DELIMITER,
+	handshake::Capabilities,
+	telemetry::LinkStats,
+};
+
+/// Longest line, end included, enough for a firmware chunk in hex
+pub const MAX_LINE_SIZE: usize = 96;
+
+/// Id given to lines that do not start with a known keyword
+pub const UNKNOWN_ID: u8 = u8::MAX;
+
+/// Keywords of the messages, by id
+const KEYWORDS: [(u8, &str); 26] = [
+	(0, "PING"),
+	(5, "HELLO"),
+	(1, "SPEED?"),
+	(2, "DIR?"),
+	(3, "BATT?"),
+	(4, "DIST?"),
+	(6, "PARAM?"),
+	(7, "PARAMS?"),
+	(8, "SUB"),
+	(9, "UNSUB"),
+	(10, "FAULT?"),
+	(11, "CHALLENGE"),
+	(12, "AUTH"),
+	(13, "LOG"),
+	(14, "TIME"),
+	(100, "SPEED"),
+	(101, "DIR"),
+	(102, "PARAM"),
+	(103, "STOP"),
+	(104, "CLEAR"),
+	(105, "ACQUIRE"),
+	(106, "RELEASE"),
+	(107, "UPDATE"),
+	(108, "CHUNK"),
+	(109, "VERIFY"),
+	(110, "COMMIT"),
+];
+
+/// Names of the telemetry topics, also the keys of the snapshot values
+const TOPICS: [(Topics, &str); 5] = [
+	(Topics::SPEED, "SPEED"),
+	(Topics::DIRECTION, "DIR"),
+	(Topics::DISTANCE, "DIST"),
+	(Topics::BATTERY, "BATT"),
+	(Topics::LINK, "LINK"),
+];
+
+/// How values are encoded on a link
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+pub enum Encoding {
+	/// Byte stuffed frames, see the [`frame`](crate::frame) module
+	#[default]
+	Binary,
+	/// Lines of ASCII words
+	Text,
+}
+
+impl Encoding {
+	/// Returns the encoding of a value starting with the byte, [`None`] for bytes between values
+	///
+	/// Binary frames start with a `COBS` code byte, which is below 64 as frames are shorter than
+	/// that, so letters always start lines. Line ends and spaces are skipped while the session is
+	/// in text, they start a frame otherwise.
+	#[must_use]
+	pub const fn detect(self, byte: u8) -> Option<Self> {
+		match (self, byte) {
+			(_, FRAME_DELIMITER) | (Self::Text, b'\r' | b'\n' | b' ') => None,
+			(_, b'A'..=b'Z' | b'a'..=b'z') => Some(Self::Text),
+			_ => Some(Self::Binary),
+		}
+	}
+}
+
+/// What lines leave implicit, kept by each side of a link
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TextContext {
+	/// Sequence number of the last message
+	pub sequence: Sequence,
+	/// Token of the last granted lease, put in the commands
+	pub lease: Option<LeaseToken>,
+	/// Sequence number and id given to the last received line, [`None`] when it had no keyword
+	pub(crate) header: Option<[u8; 2]>,
+}
+
+impl TextContext {
+	/// Creates a context without lease, before the first message
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			sequence: Sequence(0),
+			lease: None,
+			header: None,
+		}
+	}
+
+	/// Returns the envelope header given to the last received line, like
+	/// [`FrameDecoder::last_frame`](crate::FrameDecoder::last_frame)
+	///
+	/// It is empty when the line had no keyword, [`Envelope::peek`] reads the rest.
+	#[must_use]
+	pub fn last_line(&self) -> &[u8] {
+		self.header.as_ref().map_or(&[], |header| header.as_slice())
+	}
+}
+
+/// A value with a line encoding
+pub trait Text: Sized {
+	/// Writes the value as a line, without its end
+	///
+	/// # Errors
+	/// In case the output is full
+	fn write_text(&self, context: &mut TextContext, output: &mut impl fmt::Write) -> fmt::Result;
+
+	/// Parses a line without its end
+	///
+	/// # Errors
+	/// [`TransportError::InvalidId`] for unknown keywords, [`TransportError::Truncated`] for
+	/// missing values, [`TransportError::TrailingBytes`] for extra ones,
+	/// [`TransportError::OutOfRange`] for values outside of their range and
+	/// [`TransportError::InvalidPayload`] for malformed ones
+	fn parse_text(line: &str, context: &mut TextContext) -> Result<Self, TransportError>;
+}
+
+impl Text for Envelope<Message> {
+	fn write_text(&self, context: &mut TextContext, output: &mut impl fmt::Write) -> fmt::Result {
+		context.sequence = self.sequence;
+		write_message(&self.content, output)
+	}
+
+	fn parse_text(line: &str, context: &mut TextContext) -> Result<Self, TransportError> {
+		let mut words = Words(line);
+		context.header = None;
+		let keyword = words.next()?;
+
+		context.sequence = context.sequence.next();
+		let id = KEYWORDS
+			.iter()
+			.find(|(_, known)| known.eq_ignore_ascii_case(keyword))
+			.map_or(UNKNOWN_ID, |&(id, _)| id);
+		context.header = Some([context.sequence.0, id]);
+
+		let message = parse_message(id, &mut words, context.lease)?;
+		words.finish()?;
+
+		Ok(Self::new(context.sequence, message))
+	}
+}
+
+impl Text for Envelope<Answer> {
+	fn write_text(&self, context: &mut TextContext, output: &mut impl fmt::Write) -> fmt::Result {
+		track_lease(&self.content, context);
+		write_answer(&self.content, output)
+	}
+
+	fn parse_text(line: &str, context: &mut TextContext) -> Result<Self, TransportError> {
+		let mut words = Words(line);
+		let answer = parse_answer(&mut words)?;
+		words.finish()?;
+
+		track_lease(&answer, context);
+		// Answers echo the last message, lines cannot tell which one they answer
+		Ok(Self::new(context.sequence, answer))
+	}
+}
+
+/// Remembers the lease granted or released by the answer
+const fn track_lease(answer: &Answer, context: &mut TextContext) {
+	match answer {
+		Answer::ControlGranted { lease, .. } => context.lease = Some(*lease),
+		Answer::AckReleaseControl => context.lease = None,
+		_ => {}
+	}
+}
+
+/// Encodes the value as a line into `output`, end included, and returns its length
+///
+/// # Errors
+/// [`TransportError::BufferTooSmall`] when the line does not fit in `output`
+pub fn encode_line<T: Text>(
+	value: &T,
+	context: &mut TextContext,
+	output: &mut [u8],
+) -> Result<usize, TransportError> {
+	let mut writer = SliceWriter {
+		buffer: output,
+		length: 0,
+	};
+
+	value
+		.write_text(context, &mut writer)
+		.and_then(|()| fmt::Write::write_str(&mut writer, "\r\n"))
+		.map_err(|fmt::Error| TransportError::BufferTooSmall)?;
+
+	Ok(writer.length)
+}
+
+/// Gathers received bytes into lines
+#[derive(Debug)]
+pub struct LineDecoder {
+	/// Holds the bytes of the current line
+	buffer: [u8; MAX_LINE_SIZE],
+	/// Number of bytes of the current line
+	length: usize,
+	/// Whether the current line exceeded the buffer and should be dropped
+	overflowed: bool,
+}
+
+impl Default for LineDecoder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl LineDecoder {
+	/// Creates a new decoder
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			buffer: [0; MAX_LINE_SIZE],
+			length: 0,
+			overflowed: false,
+		}
+	}
+
+	/// Discards the bytes of the current partial line
+	pub const fn reset(&mut self) {
+		self.length = 0;
+		self.overflowed = false;
+	}
+
+	/// Pushes a single byte into the decoder
+	///
+	/// Returns the line without its end when the byte completes one, empty lines are skipped.
+	/// Backspaces remove the previous byte, like in a terminal, frame delimiters also end lines.
+	pub fn push(&mut self, byte: u8) -> Option<Result<&str, TransportError>> {
+		match byte {
+			b'\r' | b'\n' | FRAME_DELIMITER => {}
+			// `screen` and `minicom` send `DEL`, other terminals `BS`
+			0x08 | 0x7F => {
+				self.length = self.length.saturating_sub(1);
+				return None;
+			}
+			_ if self.length < MAX_LINE_SIZE => {
+				self.buffer[self.length] = byte;
+				self.length += 1;
+				return None;
+			}
+			_ => {
+				self.overflowed = true;
+				return None;
+			}
+		}
+
+		let (length, overflowed) = (self.length, self.overflowed);
+		self.reset();
+
+		if overflowed {
+			return Some(Err(TransportError::FrameTooLong));
+		}
+		if length == 0 {
+			return None;
+		}
+
+		Some(
+			core::str::from_utf8(&self.buffer[..length])
+				.map_err(|_| TransportError::InvalidPayload),
+		)
+	}
+}
+
+/// Writes into a byte buffer, fails when it is full
+struct SliceWriter<'a> {
+	/// Where the text goes
+	buffer: &'a mut [u8],
+	/// Number of bytes written
+	length: usize,
+}
+
+impl fmt::Write for SliceWriter<'_> {
+	fn write_str(&mut self, text: &str) -> fmt::Result {
+		let end = self.length + text.len();
+		self.buffer
+			.get_mut(self.length..end)
+			.ok_or(fmt::Error)?
+			.copy_from_slice(text.as_bytes());
+		self.length = end;
+
+		Ok(())
+	}
+}
+
+/// Returns the keyword of the message with the given id, `?` when there is none
+fn keyword(id: u8) -> &'static str {
+	KEYWORDS
+		.iter()
+		.find(|&&(known, _)| known == id)
+		.map_or("?", |&(_, keyword)| keyword)
+}
+
+/// Writes the message, see the module documentation
+fn write_message(message: &Message, output: &mut impl fmt::Write) -> fmt::Result {
+	output.write_str(keyword(message.id()))?;
+
+	match message {
+		Message::Hello { version } => write!(output, " {version}"),
+		Message::GetParam(id) => write!(output, " {}", id.0),
+		Message::ListParams { index } => write!(output, " {index}"),
+		Message::Subscribe { topics, period_ms } => {
+			output.write_char(' ')?;
+			write_topics(*topics, output)?;
+			write!(output, " {period_ms}ms")
+		}
+		Message::AuthChallenge { nonce } => write_hex(&nonce.0, output),
+		Message::Authenticate { proof } => write_hex(&proof.0, output),
+		Message::SetLogLevel { level: Some(level) } => {
+			output.write_char(' ')?;
+			write_uppercase(level.name(), output)
+		}
+		Message::SetLogLevel { level: None } => output.write_str(" OFF"),
+		Message::TimeSync { origin } => write!(output, " {origin}"),
+		Message::SetSpeed { throttle, .. } => write!(output, " {}", throttle.percent()),
+		Message::SetDirection { steering, .. } => write!(output, " {}", steering.percent()),
+		Message::SetParam { param, .. } => write!(output, " {} {}", param.id.0, param.value),
+		Message::AcquireControl { duration_ms, .. } => write!(output, " {duration_ms}ms"),
+		Message::BeginUpdate { size, hash, .. } => {
+			write!(output, " {size}")?;
+			write_hex(&hash.0, output)
+		}
+		Message::WriteUpdate { offset, chunk, .. } => {
+			write!(output, " {offset}")?;
+			if chunk.0.is_empty() {
+				return output.write_str(" -");
+			}
+			write_hex(&chunk.0, output)
+		}
+		Message::Ping
+		| Message::GetSpeed
+		| Message::GetDirection
+		| Message::GetBatteryLevel
+		| Message::GetUltrasonicDistance
+		| Message::Unsubscribe
+		| Message::GetFault
+		| Message::EmergencyStop
+		| Message::ClearFault { .. }
+		| Message::ReleaseControl { .. }
+		| Message::VerifyUpdate { .. }
+		| Message::CommitUpdate { .. } => Ok(()),
+	}
+}
+
+/// Parses the values of the message with the given id, commands carry `lease`
+fn parse_message(
+	id: u8,
+	words: &mut Words<'_>,
+	lease: Option<LeaseToken>,
+) -> Result<Message, TransportError> {
+	let renew = lease;
+	let lease = lease.unwrap_or_default();
+
+	Ok(match id {
+		0 => Message::Ping,
+		5 => Message::Hello {
+			version: words.protocol_version()?,
+		},
+		1 => Message::GetSpeed,
+		2 => Message::GetDirection,
+		3 => Message::GetBatteryLevel,
+		4 => Message::GetUltrasonicDistance,
+		6 => Message::GetParam(ParamId(words.value("")?)),
+		7 => Message::ListParams {
+			index: words.value("")?,
+		},
+		8 => Message::Subscribe {
+			topics: words.topics()?,
+			period_ms: words.value("ms")?,
+		},
+		9 => Message::Unsubscribe,
+		10 => Message::GetFault,
+		11 => Message::AuthChallenge {
+			nonce: Nonce(words.hex()?),
+		},
+		12 => Message::Authenticate {
+			proof: Tag(words.hex()?),
+		},
+		13 => Message::SetLogLevel {
+			level: if words.skip("OFF") {
+				None
+			} else {
+				Some(words.log_level()?)
+			},
+		},
+		14 => Message::TimeSync {
+			origin: words.timestamp()?,
+		},
+		100 => Message::SetSpeed {
+			lease,
+			throttle: words.percentage()?,
+		},
+		101 => Message::SetDirection {
+			lease,
+			steering: words.percentage()?,
+		},
+		102 => Message::SetParam {
+			lease,
+			param: words.param()?,
+		},
+		103 => Message::EmergencyStop,
+		104 => Message::ClearFault { lease },
+		105 => Message::AcquireControl {
+			renew,
+			duration_ms: words.value("ms")?,
+		},
+		106 => Message::ReleaseControl { lease },
+		107 => Message::BeginUpdate {
+			lease,
+			size: words.value("")?,
+			hash: ImageHash(words.hex()?),
+		},
+		108 => Message::WriteUpdate {
+			lease,
+			offset: words.value("")?,
+			chunk: words.chunk()?,
+		},
+		109 => Message::VerifyUpdate { lease },
+		110 => Message::CommitUpdate { lease },
+		_ => return Err(TransportError::InvalidId),
+	})
+}
+
+/// Writes the answer, see the module documentation
+fn write_answer(answer: &Answer, output: &mut impl fmt::Write) -> fmt::Result {
+	match answer {
+		Answer::Pong => output.write_str("PONG"),
+		Answer::Hello {
+			version,
+			firmware,
+			capabilities,
+		} => write!(output, "HELLO {version} {firmware} {}", capabilities.0),
+		Answer::Speed(throttle) => write!(output, "SPEED {throttle}"),
+		Answer::Direction(steering) => write!(output, "DIR {steering}"),
+		Answer::BatteryLevel { level, sampled_at } => {
+			write!(output, "BATT {level}")?;
+			write_sampled_at(*sampled_at, output)
+		}
+		Answer::UltrasonicDistance {
+			distance,
+			sampled_at,
+		} => {
+			match distance {
+				Some(distance) => write!(output, "DIST {distance}")?,
+				None => output.write_str("DIST -")?,
+			}
+			write_sampled_at(*sampled_at, output)
+		}
+		Answer::Param(param) => write!(output, "PARAM {} {}", param.id.0, param.value),
+		Answer::ParamEntry { count, param } => {
+			write!(output, "PARAMS {count}")?;
+			match param {
+				Some(param) => write!(output, " {} {}", param.id.0, param.value),
+				None => output.write_str(" -"),
+			}
+		}
+		Answer::Fault(fault) => {
+			output.write_str("FAULT")?;
+			write_code(fault.as_ref(), output)
+		}
+		Answer::AuthChallenge { nonce, proof } => {
+			output.write_str("CHALLENGE")?;
+			write_hex(&nonce.0, output)?;
+			write_hex(&proof.0, output)
+		}
+		Answer::TimeSync {
+			origin,
+			received,
+			transmitted,
+		} => write!(output, "TIME {origin} {received} {transmitted}"),
+		Answer::Telemetry(telemetry) => write_telemetry(telemetry, output),
+		Answer::Log {
+			level,
+			dropped,
+			text,
+		} => {
+			output.write_str("LOG ")?;
+			write_uppercase(level.name(), output)?;
+			write!(output, " {dropped} {}", text.as_str())
+		}
+		Answer::Nack { for_id, reason } => {
+			match keyword(*for_id) {
+				"?" if *for_id != UNKNOWN_ID => write!(output, "ERR #{for_id}")?,
+				keyword => write!(output, "ERR {keyword}")?,
+			}
+			write_code(Some(reason), output)
+		}
+		acknowledgement => {
+			write!(output, "OK {}", keyword(acknowledgement.id()))?;
+
+			match acknowledgement {
+				Answer::Subscribed { period_ms } => write!(output, " {period_ms}ms"),
+				Answer::AckParam(param) => write!(output, " {} {}", param.id.0, param.value),
+				Answer::AckClearFault { cleared } => write_code(cleared.as_ref(), output),
+				Answer::ControlGranted { lease, duration_ms } => {
+					write!(output, " {} {duration_ms}ms", lease.0)
+				}
+				Answer::UpdateReady { offset } => write!(output, " {offset}"),
+				Answer::AckWriteUpdate { next } => write!(output, " {next}"),
+				_ => Ok(()),
+			}
+		}
+	}
+}
+
+/// Parses an answer, see the module documentation
+fn parse_answer(words: &mut Words<'_>) -> Result<Answer, TransportError> {
+	let keyword = words.keyword()?;
+
+	Ok(match keyword.as_str() {
+		"PONG" => Answer::Pong,
+		"HELLO" => Answer::Hello {
+			version: words.protocol_version()?,
+			firmware: words.firmware_version()?,
+			capabilities: Capabilities(words.value("")?),
+		},
+		"SPEED" => Answer::Speed(words.percentage()?),
+		"DIR" => Answer::Direction(words.percentage()?),
+		"BATT" => Answer::BatteryLevel {
+			level: Millivolts(words.value("mV")?),
+			sampled_at: words.sampled_at()?,
+		},
+		"DIST" => Answer::UltrasonicDistance {
+			distance: if words.skip("-") {
+				None
+			} else {
+				Some(Millimeters(words.value("mm")?))
+			},
+			sampled_at: words.sampled_at()?,
+		},
+		"PARAM" => Answer::Param(words.param()?),
+		"PARAMS" => Answer::ParamEntry {
+			count: words.value("")?,
+			param: if words.skip("-") {
+				None
+			} else {
+				Some(words.param()?)
+			},
+		},
+		"FAULT" => Answer::Fault(words.code()?),
+		"CHALLENGE" => Answer::AuthChallenge {
+			nonce: Nonce(words.hex()?),
+			proof: Tag(words.hex()?),
+		},
+		"TIME" => Answer::TimeSync {
+			origin: words.timestamp()?,
+			received: words.timestamp()?,
+			transmitted: words.timestamp()?,
+		},
+		"TELEMETRY" => Answer::Telemetry(words.telemetry()?),
+		"LOG" => Answer::Log {
+			level: words.log_level()?,
+			dropped: words.value("")?,
+			text: LogText::truncated(words.take_rest()),
+		},
+		"ERR" => {
+			let keyword = words.next()?;
+			Answer::Nack {
+				for_id: match keyword.strip_prefix('#') {
+					Some(id) => parse_value(id, "")?,
+					None => KEYWORDS
+						.iter()
+						.find(|(_, known)| known.eq_ignore_ascii_case(keyword))
+						.map_or(UNKNOWN_ID, |&(id, _)| id),
+				},
+				reason: words.code()?.ok_or(TransportError::InvalidPayload)?,
+			}
+		}
+		"OK" => parse_acknowledgement(words)?,
+		_ => return Err(TransportError::InvalidId),
+	})
+}
+
+/// Parses the acknowledgement after `OK`
+fn parse_acknowledgement(words: &mut Words<'_>) -> Result<Answer, TransportError> {
+	let keyword = words.keyword()?;
+
+	Ok(match keyword.as_str() {
+		"SUB" => Answer::Subscribed {
+			period_ms: words.value("ms")?,
+		},
+		"UNSUB" => Answer::Unsubscribed,
+		"AUTH" => Answer::Authenticated,
+		"LOG" => Answer::AckLogLevel,
+		"SPEED" => Answer::AckSpeed,
+		"DIR" => Answer::AckDirection,
+		"PARAM" => Answer::AckParam(words.param()?),
+		"STOP" => Answer::AckEmergencyStop,
+		"CLEAR" => Answer::AckClearFault {
+			cleared: words.code()?,
+		},
+		"ACQUIRE" => Answer::ControlGranted {
+			lease: LeaseToken(words.value("")?),
+			duration_ms: words.value("ms")?,
+		},
+		"RELEASE" => Answer::AckReleaseControl,
+		"UPDATE" => Answer::UpdateReady {
+			offset: words.value("")?,
+		},
+		"CHUNK" => Answer::AckWriteUpdate {
+			next: words.value("")?,
+		},
+		"VERIFY" => Answer::UpdateVerified,
+		"COMMIT" => Answer::AckCommitUpdate,
+		_ => return Err(TransportError::InvalidId),
+	})
+}
+
+/// Writes the snapshot with the subscribed values only
+fn write_telemetry(telemetry: &Telemetry, output: &mut impl fmt::Write) -> fmt::Result {
+	output.write_str("TELEMETRY")?;
+
+	if let Some(speed) = telemetry.speed {
+		write!(output, " SPEED={speed}")?;
+	}
+	if let Some(direction) = telemetry.direction {
+		write!(output, " DIR={direction}")?;
+	}
+	match telemetry.distance {
+		Some(Some(distance)) => write!(output, " DIST={distance}")?,
+		Some(None) => output.write_str(" DIST=-")?,
+		None => {}
+	}
+	if let Some(battery) = telemetry.battery {
+		write!(output, " BATT={battery}")?;
+	}
+	if let Some(link) = telemetry.link {
+		write!(output, " LINK={}/{}", link.received, link.dropped)?;
+	}
+	if let Some(sampled_at) = telemetry.sampled_at.0 {
+		write!(output, " AT={sampled_at}")?;
+	}
+
+	Ok(())
+}
+
+/// Writes the time at which a value was sampled after a space, nothing without one
+fn write_sampled_at(sampled_at: Trailing<Timestamp>, output: &mut impl fmt::Write) -> fmt::Result {
+	sampled_at
+		.0
+		.map_or(Ok(()), |sampled_at| write!(output, " {sampled_at}"))
+}
+
+/// Writes the set of topics, `-` when it is empty
+fn write_topics(topics: Topics, output: &mut impl fmt::Write) -> fmt::Result {
+	if topics == Topics::NONE {
+		return output.write_char('-');
+	}
+
+	let mut separator = "";
+	for (topic, name) in TOPICS {
+		if topics.contains(topic) {
+			write!(output, "{separator}{name}")?;
+			separator = ",";
+		}
+	}
+
+	Ok(())
+}
+
+/// Writes the id and description of a field enum, such as an [`ErrorCode`], `-` for [`None`]
+fn write_code<T: Field + fmt::Display>(
+	value: Option<&T>,
+	output: &mut impl fmt::Write,
+) -> fmt::Result {
+	let Some(value) = value else {
+		return output.write_str(" -");
+	};
+
+	let mut id = [0_u8; 1];
+	value.encode(&mut Writer::new(&mut id));
+
+	write!(output, " {} {value}", id[0])
+}
+
+/// Writes the bytes in lowercase hex after a space
+fn write_hex(bytes: &[u8], output: &mut impl fmt::Write) -> fmt::Result {
+	output.write_char(' ')?;
+	bytes
+		.iter()
+		.try_for_each(|byte| write!(output, "{byte:02x}"))
+}
+
+/// Writes the name in uppercase, like the other keywords
+fn write_uppercase(name: &str, output: &mut impl fmt::Write) -> fmt::Result {
+	name.chars()
+		.try_for_each(|char| output.write_char(char.to_ascii_uppercase()))
+}
+
+/// The words of a line, read one after the other
+#[derive(Debug, Clone, Copy)]
+struct Words<'a>(&'a str);
+
+impl<'a> Words<'a> {
+	/// Returns the next word
+	fn next(&mut self) -> Result<&'a str, TransportError> {
+		let rest = self.0.trim_ascii_start();
+		if rest.is_empty() {
+			return Err(TransportError::Truncated);
+		}
+
+		let end = rest
+			.find(|char: char| char.is_ascii_whitespace())
+			.unwrap_or(rest.len());
+		self.0 = &rest[end..];
+
+		Ok(&rest[..end])
+	}
+
+	/// Returns the rest of the line
+	const fn rest(self) -> &'a str {
+		self.0.trim_ascii()
+	}
+
+	/// Returns the rest of the line and consumes it
+	const fn take_rest(&mut self) -> &'a str {
+		let rest = self.rest();
+		self.0 = "";
+		rest
+	}
+
+	/// Skips the next word if it is `word`, ignoring case
+	fn skip(&mut self, word: &str) -> bool {
+		let mut next = *self;
+		let skipped = next
+			.next()
+			.is_ok_and(|next| next.eq_ignore_ascii_case(word));
+		if skipped {
+			*self = next;
+		}
+
+		skipped
+	}
+
+	/// Fails when words are left
+	const fn finish(self) -> Result<(), TransportError> {
+		if self.rest().is_empty() {
+			Ok(())
+		} else {
+			Err(TransportError::TrailingBytes)
+		}
+	}
+
+	/// Returns the next word in uppercase
+	fn keyword(&mut self) -> Result<heapless::String<16>, TransportError> {
+		let word = self.next()?;
+
+		let mut keyword = heapless::String::new();
+		for char in word.chars() {
+			keyword
+				.push(char.to_ascii_uppercase())
+				.map_err(|_| TransportError::InvalidId)?;
+		}
+
+		Ok(keyword)
+	}
+
+	/// Parses the next word as a number, optionally followed by its unit
+	fn value<T: FromStr>(&mut self, unit: &str) -> Result<T, TransportError> {
+		parse_value(self.next()?, unit)
+	}
+
+	/// Parses the next word as a percentage between -100 and 100
+	fn percentage<T: TryFrom<i8>>(&mut self) -> Result<T, TransportError> {
+		let percent = self.value::<i16>("%")?;
+
+		i8::try_from(percent)
+			.ok()
+			.and_then(|percent| T::try_from(percent).ok())
+			.ok_or(TransportError::OutOfRange)
+	}
+
+	/// Parses the next word as `major.minor`
+	fn protocol_version(&mut self) -> Result<ProtocolVersion, TransportError> {
+		let (major, minor) = self
+			.next()?
+			.split_once('.')
+			.ok_or(TransportError::InvalidPayload)?;
+
+		Ok(ProtocolVersion {
+			major: parse_value(major, "")?,
+			minor: parse_value(minor, "")?,
+		})
+	}
+
+	/// Parses the next word as `major.minor.patch`
+	fn firmware_version(&mut self) -> Result<FirmwareVersion, TransportError> {
+		let mut numbers = self.next()?.split('.');
+		let mut number = || parse_value(numbers.next().unwrap_or_default(), "");
+
+		let version = FirmwareVersion {
+			major: number()?,
+			minor: number()?,
+			patch: number()?,
+		};
+		if numbers.next().is_some() {
+			return Err(TransportError::InvalidPayload);
+		}
+
+		Ok(version)
+	}
+
+	/// Parses the next word as a log level
+	fn log_level(&mut self) -> Result<LogLevel, TransportError> {
+		self.next()?
+			.parse()
+			.map_err(|_| TransportError::InvalidPayload)
+	}
+
+	/// Parses the next two words as a tunable and its value
+	fn param(&mut self) -> Result<Param, TransportError> {
+		Ok(Param {
+			id: ParamId(self.value("")?),
+			value: parse_param_value(self.next()?)?,
+		})
+	}
+
+	/// Parses the next word as topic names separated by commas, `ALL` or `-`
+	fn topics(&mut self) -> Result<Topics, TransportError> {
+		let word = self.next()?;
+		if word == "-" {
+			return Ok(Topics::NONE);
+		}
+		if word.eq_ignore_ascii_case("ALL") {
+			return Ok(Topics::ALL);
+		}
+
+		word.split(',').try_fold(Topics::NONE, |topics, name| {
+			TOPICS
+				.iter()
+				.find(|(_, known)| known.eq_ignore_ascii_case(name))
+				.map(|&(topic, _)| topics | topic)
+				.ok_or(TransportError::InvalidPayload)
+		})
+	}
+
+	/// Parses the next word as microseconds
+	fn timestamp(&mut self) -> Result<Timestamp, TransportError> {
+		self.value("us").map(Timestamp)
+	}
+
+	/// Parses the time at which a value was sampled when words are left
+	fn sampled_at(&mut self) -> Result<Trailing<Timestamp>, TransportError> {
+		if self.rest().is_empty() {
+			return Ok(Trailing::NONE);
+		}
+
+		self.timestamp().map(Some).map(Trailing)
+	}
+
+	/// Parses the id of a field enum, skipping its description, `-` for [`None`]
+	fn code<T: Field>(&mut self) -> Result<Option<T>, TransportError> {
+		if self.skip("-") {
+			return Ok(None);
+		}
+
+		let id = self.value::<u8>("")?;
+		// The description is only meant for humans
+		self.take_rest();
+
+		T::decode(&mut Reader::new(&[id])).map(Some)
+	}
+
+	/// Parses the next word as hex bytes
+	fn hex<const N: usize>(&mut self) -> Result<[u8; N], TransportError> {
+		let mut bytes = [0; N];
+		parse_hex(self.next()?, &mut bytes)?;
+
+		Ok(bytes)
+	}
+
+	/// Parses the next word as a firmware chunk in hex, `-` when it is empty
+	fn chunk(&mut self) -> Result<Chunk, TransportError> {
+		let mut chunk = Chunk::default();
+		if self.skip("-") {
+			return Ok(chunk);
+		}
+
+		let hex = self.next()?;
+		chunk
+			.0
+			.resize_default(hex.len() / 2)
+			.map_err(|_| TransportError::OutOfRange)?;
+		parse_hex(hex, &mut chunk.0)?;
+
+		Ok(chunk)
+	}
+
+	/// Parses the rest of the line as `KEY=value` pairs of a snapshot
+	fn telemetry(&mut self) -> Result<Telemetry, TransportError> {
+		let mut telemetry = Telemetry::default();
+
+		while !self.rest().is_empty() {
+			let (key, value) = self
+				.next()?
+				.split_once('=')
+				.ok_or(TransportError::InvalidPayload)?;
+			let mut value = Words(value);
+
+			// The sample time is not a topic, it is sent with any of them
+			if key.eq_ignore_ascii_case("AT") {
+				telemetry.sampled_at = Trailing(Some(value.timestamp()?));
+				value.finish()?;
+				continue;
+			}
+
+			let topic = TOPICS
+				.iter()
+				.find(|(_, known)| known.eq_ignore_ascii_case(key))
+				.map(|&(topic, _)| topic)
+				.ok_or(TransportError::InvalidPayload)?;
+			match topic {
+				Topics::SPEED => telemetry.speed = Some(value.percentage()?),
+				Topics::DIRECTION => telemetry.direction = Some(value.percentage()?),
+				Topics::DISTANCE if value.skip("-") => telemetry.distance = Some(None),
+				Topics::DISTANCE => {
+					telemetry.distance = Some(Some(Millimeters(value.value("mm")?)));
+				}
+				Topics::BATTERY => telemetry.battery = Some(Millivolts(value.value("mV")?)),
+				_ => {
+					let (received, dropped) = value
+						.next()?
+						.split_once('/')
+						.ok_or(TransportError::InvalidPayload)?;
+					telemetry.link = Some(LinkStats {
+						received: parse_value(received, "")?,
+						dropped: parse_value(dropped, "")?,
+					});
+				}
+			}
+			value.finish()?;
+		}
+
+		Ok(telemetry)
+	}
+}
+
+/// Parses a number, optionally followed by its unit
+fn parse_value<T: FromStr>(word: &str, unit: &str) -> Result<T, TransportError> {
+	let number = word
+		.len()
+		.checked_sub(unit.len())
+		.filter(|&end| word.is_char_boundary(end) && word[end..].eq_ignore_ascii_case(unit))
+		.map_or(word, |end| &word[..end]);
+
+	number.parse().map_err(|_| TransportError::InvalidPayload)
+}
+
+/// Parses the value of a tunable: `true` or `false`, a whole number, or a number with up to three
+/// decimals for gains
+fn parse_param_value(word: &str) -> Result<ParamValue, TransportError> {
+	if word.eq_ignore_ascii_case("true") || word.eq_ignore_ascii_case("false") {
+		return Ok(ParamValue::Bool(word.eq_ignore_ascii_case("true")));
+	}
+
+	let Some((whole, decimals)) = word.split_once('.') else {
+		return parse_value(word, "").map(ParamValue::Integer);
+	};
+	if decimals.is_empty() || decimals.len() > 3 || !decimals.bytes().all(|b| b.is_ascii_digit()) {
+		return Err(TransportError::InvalidPayload);
+	}
+
+	let negative = whole.starts_with('-');
+	let whole = parse_value::<i64>(whole, "")?.unsigned_abs();
+	let mut thousandths = parse_value::<u64>(decimals, "")?;
+	for _ in decimals.len()..3 {
+		thousandths *= 10;
+	}
+
+	let value =
+		i64::try_from(whole * 1000 + thousandths).map_err(|_| TransportError::OutOfRange)?;
+	i32::try_from(if negative { -value } else { value })
+		.map(ParamValue::Milli)
+		.map_err(|_| TransportError::OutOfRange)
+}
+
+/// Parses hex digits into the bytes, there must be exactly two per byte
+fn parse_hex(hex: &str, bytes: &mut [u8]) -> Result<(), TransportError> {
+	if hex.len() != bytes.len() * 2 {
+		return Err(TransportError::InvalidPayload);
+	}
+
+	for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+		let digits = core::str::from_utf8(digits).map_err(|_| TransportError::InvalidPayload)?;
+		*byte = u8::from_str_radix(digits, 16).map_err(|_| TransportError::InvalidPayload)?;
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{ErrorCode, Steering, Throttle, dfu::CHUNK_SIZE, schema::Schema};
+
+	/// Writes the value into a fresh line
+	fn line<T: Text>(value: &T, context: &mut TextContext) -> heapless::String<MAX_LINE_SIZE> {
+		let mut line = heapless::String::new();
+		value.write_text(context, &mut line).expect("line fits");
+		line
+	}
+
+	#[test]
+	fn typed_lines_are_parsed() -> Result<(), TransportError> {
+		let mut context = TextContext::new();
+
+		let envelope = Envelope::<Message>::parse_text("dist?", &mut context)?;
+		assert_eq!(
+			envelope,
+			Envelope::new(Sequence(1), Message::GetUltrasonicDistance)
+		);
+
+		// Commands carry the lease granted to the terminal
+		let granted = Envelope::new(
+			envelope.sequence,
+			Answer::ControlGranted {
+				lease: LeaseToken(4660),
+				duration_ms: 5000,
+			},
+		);
+		assert_eq!(line(&granted, &mut context), "OK ACQUIRE 4660 5000ms");
+		assert_eq!(
+			Envelope::<Message>::parse_text("  Speed   50%", &mut context)?.content,
+			Message::SetSpeed {
+				lease: LeaseToken(4660),
+				throttle: Throttle::saturating(50),
+			}
+		);
+		assert_eq!(
+			Envelope::<Message>::parse_text("PARAM 3 -1.5", &mut context)?.content,
+			Message::SetParam {
+				lease: LeaseToken(4660),
+				param: Param {
+					id: ParamId(3),
+					value: ParamValue::Milli(-1500),
+				},
+			}
+		);
+		assert_eq!(
+			Envelope::<Message>::parse_text("SUB speed,dist 500", &mut context)?.content,
+			Message::Subscribe {
+				topics: Topics::SPEED | Topics::DISTANCE,
+				period_ms: 500,
+			}
+		);
+
+		assert_eq!(
+			Envelope::<Message>::parse_text("SPEED 101", &mut context),
+			Err(TransportError::OutOfRange)
+		);
+		assert_eq!(
+			Envelope::<Message>::parse_text("SPEED", &mut context),
+			Err(TransportError::Truncated)
+		);
+		assert_eq!(
+			Envelope::<Message>::parse_text("STOP NOW", &mut context),
+			Err(TransportError::TrailingBytes)
+		);
+		assert_eq!(
+			Envelope::<Message>::parse_text("JUMP", &mut context),
+			Err(TransportError::InvalidId)
+		);
+		assert_eq!(
+			Envelope::<Message>::peek(context.last_line()),
+			Some((Sequence(8), UNKNOWN_ID))
+		);
+
+		let refused = Envelope::new(
+			Sequence(8),
+			Answer::Nack {
+				for_id: UNKNOWN_ID,
+				reason: ErrorCode::UnknownMessage,
+			},
+		);
+		assert_eq!(line(&refused, &mut context), "ERR ? 0 unknown message");
+		assert_eq!(
+			Envelope::<Answer>::parse_text("ERR ? 0", &mut context)?.content,
+			refused.content
+		);
+
+		let newer = Answer::Nack {
+			for_id: 120,
+			reason: ErrorCode::UnknownMessage,
+		};
+		let refused = line(&Envelope::new(Sequence(9), newer.clone()), &mut context);
+		assert_eq!(refused, "ERR #120 0 unknown message");
+		assert_eq!(
+			Envelope::<Answer>::parse_text(&refused, &mut context)?.content,
+			newer
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn every_variant_survives_a_round_trip() -> Result<(), TransportError> {
+		let lease = LeaseToken(0x1234);
+		let param = Param {
+			id: ParamId(7),
+			value: ParamValue::Milli(-250),
+		};
+		let chunk = Chunk(heapless::Vec::from_slice(&[0xAB; CHUNK_SIZE]).expect("chunk is full"));
+
+		#[rustfmt::skip]
+		let messages = {
+			use Message::*;
+			[Ping, Hello { version: ProtocolVersion { major: 4, minor: 3 } }, GetSpeed, GetDirection, GetBatteryLevel, GetUltrasonicDistance, GetParam(ParamId(2)), ListParams { index: 1 }, Subscribe { topics: Topics::ALL, period_ms: 100 }, Subscribe { topics: Topics::NONE, period_ms: 0 }, Unsubscribe, GetFault, AuthChallenge { nonce: Nonce([1; 8]) }, Authenticate { proof: Tag([2; 8]) }, SetLogLevel { level: Some(LogLevel::Warn) }, SetLogLevel { level: None }, TimeSync { origin: Timestamp(1_500) }, SetSpeed { lease, throttle: Throttle::MIN }, SetDirection { lease, steering: Steering::MAX }, SetParam { lease, param }, EmergencyStop, ClearFault { lease }, AcquireControl { renew: Some(lease), duration_ms: 2000 }, ReleaseControl { lease }, BeginUpdate { lease, size: 65536, hash: ImageHash([3; 32]) }, WriteUpdate { lease, offset: 1024, chunk }, WriteUpdate { lease, offset: 0, chunk: Chunk::default() }, VerifyUpdate { lease }, CommitUpdate { lease }]
+		};
+
+		let mut context = TextContext::new();
+		context.lease = Some(lease);
+		for message in messages {
+			let envelope = Envelope::new(context.sequence.next(), message);
+			let line = line(&envelope, &mut TextContext::new());
+			assert_eq!(
+				Envelope::parse_text(&line, &mut context)?,
+				envelope,
+				"{line}"
+			);
+		}
+
+		let telemetry = Telemetry {
+			speed: Some(Throttle::saturating(-20)),
+			direction: None,
+			distance: Some(None),
+			battery: Some(Millivolts(7400)),
+			link: Some(LinkStats {
+				received: 12,
+				dropped: 3,
+			}),
+			sampled_at: Trailing(Some(Timestamp(98_765_432))),
+		};
+		let at = Trailing(Some(Timestamp(123_456)));
+
+		#[rustfmt::skip]
+		let answers = {
+			use Answer::*;
+			[Pong, Hello { version: ProtocolVersion { major: 4, minor: 3 }, firmware: FirmwareVersion::parse("0.12.3"), capabilities: Capabilities(15) }, Speed(Throttle::MAX), Direction(Steering::MIN), BatteryLevel { level: Millivolts(7400), sampled_at: Trailing::NONE }, BatteryLevel { level: Millivolts(7400), sampled_at: at }, UltrasonicDistance { distance: Some(Millimeters(420)), sampled_at: Trailing::NONE }, UltrasonicDistance { distance: None, sampled_at: Trailing::NONE }, UltrasonicDistance { distance: None, sampled_at: at }, Param(crate::Param { id: ParamId(0), value: ParamValue::Bool(true) }), ParamEntry { count: 4, param: Some(param) }, ParamEntry { count: 4, param: None }, Subscribed { period_ms: 500 }, Unsubscribed, Fault(Some(crate::Fault::EmergencyStop)), Fault(None), AuthChallenge { nonce: Nonce([4; 8]), proof: Tag([5; 8]) }, Authenticated, AckLogLevel, TimeSync { origin: Timestamp(1_500), received: Timestamp(2_000), transmitted: Timestamp(2_100) }, AckSpeed, AckDirection, AckParam(crate::Param { id: ParamId(1), value: ParamValue::Integer(-12) }), AckEmergencyStop, AckClearFault { cleared: Some(crate::Fault::EmergencyStop) }, AckClearFault { cleared: None }, ControlGranted { lease, duration_ms: 5000 }, AckReleaseControl, UpdateReady { offset: 1024 }, AckWriteUpdate { next: 1056 }, UpdateVerified, AckCommitUpdate, Telemetry(telemetry), Telemetry(crate::Telemetry::default()), Log { level: LogLevel::Error, dropped: 2, text: LogText::truncated("motor stalled, stopping") }, Nack { for_id: 100, reason: ErrorCode::NotLeaseHolder }]
+		};
+
+		for answer in answers {
+			let envelope = Envelope::new(context.sequence, answer);
+			let line = line(&envelope, &mut TextContext::new());
+			assert!(line.len() + 2 <= MAX_LINE_SIZE, "{line}");
+			assert_eq!(
+				Envelope::parse_text(&line, &mut context)?,
+				envelope,
+				"{line}"
+			);
+		}
+
+		// Every message has a keyword
+		for variant in Message::VARIANTS {
+			assert_ne!(keyword(variant.id), "?", "{}", variant.name);
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn lines_are_gathered_from_terminal_bytes() {
+		let mut decoder = LineDecoder::new();
+		let mut lines = b"\r\nSPEEF\x7fD?\rPING\r\n"
+			.iter()
+			.filter_map(|&byte| decoder.push(byte).map(|line| line.map(str::len)));
+
+		assert_eq!(lines.next(), Some(Ok("SPEED?".len())));
+		assert_eq!(lines.next(), Some(Ok("PING".len())));
+		assert_eq!(lines.next(), None);
+
+		let mut output = [0; 8];
+		let ping = Envelope::new(Sequence(0), Answer::Pong);
+		let length = encode_line(&ping, &mut TextContext::new(), &mut output);
+		assert_eq!(length.map(|length| &output[..length]), Ok(&b"PONG\r\n"[..]));
+
+		assert_eq!(Encoding::Binary.detect(b'P'), Some(Encoding::Text));
+		assert_eq!(Encoding::Text.detect(0x05), Some(Encoding::Binary));
+		assert_eq!(Encoding::Text.detect(b'\n'), None);
+		assert_eq!(Encoding::Binary.detect(b'\n'), Some(Encoding::Binary));
+	}
+}
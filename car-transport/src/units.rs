@@ -0,0 +1,219 @@
+//! Physical quantities carried in payloads
+//!
+//! Ratios like [`Throttle`] and [`Steering`] are validated on construction and when decoded,
+//! measures like [`Millimeters`] and [`Millivolts`] cover their whole integer range.
+
+use core::fmt;
+
+use crate::{
+	Field, TransportError,
+	field::{Reader, Writer},
+	schema::{Member, Shape},
+};
+
+/// Defines a percentage between -100 and 100 that is checked when decoded
+macro_rules! signed_percentage {
+	($(#[doc = $doc:literal])* $name:ident) => {
+		$(#[doc = $doc])*
+		#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+		#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+		#[cfg_attr(
+			feature = "serde",
+			derive(serde::Serialize, serde::Deserialize),
+			serde(try_from = "i8", into = "i8")
+		)]
+		pub struct $name(i8);
+
+		impl $name {
+			/// The neutral position
+			pub const ZERO: Self = Self(0);
+			/// The lowest value, `-100%`
+			pub const MIN: Self = Self(-100);
+			/// The highest value, `100%`
+			pub const MAX: Self = Self(100);
+
+			/// Creates a value from a percentage, [`None`] when outside of `-100..=100`
+			#[must_use]
+			pub const fn new(percent: i8) -> Option<Self> {
+				if percent >= Self::MIN.0 && percent <= Self::MAX.0 {
+					Some(Self(percent))
+				} else {
+					None
+				}
+			}
+
+			/// Creates a value from a percentage, clamped to `-100..=100`
+			#[must_use]
+			pub const fn saturating(percent: i8) -> Self {
+				if percent < Self::MIN.0 {
+					Self::MIN
+				} else if percent > Self::MAX.0 {
+					Self::MAX
+				} else {
+					Self(percent)
+				}
+			}
+
+			/// Creates a value from a ratio, clamped to `-1.0..=1.0` and rounded to the nearest percent
+			#[must_use]
+			pub fn from_ratio(ratio: f32) -> Self {
+				let scaled = ratio.clamp(-1.0, 1.0) * 100.0;
+				let rounded = if scaled < 0.0 { scaled - 0.5 } else { scaled + 0.5 };
+
+				// The value is within `-100.5..=100.5` and truncated towards zero, or `NaN` which becomes 0
+				#[allow(clippy::cast_possible_truncation)]
+				Self(rounded as i8)
+			}
+
+			/// Returns the value as a percentage within `-100..=100`
+			#[must_use]
+			pub const fn percent(self) -> i8 {
+				self.0
+			}
+
+			/// Returns the value as a ratio within `-1.0..=1.0`
+			#[must_use]
+			pub fn ratio(self) -> f32 {
+				f32::from(self.0) / 100.0
+			}
+		}
+
+		impl TryFrom<i8> for $name {
+			type Error = TransportError;
+
+			fn try_from(percent: i8) -> Result<Self, Self::Error> {
+				Self::new(percent).ok_or(TransportError::OutOfRange)
+			}
+		}
+
+		impl From<$name> for i8 {
+			fn from(value: $name) -> Self {
+				value.0
+			}
+		}
+
+		impl fmt::Display for $name {
+			fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+				write!(f, "{}%", self.0)
+			}
+		}
+
+		impl Field for $name {
+			const MAX_SIZE: usize = i8::MAX_SIZE;
+			const SHAPE: Shape = Shape::Struct {
+				name: stringify!($name),
+				doc: concat!($($doc),*).trim_ascii(),
+				unit: Some("%"),
+				members: &[Member {
+					name: "0",
+					doc: "Percentage between -100 and 100",
+					unit: None,
+					shape: &i8::SHAPE,
+				}],
+			};
+
+			fn encode(&self, writer: &mut Writer<'_>) {
+				self.0.encode(writer);
+			}
+
+			fn decode(reader: &mut Reader<'_>) -> Result<Self, TransportError> {
+				Self::try_from(i8::decode(reader)?)
+			}
+		}
+
+		#[cfg(feature = "arbitrary")]
+		impl<'a> arbitrary::Arbitrary<'a> for $name {
+			fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+				Ok(Self(u.int_in_range(Self::MIN.0..=Self::MAX.0)?))
+			}
+		}
+	};
+}
+
+signed_percentage! {
+	/// Power sent to the motors, negative to drive backward
+	Throttle
+}
+
+signed_percentage! {
+	/// Position of the front wheels, negative to turn left
+	Steering
+}
+
+/// A distance in millimeters (`mm`)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+#[transport(unit = "mm")]
+pub struct Millimeters(pub u16);
+
+impl Millimeters {
+	/// Returns the distance in meters
+	#[must_use]
+	pub fn meters(self) -> f32 {
+		f32::from(self.0) / 1000.0
+	}
+}
+
+impl fmt::Display for Millimeters {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}mm", self.0)
+	}
+}
+
+/// An electric potential in millivolts (`mV`)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Field)]
+#[cfg_attr(feature = "defmt", derive(defmt_macros::Format))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(transparent)
+)]
+#[transport(unit = "mV")]
+pub struct Millivolts(pub u16);
+
+impl Millivolts {
+	/// Returns the potential in volts
+	#[must_use]
+	pub fn volts(self) -> f32 {
+		f32::from(self.0) / 1000.0
+	}
+}
+
+impl fmt::Display for Millivolts {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}mV", self.0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn percentages_are_validated() {
+		assert_eq!(Throttle::new(-100), Some(Throttle::MIN));
+		assert_eq!(Throttle::new(101), None);
+		assert_eq!(Steering::saturating(i8::MIN), Steering::MIN);
+
+		assert_eq!(
+			Throttle::decode(&mut Reader::new(&[(-101_i8).to_be_bytes()[0]])),
+			Err(TransportError::OutOfRange)
+		);
+	}
+
+	#[test]
+	fn ratios_are_rounded_and_clamped() {
+		assert_eq!(Throttle::from_ratio(0.424).percent(), 42);
+		assert_eq!(Throttle::from_ratio(-0.426).percent(), -43);
+		assert_eq!(Steering::from_ratio(3.0), Steering::MAX);
+		assert_eq!(Steering::from_ratio(f32::NAN), Steering::ZERO);
+		assert!((Steering::saturating(-50).ratio() + 0.5).abs() < f32::EPSILON);
+	}
+}
@@ -0,0 +1,211 @@
+//! Golden vectors of the wire format, one file per protocol revision in the `vectors` folder
+//!
+//! Each file lists frames written by the code of its revision, with the JSON of the envelopes they
+//! carry. Frames must decode to the same envelopes and envelopes encode to the same frames, so
+//! that any change of the wire format fails here.
+//!
+//! Files are never regenerated from the current code, only the JSON follows changes of the `serde`
+//! representation, which is not part of the wire format. A new revision gets a new file, written with
+//! `cargo run --bin frames -- encode`, and older files keep being decoded: with the current types
+//! for the same major revision, with [`compat`] for the previous one. Files of older majors are
+//! kept as a record of the wire format, and listed in [`UNDECODABLE`]: their frames are only
+//! checked to be well formed, with their sequence number, and their envelopes are not checked.
+
+use core::fmt;
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{
+	Answer, Envelope, FrameDecoder, FrameEncoder, Message, ProtocolVersion, Transport,
+	TransportError, compat,
+	frame::{DEFAULT_FRAME_BUFFER_SIZE, max_frame_size},
+	handshake::PROTOCOL_VERSION,
+	schema::Schema,
+};
+
+/// The vectors of every revision, oldest first
+const REVISIONS: [&str; 11] = [
+	include_str!("../vectors/1.0.json"),
+	include_str!("../vectors/2.0.json"),
+	include_str!("../vectors/2.1.json"),
+	include_str!("../vectors/2.2.json"),
+	include_str!("../vectors/3.0.json"),
+	include_str!("../vectors/3.1.json"),
+	include_str!("../vectors/4.0.json"),
+	include_str!("../vectors/4.1.json"),
+	include_str!("../vectors/4.2.json"),
+	include_str!("../vectors/4.3.json"),
+	include_str!("../vectors/4.4.json"),
+];
+
+/// The revisions older than [`compat`], whose types are gone from the code
+const UNDECODABLE: [ProtocolVersion; 4] = [
+	ProtocolVersion { major: 1, minor: 0 },
+	ProtocolVersion { major: 2, minor: 0 },
+	ProtocolVersion { major: 2, minor: 1 },
+	ProtocolVersion { major: 2, minor: 2 },
+];
+
+/// The serialized value of a frame as is, with its id, for revisions whose types are gone
+///
+/// The frame buffer also holds the sequence number and the checksum.
+#[derive(Debug)]
+struct Raw(heapless::Vec<u8, { DEFAULT_FRAME_BUFFER_SIZE - 3 }>);
+
+impl Transport for Raw {
+	const MAX_PAYLOAD_SIZE: usize = DEFAULT_FRAME_BUFFER_SIZE - 4;
+
+	fn id(&self) -> u8 {
+		self.0[0]
+	}
+
+	fn encode(&self, buffer: &mut [u8]) -> usize {
+		buffer[..self.0.len() - 1].copy_from_slice(&self.0[1..]);
+		self.0.len() - 1
+	}
+
+	fn deserialize(buffer: &[u8]) -> Result<Self, TransportError> {
+		if buffer.is_empty() {
+			return Err(TransportError::Truncated);
+		}
+
+		heapless::Vec::from_slice(buffer)
+			.map(Self)
+			.map_err(|_| TransportError::FrameTooLong)
+	}
+}
+
+/// Parses the vectors of a revision
+fn parse(revision: &str) -> (ProtocolVersion, Value) {
+	let vectors = serde_json::from_str::<Value>(revision).expect("vectors are JSON");
+	let version =
+		serde_json::from_value(vectors["version"].clone()).expect("vectors have a version");
+
+	(version, vectors)
+}
+
+/// Parses whitespace separated hex bytes into the buffer and returns their number
+fn parse_hex(hex: &str, buffer: &mut [u8]) -> usize {
+	let mut length = 0;
+	for byte in hex.split_whitespace() {
+		buffer[length] = u8::from_str_radix(byte, 16).expect("frames are hex bytes");
+		length += 1;
+	}
+
+	length
+}
+
+/// Checks that every frame decodes to its envelope and that every envelope encodes to its frame
+fn check<T>(vectors: &Value)
+where
+	T: Transport + Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+{
+	for vector in vectors.as_array().expect("vectors are a list") {
+		let hex = vector["frame"].as_str().expect("frames are strings");
+		let mut frame = [0_u8; max_frame_size(DEFAULT_FRAME_BUFFER_SIZE)];
+		let length = parse_hex(hex, &mut frame);
+
+		let mut decoder = FrameDecoder::<Envelope<T>>::new();
+		let mut envelopes = decoder.feed(&frame[..length]);
+		let envelope = envelopes
+			.next()
+			.expect("frames are complete")
+			.unwrap_or_else(|error| panic!("`{hex}` does not decode: {error}"));
+		assert!(envelopes.next().is_none(), "`{hex}` holds a single frame");
+
+		let json = serde_json::to_value(&envelope).expect("envelopes serialize");
+		assert_eq!(
+			json, vector["envelope"],
+			"`{hex}` decodes to another envelope"
+		);
+
+		let mut encoded = [0_u8; max_frame_size(DEFAULT_FRAME_BUFFER_SIZE)];
+		let encoded_length = FrameEncoder::<DEFAULT_FRAME_BUFFER_SIZE>::new()
+			.encode(&envelope, &mut encoded)
+			.expect("envelopes fit in a frame");
+		assert_eq!(
+			&encoded[..encoded_length],
+			&frame[..length],
+			"`{json}` encodes to another frame"
+		);
+	}
+}
+
+/// Checks that every frame is well formed, carries the sequence number of its envelope and encodes
+/// again to the same bytes
+fn check_frames(vectors: &Value) {
+	for vector in vectors.as_array().expect("vectors are a list") {
+		let hex = vector["frame"].as_str().expect("frames are strings");
+		let mut frame = [0_u8; max_frame_size(DEFAULT_FRAME_BUFFER_SIZE)];
+		let length = parse_hex(hex, &mut frame);
+
+		let mut decoder = FrameDecoder::<Envelope<Raw>>::new();
+		let mut envelopes = decoder.feed(&frame[..length]);
+		let envelope = envelopes
+			.next()
+			.expect("frames are complete")
+			.unwrap_or_else(|error| panic!("`{hex}` is not a valid frame: {error}"));
+		assert!(envelopes.next().is_none(), "`{hex}` holds a single frame");
+
+		assert_eq!(
+			Some(u64::from(envelope.sequence.0)),
+			vector["envelope"]["sequence"].as_u64(),
+			"`{hex}` carries another sequence number"
+		);
+
+		let mut encoded = [0_u8; max_frame_size(DEFAULT_FRAME_BUFFER_SIZE)];
+		let encoded_length = FrameEncoder::<DEFAULT_FRAME_BUFFER_SIZE>::new()
+			.encode(&envelope, &mut encoded)
+			.expect("values fit in a frame");
+		assert_eq!(
+			&encoded[..encoded_length],
+			&frame[..length],
+			"`{hex}` encodes to another frame"
+		);
+	}
+}
+
+#[test]
+fn every_revision_matches_its_vectors() {
+	for revision in REVISIONS {
+		let (version, vectors) = parse(revision);
+
+		if version.major == PROTOCOL_VERSION.major {
+			check::<Message>(&vectors["messages"]);
+			check::<Answer>(&vectors["answers"]);
+		} else if version.major == compat::VERSION.major && version <= compat::VERSION {
+			check::<compat::Message>(&vectors["messages"]);
+			check::<compat::Answer>(&vectors["answers"]);
+		} else if UNDECODABLE.contains(&version) {
+			check_frames(&vectors["messages"]);
+			check_frames(&vectors["answers"]);
+		} else {
+			panic!("revision {version} cannot be decoded anymore");
+		}
+	}
+}
+
+#[test]
+fn current_revision_has_vectors_for_every_variant() {
+	let (version, vectors) = parse(REVISIONS[REVISIONS.len() - 1]);
+	assert_eq!(
+		version, PROTOCOL_VERSION,
+		"the current revision needs vectors"
+	);
+
+	for (variants, vectors) in [
+		(Message::VARIANTS, &vectors["messages"]),
+		(Answer::VARIANTS, &vectors["answers"]),
+	] {
+		let vectors = vectors.as_array().expect("vectors are a list");
+
+		for variant in variants {
+			let covered = vectors.iter().any(|vector| {
+				let content = &vector["envelope"]["content"];
+				content == variant.name || content.get(variant.name).is_some()
+			});
+			assert!(covered, "`{}` has no vector", variant.name);
+		}
+	}
+}
@@ -36,6 +36,8 @@
               rust-toolchain
               act
               just
+              wasm-pack
+              nodejs
 
               probe-rs
               fritzing